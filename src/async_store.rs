@@ -0,0 +1,122 @@
+// An asynchronous front end to a store, for use from async code. Writes are applied in order by a
+// dedicated writer thread, which takes them from a bounded queue. Once the queue holds
+// `Options::write_queue_depth` writes, further calls to `put` and `del` wait for room rather than
+// queueing more, so a burst of writers is slowed down instead of piling up records in memory.
+// Reads run on tokio's blocking thread pool.
+
+use std::{
+    io,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use tokio::{
+    sync::{mpsc, oneshot},
+    task,
+};
+
+use crate::{store::Store, StoreError};
+
+enum WriteOp {
+    Put { key: Vec<u8>, val: Vec<u8> },
+    Del { key: Vec<u8> },
+    Flush,
+}
+
+struct Write {
+    op: WriteOp,
+    done: oneshot::Sender<Result<(), StoreError>>,
+}
+
+pub struct AsyncStore {
+    store: Arc<Mutex<Store>>,
+    writes: mpsc::Sender<Write>,
+    writer: thread::JoinHandle<()>,
+}
+
+impl AsyncStore {
+    pub fn new(store: Store) -> Self {
+        let (writes, mut queue) = mpsc::channel::<Write>(store.options().write_queue_depth);
+        let store = Arc::new(Mutex::new(store));
+
+        let writer = {
+            let store = store.clone();
+            thread::spawn(move || {
+                while let Some(write) = queue.blocking_recv() {
+                    let mut store = store.lock().unwrap();
+                    let result = match write.op {
+                        WriteOp::Put { key, val } => store.put(&key, &val),
+                        WriteOp::Del { key } => store.del(&key),
+                        WriteOp::Flush => store.flush_memtable(),
+                    };
+                    // The caller may have stopped waiting for the result.
+                    let _ = write.done.send(result);
+                }
+            })
+        };
+
+        AsyncStore {
+            store,
+            writes,
+            writer,
+        }
+    }
+
+    // Resolves once the write has been applied, which first requires room for it in the queue.
+    pub async fn put(&self, key: &[u8], val: &[u8]) -> Result<(), StoreError> {
+        self.write(WriteOp::Put {
+            key: key.to_vec(),
+            val: val.to_vec(),
+        })
+        .await
+    }
+
+    pub async fn del(&self, key: &[u8]) -> Result<(), StoreError> {
+        self.write(WriteOp::Del { key: key.to_vec() }).await
+    }
+
+    // Flushes the memtable once every write queued before it has been applied.
+    pub async fn flush_memtable(&self) -> Result<(), StoreError> {
+        self.write(WriteOp::Flush).await
+    }
+
+    pub async fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, StoreError> {
+        let store = self.store.clone();
+        let key = key.to_vec();
+        task::spawn_blocking(move || store.lock().unwrap().get(&key))
+            .await
+            .map_err(|e| StoreError::Io(io::Error::other(e)))?
+    }
+
+    // The number of writes waiting in the queue for the writer.
+    pub fn queued_writes(&self) -> usize {
+        self.writes.max_capacity() - self.writes.capacity()
+    }
+
+    // Applies every queued write and flushes the memtable, then stops the writer.
+    pub async fn close(self) -> Result<(), StoreError> {
+        let flushed = self.flush_memtable().await;
+
+        let AsyncStore { writes, writer, .. } = self;
+        drop(writes);
+        task::spawn_blocking(move || writer.join())
+            .await
+            .map_err(|e| StoreError::Io(io::Error::other(e)))?
+            .map_err(|_| StoreError::Io(io::Error::other("the writer thread panicked")))?;
+
+        flushed
+    }
+
+    async fn write(&self, op: WriteOp) -> Result<(), StoreError> {
+        let (done, result) = oneshot::channel();
+        self.writes
+            .send(Write { op, done })
+            .await
+            .map_err(|_| writer_stopped())?;
+        result.await.map_err(|_| writer_stopped())?
+    }
+}
+
+fn writer_stopped() -> StoreError {
+    StoreError::Io(io::Error::other("the writer thread has stopped"))
+}
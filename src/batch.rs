@@ -0,0 +1,102 @@
+// Puts and deletes gathered up to be written together by Store::write_batch, which writes them to
+// the WAL as a single batch, so that after a crash either every one of them is recovered or none
+// are.
+//
+// Ops are kept in the order they were added, so that the ones added since a savepoint can be
+// rolled back. When the batch is written, a later op on a key replaces an earlier one, as it would
+// have once both were written, so the batch writes at most one op for each key. Reading through a
+// batch with `get` sees its own ops ahead of what is in the store, so a batch can be built up from
+// values read as it goes.
+
+use std::collections::{btree_map, BTreeMap};
+
+use crate::{store::Store, StoreError};
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WriteBatch {
+    // Each key with the value to put there, or None to delete it, in the order they were added.
+    ops: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+    // The number of ops there were at each savepoint, most recent last.
+    savepoints: Vec<usize>,
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn put(&mut self, key: &[u8], val: &[u8]) {
+        self.ops.push((key.to_vec(), Some(val.to_vec())));
+    }
+
+    pub fn del(&mut self, key: &[u8]) {
+        self.ops.push((key.to_vec(), None));
+    }
+
+    // Marks the point that `rollback_to_savepoint` goes back to. Savepoints nest: Each rollback
+    // goes back to the most recent savepoint that hasn't been rolled back to yet.
+    pub fn set_savepoint(&mut self) {
+        self.savepoints.push(self.ops.len());
+    }
+
+    // Drops every op added since the most recent savepoint, along with the savepoint. Fails with
+    // StoreError::InvalidArgument if there is no savepoint.
+    pub fn rollback_to_savepoint(&mut self) -> Result<(), StoreError> {
+        let Some(len) = self.savepoints.pop() else {
+            return Err(StoreError::InvalidArgument(
+                "no savepoint to roll back to".to_string(),
+            ));
+        };
+        self.ops.truncate(len);
+        Ok(())
+    }
+
+    // Drops every op and savepoint.
+    pub fn clear(&mut self) {
+        self.ops.clear();
+        self.savepoints.clear();
+    }
+
+    // The value `key` would have if the batch were written to `store` now: The batch's own last op
+    // on the key if it has one, and otherwise whatever the store has.
+    pub fn get(&self, store: &Store, key: &[u8]) -> Result<Option<Vec<u8>>, StoreError> {
+        match self.ops.iter().rev().find(|(k, _)| k == key) {
+            Some((_, val)) => Ok(val.clone()),
+            None => store.get(key),
+        }
+    }
+
+    // What the batch writes in key order, as each key with the value to put there, or None for a
+    // deletion. Only the last op on each key is given.
+    pub fn iter(&self) -> Iter<'_> {
+        let ops: BTreeMap<&[u8], Option<&[u8]>> = self
+            .ops
+            .iter()
+            .map(|(key, val)| (key.as_slice(), val.as_deref()))
+            .collect();
+        Iter {
+            inner: ops.into_iter(),
+        }
+    }
+
+    // The number of keys the batch writes.
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+}
+
+pub struct Iter<'a> {
+    inner: btree_map::IntoIter<&'a [u8], Option<&'a [u8]>>,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a [u8], Option<&'a [u8]>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+}
@@ -0,0 +1,141 @@
+// Helpers shared by the dump tools.
+
+// Not every tool uses every helper.
+#![allow(dead_code)]
+
+use std::{collections::HashSet, env, fmt::Write};
+
+use crucible::protocol::ReadRecord;
+
+pub struct Args {
+    pub path: String,
+    flags: HashSet<String>,
+}
+
+impl Args {
+    // Parses a single path and any of the allowed flags from the command line, returning a usage
+    // message if anything else is given.
+    pub fn parse(allowed: &[&str], usage: &str) -> Result<Self, String> {
+        let mut path = None;
+        let mut flags = HashSet::new();
+
+        for arg in env::args().skip(1) {
+            if allowed.contains(&arg.as_str()) {
+                flags.insert(arg);
+            } else if arg.starts_with("--") || path.is_some() {
+                return Err(usage.to_string());
+            } else {
+                path = Some(arg);
+            }
+        }
+
+        match path {
+            Some(path) => Ok(Args { path, flags }),
+            None => Err(usage.to_string()),
+        }
+    }
+
+    pub fn flag(&self, name: &str) -> bool {
+        self.flags.contains(name)
+    }
+}
+
+// Keys and values are arbitrary bytes, so they are shown with anything non-printable escaped.
+pub fn escape(bytes: &[u8]) -> String {
+    bytes.escape_ascii().to_string()
+}
+
+pub fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                write!(out, "\\u{:04x}", c as u32).expect("must write to string");
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+pub fn json_bytes(bytes: &[u8]) -> String {
+    json_string(&escape(bytes))
+}
+
+// A record as a line of text, with its value in full or just its length.
+pub fn record_text(offset: u64, record: &ReadRecord, values: bool) -> String {
+    match record {
+        ReadRecord::Exists { key, val } if values => format!(
+            "{:>10}  put  \"{}\" = \"{}\"",
+            offset,
+            escape(key),
+            escape(val)
+        ),
+        ReadRecord::Exists { key, val } => format!(
+            "{:>10}  put  \"{}\" ({} byte value)",
+            offset,
+            escape(key),
+            val.len()
+        ),
+        ReadRecord::Deleted { key } => format!("{:>10}  del  \"{}\"", offset, escape(key)),
+        ReadRecord::RangeDeleted { start, end } => format!(
+            "{:>10}  del  \"{}\" .. {}",
+            offset,
+            escape(start),
+            end.as_ref()
+                .map_or("end".to_string(), |end| format!("\"{}\"", escape(end)))
+        ),
+        ReadRecord::Blob { key, blob } => format!(
+            "{:>10}  put  \"{}\" ({} byte value in blob {} at {})",
+            offset,
+            escape(key),
+            blob.len,
+            blob.file,
+            blob.offset
+        ),
+    }
+}
+
+pub fn record_json(offset: u64, record: &ReadRecord, values: bool) -> String {
+    match record {
+        ReadRecord::Exists { key, val } if values => format!(
+            "{{\"offset\":{},\"op\":\"put\",\"key\":{},\"value\":{}}}",
+            offset,
+            json_bytes(key),
+            json_bytes(val)
+        ),
+        ReadRecord::Exists { key, val } => format!(
+            "{{\"offset\":{},\"op\":\"put\",\"key\":{},\"value_length\":{}}}",
+            offset,
+            json_bytes(key),
+            val.len()
+        ),
+        ReadRecord::Deleted { key } => format!(
+            "{{\"offset\":{},\"op\":\"del\",\"key\":{}}}",
+            offset,
+            json_bytes(key)
+        ),
+        ReadRecord::RangeDeleted { start, end } => format!(
+            "{{\"offset\":{},\"op\":\"del_range\",\"start\":{},\"end\":{}}}",
+            offset,
+            json_bytes(start),
+            end.as_ref()
+                .map_or("null".to_string(), |end| json_bytes(end))
+        ),
+        ReadRecord::Blob { key, blob } => format!(
+            "{{\"offset\":{},\"op\":\"put\",\"key\":{},\"value_length\":{},\"blob\":{{\"file\":{},\"offset\":{}}}}}",
+            offset,
+            json_bytes(key),
+            blob.len,
+            blob.file,
+            blob.offset
+        ),
+    }
+}
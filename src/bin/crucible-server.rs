@@ -0,0 +1,425 @@
+// Serves a store over the Redis protocol (RESP), so that existing Redis clients and tools can be
+// used with it. Supports PING, GET, SET, DEL, EXISTS, SCAN (with MATCH limited to prefix patterns,
+// and COUNT), and SHUTDOWN, which stops the server and closes the store.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    env,
+    error::Error,
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    path,
+    process::ExitCode,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use crucible::{scan::prefix_end, store::Store, StoreError};
+
+const USAGE: &str = "usage: crucible-server [--addr <host:port>] <data-dir>
+
+Listens on 127.0.0.1:6379 unless --addr is given. The address actually bound is printed once the
+server is ready, so a port of 0 can be used to pick any free one.";
+
+const DEFAULT_ADDR: &str = "127.0.0.1:6379";
+
+// Upper bounds on what a client may send. Buffers grow only as data arrives, so a bad length
+// can't exhaust memory before the client has sent that much.
+const MAX_LINE_LENGTH: usize = 64 * 1024;
+const MAX_ARGS: usize = 1024 * 1024;
+const MAX_BULK_LENGTH: usize = 512 * 1024 * 1024;
+
+// SCAN cursors that are still open. Clients are free to abandon a scan part way, so only the most
+// recent ones are kept.
+const MAX_CURSORS: usize = 1024;
+const DEFAULT_SCAN_COUNT: usize = 10;
+
+enum Reply {
+    Simple(&'static str),
+    Error(String),
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Vec<Reply>),
+}
+
+impl Reply {
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        match self {
+            Reply::Simple(s) => write!(w, "+{}\r\n", s),
+            // Error text must stay on one line.
+            Reply::Error(e) => write!(w, "-{}\r\n", e.replace(['\r', '\n'], " ")),
+            Reply::Integer(n) => write!(w, ":{}\r\n", n),
+            Reply::Bulk(None) => write!(w, "$-1\r\n"),
+            Reply::Bulk(Some(bytes)) => {
+                write!(w, "${}\r\n", bytes.len())?;
+                w.write_all(bytes)?;
+                w.write_all(b"\r\n")
+            }
+            Reply::Array(items) => {
+                write!(w, "*{}\r\n", items.len())?;
+                items.iter().try_for_each(|item| item.write_to(w))
+            }
+        }
+    }
+}
+
+fn protocol_error(detail: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Protocol error: {}", detail),
+    )
+}
+
+// Reads a line terminated by CRLF, without the terminator. Returns None at the end of the stream.
+fn read_line<R: BufRead>(r: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut line = Vec::new();
+    let limit = MAX_LINE_LENGTH + 2;
+    if r.by_ref().take(limit as u64).read_until(b'\n', &mut line)? == 0 {
+        return Ok(None);
+    }
+    if !line.ends_with(b"\r\n") {
+        if line.len() == limit {
+            return Err(protocol_error("line too long"));
+        }
+        return Err(protocol_error("expected CRLF"));
+    }
+    line.truncate(line.len() - 2);
+    Ok(Some(line))
+}
+
+fn parse_length(bytes: &[u8], max: usize) -> io::Result<usize> {
+    std::str::from_utf8(bytes)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .filter(|n| *n <= max)
+        .ok_or_else(|| protocol_error("invalid length"))
+}
+
+// Reads the next command, which clients send as an array of bulk strings. Inline commands, which
+// are just space separated words on a line, are accepted too for the benefit of tools like telnet.
+// Returns None once the client has disconnected.
+fn read_command<R: BufRead>(r: &mut R) -> io::Result<Option<Vec<Vec<u8>>>> {
+    loop {
+        let line = match read_line(r)? {
+            Some(line) => line,
+            None => return Ok(None),
+        };
+
+        let Some(count) = line.strip_prefix(b"*") else {
+            let words = line
+                .split(|b| b.is_ascii_whitespace())
+                .filter(|word| !word.is_empty())
+                .map(|word| word.to_vec())
+                .collect::<Vec<_>>();
+            if words.is_empty() {
+                continue;
+            }
+            return Ok(Some(words));
+        };
+
+        let count = parse_length(count, MAX_ARGS)?;
+        let mut args = Vec::new();
+        for _ in 0..count {
+            let header = read_line(r)?.ok_or_else(|| protocol_error("unexpected end"))?;
+            let length = header
+                .strip_prefix(b"$")
+                .ok_or_else(|| protocol_error("expected a bulk string"))?;
+            let length = parse_length(length, MAX_BULK_LENGTH)?;
+
+            let mut arg = Vec::new();
+            if r.by_ref().take(length as u64 + 2).read_to_end(&mut arg)? < length + 2 {
+                return Err(protocol_error("unexpected end"));
+            }
+            if !arg.ends_with(b"\r\n") {
+                return Err(protocol_error("expected CRLF"));
+            }
+            arg.truncate(length);
+            args.push(arg);
+        }
+
+        if !args.is_empty() {
+            return Ok(Some(args));
+        }
+    }
+}
+
+// Continuation points of open SCANs: the key each will resume from.
+#[derive(Default)]
+struct Cursors {
+    next_id: u64,
+    keys: HashMap<u64, Vec<u8>>,
+    order: VecDeque<u64>,
+}
+
+impl Cursors {
+    fn open(&mut self, key: Vec<u8>) -> u64 {
+        if self.order.len() >= MAX_CURSORS {
+            if let Some(oldest) = self.order.pop_front() {
+                self.keys.remove(&oldest);
+            }
+        }
+
+        // Cursor 0 is the start and end of every scan, so it is never handed out.
+        self.next_id += 1;
+        self.keys.insert(self.next_id, key);
+        self.order.push_back(self.next_id);
+        self.next_id
+    }
+
+    fn take(&mut self, id: u64) -> Option<Vec<u8>> {
+        let key = self.keys.remove(&id)?;
+        self.order.retain(|open| *open != id);
+        Some(key)
+    }
+}
+
+struct Server {
+    // Taken when the server shuts down.
+    store: Mutex<Option<Store>>,
+    cursors: Mutex<Cursors>,
+    shutdown: AtomicBool,
+    addr: SocketAddr,
+}
+
+impl Server {
+    fn handle(&self, stream: TcpStream) -> io::Result<()> {
+        let mut r = BufReader::new(stream.try_clone()?);
+        let mut w = BufWriter::new(stream);
+
+        while let Some(args) = match read_command(&mut r) {
+            Ok(args) => args,
+            Err(e) => {
+                // The stream can't be resynchronized after a protocol error, so the connection is
+                // closed after reporting it.
+                Reply::Error(format!("ERR {}", e)).write_to(&mut w)?;
+                return w.flush();
+            }
+        } {
+            let name = String::from_utf8_lossy(&args[0]).to_ascii_uppercase();
+            let reply = self.execute(&name, &args[1..]);
+            reply.write_to(&mut w)?;
+            w.flush()?;
+
+            if name == "SHUTDOWN" && !matches!(reply, Reply::Error(_)) {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn execute(&self, name: &str, args: &[Vec<u8>]) -> Reply {
+        let wrong_args = || {
+            Reply::Error(format!(
+                "ERR wrong number of arguments for '{}' command",
+                name.to_ascii_lowercase()
+            ))
+        };
+
+        if name == "PING" {
+            return match args {
+                [] => Reply::Simple("PONG"),
+                [message] => Reply::Bulk(Some(message.clone())),
+                _ => wrong_args(),
+            };
+        }
+
+        let mut store = self.store.lock().unwrap();
+        let Some(store) = store.as_mut() else {
+            return Reply::Error("ERR the server is shutting down".to_string());
+        };
+
+        let result = match (name, args) {
+            ("GET", [key]) => store.get(key).map(Reply::Bulk),
+            ("SET", [key, val]) => store.put(key, val).map(|_| Reply::Simple("OK")),
+            ("SET", [_, _, ..]) => Ok(Reply::Error(
+                "ERR SET options are not supported".to_string(),
+            )),
+            ("DEL", [_, ..]) => count_keys(store, args, true),
+            ("EXISTS", [_, ..]) => count_keys(store, args, false),
+            ("SCAN", [cursor, options @ ..]) => self.scan(store, cursor, options),
+            ("SHUTDOWN", []) => {
+                self.shutdown.store(true, Ordering::SeqCst);
+                // Wake the accept loop so that it sees the shutdown.
+                let _ = TcpStream::connect(self.addr);
+                Ok(Reply::Simple("OK"))
+            }
+            ("GET" | "SET" | "DEL" | "EXISTS" | "SCAN" | "SHUTDOWN", _) => Ok(wrong_args()),
+            _ => Ok(Reply::Error(format!(
+                "ERR unknown command '{}'",
+                name.to_ascii_lowercase()
+            ))),
+        };
+
+        result.unwrap_or_else(|e| {
+            // The causes hold the details, such as the file involved.
+            let mut message = format!("ERR {}", e);
+            let mut source = e.source();
+            while let Some(cause) = source {
+                message = format!("{} {}", message, cause);
+                source = cause.source();
+            }
+            Reply::Error(message)
+        })
+    }
+
+    // SCAN cursor [MATCH prefix*] [COUNT n]
+    fn scan(
+        &self,
+        store: &Store,
+        cursor: &[u8],
+        mut options: &[Vec<u8>],
+    ) -> Result<Reply, StoreError> {
+        let syntax_error = |detail: &str| Ok(Reply::Error(format!("ERR {}", detail)));
+
+        let mut prefix = Vec::new();
+        let mut count = DEFAULT_SCAN_COUNT;
+        while let [option, value, rest @ ..] = options {
+            match option.to_ascii_uppercase().as_slice() {
+                b"MATCH" => match value.strip_suffix(b"*") {
+                    Some(p) if !p.iter().any(|b| b"*?[\\".contains(b)) => prefix = p.to_vec(),
+                    _ => return syntax_error("only prefix patterns like 'abc*' are supported"),
+                },
+                b"COUNT" => match std::str::from_utf8(value).ok().and_then(|v| v.parse().ok()) {
+                    Some(n) if n > 0 => count = n,
+                    _ => return syntax_error("value is not an integer or out of range"),
+                },
+                _ => return syntax_error("syntax error"),
+            }
+            options = rest;
+        }
+        if !options.is_empty() {
+            return syntax_error("syntax error");
+        }
+
+        let cursor = match std::str::from_utf8(cursor)
+            .ok()
+            .and_then(|c| c.parse().ok())
+        {
+            Some(cursor) => cursor,
+            None => return syntax_error("invalid cursor"),
+        };
+        let start = match cursor {
+            0 => prefix.clone(),
+            id => match self.cursors.lock().unwrap().take(id) {
+                Some(key) => key,
+                None => return syntax_error("invalid cursor"),
+            },
+        };
+
+        let end = prefix_end(&prefix);
+        let mut keys = Vec::new();
+        let mut next = 0;
+        for record in store.scan(&start, end.as_deref())? {
+            let (key, _) = record?;
+            if keys.len() == count {
+                next = self.cursors.lock().unwrap().open(key);
+                break;
+            }
+            keys.push(Reply::Bulk(Some(key)));
+        }
+
+        Ok(Reply::Array(vec![
+            Reply::Bulk(Some(next.to_string().into_bytes())),
+            Reply::Array(keys),
+        ]))
+    }
+}
+
+// Counts how many of the keys exist, deleting them if `delete` is set.
+fn count_keys(store: &mut Store, keys: &[Vec<u8>], delete: bool) -> Result<Reply, StoreError> {
+    let mut count = 0;
+    for key in keys {
+        if store.get(key)?.is_some() {
+            count += 1;
+            if delete {
+                store.del(key)?;
+            }
+        }
+    }
+    Ok(Reply::Integer(count))
+}
+
+fn parse_args() -> Result<(String, String), String> {
+    let mut addr = DEFAULT_ADDR.to_string();
+    let mut data_dir = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--addr" => addr = args.next().ok_or_else(|| USAGE.to_string())?,
+            arg if arg.starts_with("--") || data_dir.is_some() => return Err(USAGE.to_string()),
+            _ => data_dir = Some(arg),
+        }
+    }
+
+    Ok((addr, data_dir.ok_or_else(|| USAGE.to_string())?))
+}
+
+fn serve(addr: &str, data_dir: &str) -> Result<(), String> {
+    let store =
+        Store::new(path::Path::new(data_dir), None, None, None).map_err(|e| e.to_string())?;
+    let listener = TcpListener::bind(addr).map_err(|e| format!("binding {}: {}", addr, e))?;
+    let addr = listener.local_addr().map_err(|e| e.to_string())?;
+
+    let server = Arc::new(Server {
+        store: Mutex::new(Some(store)),
+        cursors: Mutex::new(Cursors::default()),
+        shutdown: AtomicBool::new(false),
+        addr,
+    });
+
+    println!("listening on {}", addr);
+    io::stdout().flush().map_err(|e| e.to_string())?;
+
+    for stream in listener.incoming() {
+        if server.shutdown.load(Ordering::SeqCst) {
+            break;
+        }
+
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("accepting a connection: {}", e);
+                continue;
+            }
+        };
+
+        let server = server.clone();
+        thread::spawn(move || {
+            if let Err(e) = server.handle(stream) {
+                eprintln!("connection closed: {}", e);
+            }
+        });
+    }
+
+    // Everything written is already in the WAL, but flushing leaves the store with nothing to
+    // recover the next time it is opened.
+    let store = server.store.lock().unwrap().take();
+    if let Some(mut store) = store {
+        store.flush_memtable().map_err(|e| e.to_string())?;
+    }
+
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let (addr, data_dir) = match parse_args() {
+        Ok(args) => args,
+        Err(usage) => {
+            eprintln!("{}", usage);
+            return ExitCode::from(2);
+        }
+    };
+
+    match serve(&addr, &data_dir) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
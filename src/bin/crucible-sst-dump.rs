@@ -0,0 +1,317 @@
+// Prints the contents of a table file: its footer, index, records, and summary statistics. Whatever
+// can be read from a damaged table is printed, along with where reading failed.
+
+mod common;
+
+use std::{
+    fs,
+    io::{self, BufReader},
+    process::ExitCode,
+};
+
+use common::{escape, json_bytes, json_string, record_json, record_text, Args};
+use crucible::{
+    protocol::{Footer, ReadRecord},
+    sst::{IndexReader, PhysicalIter},
+};
+
+const USAGE: &str =
+    "usage: crucible-sst-dump [--footer] [--index] [--records] [--stats] [--values] [--json] <path>
+
+Prints every section unless specific ones are requested. Records are shown with their value length
+unless --values is given.";
+
+// Everything that could be read from a table, and where reading each part of it stopped if it
+// failed.
+struct Dump {
+    file_length: u64,
+    footer: Result<Footer, String>,
+    // Tuples of (record offset, key).
+    index: Vec<(u32, Vec<u8>)>,
+    index_error: Option<(u64, String)>,
+    // Tuples of (offset, record).
+    records: Vec<(u64, ReadRecord)>,
+    records_error: Option<(u64, String)>,
+}
+
+impl Dump {
+    fn read(path: &str) -> io::Result<Self> {
+        let file = fs::File::open(path)?;
+        let file_length = file.metadata()?.len();
+
+        let footer = Footer::new_from_reader(&mut BufReader::new(&file)).map_err(|e| e.to_string());
+
+        let mut index = Vec::new();
+        let mut index_error = None;
+        if let Ok(footer) = &footer {
+            let mut entry_offset = footer.index_start as u64;
+            for entry in IndexReader(BufReader::new(&file)) {
+                match entry {
+                    Ok(entry) => {
+                        entry_offset += 8 + entry.key.len() as u64;
+                        index.push((entry.offset, entry.key));
+                    }
+                    Err(e) => {
+                        index_error = Some((entry_offset, e.to_string()));
+                        break;
+                    }
+                }
+            }
+        }
+
+        let mut records = Vec::new();
+        let mut records_error = None;
+        let mut iter = PhysicalIter::open(path.as_ref())?;
+        while let Some(next) = iter.next() {
+            match next {
+                Ok(next) => records.push(next),
+                Err(e) => records_error = Some((iter.offset(), e.to_string())),
+            }
+        }
+
+        Ok(Dump {
+            file_length,
+            footer,
+            index,
+            index_error,
+            records,
+            records_error,
+        })
+    }
+
+    fn is_ok(&self) -> bool {
+        self.footer.is_ok() && self.index_error.is_none() && self.records_error.is_none()
+    }
+
+    fn stats(&self) -> Stats {
+        let mut stats = Stats {
+            file_bytes: self.file_length,
+            ..Default::default()
+        };
+
+        for (_, record) in &self.records {
+            stats.key_bytes += record.key().len() as u64;
+            match record {
+                ReadRecord::Exists { val, .. } => {
+                    stats.puts += 1;
+                    stats.value_bytes += val.len() as u64;
+                }
+                // The value itself is in a blob file rather than the table.
+                ReadRecord::Blob { .. } => stats.puts += 1,
+                ReadRecord::Deleted { .. } | ReadRecord::RangeDeleted { .. } => stats.deletes += 1,
+            }
+            stats.data_bytes += record.size() as u64;
+        }
+
+        if let Ok(footer) = &self.footer {
+            let footer_length = footer.footer_length.expect("footer must have length") as u64;
+            stats.footer_bytes = footer_length;
+            stats.index_bytes = self.file_length - footer_length - footer.index_start as u64;
+        }
+
+        stats
+    }
+}
+
+#[derive(Default)]
+struct Stats {
+    puts: u64,
+    deletes: u64,
+    key_bytes: u64,
+    value_bytes: u64,
+    data_bytes: u64,
+    index_bytes: u64,
+    footer_bytes: u64,
+    file_bytes: u64,
+}
+
+struct Sections {
+    footer: bool,
+    index: bool,
+    records: bool,
+    stats: bool,
+    values: bool,
+}
+
+fn print_text(dump: &Dump, sections: &Sections) {
+    if sections.footer {
+        println!("footer:");
+        match &dump.footer {
+            Ok(footer) => {
+                println!("  start_key: \"{}\"", escape(&footer.start_key));
+                println!("  end_key: \"{}\"", escape(&footer.end_key));
+                println!("  index_start: {}", footer.index_start);
+                if let Some(start) = footer.range_deletions_start {
+                    println!("  range_deletions_start: {}", start);
+                }
+                if let Some(num_entries) = footer.num_entries {
+                    println!("  num_entries: {}", num_entries);
+                }
+                if let Some(data_length) = footer.data_length {
+                    println!("  data_length: {}", data_length);
+                }
+                if let Some(flags) = footer.index_flags {
+                    println!("  index_flags: {:#x}", flags);
+                }
+                for key in &footer.split_keys {
+                    println!("  split_key: \"{}\"", escape(key));
+                }
+                if let Some(checksum) = footer.checksum {
+                    println!("  checksum: {:#010x}", checksum);
+                }
+                println!(
+                    "  footer_length: {}",
+                    footer.footer_length.expect("footer must have length")
+                );
+            }
+            Err(e) => println!("  error: {}", e),
+        }
+    }
+
+    if sections.index {
+        println!("index ({} entries):", dump.index.len());
+        for (offset, key) in &dump.index {
+            println!("  {:>10}  \"{}\"", offset, escape(key));
+        }
+        if let Some((offset, e)) = &dump.index_error {
+            println!("  error at offset {}: {}", offset, e);
+        }
+    }
+
+    if sections.records {
+        println!("records ({}):", dump.records.len());
+        for (offset, record) in &dump.records {
+            println!("  {}", record_text(*offset, record, sections.values));
+        }
+        if let Some((offset, e)) = &dump.records_error {
+            println!("  error at offset {}: {}", offset, e);
+        }
+    }
+
+    if sections.stats {
+        let stats = dump.stats();
+        println!("stats:");
+        println!(
+            "  records: {} ({} puts, {} deletes)",
+            stats.puts + stats.deletes,
+            stats.puts,
+            stats.deletes
+        );
+        println!("  key bytes: {}", stats.key_bytes);
+        println!("  value bytes: {}", stats.value_bytes);
+        println!("  data bytes: {}", stats.data_bytes);
+        println!("  index bytes: {}", stats.index_bytes);
+        println!("  footer bytes: {}", stats.footer_bytes);
+        println!("  file bytes: {}", stats.file_bytes);
+    }
+}
+
+fn print_json(path: &str, dump: &Dump, sections: &Sections) {
+    let error = |e: &Option<(u64, String)>| match e {
+        Some((offset, e)) => format!("{{\"offset\":{},\"message\":{}}}", offset, json_string(e)),
+        None => "null".to_string(),
+    };
+
+    let mut fields = vec![format!("\"path\":{}", json_string(path))];
+
+    if sections.footer {
+        fields.push(match &dump.footer {
+            Ok(footer) => format!(
+                "\"footer\":{{\"start_key\":{},\"end_key\":{},\"index_start\":{},\"footer_length\":{}}}",
+                json_bytes(&footer.start_key),
+                json_bytes(&footer.end_key),
+                footer.index_start,
+                footer.footer_length.expect("footer must have length")
+            ),
+            Err(e) => format!("\"footer\":null,\"footer_error\":{}", json_string(e)),
+        });
+    }
+
+    if sections.index {
+        let entries = dump
+            .index
+            .iter()
+            .map(|(offset, key)| format!("{{\"offset\":{},\"key\":{}}}", offset, json_bytes(key)))
+            .collect::<Vec<_>>();
+        fields.push(format!("\"index\":[{}]", entries.join(",")));
+        fields.push(format!("\"index_error\":{}", error(&dump.index_error)));
+    }
+
+    if sections.records {
+        let records = dump
+            .records
+            .iter()
+            .map(|(offset, record)| record_json(*offset, record, sections.values))
+            .collect::<Vec<_>>();
+        fields.push(format!("\"records\":[{}]", records.join(",")));
+        fields.push(format!("\"records_error\":{}", error(&dump.records_error)));
+    }
+
+    if sections.stats {
+        let stats = dump.stats();
+        fields.push(format!(
+            "\"stats\":{{\"puts\":{},\"deletes\":{},\"key_bytes\":{},\"value_bytes\":{},\"data_bytes\":{},\"index_bytes\":{},\"footer_bytes\":{},\"file_bytes\":{}}}",
+            stats.puts,
+            stats.deletes,
+            stats.key_bytes,
+            stats.value_bytes,
+            stats.data_bytes,
+            stats.index_bytes,
+            stats.footer_bytes,
+            stats.file_bytes
+        ));
+    }
+
+    println!("{{{}}}", fields.join(","));
+}
+
+fn main() -> ExitCode {
+    let args = match Args::parse(
+        &[
+            "--footer",
+            "--index",
+            "--records",
+            "--stats",
+            "--values",
+            "--json",
+        ],
+        USAGE,
+    ) {
+        Ok(args) => args,
+        Err(usage) => {
+            eprintln!("{}", usage);
+            return ExitCode::from(2);
+        }
+    };
+
+    let all = !["--footer", "--index", "--records", "--stats"]
+        .iter()
+        .any(|flag| args.flag(flag));
+    let sections = Sections {
+        footer: all || args.flag("--footer"),
+        index: all || args.flag("--index"),
+        records: all || args.flag("--records"),
+        stats: all || args.flag("--stats"),
+        values: args.flag("--values"),
+    };
+
+    let dump = match Dump::read(&args.path) {
+        Ok(dump) => dump,
+        Err(e) => {
+            eprintln!("{}: {}", args.path, e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if args.flag("--json") {
+        print_json(&args.path, &dump, &sections);
+    } else {
+        print_text(&dump, &sections);
+    }
+
+    if dump.is_ok() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
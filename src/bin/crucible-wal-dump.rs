@@ -0,0 +1,180 @@
+// Prints the records in a WAL file, how much of it could be read, and where reading failed if it
+// did. With --follow, keeps printing records as they are appended to a live WAL.
+
+mod common;
+
+use std::{
+    fs,
+    io::{self, BufReader, Seek, SeekFrom},
+    path,
+    process::ExitCode,
+    thread,
+    time::Duration,
+};
+
+use common::{json_string, record_json, record_text, Args};
+use crucible::{
+    protocol::{ReadRecord, WalEntry},
+    wal,
+};
+
+const USAGE: &str = "usage: crucible-wal-dump [--values] [--json] [--follow] <path>
+
+Records are shown with their value length unless --values is given. With --json, each record and
+the final summary are printed as a line of JSON.";
+
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(200);
+
+struct Output {
+    json: bool,
+    values: bool,
+}
+
+impl Output {
+    fn record(&self, offset: u64, record: &ReadRecord) {
+        if self.json {
+            println!("{}", record_json(offset, record, self.values));
+        } else {
+            println!("{}", record_text(offset, record, self.values));
+        }
+    }
+
+    fn summary(&self, records: u64, clean_bytes: u64, file_bytes: u64, error: Option<&io::Error>) {
+        if self.json {
+            let error = match error {
+                Some(e) => format!(
+                    "{{\"offset\":{},\"message\":{}}}",
+                    clean_bytes,
+                    json_string(&e.to_string())
+                ),
+                None => "null".to_string(),
+            };
+            println!(
+                "{{\"records\":{},\"clean_bytes\":{},\"file_bytes\":{},\"error\":{}}}",
+                records, clean_bytes, file_bytes, error
+            );
+        } else {
+            println!("records: {}", records);
+            println!("clean bytes: {} of {}", clean_bytes, file_bytes);
+            if let Some(e) = error {
+                println!("error at offset {}: {}", clean_bytes, e);
+            }
+        }
+    }
+}
+
+// Prints every record that can be read, returning the length of the readable prefix of the WAL and
+// the error that ended it, if any.
+fn dump(path: &path::Path, output: &Output) -> io::Result<(u64, Option<io::Error>)> {
+    let file_bytes = fs::metadata(path)?.len();
+
+    // The reader expects at least one record, so an empty WAL is handled here.
+    if file_bytes == 0 {
+        output.summary(0, 0, 0, None);
+        return Ok((0, None));
+    }
+
+    let mut records = 0;
+    let mut error = None;
+    let mut reader = wal::Reader::new(path)?;
+    while let Some(record) = reader.next() {
+        match record {
+            Ok(record) => {
+                output.record(reader.position(), &record);
+                records += 1;
+            }
+            Err(e) => error = Some(e),
+        }
+    }
+
+    let offset = reader.offset();
+    output.summary(records, offset, file_bytes, error.as_ref());
+    Ok((offset, error))
+}
+
+// Polls for records appended after `offset`. A partially written record or batch is retried until
+// it is complete. If the WAL is replaced by a flush, following starts again from the beginning of the new
+// one.
+fn follow(path: &path::Path, mut offset: u64, output: &Output) -> io::Result<()> {
+    loop {
+        thread::sleep(FOLLOW_INTERVAL);
+
+        let mut file = match fs::File::open(path) {
+            Ok(file) => file,
+            // The WAL may be briefly missing while it is archived and replaced.
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+
+        let length = file.metadata()?.len();
+        if length < offset {
+            eprintln!("{} was reset", path.display());
+            offset = 0;
+        }
+
+        file.seek(SeekFrom::Start(offset))?;
+        let mut r = BufReader::new(file);
+        while offset < length {
+            match WalEntry::read_from(&mut r) {
+                Ok(WalEntry::Record(record)) => {
+                    output.record(offset, &record);
+                    offset += record.size() as u64;
+                }
+                Ok(WalEntry::Batch(records)) => {
+                    // Past the batch's header.
+                    offset += 9;
+                    for record in records {
+                        output.record(offset, &record);
+                        offset += record.size() as u64;
+                    }
+                }
+                Ok(entry @ WalEntry::Codec(id)) => {
+                    eprintln!(
+                        "values from offset {} are encoded with codec {}, and shown as they are",
+                        offset, id
+                    );
+                    offset += entry.size() as u64;
+                }
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let args = match Args::parse(&["--values", "--json", "--follow"], USAGE) {
+        Ok(args) => args,
+        Err(usage) => {
+            eprintln!("{}", usage);
+            return ExitCode::from(2);
+        }
+    };
+
+    let output = Output {
+        json: args.flag("--json"),
+        values: args.flag("--values"),
+    };
+    let path = path::Path::new(&args.path);
+
+    let (clean_bytes, error) = match dump(path, &output) {
+        Ok(dumped) => dumped,
+        Err(e) => {
+            eprintln!("{}: {}", args.path, e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if args.flag("--follow") {
+        if let Err(e) = follow(path, clean_bytes, &output) {
+            eprintln!("{}: {}", args.path, e);
+            return ExitCode::FAILURE;
+        }
+    }
+
+    if error.is_some() {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
@@ -0,0 +1,244 @@
+// Reads and writes a store from the shell. Every command opens the store through the same API an
+// embedding program would use.
+
+use std::{env, error::Error, path, process::ExitCode};
+
+use crucible::{encoding::KeyEncoding, scan::prefix_end, store::Store, StoreError};
+
+const USAGE: &str = "usage: crucible [--hex | --base64] <data-dir> <command> [arguments]
+
+commands:
+  get <key>
+  put <key> <value>
+  del <key>
+  scan [--prefix <p>] [--start <s>] [--end <e>] [--limit <n>]
+  stats
+  describe
+  flush
+  compact
+
+Keys and values are taken as given and printed with non-printable bytes escaped, unless --hex or
+--base64 is used, in which case they are both read and printed in that encoding.";
+
+#[derive(Clone, Copy)]
+enum Encoding {
+    Text,
+    Encoded(KeyEncoding),
+}
+
+impl Encoding {
+    fn decode(&self, s: &str) -> Result<Vec<u8>, String> {
+        match self {
+            Encoding::Text => Ok(s.as_bytes().to_vec()),
+            Encoding::Encoded(encoding) => encoding.decode(s),
+        }
+    }
+
+    fn encode(&self, bytes: &[u8]) -> String {
+        match self {
+            Encoding::Text => bytes.escape_ascii().to_string(),
+            Encoding::Encoded(encoding) => encoding.encode(bytes),
+        }
+    }
+}
+
+#[derive(Default)]
+struct ScanArgs {
+    prefix: Option<String>,
+    start: Option<String>,
+    end: Option<String>,
+    limit: Option<usize>,
+}
+
+enum Command {
+    Get(String),
+    Put(String, String),
+    Del(String),
+    Scan(ScanArgs),
+    Stats,
+    Describe,
+    Flush,
+    Compact,
+}
+
+struct Args {
+    encoding: Encoding,
+    data_dir: String,
+    command: Command,
+}
+
+impl Args {
+    fn parse(args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let usage = || USAGE.to_string();
+
+        let mut encoding = Encoding::Text;
+        let mut scan = ScanArgs::default();
+        let mut positional = Vec::new();
+
+        let mut args = args;
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(usage);
+            match arg.as_str() {
+                "--hex" => encoding = Encoding::Encoded(KeyEncoding::Hex),
+                "--base64" => encoding = Encoding::Encoded(KeyEncoding::Base64),
+                "--prefix" => scan.prefix = Some(value()?),
+                "--start" => scan.start = Some(value()?),
+                "--end" => scan.end = Some(value()?),
+                "--limit" => {
+                    let limit = value()?;
+                    scan.limit = Some(
+                        limit
+                            .parse()
+                            .map_err(|_| format!("invalid limit \"{}\"", limit))?,
+                    );
+                }
+                arg if arg.starts_with("--") => return Err(usage()),
+                _ => positional.push(arg),
+            }
+        }
+
+        let mut positional = positional.into_iter();
+        let data_dir = positional.next().ok_or_else(usage)?;
+        let name = positional.next().ok_or_else(usage)?;
+        let rest = positional.collect::<Vec<_>>();
+
+        let scan_options = scan.prefix.is_some()
+            || scan.start.is_some()
+            || scan.end.is_some()
+            || scan.limit.is_some();
+        let command = match (name.as_str(), rest.as_slice()) {
+            ("get", [key]) => Command::Get(key.clone()),
+            ("put", [key, value]) => Command::Put(key.clone(), value.clone()),
+            ("del", [key]) => Command::Del(key.clone()),
+            ("scan", []) => Command::Scan(scan),
+            ("stats", []) => Command::Stats,
+            ("describe", []) => Command::Describe,
+            ("flush", []) => Command::Flush,
+            ("compact", []) => Command::Compact,
+            _ => return Err(usage()),
+        };
+
+        if scan_options && !matches!(command, Command::Scan(_)) {
+            return Err(usage());
+        }
+
+        Ok(Args {
+            encoding,
+            data_dir,
+            command,
+        })
+    }
+}
+
+fn run(args: Args) -> Result<ExitCode, String> {
+    // The error followed by its causes, which hold the details such as the file involved.
+    let err = |e: StoreError| {
+        let mut message = e.to_string();
+        let mut source = e.source();
+        while let Some(cause) = source {
+            message = format!("{} {}", message, cause);
+            source = cause.source();
+        }
+        message
+    };
+    let enc = args.encoding;
+    let mut store = Store::new(path::Path::new(&args.data_dir), None, None, None).map_err(err)?;
+
+    match args.command {
+        Command::Get(key) => match store.get(&enc.decode(&key)?).map_err(err)? {
+            Some(val) => println!("{}", enc.encode(&val)),
+            None => {
+                eprintln!("not found");
+                return Ok(ExitCode::FAILURE);
+            }
+        },
+        Command::Put(key, val) => store
+            .put(&enc.decode(&key)?, &enc.decode(&val)?)
+            .map_err(err)?,
+        Command::Del(key) => store.del(&enc.decode(&key)?).map_err(err)?,
+        Command::Scan(scan) => {
+            let prefix = scan.prefix.map(|p| enc.decode(&p)).transpose()?;
+            let mut start = scan.start.map(|s| enc.decode(&s)).transpose()?;
+            let mut end = scan.end.map(|e| enc.decode(&e)).transpose()?;
+
+            // A prefix narrows whatever range was given.
+            if let Some(prefix) = prefix {
+                if start.as_ref().is_none_or(|s| *s < prefix) {
+                    start = Some(prefix.clone());
+                }
+                if let Some(prefix_end) = prefix_end(&prefix) {
+                    if end.as_ref().is_none_or(|e| *e > prefix_end) {
+                        end = Some(prefix_end);
+                    }
+                }
+            }
+
+            let start = start.unwrap_or_default();
+            let records = store.scan(&start, end.as_deref()).map_err(err)?;
+            for record in records.take(scan.limit.unwrap_or(usize::MAX)) {
+                let (key, val) = record.map_err(err)?;
+                println!("{}\t{}", enc.encode(&key), enc.encode(&val));
+            }
+        }
+        Command::Stats => {
+            let mut levels: Vec<(usize, u64)> = Vec::new();
+            for (level, table) in store.tables() {
+                let size = table.size().map_err(|e| e.to_string())?;
+                if levels.len() <= level {
+                    levels.resize(level + 1, (0, 0));
+                }
+                levels[level].0 += 1;
+                levels[level].1 += size;
+            }
+
+            let mut live_keys = 0;
+            for record in store.scan(b"", None).map_err(err)? {
+                record.map_err(err)?;
+                live_keys += 1;
+            }
+
+            println!("live keys: {}", live_keys);
+            for (level, (tables, bytes)) in levels.iter().enumerate() {
+                println!("level {}: {} tables, {} bytes", level, tables, bytes);
+            }
+            println!("wal bytes: {}", store.wal_size());
+        }
+        Command::Describe => {
+            println!("id: {}", store.id());
+            println!("data dir: {}", args.data_dir);
+            for (level, table) in store.tables() {
+                let size = table.size().map_err(|e| e.to_string())?;
+                println!(
+                    "level {}: {} ({} bytes, {} to {})",
+                    level,
+                    table.path.display(),
+                    size,
+                    enc.encode(&table.key_start()),
+                    enc.encode(&table.key_end())
+                );
+            }
+        }
+        Command::Flush => store.flush_memtable().map_err(err)?,
+        Command::Compact => store.compact().map_err(err)?,
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn main() -> ExitCode {
+    let args = match Args::parse(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(usage) => {
+            eprintln!("{}", usage);
+            return ExitCode::from(2);
+        }
+    };
+
+    match run(args) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,339 @@
+// Key-value separation. With Options::min_blob_size, values at least that large are appended to a
+// blob file instead of going into the WAL and tables, which hold a small BlobRef in their place.
+// Compactions then rewrite only the references, however large the values are. Blob files sit in
+// the data directory as "N.blob", and each holds a sequence of entries:
+//
+//      [key_length: u32][val_length: u32][key][val]
+//
+// The key is kept so that `Store::collect_blobs` can tell whether an entry is still live, by
+// checking whether the key's current record refers to it.
+//
+// A value is synced to its blob file before the reference to it is written to the WAL, so the
+// store never holds a reference to a value that a crash could lose. A crash can leave the tail of
+// the last blob file without a reference, or only partly written; that space is reclaimed along
+// with the rest of the file's garbage. The store never appends to a blob file it didn't create
+// since it was opened, so nothing is written after such a tail.
+
+use std::{
+    fs,
+    io::{self, BufReader, Read, Seek, SeekFrom, Write},
+    path,
+};
+
+use crate::{context::IoContext, protocol::read_bytes, StoreError};
+
+pub(crate) const BLOB_EXT: &str = "blob";
+
+// A file id, offset, and length.
+pub(crate) const BLOB_REF_LENGTH: usize = 20;
+
+// Once a blob file is at least this large, the next value starts a new one. Space is reclaimed a
+// whole file at a time, so this bounds how much a single live value can keep around.
+const BLOB_FILE_SIZE_LIMIT: u64 = 64 * 1024 * 1024;
+
+// Where a value is kept: `len` bytes at `offset` in blob file number `file`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlobRef {
+    pub file: u64,
+    pub offset: u64,
+    pub len: u32,
+}
+
+impl BlobRef {
+    pub fn to_bytes(&self) -> [u8; BLOB_REF_LENGTH] {
+        let mut buf = [0; BLOB_REF_LENGTH];
+        buf[0..8].copy_from_slice(&self.file.to_le_bytes());
+        buf[8..16].copy_from_slice(&self.offset.to_le_bytes());
+        buf[16..20].copy_from_slice(&self.len.to_le_bytes());
+        buf
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        if bytes.len() != BLOB_REF_LENGTH {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("blob reference has length {}", bytes.len()),
+            ));
+        }
+
+        let u64_at = |start: usize| {
+            u64::from_le_bytes(
+                bytes[start..start + 8]
+                    .try_into()
+                    .expect("must convert slice to byte array"),
+            )
+        };
+        Ok(BlobRef {
+            file: u64_at(0),
+            offset: u64_at(8),
+            len: u32::from_le_bytes(
+                bytes[16..20]
+                    .try_into()
+                    .expect("must convert slice to byte array"),
+            ),
+        })
+    }
+}
+
+pub(crate) fn blob_path(data_dir: &path::Path, file: u64) -> path::PathBuf {
+    let mut path = data_dir.join(format!("{}", file));
+    path.set_extension(BLOB_EXT);
+    path
+}
+
+// The numbers of the blob files in `data_dir`, in ascending order.
+pub(crate) fn blob_files(data_dir: &path::Path) -> io::Result<Vec<u64>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(data_dir).with_path("listing", data_dir)? {
+        let path = entry.with_path("listing", data_dir)?.path();
+        if path.extension().is_some_and(|ext| ext == BLOB_EXT) {
+            if let Some(file) = path.file_stem().and_then(|s| s.to_str()?.parse().ok()) {
+                files.push(file);
+            }
+        }
+    }
+
+    files.sort_unstable();
+    Ok(files)
+}
+
+// Reads the value a reference points to.
+pub(crate) fn read(data_dir: &path::Path, blob: BlobRef) -> Result<Vec<u8>, StoreError> {
+    let path = blob_path(data_dir, blob.file);
+    let read = || -> io::Result<Vec<u8>> {
+        let mut file = fs::File::open(&path)?;
+        file.seek(SeekFrom::Start(blob.offset))?;
+        read_bytes(&mut file, blob.len)
+    };
+
+    read().map_err(|e| StoreError::from_read(&path, blob.offset, e))
+}
+
+// A reader of the value `blob` refers to, from a handle of its own to the blob file.
+pub(crate) fn reader(
+    data_dir: &path::Path,
+    blob: BlobRef,
+) -> Result<io::Take<fs::File>, StoreError> {
+    let path = blob_path(data_dir, blob.file);
+    let open = || -> io::Result<io::Take<fs::File>> {
+        let mut file = fs::File::open(&path)?;
+        file.seek(SeekFrom::Start(blob.offset))?;
+        Ok(file.take(blob.len as u64))
+    };
+
+    open().map_err(|e| StoreError::from_read(&path, blob.offset, e))
+}
+
+// Appends values to the newest blob file, starting a new one when it gets too large.
+pub(crate) struct BlobWriter {
+    data_dir: path::PathBuf,
+    file: fs::File,
+    id: u64,
+    size: u64,
+    // Whether an append failed part way, leaving the file's end unknown. The next value starts a
+    // new file rather than being placed after what may be a partial entry.
+    failed: bool,
+}
+
+impl BlobWriter {
+    // Starts a new blob file, numbered after any already in `data_dir`.
+    pub fn open(data_dir: &path::Path) -> io::Result<Self> {
+        let id = blob_files(data_dir)?.last().map_or(1, |last| last + 1);
+        BlobWriter::create(data_dir, id)
+    }
+
+    fn create(data_dir: &path::Path, id: u64) -> io::Result<Self> {
+        let path = blob_path(data_dir, id);
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .with_path("creating", &path)?;
+
+        Ok(BlobWriter {
+            data_dir: data_dir.to_owned(),
+            file,
+            id,
+            size: 0,
+            failed: false,
+        })
+    }
+
+    // Appends a value and syncs it, returning where it was written.
+    pub fn append(&mut self, key: &[u8], val: &[u8]) -> io::Result<BlobRef> {
+        if self.failed || self.size >= BLOB_FILE_SIZE_LIMIT {
+            *self = BlobWriter::create(&self.data_dir, self.id + 1)?;
+        }
+
+        let val_length = u32::try_from(val.len()).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("length {} exceeds the maximum of {}", val.len(), u32::MAX),
+            )
+        })?;
+
+        let mut entry = Vec::with_capacity(8 + key.len() + val.len());
+        entry.extend_from_slice(&(key.len() as u32).to_le_bytes());
+        entry.extend_from_slice(&val_length.to_le_bytes());
+        entry.extend_from_slice(key);
+        entry.extend_from_slice(val);
+
+        let path = blob_path(&self.data_dir, self.id);
+        self.failed = true;
+        self.file.write_all(&entry).with_path("writing", &path)?;
+        #[cfg(test)]
+        if tests::FAIL_SYNC.get() {
+            return Err(io::Error::other("failed to sync")).with_path("syncing", &path);
+        }
+        self.file.sync_data().with_path("syncing", &path)?;
+        self.failed = false;
+
+        let blob = BlobRef {
+            file: self.id,
+            offset: self.size + 8 + key.len() as u64,
+            len: val_length,
+        };
+        self.size += entry.len() as u64;
+
+        Ok(blob)
+    }
+
+    // The number of the file being appended to.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+}
+
+// The entries of a blob file as pairs of key and reference, without reading the values. An entry
+// cut short at the end of the file was being appended when the store crashed, and ends the
+// iteration.
+pub(crate) struct BlobFileIter {
+    r: BufReader<fs::File>,
+    path: path::PathBuf,
+    id: u64,
+    pos: u64,
+    len: u64,
+}
+
+impl BlobFileIter {
+    pub fn new(data_dir: &path::Path, id: u64) -> io::Result<Self> {
+        let path = blob_path(data_dir, id);
+        let file = fs::File::open(&path).with_path("opening", &path)?;
+        let len = file
+            .metadata()
+            .with_path("reading metadata of", &path)?
+            .len();
+
+        Ok(BlobFileIter {
+            r: BufReader::new(file),
+            path,
+            id,
+            pos: 0,
+            len,
+        })
+    }
+
+    fn read_next(&mut self) -> io::Result<Option<(Vec<u8>, BlobRef)>> {
+        let mut header = [0; 8];
+        if self.len - self.pos < header.len() as u64 {
+            return Ok(None);
+        }
+        self.r.read_exact(&mut header)?;
+
+        let key_length = u32::from_le_bytes(
+            header[0..4]
+                .try_into()
+                .expect("must convert slice to byte array"),
+        );
+        let val_length = u32::from_le_bytes(
+            header[4..8]
+                .try_into()
+                .expect("must convert slice to byte array"),
+        );
+        let entry_length = 8 + key_length as u64 + val_length as u64;
+        if self.len - self.pos < entry_length {
+            return Ok(None);
+        }
+
+        let key = read_bytes(&mut self.r, key_length)?;
+        let blob = BlobRef {
+            file: self.id,
+            offset: self.pos + 8 + key_length as u64,
+            len: val_length,
+        };
+        self.r.seek_relative(val_length as i64)?;
+        self.pos += entry_length;
+
+        Ok(Some((key, blob)))
+    }
+}
+
+impl Iterator for BlobFileIter {
+    type Item = io::Result<(Vec<u8>, BlobRef)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_next()
+            .with_path("reading", &self.path)
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use tempdir::TempDir;
+
+    use super::*;
+
+    thread_local! {
+        // Makes appends on this thread fail after writing their entry but before syncing it.
+        pub(crate) static FAIL_SYNC: Cell<bool> = const { Cell::new(false) };
+    }
+
+    #[test]
+    fn test_blob_writer() {
+        let dir = TempDir::new("testing").unwrap();
+
+        let mut writer = BlobWriter::open(dir.path()).unwrap();
+        let first = writer.append(b"key1", b"val1").unwrap();
+        let second = writer.append(b"key2", &[7; 1000]).unwrap();
+        assert_eq!(b"val1".to_vec(), read(dir.path(), first).unwrap());
+        assert_eq!(vec![7; 1000], read(dir.path(), second).unwrap());
+        assert_eq!(first, BlobRef::from_bytes(&first.to_bytes()).unwrap());
+
+        // A partly written entry at the end of the file is left out.
+        let path = blob_path(dir.path(), writer.id());
+        let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[4, 0, 0, 0, 100, 0, 0, 0, b'k']).unwrap();
+
+        let entries = BlobFileIter::new(dir.path(), writer.id())
+            .unwrap()
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(
+            vec![(b"key1".to_vec(), first), (b"key2".to_vec(), second)],
+            entries
+        );
+
+        // A reopened store starts a new file rather than appending after the partial entry.
+        assert_eq!(writer.id() + 1, BlobWriter::open(dir.path()).unwrap().id());
+    }
+
+    #[test]
+    fn test_blob_writer_failed_append() {
+        let dir = TempDir::new("testing").unwrap();
+        let mut writer = BlobWriter::open(dir.path()).unwrap();
+        let first = writer.append(b"key1", b"val1").unwrap();
+
+        FAIL_SYNC.set(true);
+        assert!(writer.append(b"key2", b"lost").is_err());
+        FAIL_SYNC.set(false);
+
+        // The next value goes to a new file, rather than after an entry that may be incomplete.
+        let next = writer.append(b"key3", b"val3").unwrap();
+        assert_eq!(first.file + 1, next.file);
+        assert_eq!(b"val3".to_vec(), read(dir.path(), next).unwrap());
+        assert_eq!(b"val1".to_vec(), read(dir.path(), first).unwrap());
+    }
+}
@@ -0,0 +1,120 @@
+// CRC-32C (Castagnoli), as used for the whole-file checksums of tables. Computed a byte at a time
+// from a table, which is fast enough to keep up with writing tables out.
+
+use std::io::{self, Read, Write};
+
+const POLYNOMIAL: u32 = 0x82f6_3b78; // Reversed
+
+const TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ POLYNOMIAL
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+#[derive(Clone, Copy, Debug)]
+pub struct Crc32c(u32);
+
+impl Default for Crc32c {
+    fn default() -> Self {
+        Crc32c(!0)
+    }
+}
+
+impl Crc32c {
+    pub fn update(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 = TABLE[((self.0 ^ *b as u32) & 0xff) as usize] ^ (self.0 >> 8);
+        }
+    }
+
+    pub fn value(&self) -> u32 {
+        !self.0
+    }
+}
+
+// The checksum of everything `r` reads, up to its end.
+pub fn crc32c_reader<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut crc = Crc32c::default();
+    let mut buf = [0; 64 * 1024];
+    loop {
+        match r.read(&mut buf) {
+            Ok(0) => return Ok(crc.value()),
+            Ok(n) => crc.update(&buf[..n]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// Keeps a checksum of the bytes written through it.
+pub struct ChecksumWriter<W: Write> {
+    w: W,
+    crc: Crc32c,
+}
+
+impl<W: Write> ChecksumWriter<W> {
+    pub fn new(w: W) -> Self {
+        ChecksumWriter {
+            w,
+            crc: Crc32c::default(),
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.w
+    }
+
+    // The checksum of the bytes written so far.
+    pub fn crc(&self) -> Crc32c {
+        self.crc
+    }
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.w.write(buf)?;
+        self.crc.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.w.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32c() {
+        // The check value for CRC-32C.
+        let mut crc = Crc32c::default();
+        assert_eq!(0, crc.value());
+        crc.update(b"123456789");
+        assert_eq!(0xe306_9283, crc.value());
+
+        let mut w = ChecksumWriter::new(Vec::new());
+        w.write_all(b"12345").unwrap();
+        w.write_all(b"6789").unwrap();
+        assert_eq!(0xe306_9283, w.crc().value());
+        assert_eq!(
+            0xe306_9283,
+            crc32c_reader(&mut io::Cursor::new(b"123456789")).unwrap()
+        );
+    }
+}
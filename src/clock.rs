@@ -0,0 +1,145 @@
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+// A source of the current time for anything in the store that depends on it, such as expiring
+// records or time-based compaction triggers. A clock is set in the store's options, so tests can
+// substitute one they control, such as a ManualClock.
+//
+// There are two readings. The wall time is for times that are kept or shown, such as when a
+// compaction decision was made. The monotonic time is for measuring how long something has taken
+// or waited, such as Options::memtable_flush_after, and isn't thrown off by changes to the system
+// time.
+pub trait Clock: fmt::Debug + Send + Sync {
+    // Wall time since the Unix epoch. Successive calls must never go backwards.
+    fn now(&self) -> Duration;
+
+    // Time since some fixed point, such as when the clock was made. It only means anything
+    // compared to another reading from the same clock. Successive calls must never go backwards.
+    // Defaults to the wall time.
+    fn monotonic(&self) -> Duration {
+        self.now()
+    }
+}
+
+// The system's clocks. The system time can be stepped backwards, so wall readings are clamped to
+// never be earlier than the last one.
+#[derive(Debug)]
+pub struct SystemClock {
+    last_nanos: AtomicU64,
+    started: Instant,
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        SystemClock {
+            last_nanos: AtomicU64::new(0),
+            started: Instant::now(),
+        }
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        let last = self.last_nanos.fetch_max(now, Ordering::Relaxed);
+        Duration::from_nanos(now.max(last))
+    }
+
+    fn monotonic(&self) -> Duration {
+        self.started.elapsed()
+    }
+}
+
+// A clock that only moves when told to, for tests of anything that depends on the time. Clones
+// share the same time, so a test can keep one to move the clock of a store it gave another to.
+// Both readings move together: The monotonic time is how far the clock has moved since it was made.
+#[derive(Clone, Debug, Default)]
+pub struct ManualClock {
+    start: Duration,
+    elapsed_nanos: Arc<AtomicU64>,
+}
+
+impl ManualClock {
+    // A clock whose wall time is `start`.
+    pub fn new(start: Duration) -> Self {
+        ManualClock {
+            start,
+            elapsed_nanos: Arc::default(),
+        }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.elapsed_nanos
+            .fetch_add(by.as_nanos() as u64, Ordering::SeqCst);
+    }
+
+    // Moves the clock on to the wall time `now`. Panics if that is earlier than the time already
+    // read, since clocks never go backwards.
+    pub fn set(&self, now: Duration) {
+        let current = self.now();
+        assert!(
+            now >= current,
+            "a clock can't go back from {:?} to {:?}",
+            current,
+            now
+        );
+        self.advance(now - current);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        self.start + self.monotonic()
+    }
+
+    fn monotonic(&self) -> Duration {
+        Duration::from_nanos(self.elapsed_nanos.load(Ordering::SeqCst))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_system_clock() {
+        let clock = SystemClock::default();
+        let first = clock.now();
+        assert!(first > Duration::ZERO);
+        assert!(clock.now() >= first);
+
+        // A reading earlier than one already made is never returned.
+        let future = first + Duration::from_secs(3600);
+        clock
+            .last_nanos
+            .store(future.as_nanos() as u64, Ordering::Relaxed);
+        assert_eq!(future, clock.now());
+
+        let first = clock.monotonic();
+        assert!(clock.monotonic() >= first);
+    }
+
+    #[test]
+    fn test_manual_clock() {
+        let clock = ManualClock::new(Duration::from_secs(1000));
+        let shared = clock.clone();
+        assert_eq!(Duration::from_secs(1000), clock.now());
+        assert_eq!(Duration::ZERO, clock.monotonic());
+
+        shared.advance(Duration::from_secs(5));
+        assert_eq!(Duration::from_secs(1005), clock.now());
+        assert_eq!(Duration::from_secs(5), clock.monotonic());
+
+        clock.set(Duration::from_secs(1010));
+        assert_eq!(Duration::from_secs(10), shared.monotonic());
+    }
+}
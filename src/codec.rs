@@ -0,0 +1,300 @@
+// Encoding values on their way to disk and decoding them on their way back, such as to compress or
+// encrypt them, see Options::value_codec. Values are encoded where they are written to the WAL and
+// to tables, and decoded where they are read from them, so everything above that, from the memtable
+// to the values a read returns, only ever sees them as they were put.
+//
+// Each table records the id of the codec its values were encoded with, and each WAL names its codec
+// before the first record that needs it, so changing the codec a store is opened with leaves what
+// was written before readable, as long as the old codec is still given to read it with, see
+// Options::decode_values_with. Compactions write what they read with the store's current codec.
+//
+// Only values are encoded. Keys, which tables are sorted and indexed by, are left as they are, as
+// are values kept in blob files, see Options::min_blob_size.
+
+use std::{fmt, io, sync::Arc};
+
+// The id of Identity, which values that aren't encoded at all are taken to have been encoded with.
+pub const IDENTITY_CODEC_ID: u8 = 0;
+
+pub trait ValueCodec: Send + Sync {
+    // Names the codec on disk, so that what it encoded is decoded with it again. Ids are 1 byte, and
+    // must never be reused for a different encoding. Ids below 16 are kept for the built-in codecs.
+    fn id(&self) -> u8;
+
+    fn encode(&self, val: &[u8]) -> Vec<u8>;
+
+    // Decodes a value encoded by `encode`, failing with io::ErrorKind::InvalidData if it wasn't.
+    fn decode(&self, val: &[u8]) -> io::Result<Vec<u8>>;
+}
+
+// Leaves values as they are. This is the codec a store has unless it is given another.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Identity;
+
+impl ValueCodec for Identity {
+    fn id(&self) -> u8 {
+        IDENTITY_CODEC_ID
+    }
+
+    fn encode(&self, val: &[u8]) -> Vec<u8> {
+        val.to_vec()
+    }
+
+    fn decode(&self, val: &[u8]) -> io::Result<Vec<u8>> {
+        Ok(val.to_vec())
+    }
+}
+
+// Compresses values with LZ4. Values that don't compress come out a few bytes longer.
+#[cfg(feature = "lz4")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Lz4;
+
+#[cfg(feature = "lz4")]
+impl ValueCodec for Lz4 {
+    fn id(&self) -> u8 {
+        1
+    }
+
+    fn encode(&self, val: &[u8]) -> Vec<u8> {
+        lz4_flex::compress_prepend_size(val)
+    }
+
+    fn decode(&self, val: &[u8]) -> io::Result<Vec<u8>> {
+        lz4_flex::decompress_size_prepended(val)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+// Encrypts values with AES-256-GCM under a key, each with a nonce of its own written before it. A
+// value that was changed, or encrypted under another key, fails to decode rather than decoding to
+// something else.
+#[cfg(feature = "aes-gcm")]
+#[derive(Clone)]
+pub struct AesGcm {
+    cipher: aes_gcm::Aes256Gcm,
+}
+
+#[cfg(feature = "aes-gcm")]
+const NONCE_LENGTH: usize = 12;
+
+#[cfg(feature = "aes-gcm")]
+impl AesGcm {
+    pub fn new(key: &[u8; 32]) -> Self {
+        use aes_gcm::KeyInit;
+
+        AesGcm {
+            cipher: aes_gcm::Aes256Gcm::new(key.into()),
+        }
+    }
+}
+
+// The key isn't shown.
+#[cfg(feature = "aes-gcm")]
+impl fmt::Debug for AesGcm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("AesGcm")
+    }
+}
+
+#[cfg(feature = "aes-gcm")]
+impl ValueCodec for AesGcm {
+    fn id(&self) -> u8 {
+        2
+    }
+
+    fn encode(&self, val: &[u8]) -> Vec<u8> {
+        use aes_gcm::{aead::Aead, AeadCore};
+
+        let nonce = aes_gcm::Aes256Gcm::generate_nonce(&mut aes_gcm::aead::OsRng);
+        let mut out = nonce.to_vec();
+        out.extend(
+            self.cipher
+                .encrypt(&nonce, val)
+                .expect("encrypting into a Vec must not fail"),
+        );
+        out
+    }
+
+    fn decode(&self, val: &[u8]) -> io::Result<Vec<u8>> {
+        use aes_gcm::aead::Aead;
+
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "value failed to decrypt");
+        if val.len() < NONCE_LENGTH {
+            return Err(invalid());
+        }
+        let (nonce, ciphertext) = val.split_at(NONCE_LENGTH);
+        self.cipher
+            .decrypt(nonce.into(), ciphertext)
+            .map_err(|_| invalid())
+    }
+}
+
+// Stands in for the codec with the id it is given, for values that are already encoded with it, so
+// that they can be written out again as they are, such as by sst::repair, without the codec itself.
+pub(crate) struct Encoded(pub(crate) u8);
+
+impl ValueCodec for Encoded {
+    fn id(&self) -> u8 {
+        self.0
+    }
+
+    fn encode(&self, val: &[u8]) -> Vec<u8> {
+        val.to_vec()
+    }
+
+    fn decode(&self, val: &[u8]) -> io::Result<Vec<u8>> {
+        Ok(val.to_vec())
+    }
+}
+
+// The codecs a store has: One that values are encoded with, and any others that what was written
+// before may have been encoded with.
+#[derive(Clone)]
+pub(crate) struct Codecs {
+    write: Arc<dyn ValueCodec>,
+    read: Vec<Arc<dyn ValueCodec>>,
+}
+
+impl Codecs {
+    pub(crate) fn with_writer(mut self, codec: Arc<dyn ValueCodec>) -> Self {
+        self.write = codec;
+        self
+    }
+
+    pub(crate) fn with_reader(mut self, codec: Arc<dyn ValueCodec>) -> Self {
+        self.read.push(codec);
+        self
+    }
+
+    // The ids of the codecs given, starting with the writer's.
+    pub(crate) fn ids(&self) -> Vec<u8> {
+        std::iter::once(&self.write)
+            .chain(&self.read)
+            .map(|codec| codec.id())
+            .collect()
+    }
+
+    // The codec values are encoded with.
+    pub(crate) fn writer(&self) -> &Arc<dyn ValueCodec> {
+        &self.write
+    }
+
+    // Whether values are encoded at all.
+    pub(crate) fn encodes(&self) -> bool {
+        self.write.id() != IDENTITY_CODEC_ID
+    }
+
+    // The codec with the id `id`. Built-in codecs that don't need a key can always be found.
+    pub(crate) fn get(&self, id: u8) -> Option<Arc<dyn ValueCodec>> {
+        if let Some(codec) = std::iter::once(&self.write)
+            .chain(&self.read)
+            .find(|codec| codec.id() == id)
+        {
+            return Some(codec.clone());
+        }
+        match id {
+            IDENTITY_CODEC_ID => Some(Arc::new(Identity)),
+            #[cfg(feature = "lz4")]
+            1 => Some(Arc::new(Lz4)),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Codecs {
+    fn default() -> Self {
+        Codecs {
+            write: Arc::new(Identity),
+            read: Vec::new(),
+        }
+    }
+}
+
+impl fmt::Debug for Codecs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Codecs").field("ids", &self.ids()).finish()
+    }
+}
+
+// An error for a value encoded with a codec that isn't known.
+pub(crate) fn unknown_codec(id: u8) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "values are encoded with codec {}, which the store wasn't given",
+            id
+        ),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codecs() {
+        let codecs = Codecs::default();
+        assert!(!codecs.encodes());
+        assert_eq!(
+            b"val".to_vec(),
+            codecs.get(0).unwrap().decode(b"val").unwrap()
+        );
+        assert!(codecs.get(200).is_none());
+
+        let codecs = codecs.with_writer(Arc::new(Fixed(7)));
+        assert!(codecs.encodes());
+        assert_eq!(7, codecs.writer().id());
+        // What was written without a codec can still be read.
+        assert!(codecs.get(IDENTITY_CODEC_ID).is_some());
+        assert_eq!(vec![7, 3], codecs.with_reader(Arc::new(Fixed(3))).ids());
+    }
+
+    // Encodes nothing, under any id.
+    struct Fixed(u8);
+
+    impl ValueCodec for Fixed {
+        fn id(&self) -> u8 {
+            self.0
+        }
+
+        fn encode(&self, val: &[u8]) -> Vec<u8> {
+            val.to_vec()
+        }
+
+        fn decode(&self, val: &[u8]) -> io::Result<Vec<u8>> {
+            Ok(val.to_vec())
+        }
+    }
+
+    #[cfg(feature = "lz4")]
+    #[test]
+    fn test_lz4() {
+        let val = b"val".repeat(100);
+        let encoded = Lz4.encode(&val);
+        assert!(encoded.len() < val.len());
+        assert_eq!(val, Lz4.decode(&encoded).unwrap());
+        assert_eq!(b"".to_vec(), Lz4.decode(&Lz4.encode(b"")).unwrap());
+        assert!(Lz4.decode(&encoded[..encoded.len() - 1]).is_err());
+        assert!(Codecs::default().get(1).is_some());
+    }
+
+    #[cfg(feature = "aes-gcm")]
+    #[test]
+    fn test_aes_gcm() {
+        let codec = AesGcm::new(&[7; 32]);
+        let encoded = codec.encode(b"val");
+        assert_ne!(b"val", &encoded[NONCE_LENGTH..]);
+        assert_eq!(b"val".to_vec(), codec.decode(&encoded).unwrap());
+        // Each value gets a nonce of its own.
+        assert_ne!(encoded, codec.encode(b"val"));
+
+        let mut damaged = encoded.clone();
+        *damaged.last_mut().unwrap() ^= 1;
+        assert!(codec.decode(&damaged).is_err());
+        assert!(AesGcm::new(&[8; 32]).decode(&encoded).is_err());
+        assert!(codec.decode(b"short").is_err());
+        // A key is needed to decode.
+        assert!(Codecs::default().get(2).is_none());
+    }
+}
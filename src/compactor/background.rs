@@ -0,0 +1,167 @@
+// Runs automatic compactions in the background, for CompactionMode::Background. The store wakes its
+// worker after each flush, and the worker compacts if a compaction is due. Dropping the compactor
+// waits for a compaction under way to finish.
+//
+// The worker works from the tables on disk rather than the store's catalog, which may be out of
+// date. Changes to the tables on disk are serialized by the store's table lock. The worker holds
+// it for the whole of a compaction, and the store holds it while writing a table or reloading its
+// catalog, so neither ever sees the other's changes half done. Manual compactions hold it too, and
+// load the tables from disk once they have it, so one started while the worker is compacting waits
+// for it to finish and then works from what it left, rather than choosing the same inputs. Tables
+// removed by a compaction stay readable through the store's open handles until it reloads its
+// catalog.
+
+use std::{
+    any::Any,
+    io, mem,
+    panic::{self, AssertUnwindSafe},
+    path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use crate::{
+    env::{Env, WorkerHandle},
+    options::{Options, RecoveryMode},
+    recovery::RecoveryReport,
+    scan::prefix_end,
+    sst::{Catalog, Pins},
+    StoreError,
+};
+
+use super::{compactor::Compactor, decision::DecisionLog, read_trigger::ReadTrigger};
+
+pub(crate) struct BackgroundCompactor {
+    worker: WorkerHandle,
+    state: Arc<State>,
+}
+
+#[derive(Default)]
+struct State {
+    // Tables removed by compactions since the store last caught up.
+    removed: Mutex<Vec<path::PathBuf>>,
+    // The first error from a compaction since the store last caught up.
+    error: Mutex<Option<StoreError>>,
+}
+
+impl BackgroundCompactor {
+    // Compactions run on the threads of the Env in `options`, or a thread of the store's own if
+    // there is none.
+    pub(crate) fn spawn(
+        options: &Options,
+        data_dir: &path::Path,
+        tables_lock: Arc<Mutex<()>>,
+        freezes: Arc<AtomicUsize>,
+        pins: Pins,
+        read_trigger: Option<Arc<ReadTrigger>>,
+        decisions: Option<DecisionLog>,
+    ) -> Self {
+        let state = Arc::new(State::default());
+
+        let compactor = Compactor::new(options, data_dir)
+            .with_pins(pins)
+            .with_decisions(decisions);
+        let recovery_mode = options.recovery_mode;
+        let data_dir = data_dir.to_owned();
+        let cold_dir = options.cold_dir.clone();
+        let task = {
+            let state = state.clone();
+            move || {
+                // A frozen store mustn't change. The next flush after it thaws wakes the worker
+                // again.
+                if freezes.load(Ordering::SeqCst) > 0 {
+                    return;
+                }
+
+                // A panic, such as from a compaction scheduler, is caught while the lock is still
+                // held so that it isn't poisoned, and reported like any other failure.
+                let _tables = tables_lock.lock().unwrap();
+                let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                    compact(
+                        &compactor,
+                        &data_dir,
+                        cold_dir.as_deref(),
+                        recovery_mode,
+                        read_trigger.as_deref(),
+                    )
+                }))
+                .unwrap_or_else(|payload| Err(panicked(payload)));
+                match result {
+                    Ok(removed) => state.removed.lock().unwrap().extend(removed),
+                    Err(e) => {
+                        state.error.lock().unwrap().get_or_insert(e);
+                    }
+                }
+            }
+        };
+
+        let env = options.env.clone().unwrap_or_else(|| Env::new(1));
+        BackgroundCompactor {
+            worker: env.worker(Box::new(task)),
+            state,
+        }
+    }
+
+    // Lets the worker know that a compaction may be due. Flushes that happen while a compaction
+    // runs need only one more look.
+    pub(crate) fn wake(&self) {
+        self.worker.wake();
+    }
+
+    // Wakes the worker, and waits until it has run any compaction that is due.
+    pub(crate) fn wait(&self) {
+        self.worker.wait();
+    }
+
+    // Takes the outcome of the compactions that have finished since the last call: The tables they
+    // removed, or the first error one of them ran into.
+    pub(crate) fn take_finished(&self) -> Result<Vec<path::PathBuf>, StoreError> {
+        if let Some(e) = self.state.error.lock().unwrap().take() {
+            return Err(e);
+        }
+        Ok(mem::take(&mut *self.state.removed.lock().unwrap()))
+    }
+
+    // Whether a finished compaction removed the table at `path`.
+    pub(crate) fn removed(&self, path: &path::Path) -> bool {
+        self.state.removed.lock().unwrap().iter().any(|p| p == path)
+    }
+}
+
+// The error for a compaction that panicked, with the panic's message if it has one.
+fn panicked(payload: Box<dyn Any + Send>) -> StoreError {
+    let message = payload
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown cause".to_string());
+    StoreError::Io(io::Error::other(format!(
+        "background compaction panicked: {}",
+        message
+    )))
+}
+
+fn compact(
+    compactor: &Compactor,
+    data_dir: &path::Path,
+    cold_dir: Option<&path::Path>,
+    recovery_mode: RecoveryMode,
+    read_trigger: Option<&ReadTrigger>,
+) -> Result<Vec<path::PathBuf>, StoreError> {
+    // Anything skipped here was already skipped when the store was opened.
+    let open = || {
+        let mut report = RecoveryReport::default();
+        Catalog::open_tiered(data_dir, cold_dir, recovery_mode, &mut report)
+            .and_then(|catalog| catalog.with_format(compactor.format().clone()))
+    };
+    let mut removed = compactor.maybe_compact(&open()?.ssts)?;
+
+    // Ranges that gets found slow to read, see ReadTrigger.
+    for prefix in read_trigger.map(|t| t.take_pending()).unwrap_or_default() {
+        let end = prefix_end(&prefix);
+        removed.extend(compactor.compact_range(&open()?.ssts, &prefix, end.as_deref())?);
+    }
+    Ok(removed)
+}
@@ -8,7 +8,13 @@ use std::{
 
 use uuid::Uuid;
 
-use crate::protocol::{self, ReadRecord};
+use crate::{
+    checksum::ChecksumWriter,
+    context::IoContext,
+    durable::{atomic_rename_and_sync, preallocate, truncate_to_written, TEMP_EXT},
+    protocol::{self, ReadRecord, WriteRecord},
+    sst::{IndexEntry, TableFormat},
+};
 
 pub struct CombineTable<T>
 where
@@ -19,42 +25,92 @@ where
     pub sequence: Option<u32>,
 }
 
+// Returns the paths of the tables written.
 // TODO: A lot of this is redundant with Catalog::write_records. It would be nice to consolidate
 // these two.
 pub fn combine_tables<T: Iterator<Item = io::Result<ReadRecord>>>(
     tables: Vec<CombineTable<T>>,
     size_limit: usize, // Excluding index
     output_level: u32,
+    split_keys: &[Vec<u8>], // Sorted keys that no output table may span
+    versions: usize,        // How many of the newest versions of each key to keep
+    format: &TableFormat,
     output_dir: &path::Path,
+) -> io::Result<Vec<path::PathBuf>> {
+    let mut outputs = Vec::new();
+    let result = write_combined(
+        tables,
+        size_limit,
+        output_level,
+        split_keys,
+        versions,
+        format,
+        output_dir,
+        &mut outputs,
+    );
+
+    // The inputs are still in place, so the tables written so far would only duplicate their
+    // records, and the last of them is unfinished. Leaving them would keep the store from being
+    // opened again.
+    if result.is_err() {
+        for path in outputs {
+            let _ = fs::remove_file(path);
+        }
+        return result.map(|_| Vec::new());
+    }
+    Ok(outputs)
+}
+
+// Adds the path of each table to `outputs` as it is created.
+#[allow(clippy::too_many_arguments)]
+fn write_combined<T: Iterator<Item = io::Result<ReadRecord>>>(
+    tables: Vec<CombineTable<T>>,
+    size_limit: usize,
+    output_level: u32,
+    split_keys: &[Vec<u8>],
+    versions: usize,
+    format: &TableFormat,
+    output_dir: &path::Path,
+    outputs: &mut Vec<path::PathBuf>,
 ) -> io::Result<()> {
-    let mut merge = MergeIter::new();
+    // Tables written out of a merge that had to guess which record was newer would make the guess
+    // permanent, so the compaction fails instead.
+    let mut merge = MergeIter::with_versions(versions).reject_ambiguous();
 
     for table in tables {
         merge.push_iter(table.table, table.level, table.sequence)?;
     }
 
-    let mut merge = merge.peekable();
+    // Values are encoded before anything is measured, so that the tables fit once they are.
+    let mut merge = merge
+        .map(|record| record.map(|record| format.encode(record)))
+        .peekable();
 
     loop {
         let fname = Uuid::new_v4();
         let path = output_dir.join(format!("{}", output_level));
         // Create the directory if it doesn't yet exist.
-        fs::create_dir_all(&path)?;
+        fs::create_dir_all(&path).with_path("creating", &path)?;
         let mut path = path.join(fname.to_string());
         path.set_extension(protocol::SST_EXT);
 
-        let file = fs::OpenOptions::new()
-            .write(true)
-            .create(true)
-            .open(&path)?;
-
-        let mut w = BufWriter::new(&file);
+        // Written under a temporary name until it is complete.
+        let tmp = path.with_extension(TEMP_EXT);
+        let file = fs::File::create(&tmp).with_path("creating", &tmp)?;
+        outputs.push(tmp.clone());
+        // Tables are written until they reach the size limit, apart from the last.
+        let size = format
+            .max_file_size
+            .map_or(size_limit, |max| max.min(size_limit));
+        preallocate(&file, size as u64);
+
+        let mut w = ChecksumWriter::new(BufWriter::new(&file));
         let mut written = 0;
-        // Tuple of (key, offset)
-        let mut index_offsets: Vec<(Vec<u8>, usize)> = Vec::new();
+        let mut index_entries: Vec<IndexEntry> = Vec::new();
+        let mut entries_size = 0;
+        let mut longest_key = 0;
 
         let mut start_key = vec![];
-        let mut end_key = vec![];
         if let Some(record) = merge.peek() {
             match record {
                 Ok(r) => start_key = r.key().to_vec(),
@@ -64,35 +120,92 @@ pub fn combine_tables<T: Iterator<Item = io::Result<ReadRecord>>>(
             }
         }
 
-        while written < size_limit {
+        loop {
+            if let Some(Ok(next)) = merge.peek() {
+                // The last key written so far, which is also the last indexed.
+                let end_key = index_entries.last().map_or(&[][..], |e| e.key.as_slice());
+                // The versions of a key all go in the same table, even if they take it past the size
+                // limit, so that tables in a level still don't overlap.
+                let same_key = !index_entries.is_empty() && next.key() == end_key;
+                if written >= size_limit && !same_key {
+                    break;
+                }
+
+                // Start a new table rather than span a split key.
+                let split = split_keys.partition_point(|k| k.as_slice() <= end_key);
+                let crosses_split = split_keys
+                    .get(split)
+                    .is_some_and(|k| k.as_slice() <= next.key());
+                if !index_entries.is_empty() && crosses_split {
+                    break;
+                }
+
+                // Unlike the size limit, the maximum file size can't be gone past at all, except
+                // by the versions of a key.
+                if format.max_file_size.is_some() && !index_entries.is_empty() && !same_key {
+                    let entry = format.index_entry(next.key(), 0, &WriteRecord::from(next));
+                    let size = format.table_size(
+                        written + next.size(),
+                        entries_size + format.entry_size(&entry),
+                        index_entries.len() + 1,
+                        start_key.len() + next.key().len(),
+                        longest_key.max(next.key().len()),
+                    );
+                    if !format.fits(size) {
+                        break;
+                    }
+                }
+            }
+
             if let Some(record) = merge.next() {
                 let record = record?;
-                index_offsets.push((record.key().to_vec(), written));
-                written += record.write_to(&mut w)?;
-                end_key = record.key().to_vec();
+                // Only the newest version of a key is indexed. Older versions follow it.
+                if index_entries.last().is_none_or(|e| record.key() != e.key) {
+                    let entry = format.index_entry(
+                        record.key(),
+                        written as u32,
+                        &WriteRecord::from(&record),
+                    );
+                    entries_size += format.entry_size(&entry);
+                    longest_key = longest_key.max(entry.key.len());
+                    index_entries.push(entry);
+                }
+                written += record.write_to(&mut w).with_path("writing", &tmp)?;
             } else {
                 break;
             }
         }
 
         // Hit the size limit or ran out of records, so now write out the index and finish the file.
-        for (key, offset) in index_offsets.into_iter() {
-            w.write(&(offset as u32).to_le_bytes())?;
-            w.write(&(key.len() as u32).to_le_bytes())?;
-            w.write(&key)?;
-        }
+        let num_entries = index_entries.len() as u32;
+        format
+            .write_index(&mut w, &index_entries)
+            .with_path("writing", &tmp)?;
 
         // Write the footer.
         let footer = protocol::Footer {
             start_key,
-            end_key,
+            end_key: index_entries
+                .last()
+                .map(|e| e.key.clone())
+                .unwrap_or_default(),
             index_start: written as u32,
+            range_deletions_start: None,
+            num_entries: Some(num_entries),
+            data_length: Some(written as u32),
+            index_flags: format.index_flags(),
+            split_keys: format.split_keys(&index_entries),
+            checksum: None,
             footer_length: None,
         };
-        footer.write_to(&mut w)?;
-
-        w.flush()?;
-        file.sync_all()?;
+        footer
+            .write_checksummed(&mut w)
+            .and_then(|_| w.flush())
+            .and_then(|_| truncate_to_written(&file))
+            .with_path("writing", &tmp)?;
+        file.sync_all().with_path("syncing", &tmp)?;
+        atomic_rename_and_sync(&tmp, &path)?;
+        *outputs.last_mut().expect("output must have been added") = path;
 
         if merge.peek().is_none() {
             return Ok(());
@@ -108,6 +221,8 @@ where
     buf: Option<ReadRecord>,
     level: usize,
     sequence: Option<u32>,
+    // Unique among the iterators of a MergeIter, and larger for those pushed later.
+    id: usize,
 }
 
 impl<T> Ord for IterBuf<T>
@@ -126,13 +241,11 @@ where
                     return other.level.cmp(&self.level);
                 }
 
-                // Higher sequence within the same level (only possible in level 0) are newer.
-                match (&self.sequence, &other.sequence) {
-                    (Some(ss), Some(os)) => ss.cmp(&os),
-                    (_, _) => {
-                        unreachable!("cannot have equal keys within the same level and sequence")
-                    }
-                }
+                // Higher sequence within the same level (only possible in level 0) are newer. Tables
+                // in other levels shouldn't share keys, but if they do the iterator pushed later is
+                // taken as newer rather than leaving the order up to the heap, unless the merge
+                // rejects them, see MergeIter::reject_ambiguous.
+                (self.sequence, self.id).cmp(&(other.sequence, other.id))
             }
             (Some(_), None) => cmp::Ordering::Greater,
             (None, Some(_)) => cmp::Ordering::Less,
@@ -166,21 +279,51 @@ where
     }
 }
 
-struct MergeIter<T>
+// Merges iterators of records into one in ascending key order. Each key is followed by its older
+// versions, newest first, up to `versions` of them in total. Any older than that are skipped.
+pub(crate) struct MergeIter<T>
 where
     T: Iterator<Item = io::Result<ReadRecord>>,
 {
     iters: BinaryHeap<IterBuf<T>>,
+    versions: usize,
+    // The key of the last record popped, and how many versions of it have been popped. The buffer is
+    // reused rather than allocating a key for every record.
+    last_key: Vec<u8>,
+    popped: usize,
+    // The level, sequence and id of the iterator the last record was popped from.
+    last_source: (usize, Option<u32>, usize),
+    reject_ambiguous: bool,
+    next_id: usize,
 }
 
 impl<T> MergeIter<T>
 where
     T: Iterator<Item = io::Result<ReadRecord>>,
 {
+    // Returns only the newest version of each key.
     pub fn new() -> Self {
-        return MergeIter {
+        MergeIter::with_versions(1)
+    }
+
+    pub fn with_versions(versions: usize) -> Self {
+        MergeIter {
             iters: BinaryHeap::new(),
-        };
+            versions,
+            last_key: Vec::new(),
+            popped: 0,
+            last_source: (0, None, 0),
+            reject_ambiguous: false,
+            next_id: 0,
+        }
+    }
+
+    // Fails with io::ErrorKind::InvalidData on a key that iterators pushed with the same level and
+    // sequence both have, since which of their records is newer can't be told. A store only gets
+    // into that state through damage, such as overlapping level 1 tables.
+    pub fn reject_ambiguous(mut self) -> Self {
+        self.reject_ambiguous = true;
+        self
     }
 
     pub fn push_iter(
@@ -189,13 +332,73 @@ where
         level: usize,
         sequence: Option<u32>,
     ) -> io::Result<()> {
-        let buf = iter.next().transpose()?;
-        Ok(self.iters.push(IterBuf {
-            iter,
-            buf,
-            level,
-            sequence,
-        }))
+        // Empty iterators have nothing to contribute, and every iterator in the heap must have a
+        // buffered record.
+        if let Some(buf) = iter.next().transpose()? {
+            self.iters.push(IterBuf {
+                iter,
+                buf: Some(buf),
+                level,
+                sequence,
+                id: self.next_id,
+            });
+            self.next_id += 1;
+        }
+        Ok(())
+    }
+
+    // Same as `next`, but also returns the level and sequence the record's iterator was pushed
+    // with.
+    pub fn next_with_source(&mut self) -> Option<io::Result<(ReadRecord, usize, Option<u32>)>> {
+        loop {
+            // Get the highest priority iterator. Among those positioned at the same key, that's the
+            // newest, and an iterator's own versions of a key come newest first. So the versions of
+            // a key are popped newest first.
+            let mut n = self.iters.pop()?;
+            let record = n.buf.take().expect("Buffer must not be None");
+
+            let (level, sequence, id) = (n.level, n.sequence, n.id);
+
+            // Put this iterator back in, first re-filling its buffer, as long as the iterator isn't
+            // empty.
+            if let Some(new_buf) = n.iter.next() {
+                let new_buf = match new_buf {
+                    Ok(b) => b,
+                    Err(e) => return Some(Err(e)),
+                };
+
+                n.buf = Some(new_buf);
+                self.iters.push(n)
+            }
+
+            if self.popped > 0 && self.last_key == record.key() {
+                let (last_level, last_sequence, last_id) = self.last_source;
+                if self.reject_ambiguous
+                    && (level, sequence) == (last_level, last_sequence)
+                    && id != last_id
+                {
+                    return Some(Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "key \"{}\" is in more than one table in level {}, so which record \
+                             for it is newest can't be told",
+                            record.key().escape_ascii(),
+                            level
+                        ),
+                    )));
+                }
+                self.popped += 1;
+            } else {
+                self.last_key.clear();
+                self.last_key.extend_from_slice(record.key());
+                self.popped = 1;
+            }
+            self.last_source = (level, sequence, id);
+
+            if self.popped <= self.versions {
+                return Some(Ok((record, level, sequence)));
+            }
+        }
     }
 }
 
@@ -206,45 +409,8 @@ where
     type Item = io::Result<ReadRecord>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        // Get the highest priority iterator.
-        let mut n = self.iters.pop()?;
-        let record = n.buf.expect("Buffer must not be None");
-
-        // This might be the first of this key value and if it is it's the newest, so clear out
-        // everything with the same key value.
-        while let Some(nn) = self.iters.peek() {
-            let next_record = nn.buf.as_ref().expect("Buffer must not be None");
-            if record.key() == next_record.key() {
-                // Pop and re-fill the buffer of the next iterator.
-                if let Some(mut popped) = self.iters.pop() {
-                    if let Some(buf) = popped.iter.next() {
-                        let buf = match buf {
-                            Ok(b) => b,
-                            Err(e) => return Some(Err(e)),
-                        };
-
-                        popped.buf = Some(buf);
-                        self.iters.push(popped);
-                    }
-                };
-            } else {
-                break;
-            }
-        }
-
-        // Put this iterator back in, first re-filling its buffer, as long as the iterator isn't
-        // empty.
-        if let Some(new_buf) = n.iter.next() {
-            let new_buf = match new_buf {
-                Ok(b) => b,
-                Err(e) => return Some(Err(e)),
-            };
-
-            n.buf = Some(new_buf);
-            self.iters.push(n)
-        }
-
-        Some(Ok(record))
+        self.next_with_source()
+            .map(|next| next.map(|(record, _, _)| record))
     }
 }
 
@@ -252,7 +418,7 @@ where
 mod tests {
     use tempdir::TempDir;
 
-    use crate::sst::Catalog;
+    use crate::sst::{self, Catalog};
 
     use super::*;
 
@@ -269,7 +435,7 @@ mod tests {
             },
         ]
         .into_iter()
-        .map(|item| Ok(item))
+        .map(Ok)
         .collect::<Vec<io::Result<ReadRecord>>>()
         .into_iter();
 
@@ -291,7 +457,7 @@ mod tests {
             },
         ]
         .into_iter()
-        .map(|item| Ok(item))
+        .map(Ok)
         .collect::<Vec<io::Result<ReadRecord>>>()
         .into_iter();
 
@@ -306,7 +472,7 @@ mod tests {
             },
         ]
         .into_iter()
-        .map(|item| Ok(item))
+        .map(Ok)
         .collect::<Vec<io::Result<ReadRecord>>>()
         .into_iter();
 
@@ -315,7 +481,7 @@ mod tests {
             val: b"val6_4".to_vec(),
         }]
         .into_iter()
-        .map(|item| Ok(item))
+        .map(Ok)
         .collect::<Vec<io::Result<ReadRecord>>>()
         .into_iter();
 
@@ -343,9 +509,23 @@ mod tests {
         ];
 
         let dir = TempDir::new("testing").unwrap();
-        combine_tables(tables, 1024 * 1024, 1, dir.path()).unwrap();
-
-        let catalog = Catalog::new(&dir.path()).unwrap();
+        let outputs = combine_tables(
+            tables,
+            1024 * 1024,
+            1,
+            &[],
+            1,
+            &TableFormat::default(),
+            dir.path(),
+        )
+        .unwrap();
+
+        // The output was preallocated to the size limit, and cut back to its contents.
+        assert_eq!(1, outputs.len());
+        assert!(fs::metadata(&outputs[0]).unwrap().len() < 1024);
+        assert!(sst::verify(dir.path()).unwrap().is_ok());
+
+        let catalog = Catalog::new(dir.path()).unwrap();
 
         let cases = vec![
             (
@@ -395,4 +575,70 @@ mod tests {
             assert_eq!(Some(want), catalog.get(key).unwrap());
         }
     }
+
+    #[test]
+    fn test_combine_tables_versions() {
+        let table = |records: Vec<(&[u8], &[u8])>, sequence| CombineTable {
+            table: records
+                .into_iter()
+                .map(|(key, val)| {
+                    Ok(ReadRecord::Exists {
+                        key: key.to_vec(),
+                        val: val.to_vec(),
+                    })
+                })
+                .collect::<Vec<_>>()
+                .into_iter(),
+            level: 0,
+            sequence: Some(sequence),
+        };
+        let tables = vec![
+            table(vec![(b"a", b"1"), (b"b", b"1")], 0),
+            table(vec![(b"b", b"2"), (b"c", b"2")], 1),
+            table(vec![(b"b", b"3")], 2),
+        ];
+
+        // Every table is over the size limit after its first record, but the versions of "b" stay
+        // together.
+        let dir = TempDir::new("testing").unwrap();
+        combine_tables(tables, 1, 1, &[], 2, &TableFormat::default(), dir.path()).unwrap();
+
+        let catalog = Catalog::new(dir.path()).unwrap();
+        assert_eq!(3, catalog.ssts[1].len());
+        let versions = catalog
+            .get_versions(b"b", 10)
+            .unwrap()
+            .into_iter()
+            .map(|record| match record {
+                ReadRecord::Exists { val, .. } => val,
+                _ => panic!("unexpected record {:?}", record),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(vec![b"3".to_vec(), b"2".to_vec()], versions);
+        assert_eq!(1, catalog.get_versions(b"a", 10).unwrap().len());
+    }
+
+    #[test]
+    fn test_merge_iter_same_level() {
+        let records = |val: &[u8]| {
+            vec![Ok(ReadRecord::Exists {
+                key: b"key".to_vec(),
+                val: val.to_vec(),
+            })]
+            .into_iter()
+        };
+
+        // Neither input has a sequence, so the one pushed later is taken as newer.
+        let mut merge = MergeIter::with_versions(2);
+        merge.push_iter(records(b"first"), 2, None).unwrap();
+        merge.push_iter(records(b"second"), 2, None).unwrap();
+
+        let vals = merge
+            .map(|record| match record.unwrap() {
+                ReadRecord::Exists { val, .. } => val,
+                record => panic!("unexpected record {:?}", record),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(vec![b"second".to_vec(), b"first".to_vec()], vals);
+    }
 }
@@ -1,86 +1,942 @@
-use std::{fs, io, path};
+use std::{
+    fs, io, path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
 
-use crate::sst::table::Table;
+use crate::{
+    clock::Clock,
+    context::IoContext,
+    options::{CompactionInputs, Options, SchedulerHook},
+    protocol::WriteRecord,
+    sst::{table::Table, table_info, write_table, Pins, TableFormat},
+    tombstone::{self, RangeTombstone},
+    StoreError,
+};
 
-use super::combiner::{combine_tables, CombineTable};
+use super::{
+    combiner::{combine_tables, CombineTable, MergeIter},
+    decision::{DecisionLog, Explain, Trigger},
+    scheduler::CompactionJob,
+};
+
+// Level 1 tables smaller than the table size limit divided by this are candidates for merging with
+// their neighbors.
+const SMALL_TABLE_FRACTION: usize = 2;
 
 pub struct Compactor {
     level_0_file_limit: usize,
+    level_0_rearm_limit: Option<usize>,
+    // Whether level_0_file_limit can set off a compaction, see Options::level_0_rearm_limit.
+    level_0_armed: AtomicBool,
     table_size_limit: usize,
-    data_dir: path::PathBuf,
+    inputs: CompactionInputs,
+    small_table_merge_threshold: usize,
+    level_0_merge_threshold: usize,
+    versions_to_keep: usize,
+    format: TableFormat,
+    // Where the tables compactions write go: The data directory, or Options::cold_dir.
+    output_dir: path::PathBuf,
+    pins: Pins,
+    // Where automatic compaction decisions go, with Options::explain_compactions.
+    decisions: Option<DecisionLog>,
+    clock: Arc<dyn Clock>,
+    // Decides in place of the built-in triggers, with Options::compaction_scheduler.
+    scheduler: Option<SchedulerHook>,
+}
+
+// What an automatic compaction will do.
+enum Choice<'a> {
+    Compact(Plan<'a>),
+    MergeLevel0(&'a [Arc<Table>]),
 }
 
 impl Compactor {
-    pub fn new(level_0_file_limit: usize, table_size_limit: usize, data_dir: &path::Path) -> Self {
+    pub fn new(options: &Options, data_dir: &path::Path) -> Self {
         Compactor {
-            level_0_file_limit,
-            table_size_limit,
-            data_dir: data_dir.to_owned(),
+            level_0_file_limit: options.level_0_file_limit,
+            level_0_rearm_limit: options.level_0_rearm_limit,
+            level_0_armed: AtomicBool::new(true),
+            table_size_limit: options.table_size_limit,
+            inputs: options.compaction_inputs,
+            small_table_merge_threshold: options.small_table_merge_threshold,
+            level_0_merge_threshold: options.level_0_merge_threshold,
+            versions_to_keep: options.versions_to_keep,
+            format: TableFormat::new(options),
+            output_dir: options.cold_dir.as_deref().unwrap_or(data_dir).to_owned(),
+            pins: Pins::default(),
+            decisions: None,
+            clock: options.clock.clone(),
+            scheduler: options.compaction_scheduler.clone(),
         }
     }
 
-    pub fn maybe_compact(&self, ssts: Vec<Vec<Table>>) -> io::Result<()> {
-        if ssts[0].len() >= self.level_0_file_limit {
-            self.compact_level_0(ssts)
-        } else {
-            Ok(())
-        }
+    // Pinned tables that a compaction replaces are set aside rather than removed.
+    pub(crate) fn with_pins(mut self, pins: Pins) -> Self {
+        self.pins = pins;
+        self
     }
 
-    fn compact_level_0(&self, ssts: Vec<Vec<Table>>) -> io::Result<()> {
-        let mut key_start = Vec::new();
-        let mut key_end = Vec::new();
+    // Each decision maybe_compact makes is added to `decisions`.
+    pub(crate) fn with_decisions(mut self, decisions: Option<DecisionLog>) -> Self {
+        self.decisions = decisions;
+        self
+    }
 
-        let mut tables_to_delete = Vec::new();
-        let mut tables_to_combine = Vec::new();
+    // How the tables compactions write are laid out, and the codecs tables are read with.
+    pub(crate) fn format(&self) -> &TableFormat {
+        &self.format
+    }
 
-        let mut sst_iter = ssts.into_iter();
+    // Runs a compaction if one is due, returning the paths of the tables it removed.
+    pub fn maybe_compact(
+        &self,
+        ssts: &[Vec<Arc<Table>>],
+    ) -> Result<Vec<path::PathBuf>, StoreError> {
+        // Decisions made by a scheduler aren't explained.
+        if let Some(scheduler) = &self.scheduler {
+            return match scheduler.0.next_job(&table_info(ssts)) {
+                Some(CompactionJob::Level(level)) => {
+                    self.compact_level(ssts, level).map(|stats| stats.inputs)
+                }
+                Some(CompactionJob::Range { start, end }) => {
+                    self.compact_range(ssts, &start, end.as_deref())
+                }
+                None => Ok(Vec::new()),
+            };
+        }
 
-        for (i, table) in sst_iter
-            .next()
-            .expect("ssts must have at least 1 level 1 table")
-            .into_iter()
-            .enumerate()
+        let mut explain = match &self.decisions {
+            Some(_) => Explain::new(self.clock.now()),
+            None => Explain::disabled(),
+        };
+        let choice = self.choose(ssts, &mut explain);
+
+        if let Some(decisions) = &self.decisions {
+            let chosen = match &choice {
+                Ok(Some((trigger, choice))) => Ok(Some((*trigger, choice.inputs()))),
+                Ok(None) => Ok(None),
+                Err(e) => Err(e.to_string()),
+            };
+            decisions.push(explain.finish(chosen).expect("decision must be explained"));
+        }
+
+        match choice? {
+            Some((trigger, Choice::Compact(plan))) => {
+                let removed = self.compact(plan)?.inputs;
+                if trigger == Trigger::Level0FileLimit && self.level_0_rearm_limit.is_some() {
+                    self.level_0_armed.store(false, Ordering::SeqCst);
+                }
+                Ok(removed)
+            }
+            Some((_, Choice::MergeLevel0(run))) => self.merge_level_0(run),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    // Checks each trigger in turn, and chooses the inputs for the first that fires.
+    fn choose<'a>(
+        &self,
+        ssts: &'a [Vec<Arc<Table>>],
+        explain: &mut Explain,
+    ) -> Result<Option<(Trigger, Choice<'a>)>, StoreError> {
+        let level_0_files = ssts.first().map_or(0, Vec::len);
+        if self
+            .level_0_rearm_limit
+            .is_none_or(|rearm| level_0_files < rearm)
         {
-            if key_start.len() == 0 || table.key_start() < key_start {
-                key_start = table.key_start();
+            self.level_0_armed.store(true, Ordering::SeqCst);
+        }
+        let fired =
+            self.level_0_armed.load(Ordering::SeqCst) && level_0_files >= self.level_0_file_limit;
+        explain.trigger(
+            Trigger::Level0FileLimit,
+            level_0_files,
+            self.level_0_file_limit,
+            fired,
+        );
+        if fired {
+            let plan = self.plan_level_0(ssts, explain);
+            return Ok(Some((Trigger::Level0FileLimit, Choice::Compact(plan))));
+        }
+
+        if let Some(run) = self.plan_level_0_merge(ssts, explain)? {
+            return Ok(Some((Trigger::Level0Merge, Choice::MergeLevel0(run))));
+        }
+
+        if let Some(plan) = self.plan_small_tables(ssts, explain)? {
+            return Ok(Some((Trigger::SmallTables, Choice::Compact(plan))));
+        }
+
+        Ok(None)
+    }
+
+    // Compacts every table in `level` into the level below it, along with the tables there that
+    // they overlap, regardless of whether a compaction would otherwise be due. Level 1 is the
+    // bottom, so its tables are rewritten in place, which drops the older versions and deletions
+    // that compaction always drops. An empty level is left as it is.
+    pub fn compact_level(
+        &self,
+        ssts: &[Vec<Arc<Table>>],
+        level: usize,
+    ) -> Result<CompactionStats, StoreError> {
+        let tables = ssts.get(level).map(Vec::as_slice).unwrap_or_default();
+        if tables.is_empty() {
+            return Ok(CompactionStats::default());
+        }
+
+        let plan = match level {
+            0 => self.plan_level_0(ssts, &mut Explain::disabled()),
+            _ => Plan {
+                inputs: tables.iter().map(|table| (table, level, None)).collect(),
+                split_keys: Vec::new(),
+            },
+        };
+        self.compact(plan)
+    }
+
+    // Merges every table in the store into level 1, regardless of whether a compaction would
+    // otherwise be due.
+    pub fn compact_all(&self, ssts: &[Vec<Arc<Table>>]) -> Result<(), StoreError> {
+        let mut inputs = Vec::new();
+        for (level, tables) in ssts.iter().enumerate() {
+            for (i, table) in tables.iter().enumerate() {
+                inputs.push((table, level, (level == 0).then_some(i as u32)));
             }
+        }
+
+        if inputs.is_empty() {
+            return Ok(());
+        }
+
+        self.compact(Plan {
+            inputs,
+            split_keys: Vec::new(),
+        })
+        .map(|_| ())
+    }
+
+    // Compacts the tables with keys in [start, end), or from start onward if there is no end, into
+    // level 1, along with whatever else that requires. Returns the paths of the tables it removed.
+    pub fn compact_range(
+        &self,
+        ssts: &[Vec<Arc<Table>>],
+        start: &[u8],
+        end: Option<&[u8]>,
+    ) -> Result<Vec<path::PathBuf>, StoreError> {
+        let range = RangeTombstone {
+            start: start.to_vec(),
+            end: end.map(|end| end.to_vec()),
+        };
+
+        match self.plan_range(ssts, &range) {
+            Some(plan) => self.compact(plan).map(|stats| stats.inputs),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    // Combines the input tables of a plan into new level 1 tables, then removes the inputs. Range
+    // deletions are applied to the records of older inputs and then dropped, so a plan must include
+    // every table with records that a range deletion among its inputs covers.
+    fn compact(&self, plan: Plan) -> Result<CompactionStats, StoreError> {
+        let tables_to_delete = plan
+            .inputs
+            .iter()
+            .map(|(table, _, _)| table.path.clone())
+            .collect::<Vec<_>>();
 
-            if key_end.len() == 0 || table.key_end() > key_end {
-                key_end = table.key_end();
+        let compact = || -> io::Result<CompactionStats> {
+            let mut bytes_read = 0;
+            for (table, _, _) in &plan.inputs {
+                bytes_read += table.size()?;
             }
 
-            tables_to_delete.push(table.path.clone());
-            tables_to_combine.push(CombineTable {
-                table: table.into_iter(),
-                level: 0,
-                sequence: Some(i as u32),
+            let tables = plan
+                .inputs
+                .iter()
+                .map(|(table, level, sequence)| {
+                    let newer_deletions = plan
+                        .inputs
+                        .iter()
+                        .filter(|(_, l, s)| *l < *level || (*l == *level && *s > *sequence))
+                        .flat_map(|(t, _, _)| t.range_deletions().iter().cloned())
+                        .collect();
+                    Ok(CombineTable {
+                        table: tombstone::without_covered(table.iter()?, newer_deletions),
+                        level: *level,
+                        sequence: *sequence,
+                    })
+                })
+                .collect::<io::Result<Vec<_>>>()?;
+
+            let outputs = combine_tables(
+                tables,
+                self.table_size_limit,
+                1,
+                &plan.split_keys,
+                self.versions_to_keep,
+                &self.format,
+                &self.output_dir,
+            )?;
+            let mut bytes_written = 0;
+            for path in &outputs {
+                bytes_written += fs::metadata(path)
+                    .with_path("reading metadata of", path)?
+                    .len();
+            }
+
+            for t in &tables_to_delete {
+                // TODO: This is unlikely to be stricly correct since there is no guarantee that the
+                // file is immediately deleted.
+                self.pins.remove(t)?;
+            }
+
+            Ok(CompactionStats {
+                inputs: tables_to_delete.clone(),
+                outputs,
+                bytes_read,
+                bytes_written,
+            })
+        };
+
+        compact().map_err(|source| StoreError::Compaction {
+            inputs: tables_to_delete.clone(),
+            source,
+        })
+    }
+
+    // Merges a run of adjacent level 0 tables into one, which takes the place of the newest of them
+    // so that it keeps its position among the rest of level 0. It carries the range deletions of
+    // all of them, since those still apply to older tables. A crash part way through leaves older
+    // inputs behind the merged table, which hides everything in them. Returns the paths of the
+    // removed inputs.
+    fn merge_level_0(&self, run: &[Arc<Table>]) -> Result<Vec<path::PathBuf>, StoreError> {
+        let (newest, older) = run.split_last().expect("run must not be empty");
+        let removed = older
+            .iter()
+            .map(|table| table.path.clone())
+            .collect::<Vec<_>>();
+
+        let merge = || -> io::Result<()> {
+            let mut merge = MergeIter::with_versions(self.versions_to_keep);
+            for (i, table) in run.iter().enumerate() {
+                let newer_deletions = run[i + 1..]
+                    .iter()
+                    .flat_map(|t| t.range_deletions().iter().cloned())
+                    .collect();
+                merge.push_iter(
+                    tombstone::without_covered(table.iter()?, newer_deletions),
+                    0,
+                    Some(i as u32),
+                )?;
+            }
+            let records = merge
+                .map(|record| record.map(|record| self.format.encode(record)))
+                .collect::<io::Result<Vec<_>>>()?;
+            let records = records.iter().map(WriteRecord::from).collect::<Vec<_>>();
+            let range_deletions = run
+                .iter()
+                .flat_map(|table| table.range_deletions())
+                .map(|d| WriteRecord::RangeDeleted {
+                    start: &d.start,
+                    end: d.end.as_deref(),
+                })
+                .collect::<Vec<_>>();
+
+            write_table(&records, &range_deletions, &self.format, &newest.path)?;
+            for path in &removed {
+                self.pins.remove(path)?;
+            }
+            Ok(())
+        };
+
+        merge().map_err(|source| StoreError::Compaction {
+            inputs: run.iter().map(|table| table.path.clone()).collect(),
+            source,
+        })?;
+
+        Ok(removed)
+    }
+
+    // Finds the oldest run of as many adjacent level 0 tables as the configured threshold, each
+    // smaller than the table size limit and together small enough to fit in one file. The newest
+    // table of the run is replaced in place, so a run ending in a pinned table is passed over.
+    fn plan_level_0_merge<'a>(
+        &self,
+        ssts: &'a [Vec<Arc<Table>>],
+        explain: &mut Explain,
+    ) -> Result<Option<&'a [Arc<Table>]>, StoreError> {
+        let threshold = self.level_0_merge_threshold;
+        let level_0 = ssts.first().map(Vec::as_slice).unwrap_or_default();
+        if threshold < 2 {
+            return Ok(None);
+        }
+        if level_0.len() < threshold {
+            explain.trigger(Trigger::Level0Merge, level_0.len(), threshold, false);
+            return Ok(None);
+        }
+
+        let sizes = level_0
+            .iter()
+            .map(|table| {
+                table.size().map_err(|source| StoreError::Read {
+                    path: table.path.clone(),
+                    source,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let chosen = (0..=level_0.len() - threshold).find(|start| {
+            let run = &level_0[*start..start + threshold];
+            let sizes = &sizes[*start..start + threshold];
+            sizes
+                .iter()
+                .all(|size| (*size as usize) < self.table_size_limit)
+                && self.format.fits(sizes.iter().sum::<u64>() as usize)
+                && !self.pins.is_pinned(&run[threshold - 1].path)
+        });
+
+        explain.trigger(
+            Trigger::Level0Merge,
+            level_0.len(),
+            threshold,
+            chosen.is_some(),
+        );
+        let run = chosen.map(|start| start..start + threshold);
+        for (i, (table, size)) in level_0.iter().zip(&sizes).enumerate() {
+            let in_run = run.as_ref().is_some_and(|run| run.contains(&i));
+            explain.candidate(table, 0, in_run, || {
+                if in_run {
+                    format!(
+                        "in the oldest run of {} tables small enough to merge",
+                        threshold
+                    )
+                } else if *size as usize >= self.table_size_limit {
+                    format!(
+                        "{} bytes, at least the table size limit of {}",
+                        size, self.table_size_limit
+                    )
+                } else {
+                    "not in a run of small tables that fits in one file and doesn't end in a \
+                     pinned table"
+                        .to_string()
+                }
+            });
+        }
+
+        Ok(run.map(|run| &level_0[run]))
+    }
+
+    // Finds the longest run of adjacent level 1 tables that are each small enough to be worth
+    // merging, if it is at least as long as the configured threshold. Such runs build up from
+    // compactions that only rewrite a narrow range of keys.
+    fn plan_small_tables<'a>(
+        &self,
+        ssts: &'a [Vec<Arc<Table>>],
+        explain: &mut Explain,
+    ) -> Result<Option<Plan<'a>>, StoreError> {
+        if self.small_table_merge_threshold < 2 {
+            return Ok(None);
+        }
+
+        let mut tables = ssts.get(1).into_iter().flatten().collect::<Vec<_>>();
+        tables.sort_by_key(|table| table.key_start());
+
+        let small_size = self.table_size_limit / SMALL_TABLE_FRACTION;
+        let mut sizes = Vec::with_capacity(tables.len());
+        let mut longest = 0..0;
+        let mut run_start = 0;
+        for (i, table) in tables.iter().enumerate() {
+            let size = table.size().map_err(|source| StoreError::Read {
+                path: table.path.clone(),
+                source,
+            })?;
+            sizes.push(size);
+
+            if size as usize >= small_size {
+                run_start = i + 1;
+            } else if i + 1 - run_start > longest.len() {
+                longest = run_start..i + 1;
+            }
+        }
+
+        let fired = longest.len() >= self.small_table_merge_threshold;
+        explain.trigger(
+            Trigger::SmallTables,
+            longest.len(),
+            self.small_table_merge_threshold,
+            fired,
+        );
+        for (i, (table, size)) in tables.iter().zip(sizes).enumerate() {
+            let in_run = longest.contains(&i);
+            explain.candidate(table, 1, fired && in_run, || {
+                let relation = match size as usize >= small_size {
+                    true => "at least",
+                    false => "under",
+                };
+                let run = match in_run {
+                    true => "in",
+                    false => "not in",
+                };
+                format!(
+                    "{} bytes, {} the small table size of {}, and {} the longest run of small \
+                     tables",
+                    size, relation, small_size, run
+                )
+            });
+        }
+
+        if !fired {
+            return Ok(None);
+        }
+        let longest = &tables[longest];
+
+        Ok(Some(Plan {
+            inputs: longest.iter().map(|table| (*table, 1, None)).collect(),
+            // The run is of adjacent tables, so there are no others within its range of keys.
+            split_keys: Vec::new(),
+        }))
+    }
+
+    // Chooses the tables for a compaction of level 0 into level 1. All of level 0 is always
+    // included, since its tables overlap each other. Which level 1 tables come along depends on the
+    // configured CompactionInputs, except that tables with keys covered by a range deletion in
+    // level 0 are always included so that the deletion can be applied.
+    fn plan_level_0<'a>(&self, ssts: &'a [Vec<Arc<Table>>], explain: &mut Explain) -> Plan<'a> {
+        let level_0 = ssts.first().expect("ssts must have a level 0");
+        for table in level_0 {
+            explain.candidate(table, 0, true, || {
+                "level 0 tables overlap each other, so all of them are compacted".to_string()
             });
         }
 
-        if let Some(tables) = sst_iter.next() {
-            for table in tables {
-                if (table.key_start() >= key_start && table.key_start() <= key_end)
-                    || (table.key_end() >= key_start && table.key_end() <= key_end)
-                {
-                    tables_to_delete.push(table.path.clone());
-                    tables_to_combine.push(CombineTable {
-                        table: table.into_iter(),
-                        level: 1,
-                        sequence: None,
-                    });
+        let mut inputs = level_0
+            .iter()
+            .enumerate()
+            .map(|(i, table)| (table, 0, Some(i as u32)))
+            .collect::<Vec<_>>();
+
+        // The key ranges covered by level 0, in ascending order.
+        let mut spans = level_0
+            .iter()
+            .map(|table| (table.key_start(), table.key_end()))
+            .collect::<Vec<_>>();
+        spans.sort_unstable();
+
+        let spans = match self.inputs {
+            CompactionInputs::Overlapping => spans
+                .into_iter()
+                .reduce(|(start, end), (_, next_end)| (start, end.max(next_end)))
+                .into_iter()
+                .collect(),
+            CompactionInputs::Minimal => {
+                let mut merged: Vec<(Vec<u8>, Vec<u8>)> = Vec::with_capacity(spans.len());
+                for (start, end) in spans {
+                    match merged.last_mut() {
+                        Some((_, last_end)) if start <= *last_end => {
+                            if end > *last_end {
+                                *last_end = end;
+                            }
+                        }
+                        _ => merged.push((start, end)),
+                    }
                 }
+                merged
+            }
+        };
+
+        let range_deletions = level_0
+            .iter()
+            .flat_map(|table| table.range_deletions())
+            .collect::<Vec<_>>();
+
+        let mut split_keys = Vec::new();
+        for table in ssts.get(1).into_iter().flatten() {
+            let (start, end) = (table.key_start(), table.key_end());
+            let span = spans.iter().find(|(s, e)| start <= *e && end >= *s);
+            let deleted = range_deletions.iter().any(|d| d.overlaps(&start, &end));
+            explain.candidate(table, 1, span.is_some() || deleted, || match span {
+                Some((s, e)) => format!(
+                    "overlaps the level 0 keys from {:?} to {:?}",
+                    String::from_utf8_lossy(s),
+                    String::from_utf8_lossy(e)
+                ),
+                None if deleted => "has keys covered by a level 0 range deletion".to_string(),
+                None => "doesn't overlap level 0".to_string(),
+            });
+
+            if span.is_some() || deleted {
+                inputs.push((table, 1, None));
+            } else {
+                split_keys.push(start);
+            }
+        }
+        split_keys.sort_unstable();
+
+        Plan { inputs, split_keys }
+    }
+
+    // Chooses the tables for a compaction of the keys in `range`. These are the level 0 tables
+    // that overlap it, and then any older level 0 table that overlaps one of those or its range
+    // deletions: once the newer table is in level 1, the older one would otherwise be read as the
+    // newer of the two. Level 1 tables overlapping the range or any level 0 input come along, the
+    // same as when compacting all of level 0.
+    fn plan_range<'a>(
+        &self,
+        ssts: &'a [Vec<Arc<Table>>],
+        range: &RangeTombstone,
+    ) -> Option<Plan<'a>> {
+        let level_0 = ssts.first().map(Vec::as_slice).unwrap_or_default();
+
+        let shadows = |newer: &Table, older: &Table| {
+            let (start, end) = (older.key_start(), older.key_end());
+            (newer.key_start() <= end && newer.key_end() >= start)
+                || newer
+                    .range_deletions()
+                    .iter()
+                    .any(|d| d.overlaps(&start, &end))
+        };
+
+        let mut included = level_0
+            .iter()
+            .map(|table| {
+                range.overlaps(&table.key_start(), &table.key_end())
+                    || table.range_deletions().iter().any(|d| {
+                        range.end.as_ref().is_none_or(|end| d.start < *end)
+                            && d.end.as_ref().is_none_or(|end| range.start < *end)
+                    })
+            })
+            .collect::<Vec<_>>();
+        // Newer tables come later in level 0, so a pass from newest to oldest finds every table
+        // shadowed by an input, including through a chain of other inputs.
+        for older in (0..level_0.len()).rev() {
+            if !included[older]
+                && (older + 1..level_0.len())
+                    .any(|newer| included[newer] && shadows(&level_0[newer], &level_0[older]))
+            {
+                included[older] = true;
+            }
+        }
+
+        let mut inputs = level_0
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| included[*i])
+            .map(|(i, table)| (table, 0, Some(i as u32)))
+            .collect::<Vec<_>>();
+        let level_0_inputs = inputs.len();
+
+        let mut split_keys = Vec::new();
+        for table in ssts.get(1).into_iter().flatten() {
+            let (start, end) = (table.key_start(), table.key_end());
+            if range.overlaps(&start, &end)
+                || inputs[..level_0_inputs]
+                    .iter()
+                    .any(|(t, _, _)| shadows(t, table))
+            {
+                inputs.push((table, 1, None));
+            } else {
+                split_keys.push(start);
             }
         }
+        split_keys.sort_unstable();
 
-        combine_tables(tables_to_combine, self.table_size_limit, 1, &self.data_dir)?;
+        (!inputs.is_empty()).then_some(Plan { inputs, split_keys })
+    }
+}
+
+// What a compaction rewrote.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CompactionStats {
+    // The tables that were compacted, and have since been removed.
+    pub inputs: Vec<path::PathBuf>,
+    // The tables that replaced them, all in level 1.
+    pub outputs: Vec<path::PathBuf>,
+    // Total size of the inputs and of the outputs, in bytes.
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+}
 
-        for t in tables_to_delete {
-            // TODO: This is unlikely to be stricly correct since there is no guarantee that the
-            // file is immediately deleted.
-            fs::remove_file(t)?;
+impl Choice<'_> {
+    fn inputs(&self) -> Vec<path::PathBuf> {
+        match self {
+            Choice::Compact(plan) => plan
+                .inputs
+                .iter()
+                .map(|(table, _, _)| table.path.clone())
+                .collect(),
+            Choice::MergeLevel0(run) => run.iter().map(|table| table.path.clone()).collect(),
         }
+    }
+}
+
+struct Plan<'a> {
+    // Tuples of (table, level, sequence).
+    inputs: Vec<(&'a Arc<Table>, usize, Option<u32>)>,
+    // Start keys of the level 1 tables that aren't being compacted. An output table must not span
+    // one of these, or it would overlap a table that remains in level 1.
+    split_keys: Vec<Vec<u8>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, fs};
+
+    use tempdir::TempDir;
+
+    use crate::{
+        memtable::MemTable,
+        protocol::ReadRecord,
+        sst::{self, Catalog},
+    };
+
+    use super::*;
+
+    fn write_table(catalog: &mut Catalog, keys: &[&str]) {
+        let mut memtable = MemTable::new();
+        for key in keys {
+            memtable.put(key.as_bytes(), &[b'v'; 100]);
+        }
+        catalog.write_records(&memtable).unwrap();
+    }
+
+    // Writes tables to level 0 and then moves them to level 1, where they must not overlap.
+    fn write_level_1(data_dir: &path::Path, tables: &[&[&str]]) {
+        let mut catalog = Catalog::new(data_dir).unwrap();
+        for keys in tables {
+            write_table(&mut catalog, keys);
+        }
+        fs::rename(data_dir.join("0"), data_dir.join("1")).unwrap();
+    }
+
+    fn compact(data_dir: &path::Path, inputs: CompactionInputs) -> u64 {
+        let catalog = Catalog::new(data_dir).unwrap();
+        let before = catalog.ssts[1]
+            .iter()
+            .map(|t| t.path.clone())
+            .collect::<HashSet<_>>();
+
+        let options = Options::default()
+            .level_0_file_limit(2)
+            .compaction_inputs(inputs);
+        Compactor::new(&options, data_dir)
+            .maybe_compact(&catalog.ssts)
+            .unwrap();
+
+        let report = sst::verify(data_dir).unwrap();
+        assert!(report.is_ok(), "{:?}", report);
 
-        Ok(())
+        // Bytes written by the compaction are the sizes of the new level 1 tables.
+        fs::read_dir(data_dir.join("1"))
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| !before.contains(path))
+            .map(|path| fs::metadata(path).unwrap().len())
+            .sum()
+    }
+
+    #[test]
+    fn test_compaction_inputs() {
+        let mut written = Vec::new();
+
+        for inputs in [CompactionInputs::Overlapping, CompactionInputs::Minimal] {
+            let dir = TempDir::new("testing").unwrap();
+            write_level_1(
+                dir.path(),
+                &[&["b1", "b2"], &["m1", "m2", "m3", "m4"], &["x1", "x2"]],
+            );
+
+            let mut catalog = Catalog::new(dir.path()).unwrap();
+            write_table(&mut catalog, &["a", "c"]);
+            write_table(&mut catalog, &["w", "y"]);
+
+            written.push(compact(dir.path(), inputs));
+
+            let catalog = Catalog::new(dir.path()).unwrap();
+            assert!(catalog.ssts[0].is_empty());
+            for key in ["a", "b1", "b2", "c", "m1", "m4", "w", "x1", "x2", "y"] {
+                assert!(catalog.get(key.as_bytes()).unwrap().is_some());
+            }
+        }
+
+        // The table in the gap between the level 0 tables is only rewritten when taking everything
+        // that overlaps.
+        assert!(written[1] < written[0], "{:?}", written);
+    }
+
+    #[test]
+    fn test_level_1_table_containing_level_0() {
+        for inputs in [CompactionInputs::Overlapping, CompactionInputs::Minimal] {
+            let dir = TempDir::new("testing").unwrap();
+            write_level_1(dir.path(), &[&["a", "z"]]);
+
+            // Both level 0 tables fall entirely within the range of the level 1 table, which must
+            // be included in the compaction.
+            let mut catalog = Catalog::new(dir.path()).unwrap();
+            write_table(&mut catalog, &["m"]);
+            write_table(&mut catalog, &["n"]);
+
+            compact(dir.path(), inputs);
+
+            let catalog = Catalog::new(dir.path()).unwrap();
+            assert_eq!(1, catalog.ssts[1].len());
+        }
+    }
+
+    #[test]
+    fn test_level_0_rearm_limit() {
+        let dir = TempDir::new("testing").unwrap();
+        let options = Options::default()
+            .level_0_file_limit(2)
+            .level_0_rearm_limit(1);
+        let compactor = Compactor::new(&options, dir.path());
+
+        let mut catalog = Catalog::new(dir.path()).unwrap();
+        write_table(&mut catalog, &["a"]);
+        write_table(&mut catalog, &["b"]);
+        let catalog = Catalog::new(dir.path()).unwrap();
+        assert_eq!(2, compactor.maybe_compact(&catalog.ssts).unwrap().len());
+
+        // Level 0 is back at the limit without having dropped below the rearm limit in between.
+        let mut catalog = Catalog::new(dir.path()).unwrap();
+        write_table(&mut catalog, &["c"]);
+        write_table(&mut catalog, &["d"]);
+        let catalog = Catalog::new(dir.path()).unwrap();
+        assert!(compactor.maybe_compact(&catalog.ssts).unwrap().is_empty());
+
+        // Once it has, the limit sets off a compaction again.
+        let level_1_only = vec![Vec::new(), catalog.ssts[1].clone()];
+        assert!(compactor.maybe_compact(&level_1_only).unwrap().is_empty());
+        assert_eq!(2, compactor.maybe_compact(&catalog.ssts).unwrap().len());
+    }
+
+    #[test]
+    fn test_merge_small_tables() {
+        let dir = TempDir::new("testing").unwrap();
+
+        // Each value is 100 bytes, so only the table with 10 keys is larger than half of the
+        // 1000 byte table size limit.
+        let large = ["d0", "d1", "d2", "d3", "d4", "d5", "d6", "d7", "d8", "d9"];
+        write_level_1(
+            dir.path(),
+            &[&["a"], &["b"], &["c"], &large, &["e"], &["f"]],
+        );
+
+        let options = Options::default()
+            .table_size_limit(1000)
+            .small_table_merge_threshold(3);
+        let catalog = Catalog::new(dir.path()).unwrap();
+        Compactor::new(&options, dir.path())
+            .maybe_compact(&catalog.ssts)
+            .unwrap();
+
+        // Only the first run of small tables was long enough to merge.
+        let catalog = Catalog::new(dir.path()).unwrap();
+        let mut ranges = catalog.ssts[1]
+            .iter()
+            .map(|t| (t.key_start(), t.key_end()))
+            .collect::<Vec<_>>();
+        ranges.sort();
+        assert_eq!(
+            vec![
+                (b"a".to_vec(), b"c".to_vec()),
+                (b"d0".to_vec(), b"d9".to_vec()),
+                (b"e".to_vec(), b"e".to_vec()),
+                (b"f".to_vec(), b"f".to_vec()),
+            ],
+            ranges
+        );
+        assert!(sst::verify(dir.path()).unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_merge_level_0() {
+        let dir = TempDir::new("testing").unwrap();
+        write_level_1(dir.path(), &[&["m1", "m2"]]);
+
+        let mut catalog = Catalog::new(dir.path()).unwrap();
+        write_table(&mut catalog, &["a", "m1"]);
+        let mut memtable = MemTable::new();
+        memtable.delete_range(b"m", Some(b"n"));
+        memtable.put(b"z", b"val");
+        catalog.write_records(&memtable).unwrap();
+        write_table(&mut catalog, &["b"]);
+        write_table(&mut catalog, &["c"]);
+
+        let options = Options::default()
+            .level_0_file_limit(10)
+            .level_0_merge_threshold(3);
+        let catalog = Catalog::new(dir.path()).unwrap();
+        let level_1 = catalog.ssts[1][0].path.clone();
+        let removed = Compactor::new(&options, dir.path())
+            .maybe_compact(&catalog.ssts)
+            .unwrap();
+        assert_eq!(2, removed.len());
+
+        // The oldest three tables are merged in place of the newest of them, and level 1 is left
+        // alone. The merged table's range deletion still hides the keys in level 1.
+        let catalog = Catalog::new(dir.path()).unwrap();
+        let level_0 = catalog.ssts[0]
+            .iter()
+            .map(|table| table.sequence().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(vec![3, 4], level_0);
+        assert!(level_1.exists());
+        for key in ["a", "b", "c", "z"] {
+            assert!(matches!(
+                catalog.get(key.as_bytes()).unwrap(),
+                Some(ReadRecord::Exists { .. })
+            ));
+        }
+        for key in ["m1", "m2"] {
+            assert!(matches!(
+                catalog.get(key.as_bytes()).unwrap(),
+                Some(ReadRecord::Deleted { .. })
+            ));
+        }
+        assert!(sst::verify(dir.path()).unwrap().is_ok());
+
+        // Two tables are too few to merge again.
+        Compactor::new(&options, dir.path())
+            .maybe_compact(&catalog.ssts)
+            .unwrap();
+        assert_eq!(2, Catalog::new(dir.path()).unwrap().ssts[0].len());
+    }
+
+    #[test]
+    fn test_compact_range() {
+        let dir = TempDir::new("testing").unwrap();
+        write_level_1(dir.path(), &[&["a1", "a2"], &["m1", "m2"], &["x1", "x2"]]);
+
+        let mut catalog = Catalog::new(dir.path()).unwrap();
+        write_table(&mut catalog, &["c", "n"]);
+        write_table(&mut catalog, &["y"]);
+        write_table(&mut catalog, &["m1"]);
+
+        let catalog = Catalog::new(dir.path()).unwrap();
+        let before = catalog.ssts[1]
+            .iter()
+            .map(|t| (t.key_start(), t.path.clone()))
+            .collect::<Vec<_>>();
+
+        // Only the newest level 0 table is in the range, but the older one it overlaps must come
+        // along, and with it the level 1 tables that either overlaps.
+        let removed = Compactor::new(&Options::default(), dir.path())
+            .compact_range(&catalog.ssts, b"m", Some(b"n"))
+            .unwrap();
+        assert_eq!(3, removed.len());
+
+        let catalog = Catalog::new(dir.path()).unwrap();
+        assert_eq!(1, catalog.ssts[0].len());
+        assert_eq!(b"y".to_vec(), catalog.ssts[0][0].key_start());
+        for (start, path) in before {
+            assert_eq!(start != b"m1", path.exists());
+        }
+        for key in ["a1", "c", "m1", "m2", "n", "x2", "y"] {
+            assert!(catalog.get(key.as_bytes()).unwrap().is_some());
+        }
+        assert!(sst::verify(dir.path()).unwrap().is_ok());
     }
 }
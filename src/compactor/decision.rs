@@ -0,0 +1,168 @@
+// A record of why an automatic compaction was or wasn't run, for Options::explain_compactions. Each
+// time the compactor checks whether a compaction is due, it notes the value of each trigger against
+// its threshold, the tables it considered along with why each was or wasn't chosen, and what it
+// decided. Decisions not to compact are recorded too, which is usually what explains a compaction
+// that never happens.
+//
+// Only the most recent decisions are kept, see Store::recent_compaction_decisions.
+
+use std::{
+    collections::VecDeque,
+    path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::{options::Options, sst::table::Table};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompactionDecision {
+    // When it was made, by the store's clock.
+    pub at: Duration,
+    // The triggers that were checked, in order. Checking stops at the first that fires.
+    pub triggers: Vec<TriggerCheck>,
+    // The tables considered by the triggers that were checked, with why each was or wasn't chosen.
+    pub candidates: Vec<Candidate>,
+    // The trigger that made a compaction due, if any did.
+    pub chosen: Option<Trigger>,
+    // The tables chosen to be compacted.
+    pub inputs: Vec<path::PathBuf>,
+    // Why no decision could be made, if reading the tables to make it failed.
+    pub error: Option<String>,
+}
+
+// What makes an automatic compaction due, in the order they are checked.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Trigger {
+    // Level 0 has Options::level_0_file_limit tables, so all of it is compacted into level 1.
+    Level0FileLimit,
+    // Level 0 has a run of Options::level_0_merge_threshold small tables to merge into one.
+    Level0Merge,
+    // Level 1 has a run of Options::small_table_merge_threshold small tables to merge.
+    SmallTables,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TriggerCheck {
+    pub trigger: Trigger,
+    // What the trigger measures, such as the number of level 0 tables, and the value at which it
+    // fires. Level0FileLimit can be at its threshold without firing while it waits to be rearmed,
+    // see Options::level_0_rearm_limit.
+    pub value: usize,
+    pub threshold: usize,
+    pub fired: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Candidate {
+    pub path: path::PathBuf,
+    pub level: usize,
+    pub chosen: bool,
+    pub reason: String,
+}
+
+// The decisions kept for a store, shared by its compactors.
+#[derive(Clone)]
+pub(crate) struct DecisionLog {
+    capacity: usize,
+    decisions: Arc<Mutex<VecDeque<CompactionDecision>>>,
+}
+
+impl DecisionLog {
+    // A log for the store, if its options ask for one.
+    pub(crate) fn new(options: &Options) -> Option<Self> {
+        let capacity = options.explain_compactions?;
+        Some(DecisionLog {
+            capacity,
+            decisions: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+        })
+    }
+
+    pub(crate) fn push(&self, decision: CompactionDecision) {
+        let mut decisions = self.decisions.lock().unwrap();
+        if decisions.len() == self.capacity {
+            decisions.pop_front();
+        }
+        decisions.push_back(decision);
+    }
+
+    // The decisions kept, oldest first.
+    pub(crate) fn recent(&self) -> Vec<CompactionDecision> {
+        self.decisions.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+// Collects a decision as the compactor makes it. When decisions aren't being kept, noting anything
+// does nothing, so that the compactor's choices can be noted as they are made without costing
+// anything otherwise.
+pub(crate) struct Explain(Option<CompactionDecision>);
+
+impl Explain {
+    pub(crate) fn new(at: Duration) -> Self {
+        Explain(Some(CompactionDecision {
+            at,
+            triggers: Vec::new(),
+            candidates: Vec::new(),
+            chosen: None,
+            inputs: Vec::new(),
+            error: None,
+        }))
+    }
+
+    pub(crate) fn disabled() -> Self {
+        Explain(None)
+    }
+
+    pub(crate) fn trigger(
+        &mut self,
+        trigger: Trigger,
+        value: usize,
+        threshold: usize,
+        fired: bool,
+    ) {
+        if let Some(decision) = &mut self.0 {
+            decision.triggers.push(TriggerCheck {
+                trigger,
+                value,
+                threshold,
+                fired,
+            });
+        }
+    }
+
+    // Notes whether `table` was chosen. The reason is only worked out if it will be kept.
+    pub(crate) fn candidate(
+        &mut self,
+        table: &Table,
+        level: usize,
+        chosen: bool,
+        reason: impl FnOnce() -> String,
+    ) {
+        if let Some(decision) = &mut self.0 {
+            decision.candidates.push(Candidate {
+                path: table.path.clone(),
+                level,
+                chosen,
+                reason: reason(),
+            });
+        }
+    }
+
+    // The decision, with the trigger that fired and the inputs it chose, or the error that kept one
+    // from being made.
+    pub(crate) fn finish(
+        self,
+        chosen: Result<Option<(Trigger, Vec<path::PathBuf>)>, String>,
+    ) -> Option<CompactionDecision> {
+        let mut decision = self.0?;
+        match chosen {
+            Ok(Some((trigger, inputs))) => {
+                decision.chosen = Some(trigger);
+                decision.inputs = inputs;
+            }
+            Ok(None) => (),
+            Err(e) => decision.error = Some(e),
+        }
+        Some(decision)
+    }
+}
@@ -1,2 +1,7 @@
-mod combiner;
+pub(crate) mod background;
+pub(crate) mod combiner;
+#[allow(clippy::module_inception)]
 pub mod compactor;
+pub mod decision;
+pub(crate) mod read_trigger;
+pub mod scheduler;
@@ -0,0 +1,153 @@
+// Compactions triggered by reads, for Options::read_compaction_threshold. Gets that have to probe
+// many tables to find a key, usually because many level 0 tables overlap it, are noted by the
+// range of keys they fall in, and a range whose gets probe more than the threshold on average is
+// compacted, whether or not the tables would otherwise make a compaction due.
+//
+// Ranges are the keys sharing a prefix of Options::read_compaction_prefix_length bytes. Each is
+// tracked in one of a fixed number of slots, chosen by hashing the prefix, so tracking takes the
+// same memory however many ranges are read. A range that lands in a slot held by another takes
+// it over and starts counting afresh. Once a range has been compacted, it isn't tracked again for
+// a while, so that a range that stays slow to read isn't compacted over and over.
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    sync::Mutex,
+    time::Duration,
+};
+
+use crate::options::Options;
+
+const NUM_SLOTS: usize = 64;
+
+// The number of gets in a range that its average is taken over.
+const WINDOW: u32 = 64;
+
+// How long a compacted range is left alone, by the store's clock.
+const COOLDOWN: Duration = Duration::from_secs(60);
+
+pub(crate) struct ReadTrigger {
+    threshold: u64,
+    prefix_length: usize,
+    state: Mutex<State>,
+}
+
+struct State {
+    slots: Vec<Slot>,
+    // Prefixes of the ranges due to be compacted.
+    pending: Vec<Vec<u8>>,
+}
+
+#[derive(Default)]
+struct Slot {
+    prefix: Vec<u8>,
+    gets: u32,
+    probes: u64,
+    // The range isn't tracked again until then.
+    quiet_until: Duration,
+}
+
+impl ReadTrigger {
+    // A trigger for the store, if its options ask for one.
+    pub(crate) fn new(options: &Options) -> Option<Self> {
+        let threshold = options.read_compaction_threshold?;
+        Some(ReadTrigger {
+            threshold: threshold as u64,
+            prefix_length: options.read_compaction_prefix_length,
+            state: Mutex::new(State {
+                slots: (0..NUM_SLOTS).map(|_| Slot::default()).collect(),
+                pending: Vec::new(),
+            }),
+        })
+    }
+
+    // Notes a get of `key` that probed `probes` tables, at `now`. Returns whether it made a
+    // compaction of the key's range due.
+    pub(crate) fn record(&self, key: &[u8], probes: u64, now: Duration) -> bool {
+        let prefix = &key[..key.len().min(self.prefix_length)];
+        let mut hasher = DefaultHasher::new();
+        prefix.hash(&mut hasher);
+
+        let mut state = self.state.lock().unwrap();
+        let slot = &mut state.slots[hasher.finish() as usize % NUM_SLOTS];
+        if slot.prefix != prefix {
+            *slot = Slot {
+                prefix: prefix.to_vec(),
+                ..Slot::default()
+            };
+        }
+        if now < slot.quiet_until {
+            return false;
+        }
+
+        slot.gets += 1;
+        slot.probes += probes;
+        if slot.gets < WINDOW {
+            return false;
+        }
+
+        let due = slot.probes > self.threshold * slot.gets as u64;
+        slot.gets = 0;
+        slot.probes = 0;
+        if !due {
+            return false;
+        }
+
+        slot.quiet_until = now + COOLDOWN;
+        let prefix = slot.prefix.clone();
+        // A range is only pending once, and there can't be more of them than slots.
+        if !state.pending.contains(&prefix) && state.pending.len() < NUM_SLOTS {
+            state.pending.push(prefix);
+        }
+        true
+    }
+
+    pub(crate) fn has_pending(&self) -> bool {
+        !self.state.lock().unwrap().pending.is_empty()
+    }
+
+    // Takes the prefixes of the ranges due to be compacted.
+    pub(crate) fn take_pending(&self) -> Vec<Vec<u8>> {
+        std::mem::take(&mut self.state.lock().unwrap().pending)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_trigger() {
+        let options = Options::default()
+            .read_compaction_threshold(4)
+            .read_compaction_prefix_length(3);
+        let trigger = ReadTrigger::new(&options).unwrap();
+        let now = Duration::from_secs(1000);
+
+        // A range whose gets probe no more than the threshold on average is left alone.
+        for i in 0..WINDOW * 2 {
+            assert!(!trigger.record(b"abc1", 4, now + Duration::from_secs(i as u64)));
+        }
+        assert!(!trigger.has_pending());
+
+        // One that probes more is due once its window is full, and then quiet for a while.
+        for i in 1..WINDOW {
+            assert!(!trigger.record(format!("xyz{}", i).as_bytes(), 5, now));
+        }
+        assert!(trigger.record(b"xyz", 5, now));
+        assert_eq!(vec![b"xyz".to_vec()], trigger.take_pending());
+        for _ in 0..WINDOW * 2 {
+            assert!(!trigger.record(b"xyz", 10, now + COOLDOWN / 2));
+        }
+        assert!(!trigger.has_pending());
+
+        // After that, it is tracked again.
+        for _ in 1..WINDOW {
+            assert!(!trigger.record(b"xyz", 10, now + COOLDOWN));
+        }
+        assert!(trigger.record(b"xyz", 10, now + COOLDOWN));
+        assert_eq!(vec![b"xyz".to_vec()], trigger.take_pending());
+
+        assert!(ReadTrigger::new(&Options::default()).is_none());
+    }
+}
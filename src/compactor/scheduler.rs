@@ -0,0 +1,28 @@
+// Deciding when to compact and what, for Options::compaction_scheduler. A scheduler is asked for
+// the next compaction each time the store would check whether one is due: After each flush, and on
+// each Store::tick. It replaces the built-in triggers, level_0_file_limit and the other thresholds,
+// which are what decide without one. Compactions that gets make due with
+// Options::read_compaction_threshold still run either way.
+//
+// A scheduler only chooses what to compact. The compaction itself is run the same way as the
+// store's own, so a job that isn't worth running, such as a level with no tables, does nothing.
+
+use crate::sst::TableInfo;
+
+pub trait CompactionScheduler: Send + Sync {
+    // The compaction to run next, given every table in the store, or None to leave the tables as
+    // they are for now. Tables are described as by Store::table_info.
+    fn next_job(&self, tables: &[TableInfo]) -> Option<CompactionJob>;
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CompactionJob {
+    // Compacts every table in the level into the level below it, as Store::compact_level does.
+    Level(usize),
+    // Compacts the tables with keys in [start, end), or from start onward if there is no end, as
+    // Store::compact_range does.
+    Range {
+        start: Vec<u8>,
+        end: Option<Vec<u8>>,
+    },
+}
@@ -0,0 +1,44 @@
+use std::{error::Error, fmt, io, path};
+
+// Adds the operation being performed and the file it was performed on to I/O errors, so that an
+// error like "No space left on device" can be traced back to the WAL, a flush, or a compaction
+// output. The error kind is preserved so callers can still match on it.
+pub(crate) trait IoContext<T> {
+    fn with_path(self, op: &'static str, path: &path::Path) -> io::Result<T>;
+}
+
+impl<T> IoContext<T> for io::Result<T> {
+    fn with_path(self, op: &'static str, path: &path::Path) -> io::Result<T> {
+        self.map_err(|source| path_error(op, path, source))
+    }
+}
+
+pub(crate) fn path_error(op: &'static str, path: &path::Path, source: io::Error) -> io::Error {
+    io::Error::new(
+        source.kind(),
+        PathError {
+            op,
+            path: path.to_owned(),
+            source,
+        },
+    )
+}
+
+#[derive(Debug)]
+struct PathError {
+    op: &'static str,
+    path: path::PathBuf,
+    source: io::Error,
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}: {}", self.op, self.path.display(), self.source)
+    }
+}
+
+impl Error for PathError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.source)
+    }
+}
@@ -0,0 +1,96 @@
+// Files that must appear whole or not at all, such as new tables, are written under a temporary
+// name and then renamed into place. A crash part way through leaves only the temporary file, which
+// nothing reads and the store removes when it is next opened.
+
+use std::{
+    ffi::OsString,
+    fs,
+    io::{self, Seek},
+    path,
+};
+
+use crate::context::IoContext;
+
+// Tables being written sit in their level's directory with this extension until they are complete.
+pub(crate) const TEMP_EXT: &str = "tmp";
+
+// Renames the complete, synced file at `from` to `to`, replacing anything already there, and makes
+// the rename itself durable before returning. Both must be in the same directory.
+//
+// On Unix, a rename is atomic, but is only durable once the directory holding it is synced. On
+// Windows, the standard library renames with MoveFileExW and MOVEFILE_REPLACE_EXISTING, which is
+// atomic within a volume. Directories can't be synced there, so the file is synced again under its
+// new name, which flushes the change to its directory entry along with it.
+pub(crate) fn atomic_rename_and_sync(from: &path::Path, to: &path::Path) -> io::Result<()> {
+    #[cfg(test)]
+    if tests::CRASH_BEFORE_RENAME.get() {
+        return Err(io::Error::other("crashed before rename"));
+    }
+
+    fs::rename(from, to).with_path("renaming", from)?;
+    sync_rename(to)
+}
+
+// Extends a new file to the size it is expected to end up, so that the filesystem can lay it out in
+// one go rather than growing it a little at a time. This is only a hint, and a filesystem that
+// can't do it is left to grow the file as it is written.
+pub(crate) fn preallocate(file: &fs::File, size: u64) {
+    let _ = file.set_len(size);
+}
+
+// Cuts a file that was written from its start back to the end of what was written, dropping any
+// space `preallocate` reserved past it. Tables are read from their end, so this must be done
+// before one is renamed into place.
+pub(crate) fn truncate_to_written(mut file: &fs::File) -> io::Result<()> {
+    let written = file.stream_position()?;
+    file.set_len(written)
+}
+
+fn sync_rename(to: &path::Path) -> io::Result<()> {
+    let dir = to.parent().expect("renamed file must have a parent");
+    let name = to.file_name().expect("renamed file must have a name");
+    sync_renames(dir, &[name.to_owned()])
+}
+
+// Makes the renames of `names` into `dir` durable, for when more than one entry was moved there at
+// once. On Unix this syncs the directory, which also makes any other change to its entries, such
+// as a removal, durable. On Windows each renamed file is synced under its new name instead, as in
+// atomic_rename_and_sync. Renamed directories can't be opened for writing there, so they are left
+// to the filesystem.
+#[cfg(unix)]
+pub(crate) fn sync_renames(dir: &path::Path, _names: &[OsString]) -> io::Result<()> {
+    fs::File::open(dir)
+        .and_then(|dir| dir.sync_all())
+        .with_path("syncing", dir)
+}
+
+#[cfg(windows)]
+pub(crate) fn sync_renames(dir: &path::Path, names: &[OsString]) -> io::Result<()> {
+    for name in names {
+        let path = dir.join(name);
+        if path.is_file() {
+            fs::OpenOptions::new()
+                .write(true)
+                .open(&path)
+                .and_then(|file| file.sync_all())
+                .with_path("syncing", &path)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(any(unix, windows)))]
+pub(crate) fn sync_renames(_dir: &path::Path, _names: &[OsString]) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::cell::Cell;
+
+    thread_local! {
+        // Makes renames on this thread fail before they happen, as if the store crashed between
+        // writing a file and renaming it into place.
+        pub(crate) static CRASH_BEFORE_RENAME: Cell<bool> = const { Cell::new(false) };
+    }
+}
@@ -0,0 +1,114 @@
+// Textual encodings of keys and values, for moving arbitrary bytes through formats and tools that
+// only carry text.
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyEncoding {
+    // Bytes are taken as UTF-8, with invalid sequences replaced when encoding. Only round-trips
+    // data that is valid UTF-8.
+    Utf8Lossy,
+    // Standard base64 with padding.
+    Base64,
+    // Lowercase hex, two digits per byte. Either case is accepted when decoding.
+    Hex,
+}
+
+impl KeyEncoding {
+    pub fn encode(&self, bytes: &[u8]) -> String {
+        match self {
+            KeyEncoding::Utf8Lossy => String::from_utf8_lossy(bytes).into_owned(),
+            KeyEncoding::Base64 => {
+                let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+                for chunk in bytes.chunks(3) {
+                    let bits = chunk
+                        .iter()
+                        .enumerate()
+                        .fold(0u32, |bits, (i, &b)| bits | (b as u32) << (16 - 8 * i));
+                    for i in 0..4 {
+                        if i <= chunk.len() {
+                            let digit = (bits >> (18 - 6 * i)) & 0x3f;
+                            out.push(BASE64_ALPHABET[digit as usize] as char);
+                        } else {
+                            out.push('=');
+                        }
+                    }
+                }
+                out
+            }
+            KeyEncoding::Hex => bytes.iter().map(|b| format!("{:02x}", b)).collect(),
+        }
+    }
+
+    pub fn decode(&self, s: &str) -> Result<Vec<u8>, String> {
+        match self {
+            KeyEncoding::Utf8Lossy => Ok(s.as_bytes().to_vec()),
+            KeyEncoding::Base64 => {
+                let invalid = || format!("invalid base64 \"{}\"", s);
+                let digits = s.trim_end_matches('=');
+                if !s.len().is_multiple_of(4) || s.len() - digits.len() > 2 {
+                    return Err(invalid());
+                }
+
+                let mut out = Vec::with_capacity(digits.len() * 3 / 4);
+                let mut bits = 0u32;
+                let mut bit_count = 0;
+                for c in digits.bytes() {
+                    let value = BASE64_ALPHABET
+                        .iter()
+                        .position(|&a| a == c)
+                        .ok_or_else(invalid)?;
+                    bits = (bits << 6) | value as u32;
+                    bit_count += 6;
+                    if bit_count >= 8 {
+                        bit_count -= 8;
+                        out.push((bits >> bit_count) as u8);
+                    }
+                }
+
+                Ok(out)
+            }
+            KeyEncoding::Hex => {
+                if !s.len().is_multiple_of(2) {
+                    return Err(format!("invalid hex \"{}\": odd length", s));
+                }
+                (0..s.len())
+                    .step_by(2)
+                    .map(|i| {
+                        s.get(i..i + 2)
+                            .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                            .ok_or_else(|| format!("invalid hex \"{}\"", s))
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let cases: &[&[u8]] = &[b"", b"f", b"fo", b"foo", b"foob", &[0, 255, 10, 128, 7]];
+
+        for encoding in [KeyEncoding::Base64, KeyEncoding::Hex] {
+            for bytes in cases {
+                let encoded = encoding.encode(bytes);
+                assert_eq!(bytes.to_vec(), encoding.decode(&encoded).unwrap());
+            }
+        }
+
+        assert_eq!("Zm9vYg==", KeyEncoding::Base64.encode(b"foob"));
+        assert_eq!("00ff", KeyEncoding::Hex.encode(&[0, 255]));
+        assert_eq!(vec![0, 255], KeyEncoding::Hex.decode("00FF").unwrap());
+        assert_eq!("a\u{fffd}", KeyEncoding::Utf8Lossy.encode(&[b'a', 255]));
+
+        assert!(KeyEncoding::Base64.decode("Zm9").is_err());
+        assert!(KeyEncoding::Base64.decode("Zm9v!===").is_err());
+        assert!(KeyEncoding::Hex.decode("0").is_err());
+        assert!(KeyEncoding::Hex.decode("zz").is_err());
+    }
+}
@@ -0,0 +1,321 @@
+// Resources that many stores in one process can share, rather than each having its own. For now
+// that is the threads that run background compactions: With CompactionMode::Background, a store
+// without an Env in its options gets one of its own with a single thread, and stores opened with
+// the same Env share its threads however many of them there are.
+//
+//      let env = Env::new(2);
+//      let options = Options::default()
+//          .compaction_mode(CompactionMode::Background)
+//          .env(env.clone());
+//
+// Work is scheduled fairly between stores: Each store is queued at most once, behind every store
+// already waiting, however many times it asks for a compaction in the meantime.
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Condvar, Mutex},
+    thread,
+};
+
+#[derive(Clone)]
+pub struct Env {
+    inner: Arc<Inner>,
+}
+
+impl fmt::Debug for Env {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Env")
+            .field("threads", &self.threads())
+            .finish()
+    }
+}
+
+// Stops the threads once the last handle to the Env is gone. Each store holds one, so by then no
+// store is using them.
+struct Inner {
+    queue: Arc<Queue>,
+    threads: Vec<thread::JoinHandle<()>>,
+}
+
+#[derive(Default)]
+struct Queue {
+    state: Mutex<QueueState>,
+    ready: Condvar,
+}
+
+#[derive(Default)]
+struct QueueState {
+    workers: VecDeque<Arc<Worker>>,
+    shutdown: bool,
+}
+
+impl Env {
+    // An Env with `threads` threads for background work, which must be at least 1.
+    pub fn new(threads: usize) -> Self {
+        assert!(threads > 0, "an Env needs at least one thread");
+
+        let queue = Arc::new(Queue::default());
+        let threads = (0..threads)
+            .map(|i| {
+                let queue = queue.clone();
+                thread::Builder::new()
+                    .name(format!("crucible-env-{}", i))
+                    .spawn(move || run(&queue))
+                    .expect("must spawn Env thread")
+            })
+            .collect();
+
+        Env {
+            inner: Arc::new(Inner { queue, threads }),
+        }
+    }
+
+    // The number of threads the Env runs background work on.
+    pub fn threads(&self) -> usize {
+        self.inner.threads.len()
+    }
+
+    // Registers `task` to be run on the Env's threads each time the returned handle is woken.
+    pub(crate) fn worker(&self, task: Box<dyn Fn() + Send + Sync>) -> WorkerHandle {
+        WorkerHandle {
+            worker: Arc::new(Worker {
+                task,
+                state: Mutex::new(WorkerState::default()),
+                done: Condvar::new(),
+            }),
+            env: self.clone(),
+        }
+    }
+
+    fn schedule(&self, worker: Arc<Worker>) {
+        self.inner
+            .queue
+            .state
+            .lock()
+            .unwrap()
+            .workers
+            .push_back(worker);
+        self.inner.queue.ready.notify_one();
+    }
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        self.queue.state.lock().unwrap().shutdown = true;
+        self.queue.ready.notify_all();
+        for thread in self.threads.drain(..) {
+            // A panic on the thread has nowhere better to go.
+            let _ = thread.join();
+        }
+    }
+}
+
+fn run(queue: &Queue) {
+    loop {
+        let worker = {
+            let mut state = queue.state.lock().unwrap();
+            loop {
+                if let Some(worker) = state.workers.pop_front() {
+                    break worker;
+                }
+                if state.shutdown {
+                    return;
+                }
+                state = queue.ready.wait(state).unwrap();
+            }
+        };
+        // A worker woken while it ran goes to the back of the queue, like any other wake-up.
+        if worker.run() {
+            queue.state.lock().unwrap().workers.push_back(worker);
+            queue.ready.notify_one();
+        }
+    }
+}
+
+// A store's share of an Env: Its task, and how many times it has been asked for and run.
+struct Worker {
+    task: Box<dyn Fn() + Send + Sync>,
+    state: Mutex<WorkerState>,
+    done: Condvar,
+}
+
+#[derive(Default)]
+struct WorkerState {
+    // Whether the worker is waiting in the Env's queue, and whether it was woken while running,
+    // which puts it back in the queue once the run finishes rather than running it twice at once.
+    queued: bool,
+    pending: bool,
+    running: bool,
+    closed: bool,
+    // Wake-ups so far, and how many of them a finished run has seen to.
+    requested: u64,
+    completed: u64,
+}
+
+impl Worker {
+    // Runs the task, returning whether the worker needs to be queued again.
+    fn run(&self) -> bool {
+        let target = {
+            let mut state = self.state.lock().unwrap();
+            state.queued = false;
+            if state.closed {
+                return false;
+            }
+            state.running = true;
+            state.requested
+        };
+
+        // A task that panics has still run, as far as waiting for it goes, and the thread carries
+        // on with other stores' work. Tasks that need to know report it themselves.
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| (self.task)()));
+
+        let mut state = self.state.lock().unwrap();
+        state.running = false;
+        state.completed = state.completed.max(target);
+        self.done.notify_all();
+        if state.pending && !state.closed {
+            state.pending = false;
+            state.queued = true;
+            return true;
+        }
+        false
+    }
+}
+
+// Wakes a store's worker. Dropping it waits for a run under way to finish, and stops any more.
+pub(crate) struct WorkerHandle {
+    worker: Arc<Worker>,
+    env: Env,
+}
+
+impl WorkerHandle {
+    // Asks for the task to run. A run that is already under way doesn't count, since it may have
+    // started before whatever prompted this.
+    pub fn wake(&self) -> u64 {
+        let mut state = self.worker.state.lock().unwrap();
+        state.requested += 1;
+        if state.running {
+            state.pending = true;
+        } else if !state.queued && !state.closed {
+            state.queued = true;
+            self.env.schedule(self.worker.clone());
+        }
+        state.requested
+    }
+
+    // Wakes the worker, and waits for the task to have run since.
+    pub fn wait(&self) {
+        let target = self.wake();
+        let mut state = self.worker.state.lock().unwrap();
+        while state.completed < target && !state.closed {
+            state = self.worker.done.wait(state).unwrap();
+        }
+    }
+}
+
+impl Drop for WorkerHandle {
+    fn drop(&mut self) {
+        let mut state = self.worker.state.lock().unwrap();
+        state.closed = true;
+        while state.running {
+            state = self.worker.done.wait(state).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    #[test]
+    fn test_env() {
+        let env = Env::new(2);
+        let runs = (0..10)
+            .map(|_| Arc::new(AtomicUsize::new(0)))
+            .collect::<Vec<_>>();
+        let workers = runs
+            .iter()
+            .map(|runs| {
+                let runs = runs.clone();
+                env.worker(Box::new(move || {
+                    runs.fetch_add(1, Ordering::SeqCst);
+                }))
+            })
+            .collect::<Vec<_>>();
+
+        // Each wait sees a run that started after it.
+        for (worker, runs) in workers.iter().zip(&runs) {
+            worker.wake();
+            worker.wait();
+            assert!(runs.load(Ordering::SeqCst) >= 1);
+        }
+
+        // A dropped worker isn't run again, even if it was still queued.
+        let mut workers = workers.into_iter();
+        let first = workers.next().unwrap();
+        first.wake();
+        drop(first);
+        let dropped = runs[0].load(Ordering::SeqCst);
+        for worker in workers {
+            worker.wait();
+        }
+        assert_eq!(dropped, runs[0].load(Ordering::SeqCst));
+
+        assert_eq!(2, env.threads());
+    }
+
+    #[test]
+    fn test_env_wake_while_running() {
+        let env = Env::new(2);
+        let running = Arc::new(AtomicUsize::new(0));
+        let overlaps = Arc::new(AtomicUsize::new(0));
+        let runs = Arc::new(AtomicUsize::new(0));
+        let worker = {
+            let (running, overlaps, runs) = (running.clone(), overlaps.clone(), runs.clone());
+            env.worker(Box::new(move || {
+                if running.fetch_add(1, Ordering::SeqCst) > 0 {
+                    overlaps.fetch_add(1, Ordering::SeqCst);
+                }
+                thread::sleep(std::time::Duration::from_millis(50));
+                running.fetch_sub(1, Ordering::SeqCst);
+                runs.fetch_add(1, Ordering::SeqCst);
+            }))
+        };
+
+        // Wake-ups during a run don't start a second one on the other thread, but the worker does
+        // run again once the first finishes.
+        worker.wake();
+        while running.load(Ordering::SeqCst) == 0 {
+            thread::yield_now();
+        }
+        worker.wake();
+        worker.wake();
+        worker.wait();
+
+        assert_eq!(0, overlaps.load(Ordering::SeqCst));
+        assert!(runs.load(Ordering::SeqCst) >= 2);
+    }
+
+    #[test]
+    fn test_env_task_panics() {
+        let env = Env::new(1);
+        let panicking = env.worker(Box::new(|| panic!("task failed")));
+        let runs = Arc::new(AtomicUsize::new(0));
+        let other = {
+            let runs = runs.clone();
+            env.worker(Box::new(move || {
+                runs.fetch_add(1, Ordering::SeqCst);
+            }))
+        };
+
+        // Waiting for the task and dropping its worker return, and the thread still runs others.
+        panicking.wait();
+        drop(panicking);
+        other.wait();
+        assert_eq!(1, runs.load(Ordering::SeqCst));
+    }
+}
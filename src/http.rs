@@ -0,0 +1,202 @@
+// A small HTTP API over a store, for quick integrations and health checks:
+//
+//      GET    /kv/{key}                  The value, or 404 if there is none.
+//      PUT    /kv/{key}                  Sets the value to the request body.
+//      DELETE /kv/{key}                  Deletes the key.
+//      GET    /kv?prefix=...&limit=...   Live records in key order, as JSON Lines.
+//      GET    /stats                     Read statistics, as JSON.
+//      GET    /healthz                   "ok" while the server is running.
+//
+// Keys in paths and query strings are percent-decoded, so any bytes can be given. A '+' is taken
+// literally rather than as a space. Listed records are written like `Store::export_jsonl` with
+// base64 encoding: {"key":"...","value":"..."}.
+
+use std::{
+    io,
+    net::{SocketAddr, ToSocketAddrs},
+    sync::Mutex,
+};
+
+use tiny_http::{Method, Request, Response, StatusCode};
+
+use crate::{encoding::KeyEncoding, scan::prefix_end, store::Store, StoreError};
+
+type HttpResponse = Response<io::Cursor<Vec<u8>>>;
+
+// Serves a store until `stop` is called. Requests are handled one at a time.
+pub struct Server {
+    http: tiny_http::Server,
+    store: Mutex<Store>,
+}
+
+impl Server {
+    pub fn bind<A: ToSocketAddrs>(store: Store, addr: A) -> io::Result<Self> {
+        let http = tiny_http::Server::http(addr).map_err(io::Error::other)?;
+        Ok(Server {
+            http,
+            store: Mutex::new(store),
+        })
+    }
+
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.http.server_addr().to_ip()
+    }
+
+    // Handles requests until the server is stopped.
+    pub fn run(&self) {
+        for request in self.http.incoming_requests() {
+            self.handle(request);
+        }
+    }
+
+    // Makes `run` return. Requests already being handled are finished first.
+    pub fn stop(&self) {
+        self.http.unblock();
+    }
+
+    pub fn into_store(self) -> Store {
+        self.store.into_inner().unwrap()
+    }
+
+    fn handle(&self, mut request: Request) {
+        let (path, query) = match request.url().split_once('?') {
+            Some((path, query)) => (path.to_string(), Some(query.to_string())),
+            None => (request.url().to_string(), None),
+        };
+
+        let mut body = Vec::new();
+        let response = match request.as_reader().read_to_end(&mut body) {
+            Ok(_) => self.route(request.method(), &path, query.as_deref(), body),
+            Err(e) => error_response(400, &e.to_string()),
+        };
+
+        // The client may have gone away, which is no concern of the server's.
+        let _ = request.respond(response);
+    }
+
+    fn route(
+        &self,
+        method: &Method,
+        path: &str,
+        query: Option<&str>,
+        body: Vec<u8>,
+    ) -> HttpResponse {
+        let mut store = self.store.lock().unwrap();
+
+        let result = match (method, path) {
+            (Method::Get, "/healthz") => Ok(Response::from_string("ok")),
+            (Method::Get, "/stats") => Ok(stats(&store)),
+            (Method::Get, "/kv") => match list(&store, query) {
+                Ok(Ok(response)) => Ok(response),
+                Ok(Err(detail)) => return error_response(400, &detail),
+                Err(e) => Err(e),
+            },
+            (method, path) => match path.strip_prefix("/kv/").map(percent_decode) {
+                Some(Ok(key)) => match method {
+                    Method::Get => store.get(&key).map(|val| match val {
+                        Some(val) => Response::from_data(val),
+                        None => error_response(404, "not found"),
+                    }),
+                    Method::Put => store.put(&key, &body).map(|_| empty_response(204)),
+                    Method::Delete => store.del(&key).map(|_| empty_response(204)),
+                    _ => return error_response(405, "method not allowed"),
+                },
+                Some(Err(detail)) => return error_response(400, &detail),
+                None => return error_response(404, "not found"),
+            },
+        };
+
+        result.unwrap_or_else(|e| match e {
+            StoreError::InvalidArgument(_) => error_response(400, &e.to_string()),
+            e => error_response(500, &e.to_string()),
+        })
+    }
+}
+
+// Serves a store on `addr` until the process exits.
+pub fn serve<A: ToSocketAddrs>(store: Store, addr: A) -> io::Result<()> {
+    Server::bind(store, addr)?.run();
+    Ok(())
+}
+
+// Records matching the `prefix` and `limit` query parameters. Bad parameters are returned as the
+// inner error, to be reported to the client.
+fn list(store: &Store, query: Option<&str>) -> Result<Result<HttpResponse, String>, StoreError> {
+    let mut prefix = Vec::new();
+    let mut limit = usize::MAX;
+    for param in query
+        .unwrap_or_default()
+        .split('&')
+        .filter(|p| !p.is_empty())
+    {
+        let (name, value) = param.split_once('=').unwrap_or((param, ""));
+        match name {
+            "prefix" => match percent_decode(value) {
+                Ok(value) => prefix = value,
+                Err(detail) => return Ok(Err(detail)),
+            },
+            "limit" => match value.parse() {
+                Ok(value) => limit = value,
+                Err(_) => return Ok(Err(format!("invalid limit \"{}\"", value))),
+            },
+            _ => return Ok(Err(format!("unknown parameter \"{}\"", name))),
+        }
+    }
+
+    let end = prefix_end(&prefix);
+    let mut out = String::new();
+    for record in store.scan(&prefix, end.as_deref())?.take(limit) {
+        let (key, val) = record?;
+        out.push_str(&format!(
+            "{{\"key\":\"{}\",\"value\":\"{}\"}}\n",
+            KeyEncoding::Base64.encode(&key),
+            KeyEncoding::Base64.encode(&val)
+        ));
+    }
+
+    Ok(Ok(Response::from_string(out)))
+}
+
+fn stats(store: &Store) -> HttpResponse {
+    let stats = store.stats();
+    Response::from_string(format!(
+        "{{\"gets\":{},\"tables_probed\":{},\"seeks\":{},\"wal_bytes\":{}}}",
+        stats.gets,
+        stats.tables_probed,
+        stats.seeks,
+        store.wal_size()
+    ))
+}
+
+fn empty_response(status: u16) -> HttpResponse {
+    Response::from_data(Vec::new()).with_status_code(StatusCode(status))
+}
+
+fn error_response(status: u16, detail: &str) -> HttpResponse {
+    Response::from_string(detail).with_status_code(StatusCode(status))
+}
+
+fn percent_decode(s: &str) -> Result<Vec<u8>, String> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let byte = s
+                    .get(i + 1..i + 3)
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .ok_or_else(|| format!("invalid percent-encoding in \"{}\"", s))?;
+                out.push(byte);
+                i += 3;
+            }
+            b => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+
+    Ok(out)
+}
@@ -0,0 +1,286 @@
+use std::{
+    fs,
+    io::{self, Write},
+    path,
+};
+
+use uuid::Uuid;
+
+use crate::{
+    context::{path_error, IoContext},
+    durable::{atomic_rename_and_sync, TEMP_EXT},
+    protocol::SST_EXT,
+    sst::OBSOLETE_EXT,
+    store::WAL_FILE_NAME,
+    StoreError,
+};
+
+pub const IDENTITY_FILE_NAME: &str = "IDENTITY";
+
+// The newest on-disk format this build understands. Stores with a newer format are refused rather
+// than misread.
+pub const FORMAT_VERSION: u32 = 4;
+
+// The first format in which tables may carry values in their indexes, see
+// Options::inline_value_size. A store is only moved to it once it is opened with the option.
+pub(crate) const INLINE_VALUES_FORMAT_VERSION: u32 = 2;
+
+// The first format in which table indexes may end with a prefix filter, see
+// Options::prefix_bloom_length.
+pub(crate) const PREFIX_FILTER_FORMAT_VERSION: u32 = 3;
+
+// The first format in which values may be encoded, see Options::value_codec.
+pub(crate) const CODEC_FORMAT_VERSION: u32 = 4;
+
+const MAGIC: &str = "crucible";
+
+// Marks a directory as a store, recording the format it was written with and a unique id for it.
+// It is a small text file:
+//
+//      crucible
+//      format_version 1
+//      id 67e55044-10b1-426f-9247-bb680e5fe0c8
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Identity {
+    pub format_version: u32,
+    pub id: Uuid,
+}
+
+impl Identity {
+    // Reads the identity of the store in `data_dir`, creating one if there isn't one yet. A
+    // directory without an identity must either be empty or contain only what an older version of
+    // the store would have written.
+    pub(crate) fn load_or_create(data_dir: &path::Path) -> Result<Self, StoreError> {
+        let path = data_dir.join(IDENTITY_FILE_NAME);
+
+        match fs::read_to_string(&path) {
+            Ok(contents) => {
+                let identity =
+                    Identity::parse(&contents).map_err(|detail| StoreError::Corruption {
+                        path: path.clone(),
+                        offset: 0,
+                        detail,
+                    })?;
+
+                if identity.format_version > FORMAT_VERSION {
+                    return Err(StoreError::UnsupportedFormat {
+                        path,
+                        version: identity.format_version,
+                    });
+                }
+
+                Ok(identity)
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                check_legacy_store(data_dir)?;
+
+                let identity = Identity {
+                    format_version: FORMAT_VERSION,
+                    id: Uuid::new_v4(),
+                };
+                identity
+                    .write(&path)
+                    .map_err(StoreError::CatalogInitialization)?;
+
+                Ok(identity)
+            }
+            Err(e) => Err(StoreError::Read { path, source: e }),
+        }
+    }
+
+    // Records that the store in `data_dir` may now hold data only readable by versions that
+    // understand `format_version`, unless its format is already at least that new.
+    pub(crate) fn upgrade(
+        self,
+        data_dir: &path::Path,
+        format_version: u32,
+    ) -> Result<Self, StoreError> {
+        if self.format_version >= format_version {
+            return Ok(self);
+        }
+
+        let identity = Identity {
+            format_version,
+            ..self
+        };
+        identity
+            .write(&data_dir.join(IDENTITY_FILE_NAME))
+            .map_err(StoreError::CatalogInitialization)?;
+
+        Ok(identity)
+    }
+
+    fn parse(contents: &str) -> Result<Self, String> {
+        let mut lines = contents.lines();
+
+        if lines.next() != Some(MAGIC) {
+            return Err("not a crucible identity file".to_string());
+        }
+
+        let mut field = |name: &str| {
+            lines
+                .next()
+                .and_then(|line| line.strip_prefix(name)?.strip_prefix(' '))
+                .ok_or_else(|| format!("missing {}", name))
+        };
+
+        let format_version = field("format_version")?;
+        let format_version = format_version
+            .parse()
+            .map_err(|_| format!("invalid format_version {}", format_version))?;
+        let id = field("id")?;
+        let id = Uuid::parse_str(id).map_err(|_| format!("invalid id {}", id))?;
+
+        Ok(Identity { format_version, id })
+    }
+
+    // Written to a temporary file first so that a crash can't leave a partial identity behind.
+    fn write(&self, path: &path::Path) -> io::Result<()> {
+        let tmp = path.with_extension("tmp");
+
+        let mut file = fs::File::create(&tmp).with_path("creating", &tmp)?;
+        write!(
+            file,
+            "{}\nformat_version {}\nid {}\n",
+            MAGIC, self.format_version, self.id
+        )
+        .with_path("writing", &tmp)?;
+        file.sync_all().with_path("syncing", &tmp)?;
+
+        atomic_rename_and_sync(&tmp, path)
+    }
+}
+
+// A store written before identity files existed holds only its WAL, archived WAL segments,
+// integer-named level directories, and perhaps integer-named tables from before it had levels.
+fn check_legacy_store(data_dir: &path::Path) -> Result<(), StoreError> {
+    let list_err = |e| StoreError::CatalogInitialization(path_error("listing", data_dir, e));
+
+    for entry in fs::read_dir(data_dir).map_err(list_err)? {
+        let entry = entry.map_err(list_err)?;
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+
+        let is_wal = name == WAL_FILE_NAME
+            || name
+                .strip_prefix(WAL_FILE_NAME)
+                .and_then(|seq| seq.strip_prefix('.'))
+                .is_some_and(|seq| seq.parse::<u64>().is_ok());
+        let is_level = entry.path().is_dir() && name.parse::<usize>().is_ok();
+        let is_legacy_table = entry.path().is_file()
+            && name
+                .strip_suffix(&format!(".{}", SST_EXT))
+                .is_some_and(|seq| seq.parse::<u32>().is_ok());
+        let expected =
+            is_wal || is_level || is_legacy_table || name == format!("{}.tmp", IDENTITY_FILE_NAME);
+
+        if !expected {
+            return Err(StoreError::NotAStore {
+                path: data_dir.to_owned(),
+                detail: format!("unexpected entry {}", entry.path().display()),
+            });
+        }
+        if is_level {
+            check_level_dir(data_dir, &entry.path())?;
+        }
+    }
+
+    Ok(())
+}
+
+// A level directory only ever holds tables, including those being written or set aside.
+fn check_level_dir(data_dir: &path::Path, dir: &path::Path) -> Result<(), StoreError> {
+    let list_err = |e| StoreError::CatalogInitialization(path_error("listing", dir, e));
+
+    for entry in fs::read_dir(dir).map_err(list_err)? {
+        let path = entry.map_err(list_err)?.path();
+        let is_table = path.is_file()
+            && path
+                .extension()
+                .is_some_and(|ext| ext == SST_EXT || ext == TEMP_EXT || ext == OBSOLETE_EXT);
+        if !is_table {
+            return Err(StoreError::NotAStore {
+                path: data_dir.to_owned(),
+                detail: format!("unexpected entry {}", path.display()),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_identity() {
+        let dir = TempDir::new("testing").unwrap();
+
+        // Created once, then read back.
+        let identity = Identity::load_or_create(dir.path()).unwrap();
+        assert_eq!(FORMAT_VERSION, identity.format_version);
+        assert_eq!(identity, Identity::load_or_create(dir.path()).unwrap());
+
+        // Older formats are upgraded only when asked to be, and keep their id.
+        let path = dir.path().join(IDENTITY_FILE_NAME);
+        fs::write(
+            &path,
+            format!("crucible\nformat_version 1\nid {}\n", identity.id),
+        )
+        .unwrap();
+        let old = Identity::load_or_create(dir.path()).unwrap();
+        assert_eq!(1, old.format_version);
+        assert_eq!(old, old.upgrade(dir.path(), 1).unwrap());
+        assert_eq!(identity, old.upgrade(dir.path(), FORMAT_VERSION).unwrap());
+        assert_eq!(identity, Identity::load_or_create(dir.path()).unwrap());
+
+        // Newer formats are refused.
+        fs::write(
+            &path,
+            format!("crucible\nformat_version 5\nid {}\n", identity.id),
+        )
+        .unwrap();
+        assert!(matches!(
+            Identity::load_or_create(dir.path()),
+            Err(StoreError::UnsupportedFormat { version: 5, .. })
+        ));
+
+        fs::write(&path, "something else\n").unwrap();
+        assert!(matches!(
+            Identity::load_or_create(dir.path()),
+            Err(StoreError::Corruption { .. })
+        ));
+    }
+
+    #[test]
+    fn test_legacy_store() {
+        let dir = TempDir::new("testing").unwrap();
+        fs::create_dir(dir.path().join("0")).unwrap();
+        fs::write(dir.path().join(WAL_FILE_NAME), b"").unwrap();
+        fs::write(dir.path().join(format!("{}.3", WAL_FILE_NAME)), b"").unwrap();
+        fs::write(dir.path().join("7.sst"), b"").unwrap();
+        assert!(Identity::load_or_create(dir.path()).is_ok());
+
+        // Anything else means this probably isn't a store.
+        let dir = TempDir::new("testing").unwrap();
+        fs::write(dir.path().join("notes.txt"), b"").unwrap();
+        assert!(matches!(
+            Identity::load_or_create(dir.path()),
+            Err(StoreError::NotAStore { .. })
+        ));
+        assert!(!dir.path().join(IDENTITY_FILE_NAME).exists());
+
+        // Including directories named like levels that hold something other than tables.
+        let dir = TempDir::new("testing").unwrap();
+        fs::create_dir_all(dir.path().join("2019").join("trip")).unwrap();
+        fs::write(dir.path().join("2019").join("photo.jpg"), b"").unwrap();
+        assert!(matches!(
+            Identity::load_or_create(dir.path()),
+            Err(StoreError::NotAStore { .. })
+        ));
+        assert!(!dir.path().join(IDENTITY_FILE_NAME).exists());
+    }
+}
@@ -0,0 +1,184 @@
+// Secondary indexes, kept by the store alongside the records they index. Each index has an
+// extractor that maps a record to the index keys it should be found under, and the store keeps an
+// entry for each of them as it is written:
+//
+//      fn by_city(_key: &[u8], val: &[u8]) -> Vec<Vec<u8>> {
+//          val.split(|b| *b == b',').take(1).map(<[u8]>::to_vec).collect()
+//      }
+//
+//      let options = Options::default().index(IndexDef::new("city", by_city));
+//      ...
+//      let keys = store.lookup_index("city", b"Paris")?;
+//
+// Entries are records of their own, with empty values, under keys starting with INDEX_PREFIX:
+//
+//      [INDEX_PREFIX][name][0][index key length: u32][index key][primary key]
+//
+// Keeping an index up to date means finding the index keys of the record being replaced, so each
+// put or del of a store with indexes first reads the key's current value. That is a full get for
+// every write, and stores without indexes don't pay it.
+//
+// A record and its entries are separate writes to the WAL, so a crash between them can leave
+// entries for a value that was never written, or for one that has since been replaced. Entries are
+// added before the record they point to and removed after it, so an entry may be stale but none is
+// ever missing, and lookups check each entry against the record before returning it.
+
+use crate::{options::Options, scan::prefix_end, StoreError};
+
+// The start of every index entry's key. Keys written to a store with indexes must not start with
+// it.
+pub const INDEX_PREFIX: &[u8] = b"\xff\xffindex/";
+
+// Maps a record's key and value to the index keys it should be found under, which may be none.
+pub type Extractor = fn(&[u8], &[u8]) -> Vec<Vec<u8>>;
+
+#[derive(Clone, Debug)]
+pub struct IndexDef {
+    pub name: String,
+    pub extract: Extractor,
+}
+
+impl IndexDef {
+    pub fn new(name: &str, extract: Extractor) -> Self {
+        IndexDef {
+            name: name.to_string(),
+            extract,
+        }
+    }
+}
+
+// The key of the entry for `primary_key` under `index_key`.
+pub(crate) fn entry_key(name: &str, index_key: &[u8], primary_key: &[u8]) -> Vec<u8> {
+    let mut key = entry_prefix(name, index_key);
+    key.extend_from_slice(primary_key);
+    key
+}
+
+// The common start of the keys of every entry under `index_key`.
+pub(crate) fn entry_prefix(name: &str, index_key: &[u8]) -> Vec<u8> {
+    let mut key = INDEX_PREFIX.to_vec();
+    key.extend_from_slice(name.as_bytes());
+    key.push(0);
+    key.extend_from_slice(&(index_key.len() as u32).to_be_bytes());
+    key.extend_from_slice(index_key);
+    key
+}
+
+// The entries to add and remove when the value of `key` changes from `old` to `new`, where None is
+// a missing or deleted record. Entries that both values have are left alone.
+pub(crate) fn changes(
+    indexes: &[IndexDef],
+    key: &[u8],
+    old: Option<&[u8]>,
+    new: Option<&[u8]>,
+) -> (Vec<Vec<u8>>, Vec<Vec<u8>>) {
+    let entries = |val: Option<&[u8]>| {
+        let mut entries = Vec::new();
+        if let Some(val) = val {
+            for index in indexes {
+                for index_key in (index.extract)(key, val) {
+                    entries.push(entry_key(&index.name, &index_key, key));
+                }
+            }
+        }
+        entries.sort();
+        entries.dedup();
+        entries
+    };
+
+    let (old, new) = (entries(old), entries(new));
+    let added = new.iter().filter(|e| old.binary_search(e).is_err());
+    let removed = old.iter().filter(|e| new.binary_search(e).is_err());
+    (added.cloned().collect(), removed.cloned().collect())
+}
+
+// Whether the record `key` with value `val` is still found under `index_key` in `index`.
+pub(crate) fn matches(index: &IndexDef, index_key: &[u8], key: &[u8], val: &[u8]) -> bool {
+    (index.extract)(key, val)
+        .iter()
+        .any(|k| k.as_slice() == index_key)
+}
+
+// Checks that the indexes in `options` can be told apart from each other and from the records
+// they index.
+pub(crate) fn validate(options: &Options) -> Result<(), StoreError> {
+    for (i, index) in options.indexes.iter().enumerate() {
+        if index.name.is_empty() || index.name.as_bytes().contains(&0) {
+            return Err(StoreError::InvalidArgument(format!(
+                "index name {:?} must be non-empty and must not contain NUL",
+                index.name
+            )));
+        }
+        if options.indexes[..i].iter().any(|o| o.name == index.name) {
+            return Err(StoreError::InvalidArgument(format!(
+                "index {:?} is defined more than once",
+                index.name
+            )));
+        }
+    }
+    Ok(())
+}
+
+// Checks that `key` isn't one the store's indexes keep their entries under.
+pub(crate) fn validate_key(options: &Options, key: &[u8]) -> Result<(), StoreError> {
+    if !options.indexes.is_empty() && key.starts_with(INDEX_PREFIX) {
+        return Err(StoreError::InvalidArgument(
+            "keys starting with the index prefix are reserved".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+// Whether the range deleted by `delete_prefix(prefix)` would cover any index entries.
+pub(crate) fn overlaps_entries(prefix: &[u8]) -> bool {
+    let entries_end = prefix_end(INDEX_PREFIX).expect("index prefix must have an end");
+    prefix < entries_end.as_slice()
+        && prefix_end(prefix).is_none_or(|end| INDEX_PREFIX < end.as_slice())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(_key: &[u8], val: &[u8]) -> Vec<Vec<u8>> {
+        val.split(|b| *b == b' ').map(<[u8]>::to_vec).collect()
+    }
+
+    #[test]
+    fn test_changes() {
+        let indexes = vec![IndexDef::new("words", words)];
+
+        let (added, removed) = changes(&indexes, b"key", None, Some(b"a b a"));
+        assert_eq!(
+            vec![
+                entry_key("words", b"a", b"key"),
+                entry_key("words", b"b", b"key")
+            ],
+            added
+        );
+        assert!(removed.is_empty());
+
+        // Only the index keys that differ between the values are touched.
+        let (added, removed) = changes(&indexes, b"key", Some(b"a b"), Some(b"b c"));
+        assert_eq!(vec![entry_key("words", b"c", b"key")], added);
+        assert_eq!(vec![entry_key("words", b"a", b"key")], removed);
+
+        let (added, removed) = changes(&indexes, b"key", Some(b"a"), None);
+        assert!(added.is_empty());
+        assert_eq!(vec![entry_key("words", b"a", b"key")], removed);
+    }
+
+    #[test]
+    fn test_entry_keys() {
+        // An index key that is a prefix of another doesn't share its entries.
+        let short = entry_prefix("words", b"ab");
+        assert!(entry_key("words", b"ab", b"key").starts_with(&short));
+        assert!(!entry_key("words", b"abc", b"key").starts_with(&short));
+
+        assert!(overlaps_entries(b"\xff"));
+        assert!(overlaps_entries(INDEX_PREFIX));
+        assert!(overlaps_entries(&entry_prefix("words", b"ab")));
+        assert!(!overlaps_entries(b"a"));
+        assert!(!overlaps_entries(b"\xff\xfe"));
+    }
+}
@@ -0,0 +1,71 @@
+// Export and import of a store's live records as JSON Lines, one object per record:
+//
+//      {"key":"a2V5MQ==","value":"dmFsMQ=="}
+//
+// Keys and values are both written with the chosen encoding, and must be read back with the same
+// one.
+
+use std::io::{BufRead, Write};
+
+use serde_json::{json, Value};
+
+use crate::{encoding::KeyEncoding, store::Store, StoreError};
+
+impl Store {
+    // Writes every live record in key order, returning how many were written.
+    pub fn export_jsonl<W: Write>(
+        &self,
+        mut w: W,
+        encoding: KeyEncoding,
+    ) -> Result<u64, StoreError> {
+        let mut exported = 0;
+
+        for record in self.scan(b"", None)? {
+            let (key, val) = record?;
+            let line = json!({
+                "key": encoding.encode(&key),
+                "value": encoding.encode(&val),
+            });
+            writeln!(w, "{}", line)?;
+            exported += 1;
+        }
+
+        w.flush()?;
+        Ok(exported)
+    }
+
+    // Puts every record read from lines written by `export_jsonl`, returning how many were
+    // imported. Blank lines are ignored. Records before a malformed line have already been written
+    // when its error is returned.
+    pub fn import_jsonl<R: BufRead>(
+        &mut self,
+        r: R,
+        encoding: KeyEncoding,
+    ) -> Result<u64, StoreError> {
+        let mut imported = 0;
+
+        for (i, line) in r.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let invalid =
+                |detail: String| StoreError::InvalidArgument(format!("line {}: {}", i + 1, detail));
+
+            let object: Value = serde_json::from_str(&line).map_err(|e| invalid(e.to_string()))?;
+            let field = |name: &str| -> Result<Vec<u8>, StoreError> {
+                let s = object
+                    .get(name)
+                    .and_then(Value::as_str)
+                    .ok_or_else(|| invalid(format!("missing string field \"{}\"", name)))?;
+                encoding.decode(s).map_err(invalid)
+            };
+
+            self.put(&field("key")?, &field("value")?)?;
+            imported += 1;
+        }
+
+        Ok(imported)
+    }
+}
@@ -1,18 +1,110 @@
-use std::{error::Error, fmt, io};
+use std::{error::Error, fmt, io, path};
 
+#[cfg(feature = "async")]
+pub mod async_store;
+pub mod batch;
+pub mod blob;
+pub mod checksum;
+pub mod clock;
+pub mod codec;
 pub mod compactor;
+mod context;
+mod durable;
+pub mod encoding;
+pub mod env;
+#[cfg(feature = "http")]
+pub mod http;
+pub mod identity;
+pub mod indexing;
+#[cfg(feature = "serde_json")]
+pub mod jsonl;
 pub mod memtable;
+pub mod options;
 pub mod protocol;
+pub mod recovery;
+mod replace;
+pub mod scan;
+pub mod scrub;
+pub mod snapshot;
 pub mod sst;
+pub mod stats;
 pub mod store;
+pub mod tombstone;
 pub mod wal;
 
+pub use scan::merge_scan;
+
 #[derive(Debug)]
 pub enum StoreError {
     WalRecovery(io::Error),
     WalConversion(io::Error),
     WalInitialization(io::Error),
     CatalogInitialization(io::Error),
+    Wal(io::Error),
+    Read {
+        path: path::PathBuf,
+        source: io::Error,
+    },
+    Flush {
+        path: path::PathBuf,
+        source: io::Error,
+    },
+    Compaction {
+        inputs: Vec<path::PathBuf>,
+        source: io::Error,
+    },
+    // A record or table on disk could not be decoded. The offset is the byte offset into the file
+    // where the bad data was found.
+    Corruption {
+        path: path::PathBuf,
+        offset: u64,
+        detail: String,
+    },
+    // The store was written with a newer format than this build understands.
+    UnsupportedFormat {
+        path: path::PathBuf,
+        version: u32,
+    },
+    // A table in the catalog no longer exists on disk, so its data may be lost. Keys in other tables
+    // can still be read.
+    MissingTable {
+        path: path::PathBuf,
+    },
+    // The directory a store was opened in has things in it that a store wouldn't have written, so
+    // it probably isn't a store, and nothing is written to it.
+    NotAStore {
+        path: path::PathBuf,
+        detail: String,
+    },
+    // The store was asked to change while writes are frozen, see Store::freeze_writes.
+    Frozen,
+    Io(io::Error),
+    InvalidArgument(String),
+}
+
+impl StoreError {
+    // Classifies an error from reading a file: failures to decode its contents are corruption, a
+    // file that doesn't exist is missing, and anything else is reported as a plain read error.
+    pub(crate) fn from_read(path: &path::Path, offset: u64, err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::NotFound => Self::MissingTable { path: path.into() },
+            io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => Self::Corruption {
+                path: path.into(),
+                offset,
+                detail: err.to_string(),
+            },
+            _ => Self::Read {
+                path: path.into(),
+                source: err,
+            },
+        }
+    }
+}
+
+impl From<io::Error> for StoreError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
 }
 
 impl fmt::Display for StoreError {
@@ -24,6 +116,50 @@ impl fmt::Display for StoreError {
             }
             Self::WalInitialization(_) => write!(f, "Failed to create new WAL file."),
             Self::CatalogInitialization(_) => write!(f, "Failed to initialized SST catalog."),
+            Self::Wal(_) => write!(f, "Failed to write to WAL file."),
+            Self::Read { path, .. } => write!(f, "Failed to read from {}.", path.display()),
+            Self::Flush { path, .. } => {
+                write!(f, "Failed to flush memtable to {}.", path.display())
+            }
+            Self::Compaction { inputs, .. } => {
+                write!(f, "Failed to compact {} tables:", inputs.len())?;
+                for input in inputs {
+                    write!(f, " {}", input.display())?;
+                }
+                write!(f, ".")
+            }
+            Self::Corruption {
+                path,
+                offset,
+                detail,
+            } => write!(
+                f,
+                "Corrupt data in {} at offset {}: {}.",
+                path.display(),
+                offset,
+                detail
+            ),
+            Self::UnsupportedFormat { path, version } => write!(
+                f,
+                "Failed to open store: {} has format version {}, but only versions up to {} are supported.",
+                path.display(),
+                version,
+                identity::FORMAT_VERSION
+            ),
+            Self::MissingTable { path } => write!(
+                f,
+                "Table {} is missing, so some data may have been lost.",
+                path.display()
+            ),
+            Self::NotAStore { path, detail } => write!(
+                f,
+                "{} does not look like a crucible store: {}.",
+                path.display(),
+                detail
+            ),
+            Self::Frozen => write!(f, "The store is frozen for writes."),
+            Self::Io(_) => write!(f, "I/O error."),
+            Self::InvalidArgument(detail) => write!(f, "Invalid argument: {}.", detail),
         }
     }
 }
@@ -35,6 +171,17 @@ impl Error for StoreError {
             Self::WalConversion(err) => Some(err),
             Self::WalInitialization(err) => Some(err),
             Self::CatalogInitialization(err) => Some(err),
+            Self::Wal(err) => Some(err),
+            Self::Read { source, .. } => Some(source),
+            Self::Flush { source, .. } => Some(source),
+            Self::Compaction { source, .. } => Some(source),
+            Self::Corruption { .. } => None,
+            Self::UnsupportedFormat { .. } => None,
+            Self::MissingTable { .. } => None,
+            Self::NotAStore { .. } => None,
+            Self::Frozen => None,
+            Self::Io(err) => Some(err),
+            Self::InvalidArgument(_) => None,
         }
     }
 
@@ -42,3 +189,46 @@ impl Error for StoreError {
         self.source()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_store_error_display_and_source() {
+        let err = StoreError::Flush {
+            path: path::PathBuf::from("data/0/3.sst"),
+            source: io::Error::other("disk full"),
+        };
+        assert_eq!("Failed to flush memtable to data/0/3.sst.", err.to_string());
+        assert_eq!("disk full", err.source().unwrap().to_string());
+
+        let err = StoreError::Compaction {
+            inputs: vec![
+                path::PathBuf::from("data/0/1.sst"),
+                path::PathBuf::from("data/1/a.sst"),
+            ],
+            source: io::Error::other("disk full"),
+        };
+        assert_eq!(
+            "Failed to compact 2 tables: data/0/1.sst data/1/a.sst.",
+            err.to_string()
+        );
+        assert!(err.source().is_some());
+
+        let err = StoreError::Corruption {
+            path: path::PathBuf::from("data/1/a.sst"),
+            offset: 42,
+            detail: "invalid op byte 7".to_string(),
+        };
+        assert_eq!(
+            "Corrupt data in data/1/a.sst at offset 42: invalid op byte 7.",
+            err.to_string()
+        );
+        assert!(err.source().is_none());
+
+        let err: StoreError = io::Error::new(io::ErrorKind::NotFound, "gone").into();
+        assert!(matches!(err, StoreError::Io(_)));
+        assert_eq!("gone", err.source().unwrap().to_string());
+    }
+}
@@ -1,29 +1,67 @@
-use std::collections::{hash_map, HashMap};
+use std::{
+    collections::{btree_map, BTreeMap},
+    iter,
+    ops::Bound,
+    slice,
+};
 
-use crate::protocol::{ReadRecord, WriteRecord};
+use crate::{
+    blob::BlobRef,
+    protocol::{ReadRecord, WriteRecord},
+    tombstone::{self, RangeTombstone},
+};
 
+#[derive(Clone)]
+enum Value {
+    Inline(Vec<u8>),
+    // A value kept in a blob file, which the memtable only holds a reference to.
+    Blob(BlobRef),
+}
+
+impl Value {
+    fn to_record<'a>(&'a self, key: &'a [u8]) -> WriteRecord<'a> {
+        match self {
+            Value::Inline(val) => WriteRecord::Exists { key, val },
+            Value::Blob(blob) => WriteRecord::Blob { key, blob: *blob },
+        }
+    }
+}
+
+#[derive(Default, Clone)]
 pub struct MemTable {
-    // An entry that is present in the HashMap with a value of None represents a specific deletion
-    // record.
-    data: HashMap<Vec<u8>, Option<Vec<u8>>>,
+    // An entry that is present in the map with a value of None represents a specific deletion
+    // record. Keys are kept in sorted order so that ranges can be scanned.
+    data: BTreeMap<Vec<u8>, Option<Value>>,
+    // Every record in the map for a key covered by one of these was written after it.
+    range_deletions: Vec<RangeTombstone>,
 }
 
 impl MemTable {
     pub fn new() -> Self {
         MemTable {
-            data: HashMap::new(),
+            data: BTreeMap::new(),
+            range_deletions: Vec::new(),
         }
     }
 
     pub fn put(&mut self, key: &[u8], val: &[u8]) {
-        self.data.insert(key.to_vec(), Some(val.to_vec()));
+        self.data
+            .insert(key.to_vec(), Some(Value::Inline(val.to_vec())));
+    }
+
+    pub fn put_blob(&mut self, key: &[u8], blob: BlobRef) {
+        self.data.insert(key.to_vec(), Some(Value::Blob(blob)));
     }
 
+    // Values kept in blob files aren't in the memtable, so `lookup` is needed to find those.
     pub fn get(&self, key: &[u8]) -> Option<&Vec<u8>> {
         if let Some(val) = self.data.get(key) {
-            // HashMap contains a record for this key, but the value might still be None if it was
-            // from a deletion.
-            val.as_ref()
+            // Map contains a record for this key, but the value might still be None if it was from
+            // a deletion.
+            match val {
+                Some(Value::Inline(val)) => Some(val),
+                Some(Value::Blob(_)) | None => None,
+            }
         } else {
             // No record for this key. We have no knowledge of it ever existing or having been
             // deleted.
@@ -31,13 +69,73 @@ impl MemTable {
         }
     }
 
+    // Unlike `get`, distinguishes between a key that was deleted and a key that the memtable has no
+    // record of. A deletion must shadow any older value for the key in the SSTs.
+    pub fn lookup(&self, key: &[u8]) -> Option<WriteRecord<'_>> {
+        self.data.get_key_value(key).map(|(key, val)| match val {
+            Some(val) => val.to_record(key),
+            None => WriteRecord::Deleted { key },
+        })
+    }
+
     pub fn del(&mut self, key: &[u8]) {
         self.data.insert(key.to_vec(), None);
     }
+
+    // Makes the change that `rec` records, as recovering it from a WAL would.
+    pub fn apply(&mut self, rec: &WriteRecord) {
+        match rec {
+            WriteRecord::Exists { key, val } => self.put(key, val),
+            WriteRecord::Deleted { key } => self.del(key),
+            WriteRecord::RangeDeleted { start, end } => self.delete_range(start, *end),
+            WriteRecord::Blob { key, blob } => self.put_blob(key, *blob),
+        }
+    }
+
+    // Deletes every key in [start, end), or from start onward if there is no end. Records already in
+    // the memtable for those keys are older than the deletion, so they are dropped rather than
+    // shadowed. Keys written afterwards are recorded as usual and take precedence over it.
+    pub fn delete_range(&mut self, start: &[u8], end: Option<&[u8]>) {
+        let mut covered = self.data.split_off(start);
+        if let Some(end) = end {
+            self.data.append(&mut covered.split_off(end));
+        }
+
+        self.range_deletions.push(RangeTombstone {
+            start: start.to_vec(),
+            end: end.map(|end| end.to_vec()),
+        });
+    }
+
+    // Whether a range deletion covers a key. This only applies if `lookup` finds no record for the
+    // key, since any such record was written after the deletion.
+    pub fn range_deleted(&self, key: &[u8]) -> bool {
+        tombstone::covered(&self.range_deletions, key)
+    }
+
+    pub fn range_deletions(&self) -> &[RangeTombstone] {
+        &self.range_deletions
+    }
+
+    // The number of keys with a record, not counting range deletions.
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty() && self.range_deletions.is_empty()
+    }
+
+    // Records with keys in the range, in ascending key order.
+    pub fn range<'a>(&'a self, start: Bound<&[u8]>, end: Bound<&[u8]>) -> Iter<'a> {
+        Iter {
+            inner: self.data.range::<[u8], _>((start, end)),
+        }
+    }
 }
 
 pub struct Iter<'a> {
-    inner: hash_map::Iter<'a, Vec<u8>, Option<Vec<u8>>>,
+    inner: btree_map::Range<'a, Vec<u8>, Option<Value>>,
 }
 
 impl<'a> Iterator for Iter<'a> {
@@ -45,20 +143,25 @@ impl<'a> Iterator for Iter<'a> {
 
     fn next(&mut self) -> Option<Self::Item> {
         self.inner.next().map(|(key, val)| match val {
-            Some(val) => WriteRecord::Exists { key, val },
+            Some(val) => val.to_record(key),
             None => WriteRecord::Deleted { key },
         })
     }
 }
 
+type RangeDeletionIter<'a> =
+    iter::Map<slice::Iter<'a, RangeTombstone>, fn(&'a RangeTombstone) -> WriteRecord<'a>>;
+
+// Every record in the memtable in key order, followed by its range deletions.
 impl<'a> IntoIterator for &'a MemTable {
     type Item = WriteRecord<'a>;
-    type IntoIter = Iter<'a>;
+    type IntoIter = iter::Chain<Iter<'a>, RangeDeletionIter<'a>>;
 
     fn into_iter(self) -> Self::IntoIter {
-        Iter {
-            inner: self.data.iter(),
-        }
+        let range_deletions: RangeDeletionIter<'a> =
+            self.range_deletions.iter().map(RangeTombstone::to_record);
+        self.range(Bound::Unbounded, Bound::Unbounded)
+            .chain(range_deletions)
     }
 }
 
@@ -67,10 +170,7 @@ impl FromIterator<ReadRecord> for MemTable {
         let mut out = MemTable::new();
 
         for i in iter {
-            match i {
-                ReadRecord::Exists { key, val } => out.put(&key, &val),
-                ReadRecord::Deleted { key } => out.del(&key),
-            }
+            out.apply(&WriteRecord::from(&i));
         }
 
         out
@@ -0,0 +1,793 @@
+use std::{fmt, path, sync::Arc, time::Duration};
+
+use crate::{
+    blob::BLOB_REF_LENGTH,
+    clock::{Clock, SystemClock},
+    codec::{Codecs, ValueCodec},
+    compactor::scheduler::CompactionScheduler,
+    env::Env,
+    indexing::{self, IndexDef},
+    sst::TableFormat,
+    StoreError,
+};
+
+const WAL_SIZE_LIMIT: u32 = 4 * 1024 * 1024;
+const TABLE_SIZE_LIMIT: usize = 4 * 1024 * 1024;
+const LEVEL_0_FILE_LIMIT: usize = 5;
+const SMALL_TABLE_MERGE_THRESHOLD: usize = 4;
+const MAX_KEY_SIZE: usize = 64 * 1024;
+const MAX_VALUE_SIZE: usize = 64 * 1024 * 1024;
+const WRITE_QUEUE_DEPTH: usize = 64;
+const VERSIONS_TO_KEEP: usize = 1;
+const READ_COMPACTION_PREFIX_LENGTH: usize = 8;
+// Split keys are kept in the footer, which is read whenever a table is opened.
+const MAX_TABLE_SPLIT_POINTS: usize = 256;
+
+// Record lengths are stored as u32, and a record plus its 9 byte header must fit in a WAL whose size
+// is tracked as a u32.
+const FORMAT_MAX_RECORD_SIZE: usize = u32::MAX as usize - 9;
+
+// How compaction chooses which level 1 tables to rewrite along with level 0.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CompactionInputs {
+    // Every level 1 table overlapping the range from the smallest to the largest key in level 0.
+    Overlapping,
+    // Only the level 1 tables that overlap a level 0 table. Tables that fall in a gap between level
+    // 0 tables are left alone, which reduces the bytes rewritten when level 0 writes are clustered.
+    #[default]
+    Minimal,
+}
+
+// Where automatic compaction runs once a flush makes one due.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CompactionMode {
+    // On the thread that flushed the memtable, before the flush returns. No thread is ever spawned,
+    // so this works where spawning isn't allowed.
+    #[default]
+    Inline,
+    // On a thread of the store's own, or of its Env, so that writes carry on while it runs. The store
+    // picks up the new tables at its next write or flush; reads until then see the tables from
+    // before.
+    Background,
+}
+
+// Whether writes survive a crash before they are flushed to a table.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Durability {
+    // Every write is appended to the WAL and synced before it returns, and the WAL is recovered
+    // when the store is next opened.
+    #[default]
+    Wal,
+    // Writes only go to the memtable, which is flushed to a table as usual once it is as large as
+    // the WAL would have been. Anything not yet flushed is lost in a crash, so this suits data that
+    // can be rebuilt from elsewhere.
+    None,
+}
+
+// How much damage to tolerate when opening a store.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RecoveryMode {
+    // Fail on any anomaly.
+    #[default]
+    Strict,
+    // Allow the final record of the WAL to be truncated, as it would be after a crash part way
+    // through writing it. The truncated record is dropped.
+    TolerateCorruptTail,
+    // Additionally skip tables that can't be opened, and records that can't be read, noting
+    // everything skipped in the store's recovery report.
+    BestEffort,
+}
+
+// Called with the path of each WAL segment as it is archived.
+#[derive(Clone)]
+pub(crate) struct WalArchiveHook(pub(crate) Arc<dyn Fn(&path::Path) + Send + Sync>);
+
+impl fmt::Debug for WalArchiveHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("WalArchiveHook")
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct ScrubHook(pub(crate) Arc<dyn Fn(&StoreError) + Send + Sync>);
+
+impl fmt::Debug for ScrubHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ScrubHook")
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct SchedulerHook(pub(crate) Arc<dyn CompactionScheduler>);
+
+impl fmt::Debug for SchedulerHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SchedulerHook")
+    }
+}
+
+// Configuration for opening a store. Options are set with builder methods, starting from the
+// defaults:
+//
+//      let options = Options::default().wal_size_limit(1024 * 1024).max_key_size(256);
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub(crate) wal_size_limit: u32,
+    pub(crate) table_size_limit: usize,
+    pub(crate) level_0_file_limit: usize,
+    pub(crate) level_0_rearm_limit: Option<usize>,
+    pub(crate) max_key_size: usize,
+    pub(crate) max_value_size: usize,
+    pub(crate) compaction_inputs: CompactionInputs,
+    pub(crate) compaction_mode: CompactionMode,
+    pub(crate) small_table_merge_threshold: usize,
+    pub(crate) level_0_merge_threshold: usize,
+    pub(crate) wal_archive: Option<WalArchiveHook>,
+    pub(crate) recovery_mode: RecoveryMode,
+    pub(crate) clock: Arc<dyn Clock>,
+    pub(crate) write_queue_depth: usize,
+    pub(crate) migrate_legacy_tables: bool,
+    pub(crate) versions_to_keep: usize,
+    pub(crate) min_blob_size: Option<usize>,
+    pub(crate) inline_value_size: Option<usize>,
+    pub(crate) durability: Durability,
+    pub(crate) verify_files_on_open: bool,
+    pub(crate) prefix_bloom_length: Option<usize>,
+    pub(crate) env: Option<Env>,
+    pub(crate) max_sst_file_size: Option<usize>,
+    pub(crate) wal_dir: Option<path::PathBuf>,
+    pub(crate) cold_dir: Option<path::PathBuf>,
+    pub(crate) memtable_flush_after: Option<Duration>,
+    pub(crate) obsolete_table_grace: Option<Duration>,
+    pub(crate) indexes: Vec<IndexDef>,
+    pub(crate) read_compaction_threshold: Option<usize>,
+    pub(crate) read_compaction_prefix_length: usize,
+    pub(crate) table_split_points: Option<usize>,
+    pub(crate) explain_compactions: Option<usize>,
+    pub(crate) scrub_rate: Option<u64>,
+    pub(crate) scrub_hook: Option<ScrubHook>,
+    pub(crate) compaction_scheduler: Option<SchedulerHook>,
+    pub(crate) codecs: Codecs,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            wal_size_limit: WAL_SIZE_LIMIT,
+            table_size_limit: TABLE_SIZE_LIMIT,
+            level_0_file_limit: LEVEL_0_FILE_LIMIT,
+            level_0_rearm_limit: None,
+            max_key_size: MAX_KEY_SIZE,
+            max_value_size: MAX_VALUE_SIZE,
+            compaction_inputs: CompactionInputs::default(),
+            compaction_mode: CompactionMode::default(),
+            small_table_merge_threshold: SMALL_TABLE_MERGE_THRESHOLD,
+            level_0_merge_threshold: 0,
+            wal_archive: None,
+            recovery_mode: RecoveryMode::default(),
+            clock: Arc::new(SystemClock::default()),
+            write_queue_depth: WRITE_QUEUE_DEPTH,
+            migrate_legacy_tables: false,
+            versions_to_keep: VERSIONS_TO_KEEP,
+            min_blob_size: None,
+            inline_value_size: None,
+            durability: Durability::default(),
+            verify_files_on_open: false,
+            prefix_bloom_length: None,
+            env: None,
+            max_sst_file_size: None,
+            wal_dir: None,
+            cold_dir: None,
+            memtable_flush_after: None,
+            obsolete_table_grace: None,
+            indexes: Vec::new(),
+            read_compaction_threshold: None,
+            read_compaction_prefix_length: READ_COMPACTION_PREFIX_LENGTH,
+            table_split_points: None,
+            explain_compactions: None,
+            scrub_rate: None,
+            scrub_hook: None,
+            compaction_scheduler: None,
+            codecs: Codecs::default(),
+        }
+    }
+}
+
+// Presets that set the options for a kind of workload together, starting from the defaults. Any
+// option can still be set afterwards, such as `Options::small_footprint().wal_size_limit(..)`.
+impl Options {
+    // For reads of single keys. Gets check every level 0 table that may have the key, so level 0
+    // is compacted once it has 2 tables rather than 5, and key ranges whose gets still probe more
+    // than 2 tables are compacted too, see read_compaction_threshold. Both mean more compaction,
+    // so writes cost more.
+    //
+    // The store has no cache of its own: Indexes are held in memory, and the OS caches the rest.
+    // `cache_bytes` is the memory that may go to sparing gets a read from a table, which is spent
+    // on inlining values in the indexes, at a rule of thumb of 1 byte of value for every 1 MiB,
+    // from 16 up to 4096 bytes. See inline_value_size, which stops the tables from being read by
+    // versions of the store from before it existed.
+    pub fn optimized_for_point_lookups(cache_bytes: usize) -> Self {
+        Options::default()
+            .level_0_file_limit(2)
+            .read_compaction_threshold(2)
+            .inline_value_size((cache_bytes / (1024 * 1024)).clamp(16, 4096))
+    }
+
+    // For loading a lot of data at once. The WAL and tables are 64 MiB rather than 4 MiB, so there
+    // are fewer flushes and tables to compact, and compactions run in the background, so writes
+    // carry on while they do. Level 0 can grow to 8 tables, with runs of 4 small ones merged
+    // cheaply in the meantime, and once compacted isn't compacted again until it is below 4. Gets
+    // may check more tables until the load is done and compacted, see Store::compact, and up to
+    // 64 MiB of writes are recovered from the WAL after a crash.
+    pub fn optimized_for_bulk_ingest() -> Self {
+        Options::default()
+            .wal_size_limit(64 * 1024 * 1024)
+            .table_size_limit(64 * 1024 * 1024)
+            .level_0_file_limit(8)
+            .level_0_rearm_limit(4)
+            .level_0_merge_threshold(4)
+            .compaction_mode(CompactionMode::Background)
+    }
+
+    // For keeping memory and disk use down. The WAL, and so the memtable, and tables are 1 MiB
+    // rather than 4 MiB, level 0 is compacted at 2 tables, and runs of 2 small level 1 tables are
+    // merged, so that overwritten values are dropped sooner. Values are compressed with Lz4 when
+    // the lz4 feature is on, which stops the store from being read by versions from before value
+    // codecs existed. More, smaller flushes and compactions mean writes cost more.
+    pub fn small_footprint() -> Self {
+        let options = Options::default()
+            .wal_size_limit(1024 * 1024)
+            .table_size_limit(1024 * 1024)
+            .level_0_file_limit(2)
+            .small_table_merge_threshold(2);
+        #[cfg(feature = "lz4")]
+        let options = options.value_codec(crate::codec::Lz4);
+        options
+    }
+}
+
+impl Options {
+    // The memtable is flushed once the WAL grows beyond this many bytes.
+    pub fn wal_size_limit(mut self, bytes: u32) -> Self {
+        self.wal_size_limit = bytes;
+        self
+    }
+
+    // Target size in bytes of the tables produced by compaction.
+    pub fn table_size_limit(mut self, bytes: usize) -> Self {
+        self.table_size_limit = bytes;
+        self
+    }
+
+    // No table, whether flushed or written by a compaction, is ever larger than this, counting its
+    // index and footer. A flush or compaction moves on to a new table rather than go past it. It
+    // must leave room for a table of a single record of the largest allowed key and value. The
+    // only tables that can still be larger are those holding more than one version of a key, see
+    // `versions_to_keep`, which are never split, or a large number of range deletions.
+    pub fn max_sst_file_size(mut self, bytes: usize) -> Self {
+        self.max_sst_file_size = Some(bytes);
+        self
+    }
+
+    // Level 0 is compacted into level 1 once it has this many tables.
+    pub fn level_0_file_limit(mut self, tables: usize) -> Self {
+        self.level_0_file_limit = tables;
+        self
+    }
+
+    // Once level_0_file_limit has set off a compaction, it doesn't again until level 0 has dropped
+    // below this many tables. Tables flushed while a compaction runs are left in level 0, and
+    // without this a steady stream of writes can bring it straight back to the limit, so that
+    // compactions run back to back. It must be at least 1 and at most level_0_file_limit. Without
+    // it, level 0 is compacted whenever it is at the limit.
+    pub fn level_0_rearm_limit(mut self, tables: usize) -> Self {
+        self.level_0_rearm_limit = Some(tables);
+        self
+    }
+
+    // Writes with keys longer than this many bytes are rejected.
+    pub fn max_key_size(mut self, bytes: usize) -> Self {
+        self.max_key_size = bytes;
+        self
+    }
+
+    // Writes with values longer than this many bytes are rejected.
+    pub fn max_value_size(mut self, bytes: usize) -> Self {
+        self.max_value_size = bytes;
+        self
+    }
+
+    // Strategy for selecting level 1 tables to include when compacting level 0.
+    pub fn compaction_inputs(mut self, inputs: CompactionInputs) -> Self {
+        self.compaction_inputs = inputs;
+        self
+    }
+
+    // Whether automatic compaction runs inline or on a background thread.
+    pub fn compaction_mode(mut self, mode: CompactionMode) -> Self {
+        self.compaction_mode = mode;
+        self
+    }
+
+    // Keep each WAL segment once its records have been flushed, rather than overwriting it. Segments
+    // are renamed to "data.wal.N", with N increasing, and `hook` is called with the new path so that
+    // they can be shipped elsewhere. The store never deletes archived segments, and they are not
+    // read during recovery.
+    pub fn archive_wal<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&path::Path) + Send + Sync + 'static,
+    {
+        self.wal_archive = Some(WalArchiveHook(Arc::new(hook)));
+        self
+    }
+
+    // Check the store's tables for damage in the background, reading them through on a thread of
+    // the store's own at no more than `bytes_per_second`, see Store::scrub_now. A pass over every
+    // table starts when the store is opened, and another a minute after each finishes.
+    pub fn scrub_rate(mut self, bytes_per_second: u64) -> Self {
+        self.scrub_rate = Some(bytes_per_second);
+        self
+    }
+
+    // Call `hook` with what was found wrong with each damaged table a scrub comes across, from
+    // whichever thread made the scrub. The store carries on as it was.
+    pub fn on_scrub_error<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&StoreError) + Send + Sync + 'static,
+    {
+        self.scrub_hook = Some(ScrubHook(Arc::new(hook)));
+        self
+    }
+
+    // Keep the WAL, and any segments archived from it, in `dir` rather than in the data directory,
+    // so that it can be on a different device from the tables. The directory is created if need be,
+    // and must not be inside the data directory or shared with another store. A store that was last
+    // opened without this has its unflushed writes recovered from the data directory and moved
+    // here. One whose WAL was elsewhere must be opened with that directory first, since its WAL
+    // can't be found otherwise.
+    pub fn wal_dir(mut self, dir: &path::Path) -> Self {
+        self.wal_dir = Some(dir.to_owned());
+        self
+    }
+
+    // Keep the tables of level 1 and deeper in `dir` rather than in the data directory, so that
+    // they can be on a larger, slower device than level 0 and the WAL. Compactions write their
+    // tables there, and level 0 tables move there as they are compacted. The directory is created
+    // if need be, and must not be inside the data directory or shared with another store. Tables
+    // already in the data directory's deeper levels are still found there, and move as compactions
+    // rewrite them, but once a store has tables in `dir` it must always be opened with it.
+    // Store::repair only looks in the directory it is given, so it must be run on each.
+    pub fn cold_dir(mut self, dir: &path::Path) -> Self {
+        self.cold_dir = Some(dir.to_owned());
+        self
+    }
+
+    // Flush the memtable once the oldest write in it has been there for `after`, so that a store
+    // that is written to rarely doesn't keep its writes only in the WAL indefinitely. Time is read
+    // from the store's clock. The check is made on each write and by Store::tick, which a store
+    // with few writes should call now and then; a store with an empty memtable does nothing.
+    pub fn memtable_flush_after(mut self, after: Duration) -> Self {
+        self.memtable_flush_after = Some(after);
+        self
+    }
+
+    // Keep the tables that compactions replace for `grace` before removing them, for readers in
+    // other processes that may still have them open, see sst::Follower. Until then they are set
+    // aside under another name, which takes them out of the store. Readers on the same machine
+    // keep reading a removed table through the handle they already have, but a filesystem shared
+    // between machines may not keep it around for them. Time is read from the store's clock, and
+    // what has expired is removed on each compaction and by Store::tick.
+    pub fn obsolete_table_grace(mut self, grace: Duration) -> Self {
+        self.obsolete_table_grace = Some(grace);
+        self
+    }
+
+    // Keep a secondary index of the store's records, see the indexing module. Each put and del then
+    // reads the key's current value before writing, to find the index entries it replaces. Every
+    // index the store was written with must be given each time it is opened, or its entries fall
+    // behind.
+    pub fn index(mut self, index: IndexDef) -> Self {
+        self.indexes.push(index);
+        self
+    }
+
+    // Compact the tables holding a range of keys once gets in it probe more than `probes` tables
+    // on average, even if no compaction would otherwise be due. With CompactionMode::Background the
+    // compaction runs on the background thread; otherwise it runs on the next flush or call to
+    // Store::tick. Each range is compacted at most once a minute, by the store's clock.
+    pub fn read_compaction_threshold(mut self, probes: usize) -> Self {
+        self.read_compaction_threshold = Some(probes);
+        self
+    }
+
+    // Keep a record of the last `decisions` times the store checked whether an automatic compaction
+    // was due, including the times it wasn't, with the trigger values, tables, and reasons behind
+    // each. See Store::recent_compaction_decisions.
+    pub fn explain_compactions(mut self, decisions: usize) -> Self {
+        self.explain_compactions = Some(decisions);
+        self
+    }
+
+    // How many bytes of a key decide which range it is in, for read_compaction_threshold. Keys
+    // sharing a prefix this long are tracked, and compacted, together.
+    pub fn read_compaction_prefix_length(mut self, bytes: usize) -> Self {
+        self.read_compaction_prefix_length = bytes;
+        self
+    }
+
+    // Adjacent level 1 tables that are each less than half of table_size_limit are merged once
+    // there is a run of at least this many of them. Values below 2 disable merging small tables.
+    pub fn small_table_merge_threshold(mut self, tables: usize) -> Self {
+        self.small_table_merge_threshold = tables;
+        self
+    }
+
+    // Once there is a run of this many adjacent level 0 tables that are each smaller than
+    // table_size_limit, the oldest such run is merged into a single level 0 table, without touching
+    // level 1. During a burst of writes, this keeps the number of tables a read has to check down
+    // more cheaply than compacting into level 1 would. It only takes effect below
+    // level_0_file_limit. Values below 2 disable it, which is the default.
+    pub fn level_0_merge_threshold(mut self, tables: usize) -> Self {
+        self.level_0_merge_threshold = tables;
+        self
+    }
+
+    // Let `scheduler` decide when to compact and what, in place of level_0_file_limit and the other
+    // thresholds above. See the scheduler module.
+    pub fn compaction_scheduler<S: CompactionScheduler + 'static>(mut self, scheduler: S) -> Self {
+        self.compaction_scheduler = Some(SchedulerHook(Arc::new(scheduler)));
+        self
+    }
+
+    // Encode values with `codec` on their way to the WAL and tables, such as to compress or encrypt
+    // them. What was written with another codec, or none, can still be read, as long as that codec
+    // is given to decode_values_with; compaction rewrites it with this one. See the codec module.
+    pub fn value_codec<C: ValueCodec + 'static>(mut self, codec: C) -> Self {
+        self.codecs = self.codecs.with_writer(Arc::new(codec));
+        self
+    }
+
+    // Decode values encoded with `codec`, such as one the store used to be opened with, without
+    // encoding new values with it. Built-in codecs that don't need a key are always known.
+    pub fn decode_values_with<C: ValueCodec + 'static>(mut self, codec: C) -> Self {
+        self.codecs = self.codecs.with_reader(Arc::new(codec));
+        self
+    }
+
+    // What to do about damage found while opening the store.
+    pub fn recovery_mode(mut self, mode: RecoveryMode) -> Self {
+        self.recovery_mode = mode;
+        self
+    }
+
+    // Where the store reads the current time from. Defaults to the system clock.
+    pub fn clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    // How many writes an AsyncStore will queue for its writer before further writes wait for room.
+    pub fn write_queue_depth(mut self, writes: usize) -> Self {
+        self.write_queue_depth = writes;
+        self
+    }
+
+    // Move tables left in the root of the data directory by versions of the store from before it
+    // had levels into level 0 while opening. This is done once, and the old tables are removed.
+    // Without it, a store that still has such tables can't be opened.
+    pub fn migrate_legacy_tables(mut self, migrate: bool) -> Self {
+        self.migrate_legacy_tables = migrate;
+        self
+    }
+
+    // Compaction keeps this many of the newest versions of each key, rather than just the newest,
+    // so that older ones can still be read with `Store::get_versions`. A deletion counts as a
+    // version, and is kept like any other. A range deletion removes every older version of the keys
+    // it covers once it is compacted with them, however many there are. All of a key's versions are
+    // kept in the same table, which may take the table past `table_size_limit`.
+    pub fn versions_to_keep(mut self, versions: usize) -> Self {
+        self.versions_to_keep = versions;
+        self
+    }
+
+    // Values of at least this many bytes are kept in blob files, with only a reference to them in
+    // the WAL and tables, so that compaction doesn't rewrite them. Each such value is synced as it
+    // is written. The space of values that are overwritten or deleted is reclaimed by
+    // `Store::collect_blobs`. Off by default, though a store reads values from blob files written
+    // earlier either way.
+    pub fn min_blob_size(mut self, bytes: usize) -> Self {
+        self.min_blob_size = Some(bytes);
+        self
+    }
+
+    // Tables written from now on carry values of at most this many bytes, and deletions, in their
+    // index entries, so that reading them doesn't take a seek into the table. This makes the
+    // indexes, which are held in memory, larger. Tables written this way can't be read by versions
+    // of the store from before the option existed.
+    pub fn inline_value_size(mut self, bytes: usize) -> Self {
+        self.inline_value_size = Some(bytes);
+        self
+    }
+
+    // Whether writes go through the WAL. With Durability::None, a store can't be opened while its
+    // WAL still holds writes from when it was opened with Durability::Wal, since those would be
+    // lost; open it with the WAL first so that they are flushed to a table.
+    pub fn durability(mut self, durability: Durability) -> Self {
+        self.durability = durability;
+        self
+    }
+
+    // Read every table in full while opening the store and check it against its checksum, failing
+    // with StoreError::Corruption naming the first table that doesn't match. This catches
+    // truncated and damaged tables up front, at the cost of reading the whole store. Tables
+    // written before checksums existed aren't checked.
+    pub fn verify_files_on_open(mut self, verify: bool) -> Self {
+        self.verify_files_on_open = verify;
+        self
+    }
+
+    // Tables written from now on end their indexes with a bloom filter of the first `bytes` bytes
+    // of their keys, so that `Store::scan_prefix` can skip tables without any keys starting with a
+    // prefix of at least that length. Prefixes should be about as long as the part of the key that
+    // scans are made over, such as a tenant id. Tables written this way can't be read by versions of
+    // the store from before the option existed.
+    pub fn prefix_bloom_length(mut self, bytes: usize) -> Self {
+        self.prefix_bloom_length = Some(bytes);
+        self
+    }
+
+    // Tables written from now on record, in their footers, the keys that split them into `parts`
+    // runs of about the same number of keys, for example 16 for a key every 1/16th of the table.
+    // See TableInfo::split_keys, which a scan can be divided up by without reading any indexes.
+    // The keys take up space in the footer of every table, so `parts` can be at most 256.
+    pub fn table_split_points(mut self, parts: usize) -> Self {
+        self.table_split_points = Some(parts);
+        self
+    }
+
+    // Run background compactions on the threads of `env`, shared with every other store opened
+    // with it, rather than on a thread of the store's own. Only used with
+    // CompactionMode::Background.
+    pub fn env(mut self, env: Env) -> Self {
+        self.env = Some(env);
+        self
+    }
+
+    // The size of a table holding just a record with the largest key and value allowed. Large
+    // enough values only take up a blob reference.
+    fn smallest_table_limit(&self) -> usize {
+        let value = match self.min_blob_size {
+            Some(min) => self.max_value_size.min((min - 1).max(BLOB_REF_LENGTH)),
+            None => self.max_value_size,
+        };
+        let inline = match self.inline_value_size {
+            Some(limit) => 1 + 4 + value.min(limit),
+            None => 0,
+        };
+
+        TableFormat::new(self).table_size(
+            9 + self.max_key_size + value,
+            4 + 4 + self.max_key_size + inline,
+            1,
+            2 * self.max_key_size,
+            self.max_key_size,
+        )
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), StoreError> {
+        if self.max_key_size == 0 {
+            return Err(StoreError::InvalidArgument(
+                "max_key_size must be at least 1".to_string(),
+            ));
+        }
+
+        if self.write_queue_depth == 0 {
+            return Err(StoreError::InvalidArgument(
+                "write_queue_depth must be at least 1".to_string(),
+            ));
+        }
+
+        if self.versions_to_keep == 0 {
+            return Err(StoreError::InvalidArgument(
+                "versions_to_keep must be at least 1".to_string(),
+            ));
+        }
+
+        if self.durability == Durability::None && self.wal_archive.is_some() {
+            return Err(StoreError::InvalidArgument(
+                "archive_wal requires Durability::Wal".to_string(),
+            ));
+        }
+
+        if self.prefix_bloom_length == Some(0) {
+            return Err(StoreError::InvalidArgument(
+                "prefix_bloom_length must be at least 1".to_string(),
+            ));
+        }
+
+        if let Some(parts) = self.table_split_points {
+            if !(2..=MAX_TABLE_SPLIT_POINTS).contains(&parts) {
+                return Err(StoreError::InvalidArgument(format!(
+                    "table_split_points must be from 2 to {}",
+                    MAX_TABLE_SPLIT_POINTS
+                )));
+            }
+        }
+
+        if let Some(rearm) = self.level_0_rearm_limit {
+            if rearm == 0 || rearm > self.level_0_file_limit {
+                return Err(StoreError::InvalidArgument(
+                    "level_0_rearm_limit must be from 1 to level_0_file_limit".to_string(),
+                ));
+            }
+        }
+
+        if self.scrub_rate == Some(0) {
+            return Err(StoreError::InvalidArgument(
+                "scrub_rate must be at least 1 byte per second".to_string(),
+            ));
+        }
+
+        if self.explain_compactions == Some(0) {
+            return Err(StoreError::InvalidArgument(
+                "explain_compactions must keep at least 1 decision".to_string(),
+            ));
+        }
+
+        if self.read_compaction_prefix_length == 0 {
+            return Err(StoreError::InvalidArgument(
+                "read_compaction_prefix_length must be at least 1".to_string(),
+            ));
+        }
+
+        if self.min_blob_size == Some(0) {
+            return Err(StoreError::InvalidArgument(
+                "min_blob_size must be at least 1".to_string(),
+            ));
+        }
+
+        // Checked first, since smallest_table_limit adds them up.
+        if self
+            .max_key_size
+            .checked_add(self.max_value_size)
+            .is_none_or(|size| size > FORMAT_MAX_RECORD_SIZE)
+        {
+            return Err(StoreError::InvalidArgument(format!(
+                "max_key_size and max_value_size together must not exceed {} bytes",
+                FORMAT_MAX_RECORD_SIZE
+            )));
+        }
+
+        if let Some(max) = self.max_sst_file_size {
+            let smallest = self.smallest_table_limit();
+            if max < smallest {
+                return Err(StoreError::InvalidArgument(format!(
+                    "max_sst_file_size must be at least {} bytes to fit the largest key and value",
+                    smallest
+                )));
+            }
+        }
+
+        let mut ids = self.codecs.ids();
+        ids.sort_unstable();
+        if let Some(w) = ids.windows(2).find(|w| w[0] == w[1]) {
+            return Err(StoreError::InvalidArgument(format!(
+                "more than one value codec has id {}",
+                w[0]
+            )));
+        }
+
+        indexing::validate(self)?;
+
+        Ok(())
+    }
+
+    // Checks that a key (and value, for puts) can be written to the store. Empty keys are not
+    // allowed.
+    pub(crate) fn validate_write(&self, key: &[u8], val: Option<&[u8]>) -> Result<(), StoreError> {
+        if key.is_empty() {
+            return Err(StoreError::InvalidArgument(
+                "key must not be empty".to_string(),
+            ));
+        }
+        indexing::validate_key(self, key)?;
+
+        if key.len() > self.max_key_size {
+            return Err(StoreError::InvalidArgument(format!(
+                "key length {} exceeds the maximum of {} bytes",
+                key.len(),
+                self.max_key_size
+            )));
+        }
+
+        if let Some(val) = val {
+            if val.len() > self.max_value_size {
+                return Err(StoreError::InvalidArgument(format!(
+                    "value length {} exceeds the maximum of {} bytes",
+                    val.len(),
+                    self.max_value_size
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// What `Store::scan_raw` yields besides the live records, set with builder methods like Options.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct IterOptions {
+    pub(crate) include_tombstones: bool,
+    pub(crate) include_metadata: bool,
+}
+
+impl IterOptions {
+    // Yield the deletions of single keys that hide older records, rather than skipping them. Keys
+    // deleted by a range deletion are still skipped.
+    pub fn include_tombstones(mut self, include: bool) -> Self {
+        self.include_tombstones = include;
+        self
+    }
+
+    // Yield where each record was read from, see scan::RecordSource.
+    pub fn include_metadata(mut self, include: bool) -> Self {
+        self.include_metadata = include;
+        self
+    }
+}
+
+// What `Store::warm` reads ahead of time, set with builder methods like Options. Indexes and prefix
+// filters are always in memory once a table is open, so there is nothing to load for them.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WarmOptions {
+    pub(crate) bottom_level: bool,
+    pub(crate) max_bytes: Option<u64>,
+}
+
+impl WarmOptions {
+    // Read the tables of the bottom level, which hold most of the store's data, so that the OS has
+    // them cached before the first reads need them.
+    pub fn bottom_level(mut self, read: bool) -> Self {
+        self.bottom_level = read;
+        self
+    }
+
+    // Read no more than this many bytes in all, so that warming a store larger than the memory
+    // available to cache it doesn't push out what was cached before it.
+    pub fn max_bytes(mut self, bytes: u64) -> Self {
+        self.max_bytes = Some(bytes);
+        self
+    }
+}
+
+// How `Store::merge_from` brings in the records of another store, set with builder methods like
+// Options.
+#[derive(Clone, Debug, Default)]
+pub struct MergeOptions {
+    pub(crate) prefer_source: bool,
+    pub(crate) include_tombstones: bool,
+    pub(crate) wal_dir: Option<path::PathBuf>,
+}
+
+impl MergeOptions {
+    // Which store wins for a key both have a record for. By default the target's record is kept,
+    // whether it is a value or a deletion, as if it were newer than everything in the source. With
+    // `prefer_source`, the source's record replaces it, as if it were newer.
+    pub fn prefer_source(mut self, prefer: bool) -> Self {
+        self.prefer_source = prefer;
+        self
+    }
+
+    // Bring in the source's deletions of single keys as deletions, rather than leaving them out.
+    // Range deletions in the source only hide its own older records, and are never brought in.
+    pub fn include_tombstones(mut self, include: bool) -> Self {
+        self.include_tombstones = include;
+        self
+    }
+
+    // Where the source keeps its WAL, if it is opened with Options::wal_dir.
+    pub fn wal_dir(mut self, dir: &path::Path) -> Self {
+        self.wal_dir = Some(dir.to_owned());
+        self
+    }
+}
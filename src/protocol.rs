@@ -3,112 +3,249 @@ use std::{
     io::{self, Seek, SeekFrom, Write},
 };
 
-const EXISTS_OP_BYTE: u8 = b'0';
-const DELETED_OP_BYTE: u8 = b'1';
-pub const SST_EXT: &'static str = "sst";
+use crate::{
+    blob::{BlobRef, BLOB_REF_LENGTH},
+    checksum::ChecksumWriter,
+    codec::{ValueCodec, IDENTITY_CODEC_ID},
+};
 
-pub enum WriteRecord<'a> {
-    Exists { key: &'a [u8], val: &'a [u8] },
-    Deleted { key: &'a [u8] },
+// The kind of a record, which is the first byte of its header. Every table and WAL has these on
+// disk, so a value must never change or be reused. A new kind of record gets a new value here,
+// which older versions reject as an invalid op byte rather than misread.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Op {
+    Exists = b'0',
+    Deleted = b'1',
+    RangeDeleted = b'2',
+    Blob = b'3',
+    // Only found in a WAL: A group of records written together by Store::write_batch, which are
+    // recovered all together or not at all. The key is empty, and the value is the records one
+    // after the other, see WalEntry.
+    Batch = b'4',
+    // Only found in a WAL: Names the codec the values of the records after it are encoded with,
+    // see ValueCodec. The key is empty, and the value is the codec's 1 byte id.
+    Codec = b'5',
 }
 
-impl<'a> WriteRecord<'a> {
-    pub fn write_to<T: Write>(&self, w: &mut T) -> io::Result<usize> {
-        let mut written = 0;
+impl TryFrom<u8> for Op {
+    type Error = io::Error;
+
+    fn try_from(byte: u8) -> io::Result<Self> {
+        match byte {
+            b'0' => Ok(Op::Exists),
+            b'1' => Ok(Op::Deleted),
+            b'2' => Ok(Op::RangeDeleted),
+            b'3' => Ok(Op::Blob),
+            b'4' => Ok(Op::Batch),
+            b'5' => Ok(Op::Codec),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid op byte {}", byte),
+            )),
+        }
+    }
+}
 
-        let (op_byte, key, val) = match self {
-            WriteRecord::Exists { key, val } => (EXISTS_OP_BYTE, key, Some(val)),
-            WriteRecord::Deleted { key } => (DELETED_OP_BYTE, key, None),
-        };
+pub const SST_EXT: &str = "sst";
 
-        let key_length = key.len() as u32;
-        let val_length = if let Some(val) = val { val.len() } else { 0 } as u32;
+// Footer flag for tables whose index entries carry small values, see Options::inline_value_size.
+pub const INDEX_INLINE_VALUES: u32 = 1;
+// Footer flag for tables whose index ends with a prefix filter, see Options::prefix_bloom_length.
+pub const INDEX_PREFIX_FILTER: u32 = 2;
+// The footer flags hold the id of the codec a table's values are encoded with in their second byte,
+// see ValueCodec. Tables without one aren't encoded.
+pub const INDEX_CODEC_SHIFT: u32 = 8;
 
-        written += w.write(&vec![op_byte])?;
-        written += w.write(&key_length.to_le_bytes())?;
-        written += w.write(&val_length.to_le_bytes())?;
+#[derive(Clone, Copy)]
+pub enum WriteRecord<'a> {
+    Exists {
+        key: &'a [u8],
+        val: &'a [u8],
+    },
+    Deleted {
+        key: &'a [u8],
+    },
+    // Deletes every key in [start, end), or from start onward if there is no end. The end is
+    // written in place of a value, with no end written as an empty value.
+    RangeDeleted {
+        start: &'a [u8],
+        end: Option<&'a [u8]>,
+    },
+    // A value kept in a blob file, see the blob module. The encoded BlobRef is written in place of
+    // the value.
+    Blob {
+        key: &'a [u8],
+        blob: BlobRef,
+    },
+}
 
-        written += w.write(key)?;
-        if let Some(val) = val {
-            written += w.write(&val)?;
+impl<'a> WriteRecord<'a> {
+    pub fn write_to<T: Write>(&self, w: &mut T) -> io::Result<usize> {
+        match self {
+            WriteRecord::Exists { key, val } => write_record(w, Op::Exists, key, Some(val)),
+            WriteRecord::Deleted { key } => write_record(w, Op::Deleted, key, None),
+            WriteRecord::RangeDeleted { start, end } => {
+                write_record(w, Op::RangeDeleted, start, Some(end.unwrap_or_default()))
+            }
+            WriteRecord::Blob { key, blob } => {
+                write_record(w, Op::Blob, key, Some(&blob.to_bytes()))
+            }
         }
-
-        Ok(written)
     }
 
+    // For a range deletion, this is the start of the range.
     pub fn key(&self) -> &[u8] {
         match self {
             WriteRecord::Exists { key, .. } => key,
             WriteRecord::Deleted { key } => key,
+            WriteRecord::RangeDeleted { start, .. } => start,
+            WriteRecord::Blob { key, .. } => key,
+        }
+    }
+
+    // Size as written by `write_to`, including the 9 byte record header, in bytes.
+    pub fn size(&self) -> usize {
+        9 + match self {
+            WriteRecord::Exists { key, val } => key.len() + val.len(),
+            WriteRecord::Deleted { key } => key.len(),
+            WriteRecord::RangeDeleted { start, end } => start.len() + end.map_or(0, <[u8]>::len),
+            WriteRecord::Blob { key, .. } => key.len() + BLOB_REF_LENGTH,
+        }
+    }
+}
+
+impl<'a> From<WriteRecord<'a>> for ReadRecord {
+    fn from(rec: WriteRecord<'a>) -> Self {
+        match rec {
+            WriteRecord::Exists { key, val } => ReadRecord::Exists {
+                key: key.to_vec(),
+                val: val.to_vec(),
+            },
+            WriteRecord::Deleted { key } => ReadRecord::Deleted { key: key.to_vec() },
+            WriteRecord::RangeDeleted { start, end } => ReadRecord::RangeDeleted {
+                start: start.to_vec(),
+                end: end.map(|end| end.to_vec()),
+            },
+            WriteRecord::Blob { key, blob } => ReadRecord::Blob {
+                key: key.to_vec(),
+                blob,
+            },
+        }
+    }
+}
+
+impl<'a> From<&'a ReadRecord> for WriteRecord<'a> {
+    fn from(rec: &'a ReadRecord) -> Self {
+        match rec {
+            ReadRecord::Exists { key, val } => WriteRecord::Exists { key, val },
+            ReadRecord::Deleted { key } => WriteRecord::Deleted { key },
+            ReadRecord::RangeDeleted { start, end } => WriteRecord::RangeDeleted {
+                start,
+                end: end.as_deref(),
+            },
+            ReadRecord::Blob { key, blob } => WriteRecord::Blob { key, blob: *blob },
         }
     }
 }
 
 #[derive(PartialEq, Debug)]
 pub enum ReadRecord {
-    Exists { key: Vec<u8>, val: Vec<u8> },
-    Deleted { key: Vec<u8> },
+    Exists {
+        key: Vec<u8>,
+        val: Vec<u8>,
+    },
+    Deleted {
+        key: Vec<u8>,
+    },
+    RangeDeleted {
+        start: Vec<u8>,
+        end: Option<Vec<u8>>,
+    },
+    Blob {
+        key: Vec<u8>,
+        blob: BlobRef,
+    },
 }
 
 impl ReadRecord {
     pub fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
-        // Big enough for operation, key length, and val length
-        // 1 byte + 4 bytes + 4 bytes
-        let mut buf = [0; 9];
-        reader.read_exact(&mut buf)?;
-
-        let key_length = u32::from_le_bytes(
-            buf[1..5]
-                .try_into()
-                .expect("must convert slice to byte array"),
-        );
-        let mut key = vec![0; key_length as usize];
-        reader.read_exact(&mut key)?;
-
-        match buf[0] {
-            EXISTS_OP_BYTE => {
-                let val_length = u32::from_le_bytes(
-                    buf[5..9]
-                        .try_into()
-                        .expect("must convert slice to byte array"),
-                );
-                let mut val = vec![0; val_length as usize];
-                reader.read_exact(&mut val)?;
-
-                Ok(ReadRecord::Exists { key: key, val: val })
+        let (op, key, val_length) = read_header(reader)?;
+        Self::read_body(reader, op, key, val_length)
+    }
+
+    // Reads the value of a record whose header has been read.
+    fn read_body<R: Read>(
+        reader: &mut R,
+        op: Op,
+        key: Vec<u8>,
+        val_length: u32,
+    ) -> io::Result<Self> {
+        match op {
+            Op::Deleted => Ok(ReadRecord::Deleted { key }),
+            Op::Exists => Ok(ReadRecord::Exists {
+                key,
+                val: read_bytes(reader, val_length)?,
+            }),
+            Op::Blob => Ok(ReadRecord::Blob {
+                key,
+                blob: BlobRef::from_bytes(&read_bytes(reader, val_length)?)?,
+            }),
+            Op::RangeDeleted => {
+                let val = read_bytes(reader, val_length)?;
+                let end = (!val.is_empty()).then_some(val);
+                if end.as_ref().is_some_and(|end| *end <= key) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "range deletion ends before it starts",
+                    ));
+                }
+                Ok(ReadRecord::RangeDeleted { start: key, end })
             }
-            DELETED_OP_BYTE => Ok(ReadRecord::Deleted { key: key }),
-            b => panic!("invalid op byte {}", b),
+            Op::Batch | Op::Codec => Err(not_a_record(op)),
         }
     }
 
-    pub fn write_to<T: Write>(&self, w: &mut T) -> io::Result<usize> {
-        let mut written = 0;
-
-        let (op_byte, key, val) = match self {
-            ReadRecord::Exists { key, val } => (EXISTS_OP_BYTE, key, Some(val)),
-            ReadRecord::Deleted { key } => (DELETED_OP_BYTE, key, None),
-        };
-
-        let key_length = key.len() as u32;
-        let val_length = if let Some(val) = val { val.len() } else { 0 } as u32;
-
-        written += w.write(&vec![op_byte])?;
-        written += w.write(&key_length.to_le_bytes())?;
-        written += w.write(&val_length.to_le_bytes())?;
+    // The record with its value decoded by `codec`. Only values are encoded, so other records are
+    // returned as they are.
+    pub fn decode(self, codec: &dyn ValueCodec) -> io::Result<Self> {
+        match self {
+            ReadRecord::Exists { key, val } => Ok(ReadRecord::Exists {
+                key,
+                val: codec.decode(&val)?,
+            }),
+            record => Ok(record),
+        }
+    }
 
-        written += w.write(key)?;
-        if let Some(val) = val {
-            written += w.write(&val)?;
+    // The record with its value encoded by `codec`, see `decode`.
+    pub fn encode(self, codec: &dyn ValueCodec) -> Self {
+        match self {
+            ReadRecord::Exists { key, val } => ReadRecord::Exists {
+                val: codec.encode(&val),
+                key,
+            },
+            record => record,
         }
+    }
 
-        Ok(written)
+    pub fn write_to<T: Write>(&self, w: &mut T) -> io::Result<usize> {
+        match self {
+            ReadRecord::Exists { key, val } => write_record(w, Op::Exists, key, Some(val)),
+            ReadRecord::Deleted { key } => write_record(w, Op::Deleted, key, None),
+            ReadRecord::RangeDeleted { .. } | ReadRecord::Blob { .. } => {
+                WriteRecord::from(self).write_to(w)
+            }
+        }
     }
 
+    // For a range deletion, this is the start of the range.
     pub fn key(&self) -> &[u8] {
         match self {
             ReadRecord::Exists { key, .. } => key,
             ReadRecord::Deleted { key } => key,
+            ReadRecord::RangeDeleted { start, .. } => start,
+            ReadRecord::Blob { key, .. } => key,
         }
     }
 
@@ -117,15 +254,168 @@ impl ReadRecord {
         9 + match self {
             ReadRecord::Exists { key, val } => key.len() + val.len(),
             ReadRecord::Deleted { key } => key.len(),
+            ReadRecord::RangeDeleted { start, end } => {
+                start.len() + end.as_ref().map_or(0, Vec::len)
+            }
+            ReadRecord::Blob { key, .. } => key.len() + BLOB_REF_LENGTH,
         }
     }
 }
 
-#[derive(Default)]
+// A record read without its value, see KeyRecord::read_from.
+#[derive(PartialEq, Debug)]
+pub struct KeyRecord {
+    pub key: Vec<u8>,
+    // The length of the value, which for a value kept in a blob file is the length in the blob
+    // file. Deletions have no value.
+    pub value_len: u32,
+    pub is_tombstone: bool,
+    size: usize,
+}
+
+impl KeyRecord {
+    // Reads a record's header and key, and seeks past its value rather than reading it. Only a
+    // reference to a blob file is read, since that's where the length of such a value is. Seeking
+    // doesn't check that the value is all there, so a record cut short at the end of the reader
+    // isn't noticed until the next read.
+    pub fn read_from<R: Read + Seek>(reader: &mut R) -> io::Result<Self> {
+        let (op, key, val_length) = read_header(reader)?;
+
+        // Deletions are written without a value, whatever length the header gives.
+        let (value_len, is_tombstone, stored_length) = match op {
+            Op::Deleted => (0, true, 0),
+            Op::Blob => {
+                let blob = BlobRef::from_bytes(&read_bytes(reader, val_length)?)?;
+                (blob.len, false, val_length)
+            }
+            Op::Exists => {
+                reader.seek(SeekFrom::Current(val_length as i64))?;
+                (val_length, false, val_length)
+            }
+            Op::RangeDeleted => {
+                reader.seek(SeekFrom::Current(val_length as i64))?;
+                (0, true, val_length)
+            }
+            Op::Batch | Op::Codec => return Err(not_a_record(op)),
+        };
+
+        Ok(KeyRecord {
+            size: 9 + key.len() + stored_length as usize,
+            key,
+            value_len,
+            is_tombstone,
+        })
+    }
+
+    // Size as read from disk, including the 9 byte record header, in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+}
+
+// What is read from a WAL: Either a single record, a batch of them written together, see
+// Op::Batch, or the id of the codec the values after it are encoded with, see Op::Codec.
+#[derive(PartialEq, Debug)]
+pub enum WalEntry {
+    Record(ReadRecord),
+    Batch(Vec<ReadRecord>),
+    Codec(u8),
+}
+
+impl WalEntry {
+    // Reads an entry, which for a batch means all of its records. A batch cut short is an error like
+    // any other record cut short, so none of its records are read.
+    pub fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let (op, key, val_length) = read_header(reader)?;
+        match op {
+            Op::Batch => (),
+            Op::Codec => {
+                return match read_bytes(reader, val_length)?.as_slice() {
+                    [id] => Ok(WalEntry::Codec(*id)),
+                    _ => Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "a codec's id must be 1 byte",
+                    )),
+                };
+            }
+            _ => return ReadRecord::read_body(reader, op, key, val_length).map(WalEntry::Record),
+        }
+
+        let buf = read_bytes(reader, val_length)?;
+        let mut r = buf.as_slice();
+        let mut records = Vec::new();
+        while !r.is_empty() {
+            // The batch was read whole, so a record running past its end is damage, not a record
+            // cut short by the end of the WAL.
+            let record = ReadRecord::read_from(&mut r).map_err(|e| match e.kind() {
+                io::ErrorKind::UnexpectedEof => io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "record runs past the end of its batch",
+                ),
+                _ => e,
+            })?;
+            records.push(record);
+        }
+        Ok(WalEntry::Batch(records))
+    }
+
+    // Size as read from disk, including the 9 byte header of a batch, in bytes.
+    pub fn size(&self) -> usize {
+        match self {
+            WalEntry::Record(record) => record.size(),
+            WalEntry::Batch(records) => 9 + records.iter().map(ReadRecord::size).sum::<usize>(),
+            WalEntry::Codec(_) => 10,
+        }
+    }
+}
+
+// Writes `records` as a single batch, see Op::Batch. Nothing is written if the batch is too long to
+// be written whole.
+pub fn write_batch<T: Write>(w: &mut T, records: &[WriteRecord]) -> io::Result<usize> {
+    let mut buf = Vec::with_capacity(records.iter().map(WriteRecord::size).sum());
+    for record in records {
+        record.write_to(&mut buf)?;
+    }
+    write_record(w, Op::Batch, b"", Some(&buf))
+}
+
+// Names the codec that the values of the records written after it are encoded with, see Op::Codec.
+pub fn write_codec<T: Write>(w: &mut T, id: u8) -> io::Result<usize> {
+    write_record(w, Op::Codec, b"", Some(&[id]))
+}
+
+// Start key length, end key length, index start, and footer length.
+const MIN_FOOTER_LENGTH: u32 = 16;
+
+#[derive(Clone, Default)]
 pub struct Footer {
     pub start_key: Vec<u8>,
     pub end_key: Vec<u8>,
     pub index_start: u32,
+    // Where the table's range deletions start, if it has any. They follow the rest of the records,
+    // and run up to the index. Tables written before range deletions existed omit this field.
+    pub range_deletions_start: Option<u32>,
+    // The number of keys with records other than range deletions, which is the number of index
+    // entries. Older versions of a key aren't counted. Tables written before it existed omit this
+    // field.
+    pub num_entries: Option<u32>,
+    // The length in bytes of the records other than range deletions, which is where they end. Tables
+    // written before it existed omit this field.
+    pub data_length: Option<u32>,
+    // Flags describing the index, such as INDEX_INLINE_VALUES. Tables written before it existed
+    // omit this field, and have none.
+    pub index_flags: Option<u32>,
+    // Keys that split the table's keys into runs of about the same number of keys, in order, see
+    // Options::table_split_points. Written just before the checksum, and only with one, as a count
+    // followed by each key and its length:
+    //
+    //      [count: u32]([key length: u32][key])...
+    //
+    // Tables written before it existed omit this field, and have none.
+    pub split_keys: Vec<Vec<u8>>,
+    // The CRC-32C of every byte of the file before this field, which is always the last before
+    // footer_length. Set by `write_checksummed`. Tables written before it existed omit this field.
+    pub checksum: Option<u32>,
     // Includes the value for footer_length itself, which is 4 bytes. Will be None will initializing
     // a footer for a new table, but should always be Some(...) when decoding the footer from a
     // table.
@@ -138,38 +428,175 @@ impl Footer {
             ..Default::default()
         };
 
+        let file_length = r.seek(SeekFrom::End(0))?;
+        // The smallest possible footer has two zero-length keys.
+        if file_length < MIN_FOOTER_LENGTH as u64 {
+            return Err(invalid_footer(format!(
+                "file length {} is too short for a footer",
+                file_length
+            )));
+        }
+
         r.seek(SeekFrom::End(-4))?;
 
         let mut buf = [0; 4];
         let footer_length = read_u32(r, &mut buf)?;
+        if footer_length < MIN_FOOTER_LENGTH || footer_length as u64 > file_length {
+            return Err(invalid_footer(format!(
+                "footer length {} is out of range for a file of length {}",
+                footer_length, file_length
+            )));
+        }
         footer.footer_length = Some(footer_length);
 
         r.seek(SeekFrom::End(0 - footer_length as i64))?;
+        // Limit reads to the footer itself so that a corrupt key length can't run past it.
+        let mut r = r.take(footer_length as u64 - 4);
+
+        let start_key_length = read_u32(&mut r, &mut buf)?;
+        footer.start_key = read_bytes(&mut r, start_key_length)?;
 
-        let start_key_length = read_u32(r, &mut buf)?;
-        footer.start_key = vec![0; start_key_length as usize];
-        r.read_exact(&mut footer.start_key)?;
+        let end_key_length = read_u32(&mut r, &mut buf)?;
+        footer.end_key = read_bytes(&mut r, end_key_length)?;
 
-        let end_key_length = read_u32(r, &mut buf)?;
-        footer.end_key = vec![0; end_key_length as usize];
-        r.read_exact(&mut footer.end_key)?;
+        footer.index_start = read_u32(&mut r, &mut buf)?;
+        if footer.index_start as u64 > file_length - footer_length as u64 {
+            return Err(invalid_footer(format!(
+                "index start {} is past the end of the index",
+                footer.index_start
+            )));
+        }
 
-        footer.index_start = read_u32(r, &mut buf)?;
+        if r.limit() >= 4 {
+            let start = read_u32(&mut r, &mut buf)?;
+            if start > footer.index_start {
+                return Err(invalid_footer(format!(
+                    "range deletions start {} is past the start of the index",
+                    start
+                )));
+            }
+            // A table that records its number of entries always records where its range deletions
+            // start, which is the start of the index if it has none.
+            footer.range_deletions_start = (start < footer.index_start).then_some(start);
+        }
+
+        if r.limit() >= 4 {
+            footer.num_entries = Some(read_u32(&mut r, &mut buf)?);
+        }
+
+        if r.limit() >= 4 {
+            footer.data_length = Some(read_u32(&mut r, &mut buf)?);
+        }
+
+        if r.limit() >= 4 {
+            footer.index_flags = Some(read_u32(&mut r, &mut buf)?);
+        }
+
+        // The checksum is the last field, so anything more before it is the split keys.
+        if r.limit() > 4 {
+            let count = read_u32(&mut r, &mut buf)?;
+            for _ in 0..count {
+                let length = read_u32(&mut r, &mut buf)?;
+                footer.split_keys.push(read_bytes(&mut r, length)?);
+            }
+        }
+
+        if r.limit() >= 4 {
+            footer.checksum = Some(read_u32(&mut r, &mut buf)?);
+        }
 
         Ok(footer)
     }
 
+    // The length of the footer of a new table, which has every field set, given the combined
+    // length of its start and end keys, and of its split keys with their lengths.
+    pub fn new_length(keys_length: usize, split_keys_length: usize) -> usize {
+        4 + 4 + keys_length + 4 + 5 * 4 + 4 + split_keys_length + 4
+    }
+
+    // Whether the index entries carry inline values.
+    pub fn inline_values(&self) -> bool {
+        self.index_flags
+            .is_some_and(|flags| flags & INDEX_INLINE_VALUES != 0)
+    }
+
+    // The id of the codec the table's values are encoded with.
+    pub fn codec_id(&self) -> u8 {
+        self.index_flags.map_or(IDENTITY_CODEC_ID, |flags| {
+            (flags >> INDEX_CODEC_SHIFT) as u8
+        })
+    }
+
+    // Whether the index ends with a prefix filter.
+    pub fn prefix_filter(&self) -> bool {
+        self.index_flags
+            .is_some_and(|flags| flags & INDEX_PREFIX_FILTER != 0)
+    }
+
+    // Where the records other than range deletions end.
+    pub fn records_end(&self) -> u32 {
+        self.range_deletions_start.unwrap_or(self.index_start)
+    }
+
     pub fn write_to<T: Write>(&self, w: &mut T) -> io::Result<usize> {
-        let mut written = 0;
+        let buf = self.encode();
+        w.write_all(&buf)?;
 
-        written += w.write(&(self.start_key.len() as u32).to_le_bytes())?;
-        written += w.write(&self.start_key)?;
-        written += w.write(&(self.end_key.len() as u32).to_le_bytes())?;
-        written += w.write(&self.end_key)?;
-        written += w.write(&self.index_start.to_le_bytes())?;
-        written += w.write(&(written as u32 + 4).to_le_bytes())?;
+        Ok(buf.len())
+    }
 
-        Ok(written)
+    // Writes the footer with a checksum of everything written through `w` before it, which must
+    // be the rest of the table.
+    pub fn write_checksummed<T: Write>(&self, w: &mut ChecksumWriter<T>) -> io::Result<usize> {
+        let mut buf = Footer {
+            checksum: Some(0),
+            ..self.clone()
+        }
+        .encode();
+
+        // The checksum is followed by footer_length.
+        let checksum_start = buf.len() - 8;
+        let mut crc = w.crc();
+        crc.update(&buf[..checksum_start]);
+        buf[checksum_start..checksum_start + 4].copy_from_slice(&crc.value().to_le_bytes());
+        w.write_all(&buf)?;
+
+        Ok(buf.len())
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+
+        buf.extend_from_slice(&(self.start_key.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&self.start_key);
+        buf.extend_from_slice(&(self.end_key.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&self.end_key);
+        buf.extend_from_slice(&self.index_start.to_le_bytes());
+        // Each optional field can only be written if the ones before it are, so those before the
+        // last that is set are written even if they aren't.
+        let optional = [
+            self.range_deletions_start,
+            self.num_entries,
+            self.data_length,
+            self.index_flags,
+            self.checksum,
+        ];
+        if let Some(last) = optional.iter().rposition(Option::is_some) {
+            let defaults = [self.index_start, 0, 0, 0, 0];
+            for (i, (field, default)) in optional[..=last].iter().zip(defaults).enumerate() {
+                if i == 4 {
+                    buf.extend_from_slice(&(self.split_keys.len() as u32).to_le_bytes());
+                    for key in &self.split_keys {
+                        buf.extend_from_slice(&(key.len() as u32).to_le_bytes());
+                        buf.extend_from_slice(key);
+                    }
+                }
+                buf.extend_from_slice(&field.unwrap_or(default).to_le_bytes());
+            }
+        }
+        buf.extend_from_slice(&(buf.len() as u32 + 4).to_le_bytes());
+
+        buf
     }
 }
 
@@ -182,3 +609,322 @@ fn read_u32<T: Read>(r: &mut T, buf: &mut [u8; 4]) -> io::Result<u32> {
             .expect("must convert slice to byte array"),
     ))
 }
+
+// Reads the framing shared by every record: its op byte, key, and the length of its value.
+pub(crate) fn read_header<R: Read>(reader: &mut R) -> io::Result<(Op, Vec<u8>, u32)> {
+    // Big enough for operation, key length, and val length
+    // 1 byte + 4 bytes + 4 bytes
+    let mut buf = [0; 9];
+    reader.read_exact(&mut buf)?;
+    let op = Op::try_from(buf[0])?;
+
+    let key_length = u32::from_le_bytes(
+        buf[1..5]
+            .try_into()
+            .expect("must convert slice to byte array"),
+    );
+    let key = read_bytes(reader, key_length)?;
+
+    let val_length = u32::from_le_bytes(
+        buf[5..9]
+            .try_into()
+            .expect("must convert slice to byte array"),
+    );
+
+    Ok((op, key, val_length))
+}
+
+fn write_record<T: Write>(w: &mut T, op: Op, key: &[u8], val: Option<&[u8]>) -> io::Result<usize> {
+    // Check the lengths before writing anything so a record is never partially written.
+    let key_length = encode_length(key.len())?;
+    let val_length = encode_length(val.map_or(0, |val| val.len()))?;
+
+    w.write_all(&[op as u8])?;
+    w.write_all(&key_length.to_le_bytes())?;
+    w.write_all(&val_length.to_le_bytes())?;
+    w.write_all(key)?;
+    if let Some(val) = val {
+        w.write_all(val)?;
+    }
+
+    Ok(9 + key.len() + val.map_or(0, |val| val.len()))
+}
+
+// Lengths are encoded as u32, so anything larger can't be represented.
+fn encode_length(length: usize) -> io::Result<u32> {
+    u32::try_from(length).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("length {} exceeds the maximum of {}", length, u32::MAX),
+        )
+    })
+}
+
+// Reads exactly `length` bytes. The buffer grows as bytes are actually read rather than being
+// allocated up front, so a corrupt length can't trigger a huge allocation.
+pub(crate) fn read_bytes<T: Read>(r: &mut T, length: u32) -> io::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    r.take(length as u64).read_to_end(&mut out)?;
+
+    if out.len() != length as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("expected {} bytes but only {} remain", length, out.len()),
+        ));
+    }
+
+    Ok(out)
+}
+
+// An error for a WAL entry that isn't a record, found where only a record can be.
+fn not_a_record(op: Op) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("found {:?} where a single record was expected", op),
+    )
+}
+
+fn invalid_footer(detail: String) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid footer: {}", detail),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::checksum::Crc32c;
+
+    use super::*;
+
+    #[test]
+    fn test_op() {
+        let ops = [
+            Op::Exists,
+            Op::Deleted,
+            Op::RangeDeleted,
+            Op::Blob,
+            Op::Batch,
+            Op::Codec,
+        ];
+        for op in ops {
+            assert_eq!(op, Op::try_from(op as u8).unwrap());
+        }
+        // The values are on disk, and must not change.
+        assert_eq!(b"012345", &ops.map(|op| op as u8));
+
+        let err = Op::try_from(b'x').unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        let mut record = Vec::new();
+        WriteRecord::Deleted { key: b"key" }
+            .write_to(&mut record)
+            .unwrap();
+        record[0] = b'9';
+        assert!(ReadRecord::read_from(&mut record.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_empty_value() {
+        // An empty value differs from a deletion only in its op.
+        let round_trip = |record: WriteRecord| {
+            let mut buf = Vec::new();
+            record.write_to(&mut buf).unwrap();
+            ReadRecord::read_from(&mut buf.as_slice()).unwrap()
+        };
+        assert_eq!(
+            ReadRecord::Exists {
+                key: b"key".to_vec(),
+                val: Vec::new(),
+            },
+            round_trip(WriteRecord::Exists {
+                key: b"key",
+                val: b"",
+            })
+        );
+        assert_eq!(
+            ReadRecord::Deleted {
+                key: b"key".to_vec(),
+            },
+            round_trip(WriteRecord::Deleted { key: b"key" })
+        );
+    }
+
+    #[test]
+    fn test_wal_entry() {
+        let mut buf = Vec::new();
+        WriteRecord::Exists {
+            key: b"key0",
+            val: b"val",
+        }
+        .write_to(&mut buf)
+        .unwrap();
+        let batch = [
+            WriteRecord::Exists {
+                key: b"key1",
+                val: b"val",
+            },
+            WriteRecord::Deleted { key: b"key2" },
+        ];
+        let written = write_batch(&mut buf, &batch).unwrap();
+
+        let mut r = buf.as_slice();
+        let record = WalEntry::read_from(&mut r).unwrap();
+        assert_eq!(
+            WalEntry::Record(ReadRecord::Exists {
+                key: b"key0".to_vec(),
+                val: b"val".to_vec(),
+            }),
+            record
+        );
+        let entry = WalEntry::read_from(&mut r).unwrap();
+        assert_eq!(written, entry.size());
+        assert_eq!(
+            WalEntry::Batch(vec![
+                ReadRecord::Exists {
+                    key: b"key1".to_vec(),
+                    val: b"val".to_vec(),
+                },
+                ReadRecord::Deleted {
+                    key: b"key2".to_vec(),
+                },
+            ]),
+            entry
+        );
+        assert!(r.is_empty());
+
+        // A batch is never read as a single record.
+        let batch_start = buf.len() - written;
+        assert!(ReadRecord::read_from(&mut &buf[batch_start..]).is_err());
+
+        // A batch cut short is cut short as a whole, but a record that runs past the end of an
+        // intact batch is damage.
+        let err = WalEntry::read_from(&mut &buf[batch_start..buf.len() - 1]).unwrap_err();
+        assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+        let mut damaged = buf[batch_start..].to_vec();
+        damaged[9 + 5] += 1;
+        let err = WalEntry::read_from(&mut damaged.as_slice()).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    }
+
+    #[test]
+    fn test_encode_length() {
+        assert_eq!(u32::MAX, encode_length(u32::MAX as usize).unwrap());
+        assert_eq!(
+            io::ErrorKind::InvalidInput,
+            encode_length(u32::MAX as usize + 1).unwrap_err().kind()
+        );
+    }
+
+    #[test]
+    fn test_footer_fields() {
+        let round_trip = |range_deletions_start, num_entries, data_length| {
+            let mut buf = vec![0; 20];
+            Footer {
+                start_key: b"a".to_vec(),
+                end_key: b"b".to_vec(),
+                index_start: 20,
+                range_deletions_start,
+                num_entries,
+                data_length,
+                index_flags: None,
+                split_keys: Vec::new(),
+                checksum: None,
+                footer_length: None,
+            }
+            .write_to(&mut buf)
+            .unwrap();
+
+            let footer = Footer::new_from_reader(&mut io::Cursor::new(buf)).unwrap();
+            (
+                footer.range_deletions_start,
+                footer.num_entries,
+                footer.data_length,
+            )
+        };
+
+        // Tables written before a field existed omit it, and it reads as None.
+        assert_eq!((None, None, None), round_trip(None, None, None));
+        assert_eq!((Some(10), None, None), round_trip(Some(10), None, None));
+        assert_eq!(
+            (Some(10), Some(3), None),
+            round_trip(Some(10), Some(3), None)
+        );
+        assert_eq!((None, Some(3), None), round_trip(None, Some(3), None));
+        assert_eq!(
+            (Some(10), Some(3), Some(10)),
+            round_trip(Some(10), Some(3), Some(10))
+        );
+        assert_eq!(
+            (None, Some(3), Some(20)),
+            round_trip(None, Some(3), Some(20))
+        );
+
+        let mut buf = vec![0; 20];
+        Footer {
+            index_start: 20,
+            index_flags: Some(INDEX_INLINE_VALUES),
+            ..Default::default()
+        }
+        .write_to(&mut buf)
+        .unwrap();
+        let footer = Footer::new_from_reader(&mut io::Cursor::new(buf)).unwrap();
+        assert!(footer.inline_values());
+        assert_eq!(
+            (None, Some(0), Some(0)),
+            (
+                footer.range_deletions_start,
+                footer.num_entries,
+                footer.data_length
+            )
+        );
+
+        // The checksum covers the rest of the table and the footer up to itself.
+        let mut w = ChecksumWriter::new(Vec::new());
+        w.write_all(&[7; 20]).unwrap();
+        Footer {
+            index_start: 20,
+            ..Default::default()
+        }
+        .write_checksummed(&mut w)
+        .unwrap();
+        let buf = w.into_inner();
+        let footer = Footer::new_from_reader(&mut io::Cursor::new(&buf)).unwrap();
+        let mut crc = Crc32c::default();
+        crc.update(&buf[..buf.len() - 8]);
+        assert_eq!(Some(crc.value()), footer.checksum);
+        assert!(!footer.inline_values());
+        assert!(footer.split_keys.is_empty());
+
+        // Split keys come before the checksum, which still covers them.
+        let mut w = ChecksumWriter::new(Vec::new());
+        w.write_all(&[7; 20]).unwrap();
+        let split_keys = vec![b"b".to_vec(), b"".to_vec(), b"cde".to_vec()];
+        Footer {
+            index_start: 20,
+            split_keys: split_keys.clone(),
+            ..Default::default()
+        }
+        .write_checksummed(&mut w)
+        .unwrap();
+        let buf = w.into_inner();
+        let footer = Footer::new_from_reader(&mut io::Cursor::new(&buf)).unwrap();
+        let mut crc = Crc32c::default();
+        crc.update(&buf[..buf.len() - 8]);
+        assert_eq!(Some(crc.value()), footer.checksum);
+        assert_eq!(split_keys, footer.split_keys);
+        assert_eq!(
+            Footer::new_length(0, 3 * 4 + 4),
+            footer.footer_length.unwrap() as usize
+        );
+
+        // Tables written before split keys existed go straight from the index flags to the
+        // checksum.
+        let mut buf = vec![0; 20];
+        for field in [0, 0, 20, 20, 0, 0, 0, 0xabcd, 36] {
+            buf.extend_from_slice(&(field as u32).to_le_bytes());
+        }
+        let footer = Footer::new_from_reader(&mut io::Cursor::new(&buf)).unwrap();
+        assert_eq!(Some(0xabcd), footer.checksum);
+        assert!(footer.split_keys.is_empty());
+    }
+}
@@ -0,0 +1,30 @@
+use std::path;
+
+// What was skipped while opening a store with a recovery mode that tolerates damage. This is empty
+// unless something had to be skipped.
+#[derive(Clone, Debug, Default)]
+pub struct RecoveryReport {
+    pub skipped: Vec<Skipped>,
+}
+
+impl RecoveryReport {
+    pub fn is_empty(&self) -> bool {
+        self.skipped.is_empty()
+    }
+
+    pub(crate) fn skip(&mut self, path: &path::Path, offset: u64, detail: String) {
+        self.skipped.push(Skipped {
+            path: path.to_owned(),
+            offset,
+            detail,
+        });
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Skipped {
+    pub path: path::PathBuf,
+    // Where in the file the skipped data starts.
+    pub offset: u64,
+    pub detail: String,
+}
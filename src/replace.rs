@@ -0,0 +1,203 @@
+// Swapping the whole of a store's data for another store's, for Store::replace_with. The tables,
+// blob files and identity of the store in a staging directory are moved into the data directory in
+// place of the store's own, which are moved aside and then removed.
+//
+// Moving a number of files and directories can't be done atomically, so the swap is recorded in a
+// marker file in the data directory before anything is moved, naming the staging directory and the
+// phase the swap is in:
+//
+//      aside   The store's own data is being moved aside, into REPLACED_DIR_NAME.
+//      in      The staged data is being moved in.
+//
+// The marker is only removed once the swap is complete. A store that finds one when it is opened
+// finishes the swap before reading anything else, so that it never sees a mix of the two. The WAL
+// is emptied as soon as the marker is written, since the writes in it belong to the data being
+// replaced.
+
+use std::{
+    fs, io,
+    path::{self, Path},
+};
+
+use crate::{
+    blob::BLOB_EXT,
+    context::IoContext,
+    durable::{atomic_rename_and_sync, sync_renames, TEMP_EXT},
+    identity::IDENTITY_FILE_NAME,
+};
+
+const MARKER_FILE_NAME: &str = "REPLACING";
+const REPLACED_DIR_NAME: &str = "REPLACED";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    Aside,
+    In,
+}
+
+impl Phase {
+    fn name(self) -> &'static str {
+        match self {
+            Phase::Aside => "aside",
+            Phase::In => "in",
+        }
+    }
+}
+
+// Swaps the data in `data_dir` for the store in `staging`, emptying the WAL at each of `wals` that
+// exists. Both directories must be on the same filesystem, which is checked before anything is
+// changed.
+pub(crate) fn replace(data_dir: &Path, staging: &Path, wals: &[&Path]) -> io::Result<()> {
+    // A rename between filesystems fails, which would leave the swap unable to finish.
+    let probe = staging.join(MARKER_FILE_NAME).with_extension(TEMP_EXT);
+    let probed = data_dir.join(MARKER_FILE_NAME).with_extension(TEMP_EXT);
+    fs::write(&probe, b"").with_path("creating", &probe)?;
+    fs::rename(&probe, &probed).with_path("moving", &probe)?;
+    fs::remove_file(&probed).with_path("removing", &probed)?;
+
+    write_marker(data_dir, Phase::Aside, staging)?;
+    finish(data_dir, wals)
+}
+
+// Finishes a swap that was under way in `data_dir`, if there was one.
+pub(crate) fn finish(data_dir: &Path, wals: &[&Path]) -> io::Result<()> {
+    let marker = data_dir.join(MARKER_FILE_NAME);
+    let contents = match fs::read_to_string(&marker) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).with_path("reading", &marker),
+    };
+    let (phase, staging) = match contents.split_once('\n') {
+        Some(("aside", staging)) => (Phase::Aside, path::PathBuf::from(staging)),
+        Some(("in", staging)) => (Phase::In, path::PathBuf::from(staging)),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unrecognized replacement marker",
+            ))
+            .with_path("reading", &marker)
+        }
+    };
+
+    for wal in wals {
+        if wal.exists() {
+            let file = fs::OpenOptions::new()
+                .write(true)
+                .truncate(true)
+                .open(wal)
+                .with_path("emptying", wal)?;
+            file.sync_all().with_path("syncing", wal)?;
+        }
+    }
+
+    let replaced = data_dir.join(REPLACED_DIR_NAME);
+    if phase == Phase::Aside {
+        // Nothing has been moved in yet, so everything here belongs to the data being replaced.
+        fs::create_dir_all(&replaced).with_path("creating", &replaced)?;
+        let names = dataset_entries(data_dir)?;
+        for name in &names {
+            let from = data_dir.join(name);
+            fs::rename(&from, replaced.join(name)).with_path("moving", &from)?;
+        }
+        // Both ends of the moves: the entries now in the replaced directory, and their removal
+        // from the data directory.
+        sync_renames(&replaced, &names)?;
+        sync_renames(data_dir, &[])?;
+        write_marker(data_dir, Phase::In, &staging)?;
+    }
+
+    let names = dataset_entries(&staging)?;
+    for name in &names {
+        let from = staging.join(name);
+        fs::rename(&from, data_dir.join(name)).with_path("moving", &from)?;
+    }
+    sync_renames(data_dir, &names)?;
+
+    if replaced.exists() {
+        fs::remove_dir_all(&replaced).with_path("removing", &replaced)?;
+    }
+    fs::remove_file(&marker).with_path("removing", &marker)?;
+    sync_renames(data_dir, &[])
+}
+
+fn write_marker(data_dir: &Path, phase: Phase, staging: &Path) -> io::Result<()> {
+    let staging = staging.to_str().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("staging directory {} isn't valid UTF-8", staging.display()),
+        )
+    })?;
+    let marker = data_dir.join(MARKER_FILE_NAME);
+    let tmp = marker.with_extension(TEMP_EXT);
+    fs::write(&tmp, format!("{}\n{}", phase.name(), staging)).with_path("writing", &tmp)?;
+    fs::File::open(&tmp)
+        .and_then(|file| file.sync_all())
+        .with_path("syncing", &tmp)?;
+    atomic_rename_and_sync(&tmp, &marker)
+}
+
+// The names of what makes up the data of the store in `dir`: Its identity, level directories and
+// blob files. The WAL isn't included.
+fn dataset_entries(dir: &Path) -> io::Result<Vec<std::ffi::OsString>> {
+    let mut names = Vec::new();
+    for entry in fs::read_dir(dir).with_path("listing", dir)? {
+        let path = entry.with_path("listing", dir)?.path();
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        let is_level = path.is_dir() && name.parse::<usize>().is_ok();
+        let is_blob = path.is_file() && path.extension().is_some_and(|ext| ext == BLOB_EXT);
+        if name == IDENTITY_FILE_NAME || is_level || is_blob {
+            names.push(name.into());
+        }
+    }
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_finish_interrupted() {
+        let dir = TempDir::new("testing").unwrap();
+        let staging = TempDir::new("staging").unwrap();
+        let wal = dir.path().join("data.wal");
+        fs::write(&wal, b"old writes").unwrap();
+        fs::write(dir.path().join(IDENTITY_FILE_NAME), b"old").unwrap();
+        fs::create_dir(dir.path().join("2")).unwrap();
+        fs::write(dir.path().join("1.blob"), b"old").unwrap();
+        fs::write(staging.path().join(IDENTITY_FILE_NAME), b"new").unwrap();
+        fs::create_dir(staging.path().join("0")).unwrap();
+        fs::create_dir(staging.path().join("1")).unwrap();
+
+        // As if a crash came after the old data was moved aside and part of the new moved in.
+        write_marker(dir.path(), Phase::Aside, staging.path()).unwrap();
+        let replaced = dir.path().join(REPLACED_DIR_NAME);
+        fs::create_dir(&replaced).unwrap();
+        for name in [IDENTITY_FILE_NAME, "2", "1.blob"] {
+            fs::rename(dir.path().join(name), replaced.join(name)).unwrap();
+        }
+        write_marker(dir.path(), Phase::In, staging.path()).unwrap();
+        fs::rename(staging.path().join("0"), dir.path().join("0")).unwrap();
+
+        finish(dir.path(), &[&wal]).unwrap();
+        assert_eq!(
+            b"new".to_vec(),
+            fs::read(dir.path().join(IDENTITY_FILE_NAME)).unwrap()
+        );
+        assert!(dir.path().join("0").is_dir());
+        assert!(dir.path().join("1").is_dir());
+        assert!(!dir.path().join("2").exists());
+        assert!(!dir.path().join("1.blob").exists());
+        assert!(!replaced.exists());
+        assert!(!dir.path().join(MARKER_FILE_NAME).exists());
+        assert_eq!(0, fs::metadata(&wal).unwrap().len());
+
+        // Without a marker, there is nothing to finish.
+        finish(dir.path(), &[&wal]).unwrap();
+        assert!(dir.path().join("1").is_dir());
+    }
+}
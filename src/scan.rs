@@ -0,0 +1,342 @@
+use std::{io, ops::Bound, path, sync::Arc};
+
+use crate::{
+    blob,
+    compactor::combiner::MergeIter,
+    memtable::MemTable,
+    options::IterOptions,
+    protocol::ReadRecord,
+    sst::Catalog,
+    stats::ReadCounters,
+    store::Store,
+    tombstone::{self, RangeTombstone},
+    StoreError,
+};
+
+type RecordIter = Box<dyn Iterator<Item = io::Result<ReadRecord>> + Send>;
+
+// The memtable is merged in with this sequence, above that of any level 0 table.
+const MEMTABLE_SEQUENCE: u32 = u32::MAX;
+
+// The smallest key greater than every key starting with `prefix`, for use as the end of a scan of
+// the prefix. There is none if the prefix is empty or made up entirely of 0xff bytes.
+pub fn prefix_end(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < u8::MAX {
+            end.push(last + 1);
+            return Some(end);
+        }
+    }
+    None
+}
+
+// An iterator over the live records of a store with keys in [start, end), in ascending key order.
+// The newest version of each key wins, and deleted keys are skipped. The scan holds its own
+// references to the memtable and tables it reads from, so it is unaffected by later writes,
+// flushes, or compactions.
+pub struct Scan {
+    merge: MergeIter<RecordIter>,
+    // Where to read values kept in blob files.
+    data_dir: path::PathBuf,
+}
+
+impl Scan {
+    pub(crate) fn new(
+        memtable: Arc<MemTable>,
+        catalog: &Catalog,
+        start: &[u8],
+        end: Option<&[u8]>,
+        counters: &ReadCounters,
+    ) -> Result<Self, StoreError> {
+        Ok(Scan {
+            merge: merged_records(memtable, catalog, start, end, None, counters)?,
+            data_dir: catalog.data_dir().to_owned(),
+        })
+    }
+
+    // A scan of the keys starting with `prefix`. Tables whose prefix filter rules the prefix out
+    // aren't read, and are counted in `counters`.
+    pub(crate) fn prefix(
+        memtable: Arc<MemTable>,
+        catalog: &Catalog,
+        prefix: &[u8],
+        counters: &ReadCounters,
+    ) -> Result<Self, StoreError> {
+        let end = prefix_end(prefix);
+        Ok(Scan {
+            merge: merged_records(
+                memtable,
+                catalog,
+                prefix,
+                end.as_deref(),
+                Some(prefix),
+                counters,
+            )?,
+            data_dir: catalog.data_dir().to_owned(),
+        })
+    }
+}
+
+// The newest record for each key in [start, end) of the memtable and tables, in ascending key
+// order. Deletions are included, except of keys covered by a newer range deletion, which are left
+// out along with the range deletions themselves. Tables without keys in the range aren't read, and
+// neither are the records of the others before the start of the range. If every key in the range
+// starts with `prefix`, tables that can't have such keys are skipped too.
+pub(crate) fn merged_records(
+    memtable: Arc<MemTable>,
+    catalog: &Catalog,
+    start: &[u8],
+    end: Option<&[u8]>,
+    prefix: Option<&[u8]>,
+    counters: &ReadCounters,
+) -> Result<MergeIter<RecordIter>, StoreError> {
+    let mut merge: MergeIter<RecordIter> = MergeIter::new();
+
+    // Range deletions hide the records of older tables. This accumulates them from newest to
+    // oldest as the tables are added.
+    let mut newer = memtable.range_deletions().to_vec();
+
+    // The memtable is newer than every table, including all of those in level 0.
+    merge.push_iter(
+        Box::new(MemTableIter {
+            memtable,
+            next_start: Bound::Included(start.to_vec()),
+            end: end.map(|e| e.to_vec()),
+        }),
+        0,
+        Some(MEMTABLE_SEQUENCE),
+    )?;
+
+    for (level, tables) in catalog.ssts.iter().enumerate() {
+        let mut level_deletions = Vec::new();
+        for table in tables.iter().rev() {
+            if table.is_missing() {
+                return Err(StoreError::MissingTable {
+                    path: table.path.clone(),
+                });
+            }
+            // A skipped table's range deletions still hide the records of older tables.
+            if prefix.is_some_and(|prefix| !table.may_contain_prefix(prefix)) {
+                counters.record_prefix_skip();
+            } else if !table.overlaps(start, end) {
+                counters.record_range_skip();
+            } else {
+                let end = end.map(|e| e.to_vec());
+
+                let iter = table
+                    .iter_from(start)?
+                    .take_while(move |rec| match (rec, &end) {
+                        (Ok(rec), Some(end)) => rec.key() < end.as_slice(),
+                        _ => true,
+                    });
+
+                // Only level 0 tables can have overlapping keys, and they are ordered oldest to
+                // newest, as are their sequence numbers.
+                let sequence = if level == 0 { table.sequence() } else { None };
+                let iter = tombstone::without_covered(iter, newer.clone());
+                merge.push_iter(Box::new(iter), level, sequence)?;
+            }
+
+            // Tables in higher levels don't overlap, so only level 0 tables can hide records in
+            // the same level.
+            if level == 0 {
+                newer.extend_from_slice(table.range_deletions());
+            } else {
+                level_deletions.extend_from_slice(table.range_deletions());
+            }
+        }
+        newer.append(&mut level_deletions);
+    }
+
+    Ok(merge)
+}
+
+impl Iterator for Scan {
+    type Item = Result<(Vec<u8>, Vec<u8>), StoreError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.merge.next()? {
+                Ok(ReadRecord::Exists { key, val }) => return Some(Ok((key, val))),
+                Ok(ReadRecord::Blob { key, blob }) => {
+                    return Some(blob::read(&self.data_dir, blob).map(|val| (key, val)))
+                }
+                Ok(ReadRecord::Deleted { .. } | ReadRecord::RangeDeleted { .. }) => continue,
+                Err(e) => return Some(Err(e.into())),
+            }
+        }
+    }
+}
+
+// A scan of the union of several stores, as though their records had all been written to one: The
+// live records with keys in [start, end), or from start onward if there is no end, in ascending key
+// order. Where the stores have records for the same key, the record of the store that comes first
+// in `stores` wins, whichever was written last, so a deletion in one store, including a range
+// deletion, hides the key in the stores after it. Like a scan of a single store, it is unaffected
+// by later writes to any of them.
+pub fn merge_scan(
+    stores: &[&Store],
+    start: &[u8],
+    end: Option<&[u8]>,
+) -> Result<MergeScan, StoreError> {
+    let mut merge: MergeIter<RecordIter> = MergeIter::new();
+    let mut data_dirs = Vec::with_capacity(stores.len());
+    // The range deletions of the stores ahead of the next one.
+    let mut earlier: Vec<RangeTombstone> = Vec::new();
+
+    // Each store is merged in as though it were a level of its own, with the first the newest.
+    for (level, store) in stores.iter().enumerate() {
+        let (memtable, catalog, counters) = store.scan_sources();
+        let deletions = memtable
+            .range_deletions()
+            .iter()
+            .chain(
+                catalog
+                    .ssts
+                    .iter()
+                    .flatten()
+                    .flat_map(|t| t.range_deletions()),
+            )
+            .cloned()
+            .collect::<Vec<_>>();
+        let records = merged_records(memtable, catalog, start, end, None, counters)?;
+        let records = tombstone::without_covered(records, earlier.clone());
+        merge.push_iter(Box::new(records), level, None)?;
+        data_dirs.push(catalog.data_dir().to_owned());
+        earlier.extend(deletions);
+    }
+
+    Ok(MergeScan { merge, data_dirs })
+}
+
+// See merge_scan.
+pub struct MergeScan {
+    merge: MergeIter<RecordIter>,
+    // Where to read values kept in blob files, for each store by its level in the merge.
+    data_dirs: Vec<path::PathBuf>,
+}
+
+impl Iterator for MergeScan {
+    type Item = Result<(Vec<u8>, Vec<u8>), StoreError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.merge.next_with_source()? {
+                Ok((ReadRecord::Exists { key, val }, _, _)) => return Some(Ok((key, val))),
+                Ok((ReadRecord::Blob { key, blob }, level, _)) => {
+                    return Some(blob::read(&self.data_dirs[level], blob).map(|val| (key, val)))
+                }
+                Ok((ReadRecord::Deleted { .. } | ReadRecord::RangeDeleted { .. }, _, _)) => {
+                    continue
+                }
+                Err(e) => return Some(Err(e.into())),
+            }
+        }
+    }
+}
+
+// Where a record yielded by a RawScan was read from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecordSource {
+    Memtable,
+    // Level 0 tables also have their sequence number, which is larger for newer tables.
+    Table { level: usize, sequence: Option<u32> },
+}
+
+// Same as Scan, but yields records rather than pairs of key and value, so that with
+// IterOptions::include_tombstones it can include deletions. Values in blob files are read in, so
+// only Exists and Deleted records are yielded. With IterOptions::include_metadata, each record
+// comes with where it was read from.
+pub struct RawScan {
+    merge: MergeIter<RecordIter>,
+    data_dir: path::PathBuf,
+    options: IterOptions,
+}
+
+impl RawScan {
+    pub(crate) fn new(
+        memtable: Arc<MemTable>,
+        catalog: &Catalog,
+        start: &[u8],
+        end: Option<&[u8]>,
+        options: IterOptions,
+        counters: &ReadCounters,
+    ) -> Result<Self, StoreError> {
+        Ok(RawScan {
+            merge: merged_records(memtable, catalog, start, end, None, counters)?,
+            data_dir: catalog.data_dir().to_owned(),
+            options,
+        })
+    }
+}
+
+impl Iterator for RawScan {
+    type Item = Result<(ReadRecord, Option<RecordSource>), StoreError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (record, level, sequence) = match self.merge.next_with_source()? {
+                Ok(next) => next,
+                Err(e) => return Some(Err(e.into())),
+            };
+
+            let record = match record {
+                ReadRecord::Blob { key, blob } => match blob::read(&self.data_dir, blob) {
+                    Ok(val) => ReadRecord::Exists { key, val },
+                    Err(e) => return Some(Err(e)),
+                },
+                ReadRecord::Deleted { .. } if !self.options.include_tombstones => continue,
+                ReadRecord::RangeDeleted { .. } => continue,
+                record => record,
+            };
+
+            let source = self
+                .options
+                .include_metadata
+                .then_some(match (level, sequence) {
+                    (0, Some(MEMTABLE_SEQUENCE)) => RecordSource::Memtable,
+                    (level, sequence) => RecordSource::Table { level, sequence },
+                });
+            return Some(Ok((record, source)));
+        }
+    }
+}
+
+// Iterates a range of a shared memtable. Rather than borrowing the memtable, each step looks up the
+// next key after the previous one, which allows the iterator to own its reference.
+struct MemTableIter {
+    memtable: Arc<MemTable>,
+    next_start: Bound<Vec<u8>>,
+    end: Option<Vec<u8>>,
+}
+
+impl Iterator for MemTableIter {
+    type Item = io::Result<ReadRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // A range that ends where or before it starts is empty, which BTreeMap::range panics on.
+        if let (Bound::Included(start) | Bound::Excluded(start), Some(end)) =
+            (&self.next_start, &self.end)
+        {
+            if start >= end {
+                return None;
+            }
+        }
+
+        let start = match &self.next_start {
+            Bound::Included(k) => Bound::Included(k.as_slice()),
+            Bound::Excluded(k) => Bound::Excluded(k.as_slice()),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        let end = match &self.end {
+            Some(end) => Bound::Excluded(end.as_slice()),
+            None => Bound::Unbounded,
+        };
+
+        let rec: ReadRecord = self.memtable.range(start, end).next()?.into();
+        self.next_start = Bound::Excluded(rec.key().to_vec());
+
+        Some(Ok(rec))
+    }
+}
@@ -0,0 +1,239 @@
+// Finds damage to tables before a read does, by reading every table through and checking it against
+// the checksum in its footer. With Options::scrub_rate, a thread of the store's own does this
+// continually, a pass over every table at a time, reading no faster than the rate so that it
+// doesn't compete with the store's own reads. Store::scrub_now makes a pass right away, as fast as
+// the tables can be read.
+//
+// A damaged table is reported to the hook given to Options::on_scrub_error, and in the report of
+// `scrub_now`. Nothing else changes: The store goes on serving whatever can still be read from the
+// table, and it is reported again on each pass until something is done about it. Tables written
+// before checksums existed can't be checked, and are skipped.
+//
+// A table may be compacted away while it is being read. Each table is pinned while it is read, so a
+// compaction sets it aside rather than removing it, and anything found wrong with a table that is
+// no longer in the store isn't reported.
+
+use std::{
+    collections::HashMap,
+    fs, io, path,
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    clock::Clock,
+    context::IoContext,
+    options::{Options, ScrubHook},
+    protocol::SST_EXT,
+    sst::{verify_checksum_paced, Pins},
+    StoreError,
+};
+
+// How long the background scrubber waits after finishing one pass before starting the next.
+const PASS_INTERVAL: Duration = Duration::from_secs(60);
+
+// The outcome of a pass over the store's tables.
+#[derive(Debug, Default)]
+pub struct ScrubReport {
+    // Tables that were read through and checked, including the damaged ones.
+    pub tables_checked: usize,
+    // Tables without a checksum to check.
+    pub tables_skipped: usize,
+    pub bytes_read: u64,
+    // What was found wrong with each damaged table, usually StoreError::Corruption.
+    pub errors: Vec<StoreError>,
+}
+
+impl ScrubReport {
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+// What the store and its scrubber thread share.
+#[derive(Clone)]
+pub(crate) struct Scrub {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    // The data directory, and Options::cold_dir if there is one.
+    dirs: Vec<path::PathBuf>,
+    pins: Pins,
+    clock: Arc<dyn Clock>,
+    hook: Option<ScrubHook>,
+    // When each table was last found undamaged, by the store's clock.
+    last_verified: Mutex<HashMap<path::PathBuf, Duration>>,
+    stopped: Mutex<bool>,
+    stop: Condvar,
+}
+
+impl Scrub {
+    pub(crate) fn new(options: &Options, data_dir: &path::Path, pins: Pins) -> Self {
+        Scrub {
+            inner: Arc::new(Inner {
+                dirs: std::iter::once(data_dir)
+                    .chain(options.cold_dir.as_deref())
+                    .map(path::Path::to_owned)
+                    .collect(),
+                pins,
+                clock: options.clock.clone(),
+                hook: options.scrub_hook.clone(),
+                last_verified: Mutex::new(HashMap::new()),
+                stopped: Mutex::new(false),
+                stop: Condvar::new(),
+            }),
+        }
+    }
+
+    // When the table at `path` was last found undamaged, if it has been since the store was opened.
+    pub(crate) fn last_verified(&self, path: &path::Path) -> Option<Duration> {
+        self.inner.last_verified.lock().unwrap().get(path).copied()
+    }
+
+    // Reads every table in the store, no faster than `rate` bytes a second if there is one. An
+    // error is only returned if the tables can't be listed, or the pass was stopped.
+    pub(crate) fn pass(&self, rate: Option<u64>) -> io::Result<ScrubReport> {
+        let mut report = ScrubReport::default();
+        let started = Instant::now();
+        let mut pace = |n: usize| {
+            report.bytes_read += n as u64;
+            match rate {
+                Some(rate) => {
+                    let due = Duration::from_secs_f64(report.bytes_read as f64 / rate as f64);
+                    self.sleep(due.saturating_sub(started.elapsed()))
+                }
+                None => Ok(()),
+            }
+        };
+
+        let mut checked = Vec::new();
+        let mut verified = Vec::new();
+        let mut errors = Vec::new();
+        let mut skipped = 0;
+        let mut tables = Vec::new();
+        for dir in &self.inner.dirs {
+            tables.extend(list_tables(dir)?);
+        }
+        for path in tables {
+            let _pin = self.inner.pins.pin(vec![path.clone()]);
+            match verify_checksum_paced(&path, &mut pace) {
+                Ok(Some(_)) => {
+                    checked.push(path.clone());
+                    verified.push(path);
+                }
+                Ok(None) => skipped += 1,
+                Err(_) if self.is_stopped() => {
+                    return Err(io::Error::other("the scrubber was stopped"));
+                }
+                // Compacted away since it was listed.
+                Err(_) if !path.exists() => (),
+                Err(e) => {
+                    if let Some(hook) = &self.inner.hook {
+                        (hook.0)(&e);
+                    }
+                    checked.push(path);
+                    errors.push(e);
+                }
+            }
+        }
+
+        let now = self.inner.clock.now();
+        let mut last_verified = self.inner.last_verified.lock().unwrap();
+        // Tables that are gone are forgotten.
+        last_verified.retain(|path, _| checked.contains(path));
+        for path in verified {
+            last_verified.insert(path, now);
+        }
+
+        report.tables_checked = checked.len();
+        report.tables_skipped = skipped;
+        report.errors = errors;
+        Ok(report)
+    }
+
+    // Waits for `duration`, returning an error if the scrubber is stopped first.
+    fn sleep(&self, duration: Duration) -> io::Result<()> {
+        let stopped = self.inner.stopped.lock().unwrap();
+        let (stopped, _) = self
+            .inner
+            .stop
+            .wait_timeout_while(stopped, duration, |stopped| !*stopped)
+            .unwrap();
+        match *stopped {
+            true => Err(io::Error::other("the scrubber was stopped")),
+            false => Ok(()),
+        }
+    }
+
+    fn is_stopped(&self) -> bool {
+        *self.inner.stopped.lock().unwrap()
+    }
+}
+
+// Scrubs the store's tables continually, for Options::scrub_rate. Dropping it stops the thread,
+// interrupting any pass under way.
+pub(crate) struct Scrubber {
+    scrub: Scrub,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Scrubber {
+    pub(crate) fn spawn(scrub: Scrub, rate: u64) -> Self {
+        let thread = {
+            let scrub = scrub.clone();
+            thread::Builder::new()
+                .name("crucible-scrubber".to_string())
+                .spawn(move || loop {
+                    // Errors listing the tables are left for the next pass to try again.
+                    if scrub.pass(Some(rate)).is_err() && scrub.is_stopped() {
+                        return;
+                    }
+                    if scrub.sleep(PASS_INTERVAL).is_err() {
+                        return;
+                    }
+                })
+                .expect("must spawn scrubber thread")
+        };
+
+        Scrubber {
+            scrub,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for Scrubber {
+    fn drop(&mut self) {
+        *self.scrub.inner.stopped.lock().unwrap() = true;
+        self.scrub.inner.stop.notify_all();
+        if let Some(thread) = self.thread.take() {
+            // A panic on the thread has nowhere better to go.
+            let _ = thread.join();
+        }
+    }
+}
+
+// The tables in each level of the store, in no particular order.
+fn list_tables(data_dir: &path::Path) -> io::Result<Vec<path::PathBuf>> {
+    let mut tables = Vec::new();
+    for entry in fs::read_dir(data_dir).with_path("listing", data_dir)? {
+        let dir = entry.with_path("listing", data_dir)?.path();
+        let is_level = dir
+            .file_name()
+            .and_then(|name| name.to_str()?.parse::<usize>().ok())
+            .is_some();
+        if !dir.is_dir() || !is_level {
+            continue;
+        }
+
+        for entry in fs::read_dir(&dir).with_path("listing", &dir)? {
+            let path = entry.with_path("listing", &dir)?.path();
+            if path.extension().is_some_and(|ext| ext == SST_EXT) {
+                tables.push(path);
+            }
+        }
+    }
+    Ok(tables)
+}
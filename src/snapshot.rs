@@ -0,0 +1,178 @@
+use std::{
+    io::{self, Read},
+    sync::Arc,
+};
+
+use crate::{
+    blob::{self, BlobRef},
+    memtable::MemTable,
+    protocol::{read_bytes, Op, ReadRecord, WriteRecord},
+    scan::Scan,
+    sst::{Catalog, Found, PinGuard},
+    stats::ReadCounters,
+    StoreError,
+};
+
+// A read-only view of a store as of the moment it was created. Writes made to the store afterwards
+// are not visible. Creating a snapshot only clones reference counts: The memtable is copied the
+// next time the store writes to it while the snapshot is alive, and tables stay readable through
+// their open file handles even if a compaction removes them.
+pub struct ReadOnlySnapshot {
+    pub(crate) memtable: Arc<MemTable>,
+    pub(crate) catalog: Arc<Catalog>,
+    pub(crate) read_counters: Arc<ReadCounters>,
+}
+
+impl ReadOnlySnapshot {
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, StoreError> {
+        get(
+            &self.memtable,
+            &self.catalog,
+            &self.read_counters,
+            key,
+            false,
+        )
+    }
+
+    // Live records with keys in [start, end), or from start onward if there is no end.
+    pub fn scan(&self, start: &[u8], end: Option<&[u8]>) -> Result<Scan, StoreError> {
+        Scan::new(
+            self.memtable.clone(),
+            &self.catalog,
+            start,
+            end,
+            &self.read_counters,
+        )
+    }
+}
+
+// A scan of the whole store that keeps the tables it reads pinned, see Store::snapshot_iter.
+pub struct SnapshotIter {
+    pub(crate) scan: Scan,
+    pub(crate) _pin: PinGuard,
+}
+
+impl Iterator for SnapshotIter {
+    type Item = Result<(Vec<u8>, Vec<u8>), StoreError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.scan.next()
+    }
+}
+
+pub(crate) fn get(
+    memtable: &MemTable,
+    catalog: &Catalog,
+    counters: &ReadCounters,
+    key: &[u8],
+    verify: bool,
+) -> Result<Option<Vec<u8>>, StoreError> {
+    counters.record_get();
+
+    // A deletion in the memtable shadows any value in the tables.
+    match memtable.lookup(key) {
+        Some(WriteRecord::Exists { val, .. }) => return Ok(Some(val.to_vec())),
+        Some(WriteRecord::Blob { blob, .. }) => {
+            return blob::read(catalog.data_dir(), blob).map(Some)
+        }
+        Some(WriteRecord::Deleted { .. } | WriteRecord::RangeDeleted { .. }) => return Ok(None),
+        None if memtable.range_deleted(key) => return Ok(None),
+        None => (),
+    }
+
+    match catalog.get_counted(key, counters, verify)? {
+        Some(ReadRecord::Exists { val, .. }) => Ok(Some(val)),
+        Some(ReadRecord::Blob { blob, .. }) => blob::read(catalog.data_dir(), blob).map(Some),
+        Some(ReadRecord::Deleted { .. } | ReadRecord::RangeDeleted { .. }) | None => Ok(None),
+    }
+}
+
+// A value being read a piece at a time, see Store::get_reader. Values in the memtable, or carried
+// by a table's index, are already in memory and are read from there.
+pub struct ValueReader {
+    r: Box<dyn Read + Send>,
+    len: u64,
+}
+
+impl ValueReader {
+    fn new(r: impl Read + Send + 'static, len: u64) -> Self {
+        ValueReader {
+            r: Box::new(r),
+            len,
+        }
+    }
+
+    fn from_vec(val: Vec<u8>) -> Self {
+        let len = val.len() as u64;
+        ValueReader::new(io::Cursor::new(val), len)
+    }
+
+    // The length of the whole value, however much of it has been read.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl Read for ValueReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.r.read(buf)
+    }
+}
+
+pub(crate) fn get_reader(
+    memtable: &MemTable,
+    catalog: &Catalog,
+    counters: &ReadCounters,
+    key: &[u8],
+) -> Result<Option<ValueReader>, StoreError> {
+    counters.record_get();
+
+    let blob_reader = |blob: BlobRef| {
+        blob::reader(catalog.data_dir(), blob).map(|r| Some(ValueReader::new(r, blob.len as u64)))
+    };
+
+    // A deletion in the memtable shadows any value in the tables.
+    match memtable.lookup(key) {
+        Some(WriteRecord::Exists { val, .. }) => {
+            return Ok(Some(ValueReader::from_vec(val.to_vec())))
+        }
+        Some(WriteRecord::Blob { blob, .. }) => return blob_reader(blob),
+        Some(WriteRecord::Deleted { .. } | WriteRecord::RangeDeleted { .. }) => return Ok(None),
+        None if memtable.range_deleted(key) => return Ok(None),
+        None => (),
+    }
+
+    let (sst, offset) = match catalog.find(key, counters, true) {
+        Some(Found::Record(ReadRecord::Exists { val, .. })) => {
+            return Ok(Some(ValueReader::from_vec(val)))
+        }
+        Some(Found::Record(_)) | None => return Ok(None),
+        Some(Found::At(sst, offset)) => (sst, offset),
+    };
+
+    counters.record_seek();
+    let (op, len, mut r) = sst.value_reader(offset)?;
+    match op {
+        Op::Exists => Ok(Some(ValueReader::new(r, len as u64))),
+        // The value in the table is only a reference to where the value really is.
+        Op::Blob => {
+            let blob = read_bytes(&mut r, len)
+                .and_then(|bytes| BlobRef::from_bytes(&bytes))
+                .map_err(|e| StoreError::from_read(&sst.path, offset as u64, e))?;
+            blob_reader(blob)
+        }
+        Op::Deleted | Op::RangeDeleted => Ok(None),
+        Op::Batch | Op::Codec => Err(StoreError::from_read(
+            &sst.path,
+            offset as u64,
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("found {:?} in a table", op),
+            ),
+        )),
+    }
+}
@@ -3,188 +3,1130 @@ use std::{
     fs,
     io::{self, BufWriter, Write},
     path,
+    sync::Arc,
+    time::Duration,
 };
 
-use crate::protocol::{self, ReadRecord, WriteRecord, SST_EXT};
+use crate::{
+    checksum::ChecksumWriter,
+    codec::Codecs,
+    context::{path_error, IoContext},
+    durable::{atomic_rename_and_sync, preallocate, truncate_to_written, TEMP_EXT},
+    memtable::MemTable,
+    options::{Options, RecoveryMode},
+    protocol::{
+        self, ReadRecord, WriteRecord, INDEX_CODEC_SHIFT, INDEX_INLINE_VALUES, INDEX_PREFIX_FILTER,
+        SST_EXT,
+    },
+    recovery::RecoveryReport,
+    scan::Scan,
+    stats::ReadCounters,
+    StoreError,
+};
+
+use super::{IndexEntry, InlineValue, PrefixFilter, PrefixFilterBuilder, Table};
+
+// How many times Catalog::refresh lists the tables again after one is removed before it could be
+// opened, before giving up. Each time means a compaction finished in the meantime, so running out
+// takes a store compacting faster than its tables can be opened.
+const REFRESH_ATTEMPTS: usize = 8;
+
+// The newest record for a key, see Catalog::find: Either the record itself, when no seek was needed
+// to find it, or the table it's in and its offset there.
+pub(crate) enum Found<'a> {
+    Record(ReadRecord),
+    At(&'a Table, u32),
+}
+
+// A description of a table in the catalog, for working out which table a record was read from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TableInfo {
+    pub path: path::PathBuf,
+    pub level: usize,
+    // Level 0 tables may overlap each other, and are searched newest first. Tables in other levels
+    // never overlap another in the same level.
+    pub overlapping: bool,
+    // The number a level 0 table is named with. Every record in it was written after the records in
+    // level 0 tables with smaller numbers, and before those in tables with larger ones. Records
+    // don't carry sequence numbers of their own, so tables in other levels have none, and there is
+    // no finer ordering within a table.
+    pub sequence: Option<u32>,
+    pub key_start: Vec<u8>,
+    pub key_end: Vec<u8>,
+    pub num_entries: usize,
+    // When a scrub last found the table undamaged, by the store's clock, see Store::scrub_now.
+    // Catalogs opened on their own don't scrub, and don't know.
+    pub last_verified: Option<Duration>,
+    split_keys: Vec<Vec<u8>>,
+}
 
-use super::Table;
+impl TableInfo {
+    // Keys that split the table into runs of about the same number of keys, in key order, read
+    // from its footer. A scan of the table can be divided up at them, each part covering from one
+    // split key up to the next. Tables written without Options::table_split_points, or before it
+    // existed, have none.
+    pub fn split_keys(&self) -> &[Vec<u8>] {
+        &self.split_keys
+    }
+}
 
+// The tables of a store, level by level. Tables are reference counted so that a catalog can be
+// cheaply cloned into a snapshot that outlives later changes to the store.
+//
+// A catalog can also be opened on its own, to inspect a store's tables without opening the store:
+//
+//      let catalog = Catalog::new(data_dir)?;
+//      for record in catalog.scan(b"", None)? {
+//          let (key, val) = record?;
+//      }
+//
+// Opening a catalog this way only reads the data directory. Nothing is written, and the store's
+// WAL isn't read, so writes the store hasn't flushed yet aren't seen. The tables are read as they
+// were when it was opened; a store that is open at the same time may compact them away, which
+// open handles keep readable on most platforms.
+#[derive(Clone)]
 pub struct Catalog {
-    pub ssts: Vec<Vec<Table>>, // Index 0 is level 0, 1 is 1, etc.
+    pub(crate) ssts: Vec<Vec<Arc<Table>>>, // Index 0 is level 0, 1 is 1, etc.
     watermark: u32,
     data_dir: path::PathBuf,
+    cold_dir: Option<path::PathBuf>,
+    format: TableFormat,
 }
 
-impl Catalog {
-    pub fn new(data_dir: &path::Path) -> io::Result<Self> {
-        let mut dirs = fs::read_dir(data_dir)?
-            .into_iter()
-            .collect::<io::Result<Vec<fs::DirEntry>>>()?
+// How new tables are laid out, according to the store's options.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct TableFormat {
+    // Index entries carry values up to this size, see Options::inline_value_size.
+    pub inline_value_size: Option<usize>,
+    // The index ends with a filter of key prefixes this long, see Options::prefix_bloom_length.
+    pub prefix_bloom_length: Option<usize>,
+    // No table may be larger than this, see Options::max_sst_file_size.
+    pub max_file_size: Option<usize>,
+    // The footer records the keys that split the table into this many parts, see
+    // Options::table_split_points.
+    pub split_parts: Option<usize>,
+    // Values are encoded with the writer, see Options::value_codec. Tables are read with any of them.
+    pub codecs: Codecs,
+}
+
+impl TableFormat {
+    pub fn new(options: &Options) -> Self {
+        TableFormat {
+            inline_value_size: options.inline_value_size,
+            prefix_bloom_length: options.prefix_bloom_length,
+            max_file_size: options.max_sst_file_size,
+            split_parts: options.table_split_points,
+            codecs: options.codecs.clone(),
+        }
+    }
+
+    // A record as it is written to a table, with its value encoded. Records are encoded before
+    // they are split into tables, so that the tables fit once they are.
+    pub fn encode(&self, record: ReadRecord) -> ReadRecord {
+        match self.codecs.encodes() {
+            true => record.encode(self.codecs.writer().as_ref()),
+            false => record,
+        }
+    }
+
+    // The size of a table with `records` bytes of records, and `entries` bytes of index entries
+    // for `num_entries` keys, whose start and end keys are `keys_length` bytes together and none
+    // of which is longer than `longest_key`. Tables with a prefix filter or split keys may come out
+    // a little smaller.
+    pub fn table_size(
+        &self,
+        records: usize,
+        entries: usize,
+        num_entries: usize,
+        keys_length: usize,
+        longest_key: usize,
+    ) -> usize {
+        let filter = match self.prefix_bloom_length {
+            Some(_) => PrefixFilter::max_length(num_entries),
+            None => 0,
+        };
+        let split_keys = match self.split_parts {
+            Some(parts) => (parts - 1).min(num_entries.saturating_sub(1)) * (4 + longest_key),
+            None => 0,
+        };
+        records + entries + filter + protocol::Footer::new_length(keys_length, split_keys)
+    }
+
+    // The keys of the index entries, in key order, that split them into `split_parts` runs of
+    // about the same length. The first key is never one of them, so a table with fewer keys than
+    // parts has fewer split keys.
+    pub fn split_keys(&self, entries: &[IndexEntry]) -> Vec<Vec<u8>> {
+        let Some(parts) = self.split_parts else {
+            return Vec::new();
+        };
+
+        let mut positions = (1..parts)
+            .map(|i| i * entries.len() / parts)
+            .collect::<Vec<_>>();
+        positions.dedup();
+        positions
             .into_iter()
-            .filter(|entry| entry.path().is_dir())
-            .collect::<Vec<fs::DirEntry>>();
-
-        // Directories will be sorted ascending by the integer value of their name. Each of these
-        // directories represents a compaction level. Level 0 is special and contains the flushed
-        // memtables that have not undergone any compaction: These tables will have overlapping key
-        // ranges. Tables at higher levels will not have overlapping key ranges.
-        dirs.sort_unstable_by_key(|dir| {
-            dir.file_name()
-                .to_str()
-                .expect("must convert dir name to string")
-                .parse::<usize>()
-                .expect("must parse dir name as usize")
-        });
+            .filter(|i| *i > 0)
+            .map(|i| entries[i].key.clone())
+            .collect()
+    }
+
+    // The size of an index entry in tables of this format.
+    pub fn entry_size(&self, entry: &IndexEntry) -> usize {
+        entry.size(self.inline_value_size.is_some())
+    }
+
+    // Whether a table of `size` bytes is within the maximum file size.
+    pub fn fits(&self, size: usize) -> bool {
+        self.max_file_size.is_none_or(|max| size <= max)
+    }
+
+    // Splits records sorted by key into runs that each fit in a table, with `range_deletions`
+    // going in the first of them. A run holds at least one record, or the range deletions, however
+    // large.
+    pub fn split<'a, 'b>(
+        &self,
+        sorted_records: &'a [WriteRecord<'b>],
+        range_deletions: &[WriteRecord],
+    ) -> Vec<&'a [WriteRecord<'b>]> {
+        let mut runs = Vec::new();
+        let mut start = 0;
+        let mut records: usize = range_deletions.iter().map(WriteRecord::size).sum();
+        let mut entries = 0;
+        let mut longest_key = 0;
+        for (i, record) in sorted_records.iter().enumerate() {
+            let entry_size = self.entry_size(&self.index_entry(record.key(), 0, record));
+            let start_key = sorted_records.get(start).map_or(record.key(), |r| r.key());
+            let size = self.table_size(
+                records + record.size(),
+                entries + entry_size,
+                i - start + 1,
+                start_key.len() + record.key().len(),
+                longest_key.max(record.key().len()),
+            );
 
-        let mut watermark = 0;
-        let mut ssts = vec![];
-
-        for (level, dir) in dirs.iter().enumerate() {
-            let mut these_ssts = vec![];
-
-            let files = fs::read_dir(dir.path()).unwrap();
-
-            let mut files = files
-                .into_iter()
-                .collect::<io::Result<Vec<fs::DirEntry>>>()
-                .unwrap()
-                .into_iter()
-                .filter(|file| file.path().is_file())
-                .collect::<Vec<fs::DirEntry>>();
-
-            // Sort files in level 0 in ascending order.
-            if level == 0 {
-                files.sort_unstable_by_key(|file| {
-                    file.path()
-                        .file_stem()
-                        .unwrap()
-                        .to_string_lossy()
-                        .parse::<usize>()
-                        .unwrap()
-                });
+            let run_is_empty = i == start && (!runs.is_empty() || range_deletions.is_empty());
+            if !self.fits(size) && !run_is_empty {
+                runs.push(&sorted_records[start..i]);
+                start = i;
+                records = 0;
+                entries = 0;
+                longest_key = 0;
             }
+            records += record.size();
+            entries += entry_size;
+            longest_key = longest_key.max(record.key().len());
+        }
+        runs.push(&sorted_records[start..]);
 
-            files.into_iter().for_each(|file| {
-                let path = file.path();
-                if let Some(ext) = path.extension() {
-                    if ext.eq_ignore_ascii_case(SST_EXT) {
-                        if level == 0 {
-                            // Parse the file stem (name without extension) to update the watermark.
-                            // The files are already sorted in ascending order.
-                            if let Some(seq) = table_sequence(&path) {
-                                watermark = seq;
-                            }
-                        }
+        runs
+    }
+
+    // The index entry carrying a key's newest record.
+    pub fn index_entry(&self, key: &[u8], offset: u32, record: &WriteRecord) -> IndexEntry {
+        IndexEntry {
+            key: key.to_vec(),
+            offset,
+            inline: self
+                .inline_value_size
+                .and_then(|limit| InlineValue::for_record(record, limit)),
+        }
+    }
+
+    // Writes the index of a table, given its entries in key order.
+    pub fn write_index<W: Write>(&self, w: &mut W, entries: &[IndexEntry]) -> io::Result<()> {
+        let mut filter = self.prefix_bloom_length.map(PrefixFilterBuilder::new);
+        for entry in entries {
+            entry.write_to(w, self.inline_value_size.is_some())?;
+            if let Some(filter) = &mut filter {
+                filter.add(&entry.key);
+            }
+        }
+
+        if let Some(filter) = filter {
+            filter.finish().write_to(w)?;
+        }
+        Ok(())
+    }
+
+    // The footer's flags for an index written by `write_index`.
+    pub fn index_flags(&self) -> Option<u32> {
+        let mut flags = 0;
+        if self.inline_value_size.is_some() {
+            flags |= INDEX_INLINE_VALUES;
+        }
+        if self.prefix_bloom_length.is_some() {
+            flags |= INDEX_PREFIX_FILTER;
+        }
+        if self.codecs.encodes() {
+            flags |= (self.codecs.writer().id() as u32) << INDEX_CODEC_SHIFT;
+        }
+        (flags != 0).then_some(flags)
+    }
+}
+
+impl Catalog {
+    // Loads the tables in `data_dir`, failing if any of them can't be read.
+    pub fn new(data_dir: &path::Path) -> Result<Self, StoreError> {
+        Catalog::open(
+            data_dir,
+            RecoveryMode::Strict,
+            &mut RecoveryReport::default(),
+        )
+    }
+
+    // Loads the tables in `data_dir`, tolerating damage according to `mode`. With
+    // RecoveryMode::BestEffort, tables that can't be opened are left out and noted in `report`.
+    pub fn open(
+        data_dir: &path::Path,
+        mode: RecoveryMode,
+        report: &mut RecoveryReport,
+    ) -> Result<Self, StoreError> {
+        Catalog::open_tiered(data_dir, None, mode, report)
+    }
 
-                        these_ssts.push(Table::new(&path).unwrap());
+    // Same as `open`, but the tables of levels past 0 may also be in `cold_dir`, see
+    // Options::cold_dir. A level 0 directory there is ignored, since nothing is written to it.
+    pub(crate) fn open_tiered(
+        data_dir: &path::Path,
+        cold_dir: Option<&path::Path>,
+        mode: RecoveryMode,
+        report: &mut RecoveryReport,
+    ) -> Result<Self, StoreError> {
+        let (levels, watermark) = list_levels(data_dir, cold_dir)?;
+
+        let mut ssts: Vec<Vec<Arc<Table>>> = Vec::with_capacity(levels.len());
+        for files in levels {
+            let mut tables = Vec::with_capacity(files.len());
+            for path in files {
+                match Table::open(&path, mode, report) {
+                    Ok(table) => tables.push(Arc::new(table)),
+                    Err(e) if mode == RecoveryMode::BestEffort => {
+                        report.skip(&path, 0, format!("skipped unreadable table: {}", e))
                     }
+                    Err(e) => return Err(e),
                 }
-            });
-
-            ssts.push(these_ssts);
+            }
+            ssts.push(tables);
         }
 
         Ok(Catalog {
             ssts,
             watermark,
             data_dir: data_dir.to_owned(),
+            cold_dir: cold_dir.map(path::Path::to_owned),
+            format: TableFormat::default(),
+        })
+    }
+
+    // Loads the tables now on disk, as the catalog was loaded, but reusing the tables past level 0
+    // that it already has open rather than opening them again. Level 0 tables are always opened
+    // again, since their names are used again once level 0 is compacted away. This is for
+    // following a store that another process writes, see Follower. A table that is removed between
+    // being listed and being opened means a compaction has replaced it since, so the tables are
+    // listed again.
+    pub fn refresh(&self) -> Result<Catalog, StoreError> {
+        let open: HashMap<&path::Path, &Arc<Table>> = self
+            .ssts
+            .iter()
+            .skip(1)
+            .flatten()
+            .map(|table| (table.path.as_path(), table))
+            .collect();
+
+        let mut attempts = 0;
+        'list: loop {
+            let (levels, watermark) = list_levels(&self.data_dir, self.cold_dir.as_deref())?;
+
+            let mut ssts: Vec<Vec<Arc<Table>>> = Vec::with_capacity(levels.len());
+            for files in levels {
+                let mut tables = Vec::with_capacity(files.len());
+                for path in files {
+                    if let Some(table) = open.get(path.as_path()) {
+                        tables.push(Arc::clone(table));
+                        continue;
+                    }
+                    match Table::new(&path) {
+                        Ok(table) => {
+                            table.set_codecs(&self.format.codecs)?;
+                            tables.push(Arc::new(table))
+                        }
+                        Err(StoreError::MissingTable { .. }) if attempts < REFRESH_ATTEMPTS => {
+                            attempts += 1;
+                            continue 'list;
+                        }
+                        Err(e) => return Err(e),
+                    }
+                }
+                ssts.push(tables);
+            }
+
+            return Ok(Catalog {
+                ssts,
+                watermark,
+                data_dir: self.data_dir.clone(),
+                cold_dir: self.cold_dir.clone(),
+                format: self.format.clone(),
+            });
+        }
+    }
+
+    // Sets how the tables written by the catalog are laid out, and the codecs its tables are read
+    // with, failing if a table's values are encoded with a codec that isn't among them.
+    pub(crate) fn with_format(mut self, format: TableFormat) -> Result<Self, StoreError> {
+        for table in self.ssts.iter().flatten() {
+            table.set_codecs(&format.codecs)?;
+        }
+        self.format = format;
+        Ok(self)
+    }
+
+    // The number of levels with a directory, counting level 0. Levels past this have no tables.
+    pub fn num_levels(&self) -> usize {
+        self.ssts.len()
+    }
+
+    // The tables in `level`. Level 0 tables are oldest first and may overlap; tables in other
+    // levels are in key order and don't.
+    pub fn tables(&self, level: usize) -> &[Arc<Table>] {
+        self.ssts.get(level).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    // The smallest and largest keys with records in any table, or None if there are no records.
+    pub fn key_range(&self) -> Option<(Vec<u8>, Vec<u8>)> {
+        let tables = self.ssts.iter().flatten().filter(|t| t.num_entries() > 0);
+        tables.fold(None, |range, table| {
+            let (start, end) = (table.key_start(), table.key_end());
+            Some(match range {
+                None => (start, end),
+                Some((s, e)) => (s.min(start), e.max(end)),
+            })
         })
     }
 
-    pub fn get(&self, key: &[u8]) -> io::Result<Option<ReadRecord>> {
+    // The live records with keys in [start, end), or from start onward if there is no end, in
+    // ascending key order. The newest record for each key wins, as it does for a store's scans.
+    pub fn scan(&self, start: &[u8], end: Option<&[u8]>) -> Result<Scan, StoreError> {
+        Scan::new(
+            Arc::new(MemTable::new()),
+            self,
+            start,
+            end,
+            &ReadCounters::default(),
+        )
+    }
+
+    // Every table, level by level, with level 0 tables oldest first.
+    pub fn table_info(&self) -> Vec<TableInfo> {
+        table_info(&self.ssts)
+    }
+
+    pub(crate) fn data_dir(&self) -> &path::Path {
+        &self.data_dir
+    }
+
+    // The newest record for a key in any table, which may be a deletion. A value kept in a blob
+    // file is returned as a reference to it.
+    pub fn get(&self, key: &[u8]) -> Result<Option<ReadRecord>, StoreError> {
+        self.get_counted(key, &ReadCounters::default(), false)
+    }
+
+    // Same as `get`, but records the tables probed and seeks performed into `counters`. With
+    // `verify`, the record read is checked against the key it was looked up by, see
+    // Table::read_verified.
+    pub(crate) fn get_counted(
+        &self,
+        key: &[u8],
+        counters: &ReadCounters,
+        verify: bool,
+    ) -> Result<Option<ReadRecord>, StoreError> {
+        // A record the index carries needs no seek, but can't be checked against the table, so it
+        // is only used when not verifying.
+        match self.find(key, counters, !verify) {
+            Some(Found::Record(record)) => Ok(Some(record)),
+            Some(Found::At(sst, offset)) => {
+                counters.record_seek();
+                sst.read_record(offset, key, verify).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    // Where the newest record for a key is, recording the tables probed into `counters`. With
+    // `inline`, a record the table's index carries is returned as is.
+    pub(crate) fn find(
+        &self,
+        key: &[u8],
+        counters: &ReadCounters,
+        inline: bool,
+    ) -> Option<Found<'_>> {
         // Start at the lowest level (newest data) and check newest to oldest tables for the record.
         // The first one found is returned.
         for level in self.ssts.iter() {
             for sst in level.iter().rev() {
-                if let Some(rec) = sst.get(key)? {
-                    return Ok(Some(rec));
+                counters.record_table_probe();
+                if inline {
+                    if let Some(record) = sst.inline_record(key) {
+                        return Some(Found::Record(record));
+                    }
+                }
+
+                match sst.locate(key) {
+                    Some(offset) => return Some(Found::At(sst, offset)),
+                    // The table's range deletions are held in memory, so no seek is needed.
+                    None if sst.range_deleted(key) => {
+                        return Some(Found::Record(ReadRecord::Deleted { key: key.to_vec() }))
+                    }
+                    None => (),
                 }
             }
         }
 
-        Ok(None)
+        None
+    }
+
+    // Whether any table has a record for a key, or a range deletion covering it. Only the indexes
+    // are consulted, so nothing is read from disk.
+    pub fn has_record(&self, key: &[u8]) -> bool {
+        self.ssts
+            .iter()
+            .flatten()
+            .any(|sst| sst.locate(key).is_some() || sst.range_deleted(key))
     }
 
-    pub fn write_records<'a, T: IntoIterator<Item = WriteRecord<'a>>>(
+    // Up to `limit` versions of a key, newest first, from every table that has one. A range
+    // deletion covering the key ends the search, as a deletion, since it hides every older version.
+    pub fn get_versions(&self, key: &[u8], limit: usize) -> Result<Vec<ReadRecord>, StoreError> {
+        let mut versions = Vec::new();
+
+        for level in self.ssts.iter() {
+            for sst in level.iter().rev() {
+                if versions.len() >= limit {
+                    break;
+                }
+                let table_versions = sst.versions(key)?;
+                let ends_deleted =
+                    matches!(table_versions.last(), Some(ReadRecord::Deleted { .. }));
+                versions.extend(table_versions);
+                if sst.range_deleted(key) {
+                    // A table with only range deletions has a deletion record for the start of the
+                    // first, which is the same deletion.
+                    if !ends_deleted {
+                        versions.push(ReadRecord::Deleted { key: key.to_vec() });
+                    }
+                    versions.truncate(limit);
+                    return Ok(versions);
+                }
+            }
+        }
+
+        versions.truncate(limit);
+        Ok(versions)
+    }
+
+    // Writes the records as a new level 0 table, or several if they are too large for one. The
+    // tables don't overlap each other, and any range deletions go in the first, so that they don't
+    // delete the records written alongside them.
+    pub(crate) fn write_records<'a, T: IntoIterator<Item = WriteRecord<'a>>>(
         &mut self,
         records: T,
-    ) -> io::Result<()> {
-        let mut sorted_records: Vec<WriteRecord> = records.into_iter().collect();
-        sorted_records.sort_unstable_by_key(|v| v.key().to_vec());
+    ) -> Result<(), StoreError> {
+        let (range_deletions, sorted_records) = sort_records(records);
+        let encoded = self.format.codecs.encodes().then(|| {
+            sorted_records
+                .iter()
+                .map(|record| self.format.encode(ReadRecord::from(*record)))
+                .collect::<Vec<_>>()
+        });
+        let sorted_records = match &encoded {
+            Some(encoded) => encoded.iter().map(WriteRecord::from).collect(),
+            None => sorted_records,
+        };
+
+        for (i, run) in self
+            .format
+            .split(&sorted_records, &range_deletions)
+            .into_iter()
+            .enumerate()
+        {
+            let range_deletions = if i == 0 { &range_deletions[..] } else { &[] };
+
+            // Flush to level 0 exclusively.
+            let mut path: path::PathBuf = path::PathBuf::from(&self.data_dir).join("0");
+            path = path.join(format!("{}", self.watermark + 1));
+            path.set_extension(SST_EXT);
+
+            if let Err(source) = write_table(run, range_deletions, &self.format, &path) {
+                return Err(StoreError::Flush { path, source });
+            }
+            // TODO: Instead of reading in this file that was just written, build the SST index
+            // while writing it.
+            let new = Table::new(&path)?;
+            new.set_codecs(&self.format.codecs)?;
+
+            // Add the new table, which must be the highest numbered, to the end of the list of
+            // level 0 tables. This preserves the requirement that the tables be in order of oldest
+            // to newest.
+            if self.ssts.is_empty() {
+                self.ssts.push(Vec::new());
+            }
+            self.ssts[0].push(Arc::new(new));
+
+            self.watermark += 1;
+        }
+
+        Ok(())
+    }
+}
+
+// Separates the range deletions from the rest of the records, which are sorted by key with only the
+// last record given for each key kept.
+fn sort_records<'a, T: IntoIterator<Item = WriteRecord<'a>>>(
+    records: T,
+) -> (Vec<WriteRecord<'a>>, Vec<WriteRecord<'a>>) {
+    let (range_deletions, mut sorted_records): (Vec<WriteRecord>, Vec<WriteRecord>) = records
+        .into_iter()
+        .partition(|record| matches!(record, WriteRecord::RangeDeleted { .. }));
+    // The sort is stable so that if the same key appears more than once, the records for it remain
+    // in the order they were given. The last one wins.
+    sorted_records.sort_by(|a, b| a.key().cmp(b.key()));
+    sorted_records.dedup_by(|later, earlier| {
+        if later.key() == earlier.key() {
+            // The earlier record is the one retained, so replace it with the later one.
+            std::mem::swap(later, earlier);
+            true
+        } else {
+            false
+        }
+    });
+
+    (range_deletions, sorted_records)
+}
+
+// Range deletions only delete keys in older tables. Every other record in the table is taken to
+// have been written after them. The table only appears at `path` once it is complete, replacing any
+// table already there.
+pub(crate) fn write_table(
+    sorted_records: &[WriteRecord],
+    range_deletions: &[WriteRecord],
+    format: &TableFormat,
+    path: &path::Path,
+) -> io::Result<()> {
+    // The level 0 directory may not exist yet.
+    let dir = path.parent().expect("table path must have a parent");
+    fs::create_dir_all(dir).with_path("creating", dir)?;
 
-        // Flush to level 0 exclusively.
-        let mut path: path::PathBuf = path::PathBuf::from(&self.data_dir).join("0");
-        // The level 0 directory may not exist yet.
-        fs::create_dir_all(&path)?;
+    let tmp = path.with_extension(TEMP_EXT);
+    let file = fs::File::create(&tmp).with_path("creating", &tmp)?;
+    // The records take up most of the table.
+    let size = sorted_records
+        .iter()
+        .chain(range_deletions)
+        .map(WriteRecord::size)
+        .sum::<usize>();
+    preallocate(&file, size as u64);
 
-        path = path.join(format!("{}", &self.watermark + 1));
-        path.set_extension(SST_EXT);
+    let mut w = BufWriter::new(&file);
+    write_table_contents(&mut w, sorted_records, range_deletions, format)
+        .and_then(|_| w.flush())
+        .and_then(|_| truncate_to_written(&file))
+        .with_path("writing", &tmp)?;
+    file.sync_all().with_path("syncing", &tmp)?;
 
-        let file = fs::OpenOptions::new()
-            .append(true)
-            .create(true)
-            .open(&path)?;
+    atomic_rename_and_sync(&tmp, path)
+}
 
-        let mut w = BufWriter::new(&file);
+// Records for the same key must be adjacent and newest first, in which case all of them are kept as
+// versions of the key.
+pub(super) fn write_table_contents<W: Write>(
+    w: &mut W,
+    sorted_records: &[WriteRecord],
+    range_deletions: &[WriteRecord],
+    format: &TableFormat,
+) -> io::Result<()> {
+    let w = &mut ChecksumWriter::new(w);
 
-        let mut index_offsets: HashMap<&[u8], u32> = HashMap::new();
+    // A table must have at least one indexed record to give it a key range. The start of a range
+    // deletion is one of the keys it deletes, so a deletion record for it changes nothing.
+    let anchor;
+    let sorted_records = match (sorted_records, range_deletions.first()) {
+        ([], Some(first)) => {
+            anchor = [WriteRecord::Deleted { key: first.key() }];
+            &anchor[..]
+        }
+        _ => sorted_records,
+    };
 
-        // Write the records. After the records comes the index.
-        let index_start = sorted_records.iter().try_fold(0, |written, record| {
-            index_offsets.insert(record.key(), written);
-            Ok::<u32, io::Error>(written + record.write_to(&mut w)? as u32)
+    // Write the records, followed by any range deletions. After those comes the index, which has an
+    // entry for just the first of the records for each key.
+    let mut entries = Vec::new();
+    let records_end = sorted_records
+        .iter()
+        .enumerate()
+        .try_fold(0, |written, (i, record)| {
+            if i == 0 || sorted_records[i - 1].key() != record.key() {
+                entries.push(format.index_entry(record.key(), written, record));
+            }
+            Ok::<u32, io::Error>(written + record.write_to(w)? as u32)
+        })?;
+    let index_start = range_deletions
+        .iter()
+        .try_fold(records_end, |written, record| {
+            Ok::<u32, io::Error>(written + record.write_to(w)? as u32)
         })?;
+    format.write_index(w, &entries)?;
 
-        // Write the index.
-        for record in &sorted_records {
-            let key = record.key();
-
-            let offset = index_offsets
-                .get(record.key())
-                .expect("must get key that was just written");
-            w.write(&offset.to_le_bytes())?;
-
-            w.write(&(key.len() as u32).to_le_bytes())?;
-            w.write(key)?;
-        }
-
-        // Write the footer.
-        let footer = protocol::Footer {
-            start_key: sorted_records
-                .first()
-                .expect("records must not be empty")
-                .key()
-                .to_owned(),
-            end_key: sorted_records
-                .last()
-                .expect("records must not be empty")
-                .key()
-                .to_owned(),
-            index_start,
-            footer_length: None,
-        };
-        footer.write_to(&mut w)?;
+    // Write the footer.
+    let footer = protocol::Footer {
+        start_key: sorted_records
+            .first()
+            .expect("records must not be empty")
+            .key()
+            .to_owned(),
+        end_key: sorted_records
+            .last()
+            .expect("records must not be empty")
+            .key()
+            .to_owned(),
+        index_start,
+        range_deletions_start: (!range_deletions.is_empty()).then_some(records_end),
+        num_entries: Some(entries.len() as u32),
+        data_length: Some(records_end),
+        index_flags: format.index_flags(),
+        split_keys: format.split_keys(&entries),
+        checksum: None,
+        footer_length: None,
+    };
+    footer.write_checksummed(w)?;
 
-        w.flush()?;
-        file.sync_all()?;
+    Ok(())
+}
 
-        // TODO: Instead of reading in this file that was just written, build the SST index while
-        // writing it.
-        let new = Table::new(&path)?;
+// The tables in each level of `data_dir`, and of `cold_dir` past level 0, by level number, along
+// with the highest sequence number of the level 0 tables. Level 0 is in the order its tables were
+// written.
+fn list_levels(
+    data_dir: &path::Path,
+    cold_dir: Option<&path::Path>,
+) -> Result<(Vec<Vec<path::PathBuf>>, u32), StoreError> {
+    let mut dirs = Vec::new();
+    for root in std::iter::once(data_dir).chain(cold_dir) {
+        let list_err = |e| StoreError::CatalogInitialization(path_error("listing", root, e));
+        for entry in fs::read_dir(root).map_err(list_err)? {
+            let path = entry.map_err(list_err)?.path();
+            if path.is_dir() {
+                let level = level_number(&path)?;
+                if level > 0 || root == data_dir {
+                    dirs.push((level, path));
+                }
+            }
+        }
+    }
+
+    // Directories will be sorted ascending by the integer value of their name. Each of these
+    // directories represents a compaction level. Level 0 is special and contains the flushed
+    // memtables that have not undergone any compaction: These tables will have overlapping key
+    // ranges. Tables at higher levels will not have overlapping key ranges, including with the
+    // tables of the same level in the other directory.
+    dirs.sort_unstable_by_key(|(level, _)| *level);
+
+    let mut watermark = 0;
+    let mut levels: Vec<Vec<path::PathBuf>> = vec![];
+
+    for (level, dir) in dirs {
+        let read_dir = |source| StoreError::Read {
+            path: dir.clone(),
+            source,
+        };
 
-        // Add the new table, which must be the highest numbered, to the end of the list of level 0
-        // tables. This preserves the requirement that the tables be in order of oldest to newest.
-        if self.ssts.len() == 0 {
-            self.ssts.push(Vec::new());
+        let mut files = Vec::new();
+        for entry in fs::read_dir(&dir).map_err(read_dir)? {
+            let path = entry.map_err(read_dir)?.path();
+            let is_sst = path
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case(SST_EXT));
+            if path.is_file() && is_sst {
+                files.push(path);
+            }
         }
-        self.ssts[0].push(new);
 
-        self.watermark += 1;
+        // Sort files in level 0 in ascending order.
+        if level == 0 {
+            let mut sequenced = Vec::with_capacity(files.len());
+            for path in files {
+                match table_sequence(&path) {
+                    Some(seq) => sequenced.push((seq, path)),
+                    None => {
+                        return Err(StoreError::Corruption {
+                            path,
+                            offset: 0,
+                            detail: "level 0 table name is not a sequence number".to_string(),
+                        })
+                    }
+                }
+            }
+            sequenced.sort_unstable_by_key(|(seq, _)| *seq);
 
-        Ok(())
+            // The files are sorted in ascending order, so the last one is the watermark.
+            if let Some((seq, _)) = sequenced.last() {
+                watermark = *seq;
+            }
+            files = sequenced.into_iter().map(|(_, path)| path).collect();
+        }
+
+        // Levels without a directory are empty.
+        if levels.len() <= level {
+            levels.resize_with(level + 1, Vec::new);
+        }
+        levels[level].extend(files);
     }
+
+    Ok((levels, watermark))
+}
+
+pub(super) fn table_sequence(path: &path::Path) -> Option<u32> {
+    path.file_stem()?.to_str()?.parse().ok()
+}
+
+// Describes every table in `ssts`, as Catalog::table_info does.
+pub(crate) fn table_info(ssts: &[Vec<Arc<Table>>]) -> Vec<TableInfo> {
+    ssts.iter()
+        .enumerate()
+        .flat_map(|(level, tables)| {
+            tables.iter().map(move |table| TableInfo {
+                path: table.path.clone(),
+                level,
+                overlapping: level == 0,
+                sequence: table.sequence(),
+                key_start: table.key_start(),
+                key_end: table.key_end(),
+                num_entries: table.num_entries(),
+                last_verified: None,
+                split_keys: table.split_keys().to_vec(),
+            })
+        })
+        .collect()
+}
+
+fn level_number(dir: &path::Path) -> Result<usize, StoreError> {
+    dir.file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| name.parse().ok())
+        .ok_or_else(|| StoreError::Corruption {
+            path: dir.to_owned(),
+            offset: 0,
+            detail: "level directory name is not an integer".to_string(),
+        })
+}
+
+// Writes a level 0 table of key1 and key2, and a deletion of key3, to a new store in `data_dir`,
+// returning its path. Shared by the tests of the sst modules.
+#[cfg(test)]
+pub(crate) fn write_test_table(data_dir: &path::Path) -> path::PathBuf {
+    let mut memtable = crate::memtable::MemTable::new();
+    memtable.put(b"key1", b"val1");
+    memtable.put(b"key2", b"val2");
+    memtable.del(b"key3");
+
+    let mut catalog = Catalog::new(data_dir).unwrap();
+    catalog.write_records(&memtable).unwrap();
+
+    data_dir.join("0").join("1.sst")
 }
 
-fn table_sequence(path: &path::Path) -> Option<u32> {
-    path.file_stem().unwrap().to_string_lossy().parse().ok()
+#[cfg(test)]
+mod tests {
+    use std::{
+        alloc::{GlobalAlloc, Layout, System},
+        cell::Cell,
+        io::{Seek, SeekFrom},
+    };
+
+    use tempdir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_write_records_duplicate_keys() {
+        let dir = TempDir::new("testing").unwrap();
+        let mut catalog = Catalog::new(dir.path()).unwrap();
+
+        catalog
+            .write_records(vec![
+                WriteRecord::Exists {
+                    key: b"key2",
+                    val: b"val2_1",
+                },
+                WriteRecord::Exists {
+                    key: b"key1",
+                    val: b"val1_1",
+                },
+                WriteRecord::Exists {
+                    key: b"key2",
+                    val: b"val2_2",
+                },
+                WriteRecord::Deleted { key: b"key1" },
+                WriteRecord::Exists {
+                    key: b"key2",
+                    val: b"val2_3",
+                },
+                WriteRecord::Deleted { key: b"key3" },
+                WriteRecord::Exists {
+                    key: b"key3",
+                    val: b"val3_1",
+                },
+            ])
+            .unwrap();
+
+        let records = catalog.ssts[0][0]
+            .iter()
+            .unwrap()
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(
+            vec![
+                ReadRecord::Deleted {
+                    key: b"key1".to_vec()
+                },
+                ReadRecord::Exists {
+                    key: b"key2".to_vec(),
+                    val: b"val2_3".to_vec()
+                },
+                ReadRecord::Exists {
+                    key: b"key3".to_vec(),
+                    val: b"val3_1".to_vec()
+                },
+            ],
+            records
+        );
+    }
+
+    // Counts the allocations made by each thread, so that a test can tell how many something made
+    // while other tests run alongside it.
+    struct CountingAllocator;
+
+    thread_local! {
+        static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    fn allocations<T>(f: impl FnOnce() -> T) -> (usize, T) {
+        let before = ALLOCATIONS.with(Cell::get);
+        let out = f();
+        (ALLOCATIONS.with(Cell::get) - before, out)
+    }
+
+    #[test]
+    fn test_flush_allocations() {
+        let keys = (0..1000)
+            .rev()
+            .map(|i| format!("key{:04}", i).into_bytes())
+            .collect::<Vec<_>>();
+        let records = keys
+            .iter()
+            .map(|key| WriteRecord::Exists { key, val: b"val" })
+            .collect::<Vec<_>>();
+
+        // Sorting allocates for the lists of records, but not for each record.
+        let (n, (range_deletions, sorted)) = allocations(|| sort_records(records));
+        assert!(n < 50, "sorting made {} allocations", n);
+        assert_eq!(b"key0000", sorted[0].key());
+
+        // Writing a table allocates once for each index entry, and otherwise only for the table as
+        // a whole.
+        let mut table = Vec::with_capacity(64 * 1024);
+        let format = TableFormat::new(&Options::default());
+        let (n, result) =
+            allocations(|| write_table_contents(&mut table, &sorted, &range_deletions, &format));
+        result.unwrap();
+        assert!(n < sorted.len() + 50, "writing made {} allocations", n);
+    }
+
+    #[test]
+    fn test_truncated_tables() {
+        let dir = TempDir::new("testing").unwrap();
+        let path = write_test_table(dir.path());
+        let len = fs::metadata(&path).unwrap().len();
+
+        // Chopping off part of the footer, all of the footer, or nearly the entire file must produce
+        // an error rather than a panic.
+        for truncate_to in [len - 1, len - 10, 20, 3, 0] {
+            let file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+            file.set_len(truncate_to).unwrap();
+
+            assert!(matches!(
+                Catalog::new(dir.path()),
+                Err(StoreError::Corruption { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_corrupt_footer_lengths() {
+        let dir = TempDir::new("testing").unwrap();
+        let path = write_test_table(dir.path());
+
+        // Overwrite the start key length with something enormous.
+        let footer =
+            protocol::Footer::new_from_reader(&mut fs::File::open(&path).unwrap()).unwrap();
+        let mut file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.seek(SeekFrom::End(-(footer.footer_length.unwrap() as i64)))
+            .unwrap();
+        file.write_all(&u32::MAX.to_le_bytes()).unwrap();
+
+        assert!(matches!(
+            Catalog::new(dir.path()),
+            Err(StoreError::Corruption { .. })
+        ));
+    }
+
+    #[test]
+    fn test_corrupt_record() {
+        let dir = TempDir::new("testing").unwrap();
+        let path = write_test_table(dir.path());
+
+        // The first record in the table is for key1. Give it an invalid op byte.
+        let mut file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.write_all(b"x").unwrap();
+
+        let catalog = Catalog::new(dir.path()).unwrap();
+        match catalog.get(b"key1") {
+            Err(StoreError::Corruption { offset, .. }) => assert_eq!(0, offset),
+            _ => panic!("expected corruption error"),
+        }
+        assert!(catalog.get(b"key2").unwrap().is_some());
+    }
+
+    #[test]
+    fn test_preallocated_length() {
+        let dir = TempDir::new("testing").unwrap();
+        let records = (0..100u32)
+            .map(|i| (i.to_be_bytes(), [i as u8; 100]))
+            .collect::<Vec<_>>();
+        let records = records
+            .iter()
+            .map(|(key, val)| WriteRecord::Exists { key, val })
+            .collect::<Vec<_>>();
+        let range_deletions = [WriteRecord::RangeDeleted {
+            start: b"a",
+            end: None,
+        }];
+
+        // The space reserved for the table is given back once it is written, leaving exactly what
+        // was written.
+        let path = dir.path().join("0").join("1.sst");
+        write_table(&records, &range_deletions, &TableFormat::default(), &path).unwrap();
+        let mut written = Vec::new();
+        write_table_contents(
+            &mut written,
+            &records,
+            &range_deletions,
+            &TableFormat::default(),
+        )
+        .unwrap();
+        assert_eq!(written.len() as u64, fs::metadata(&path).unwrap().len());
+        assert!(Table::new(&path).unwrap().verify().unwrap().is_some());
+    }
+
+    #[test]
+    fn test_crash_before_rename() {
+        let dir = TempDir::new("testing").unwrap();
+        write_test_table(dir.path());
+
+        // A flush that crashes after writing its table, but before renaming it into place, leaves
+        // only the temporary file behind.
+        let mut catalog = Catalog::new(dir.path()).unwrap();
+        crate::durable::tests::CRASH_BEFORE_RENAME.set(true);
+        let result = catalog.write_records(vec![WriteRecord::Exists {
+            key: b"key4",
+            val: b"val4",
+        }]);
+        crate::durable::tests::CRASH_BEFORE_RENAME.set(false);
+        assert!(matches!(result, Err(StoreError::Flush { .. })));
+        let tmp = dir.path().join("0").join("2.tmp");
+        assert!(tmp.exists());
+
+        // The catalog opens to what it was before the flush.
+        let catalog = Catalog::new(dir.path()).unwrap();
+        assert_eq!(1, catalog.ssts[0].len());
+        assert!(catalog.get(b"key1").unwrap().is_some());
+        assert!(catalog.get(b"key4").unwrap().is_none());
+
+        super::super::remove_obsolete(dir.path(), &super::super::Pins::default()).unwrap();
+        assert!(!tmp.exists());
+    }
+
+    #[test]
+    fn test_unexpected_names() {
+        let dir = TempDir::new("testing").unwrap();
+        write_test_table(dir.path());
+
+        // Directories where files are expected, and files that aren't tables, are ignored.
+        fs::create_dir(dir.path().join("0").join("2.sst")).unwrap();
+        fs::write(dir.path().join("0").join("notes.txt"), b"hello").unwrap();
+        fs::write(dir.path().join("1"), b"not a level").unwrap();
+        let catalog = Catalog::new(dir.path()).unwrap();
+        assert_eq!(1, catalog.ssts[0].len());
+
+        // A level 0 table that isn't named by its sequence number is an error.
+        fs::write(dir.path().join("0").join("abc.sst"), b"").unwrap();
+        assert!(matches!(
+            Catalog::new(dir.path()),
+            Err(StoreError::Corruption { .. })
+        ));
+        fs::remove_file(dir.path().join("0").join("abc.sst")).unwrap();
+
+        // As is a level directory that isn't named by its level.
+        fs::create_dir(dir.path().join("level")).unwrap();
+        assert!(matches!(
+            Catalog::new(dir.path()),
+            Err(StoreError::Corruption { .. })
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_non_utf8_names() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let dir = TempDir::new("testing").unwrap();
+        write_test_table(dir.path());
+
+        fs::write(
+            dir.path()
+                .join("0")
+                .join(OsStr::from_bytes(b"\xff\xfe.sst")),
+            b"",
+        )
+        .unwrap();
+        assert!(matches!(
+            Catalog::new(dir.path()),
+            Err(StoreError::Corruption { .. })
+        ));
+        fs::remove_file(
+            dir.path()
+                .join("0")
+                .join(OsStr::from_bytes(b"\xff\xfe.sst")),
+        )
+        .unwrap();
+
+        fs::create_dir(dir.path().join(OsStr::from_bytes(b"\xff\xfe"))).unwrap();
+        assert!(matches!(
+            Catalog::new(dir.path()),
+            Err(StoreError::Corruption { .. })
+        ));
+    }
 }
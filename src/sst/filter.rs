@@ -0,0 +1,217 @@
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use crate::protocol::read_bytes;
+
+// Filter bits per distinct prefix, which gives a false positive rate of about 1%.
+const BITS_PER_PREFIX: usize = 10;
+const NUM_HASHES: u32 = 6;
+
+// Number of hashes, prefix length, and the length of the whole filter.
+const TRAILER_LENGTH: u32 = 12;
+
+// A bloom filter of the first `prefix_length` bytes of each key in a table, so that a scan of a
+// prefix at least that long can skip tables without any keys that start with it. Keys shorter than
+// the prefix length are added whole. With Options::prefix_bloom_length, the filter ends the index
+// of each table, after the index entries:
+//
+//      [bits][num_hashes: u32][prefix_length: u32][filter_length: u32]
+//
+// The filter length includes the trailer, so that the entries can be told apart from the filter
+// by reading the last 4 bytes of the index.
+#[derive(Debug)]
+pub struct PrefixFilter {
+    prefix_length: usize,
+    num_hashes: u32,
+    bits: Vec<u8>,
+}
+
+impl PrefixFilter {
+    // Whether the table may have keys starting with `prefix`. A prefix shorter than the filter's
+    // prefix length can't be checked, and always may.
+    pub fn may_contain(&self, prefix: &[u8]) -> bool {
+        if prefix.len() < self.prefix_length {
+            return true;
+        }
+
+        let num_bits = self.bits.len() as u64 * 8;
+        bit_positions(
+            hash(&prefix[..self.prefix_length]),
+            self.num_hashes,
+            num_bits,
+        )
+        .all(|bit| self.bits[(bit / 8) as usize] & (1 << (bit % 8)) != 0)
+    }
+
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<usize> {
+        w.write_all(&self.bits)?;
+        w.write_all(&self.num_hashes.to_le_bytes())?;
+        w.write_all(&(self.prefix_length as u32).to_le_bytes())?;
+        let length = self.bits.len() as u32 + TRAILER_LENGTH;
+        w.write_all(&length.to_le_bytes())?;
+        Ok(length as usize)
+    }
+
+    // The most a filter of `prefixes` prefixes can take up in a table.
+    pub fn max_length(prefixes: usize) -> usize {
+        (prefixes * BITS_PER_PREFIX).div_ceil(8).max(8) + TRAILER_LENGTH as usize
+    }
+
+    // The length of the filter that ends at `index_end`, which is where the footer starts.
+    pub fn length_at<R: Read + Seek>(r: &mut R, index_end: u64) -> io::Result<u32> {
+        r.seek(SeekFrom::Start(
+            index_end.checked_sub(4).ok_or_else(too_short)?,
+        ))?;
+        let mut buf = [0; 4];
+        r.read_exact(&mut buf)?;
+        let length = u32::from_le_bytes(buf);
+        if length < TRAILER_LENGTH || length as u64 > index_end {
+            return Err(too_short());
+        }
+        Ok(length)
+    }
+
+    // Reads the filter that ends at `index_end`.
+    pub fn read_from<R: Read + Seek>(r: &mut R, index_end: u64) -> io::Result<Self> {
+        let length = PrefixFilter::length_at(r, index_end)?;
+        r.seek(SeekFrom::Start(index_end - length as u64))?;
+        let bits = read_bytes(r, length - TRAILER_LENGTH)?;
+
+        let mut buf = [0; 4];
+        r.read_exact(&mut buf)?;
+        let num_hashes = u32::from_le_bytes(buf);
+        r.read_exact(&mut buf)?;
+        let prefix_length = u32::from_le_bytes(buf) as usize;
+        if bits.is_empty() || num_hashes == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "prefix filter has no bits or hashes",
+            ));
+        }
+
+        Ok(PrefixFilter {
+            prefix_length,
+            num_hashes,
+            bits,
+        })
+    }
+}
+
+fn too_short() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "prefix filter length is out of range",
+    )
+}
+
+// Collects the prefixes of a table's keys, which must be added in ascending order.
+pub struct PrefixFilterBuilder {
+    prefix_length: usize,
+    hashes: Vec<u64>,
+    last: Option<Vec<u8>>,
+}
+
+impl PrefixFilterBuilder {
+    pub fn new(prefix_length: usize) -> Self {
+        PrefixFilterBuilder {
+            prefix_length,
+            hashes: Vec::new(),
+            last: None,
+        }
+    }
+
+    pub fn add(&mut self, key: &[u8]) {
+        let prefix = &key[..key.len().min(self.prefix_length)];
+        // Keys with the same prefix are adjacent, so each prefix is only hashed once.
+        if self.last.as_deref() != Some(prefix) {
+            self.hashes.push(hash(prefix));
+            self.last = Some(prefix.to_vec());
+        }
+    }
+
+    pub fn finish(self) -> PrefixFilter {
+        let num_bytes = (self.hashes.len() * BITS_PER_PREFIX).div_ceil(8).max(8);
+        let mut bits = vec![0; num_bytes];
+        for h in self.hashes {
+            for bit in bit_positions(h, NUM_HASHES, num_bytes as u64 * 8) {
+                bits[(bit / 8) as usize] |= 1 << (bit % 8);
+            }
+        }
+
+        PrefixFilter {
+            prefix_length: self.prefix_length,
+            num_hashes: NUM_HASHES,
+            bits,
+        }
+    }
+}
+
+// The bits for a hash, by double hashing with its two halves.
+fn bit_positions(hash: u64, num_hashes: u32, num_bits: u64) -> impl Iterator<Item = u64> {
+    let (h1, h2) = (hash & 0xffff_ffff, hash >> 32);
+    (0..num_hashes as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % num_bits)
+}
+
+// 64 bit FNV-1a, which is stable across builds, unlike the standard library's hashers. Its high
+// bits change little between keys that differ only at the end, so they are mixed in with the
+// finalizer from MurmurHash3.
+fn hash(bytes: &[u8]) -> u64 {
+    let mut h: u64 = 0xcbf2_9ce4_8422_2325;
+    for b in bytes {
+        h ^= *b as u64;
+        h = h.wrapping_mul(0x0100_0000_01b3);
+    }
+
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    h ^ (h >> 33)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prefix_filter() {
+        let mut builder = PrefixFilterBuilder::new(4);
+        for i in 0..100u32 {
+            builder.add(format!("user{:03}/a", i * 2).as_bytes());
+            builder.add(format!("user{:03}/b", i * 2).as_bytes());
+        }
+        builder.add(b"abc");
+        let filter = builder.finish();
+
+        // Every prefix that was added may be there. Shorter prefixes can't be checked.
+        assert!(filter.may_contain(b"user"));
+        assert!(filter.may_contain(b"user042/"));
+        assert!(filter.may_contain(b"abc"));
+        assert!(filter.may_contain(b"us"));
+        assert!(!filter.may_contain(b"item"));
+        assert!(!filter.may_contain(b"item042"));
+
+        let mut buf = Vec::new();
+        let length = filter.write_to(&mut buf).unwrap();
+        buf.extend_from_slice(b"footer");
+        let mut r = io::Cursor::new(&buf);
+        assert_eq!(
+            length as u32,
+            PrefixFilter::length_at(&mut r, length as u64).unwrap()
+        );
+        let read = PrefixFilter::read_from(&mut r, length as u64).unwrap();
+        assert!(read.may_contain(b"user123"));
+        assert!(!read.may_contain(b"item"));
+
+        // With the prefix length covering the whole distinguishing part, absent prefixes are
+        // mostly ruled out.
+        let mut builder = PrefixFilterBuilder::new(7);
+        for i in 0..100u32 {
+            builder.add(format!("user{:03}/a", i * 2).as_bytes());
+        }
+        let filter = builder.finish();
+        let false_positives = (0..100u32)
+            .filter(|i| filter.may_contain(format!("user{:03}", i * 2 + 1).as_bytes()))
+            .count();
+        assert!(false_positives < 10, "{}", false_positives);
+    }
+}
@@ -0,0 +1,73 @@
+// Reading a store's tables from another process while the store is open and being written, such
+// as by read-only analytics processes alongside the one process that writes. Only the tables are
+// read: Writes still in the writer's memtable aren't seen until they are flushed.
+//
+// A catalog is a snapshot of the tables as they were when it was loaded, and compactions in the
+// writer soon replace them. A follower keeps to the latest tables by loading the catalog again
+// once it is older than the refresh interval. Tables are read through handles opened when they are
+// loaded, so a catalog that is still in use keeps working after the writer removes its tables, as
+// long as the filesystem keeps removed files readable for handles already open to them. Where it
+// doesn't, such as a filesystem shared between machines, the writer should keep replaced tables
+// around for longer than any catalog is used, see Options::obsolete_table_grace.
+
+use std::{
+    path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::{
+    clock::{Clock, SystemClock},
+    StoreError,
+};
+
+use super::Catalog;
+
+pub struct Follower {
+    interval: Duration,
+    clock: Arc<dyn Clock>,
+    // The latest catalog, and when it was loaded by the clock.
+    latest: Mutex<(Arc<Catalog>, Duration)>,
+}
+
+impl Follower {
+    // Follows the store in `data_dir`, loading its catalog again whenever it is asked for once
+    // `interval` has passed since it was last loaded.
+    pub fn open(data_dir: &path::Path, interval: Duration) -> Result<Self, StoreError> {
+        Follower::with_clock(data_dir, interval, Arc::new(SystemClock::default()))
+    }
+
+    // Same as `open`, but with time read from `clock`.
+    pub fn with_clock(
+        data_dir: &path::Path,
+        interval: Duration,
+        clock: Arc<dyn Clock>,
+    ) -> Result<Self, StoreError> {
+        let catalog = Catalog::new(data_dir)?;
+        let now = clock.monotonic();
+        Ok(Follower {
+            interval,
+            clock,
+            latest: Mutex::new((Arc::new(catalog), now)),
+        })
+    }
+
+    // The latest catalog, loading it again first if it is older than the refresh interval. The
+    // catalog can be kept for as long as it is needed, but doesn't change once returned.
+    pub fn catalog(&self) -> Result<Arc<Catalog>, StoreError> {
+        let now = self.clock.monotonic();
+        let mut latest = self.latest.lock().unwrap();
+        if now.saturating_sub(latest.1) >= self.interval {
+            *latest = (Arc::new(latest.0.refresh()?), now);
+        }
+        Ok(latest.0.clone())
+    }
+
+    // Loads the catalog again now, whatever its age.
+    pub fn refresh(&self) -> Result<Arc<Catalog>, StoreError> {
+        let now = self.clock.monotonic();
+        let mut latest = self.latest.lock().unwrap();
+        *latest = (Arc::new(latest.0.refresh()?), now);
+        Ok(latest.0.clone())
+    }
+}
@@ -1,49 +1,153 @@
 use std::{
-    collections::HashMap,
-    io::{self, Read, Seek, SeekFrom},
+    collections::BTreeMap,
+    io::{self, Read, Seek, SeekFrom, Write},
+    ops::Bound,
 };
 
-use crate::protocol::Footer;
+use crate::protocol::{read_bytes, Footer, WriteRecord};
+
+use super::PrefixFilter;
+
+// In a table whose footer has INDEX_INLINE_VALUES, each index entry ends with one of these tags.
+// INLINE_VALUE_TAG is followed by the length of the value as a u32, and the value.
+const NOT_INLINE_TAG: u8 = 0;
+const INLINE_VALUE_TAG: u8 = 1;
+const INLINE_DELETED_TAG: u8 = 2;
 
 pub struct Index {
-    map: HashMap<Vec<u8>, u32>, // Keys (as byte slices) to file offsets
+    // Keys to file offsets, in key order so that a scan can start part way through the table.
+    map: BTreeMap<Vec<u8>, Entry>,
     pub key_start: Vec<u8>,
     pub key_end: Vec<u8>,
 }
 
+struct Entry {
+    offset: u32,
+    inline: Option<InlineValue>,
+}
+
 impl Index {
     pub fn get_offset(&self, key: &[u8]) -> Option<&u32> {
-        self.map.get(key)
+        self.map.get(key).map(|entry| &entry.offset)
+    }
+
+    // The newest record for a key, if the index carries it, which saves reading it from the table.
+    pub fn get_inline(&self, key: &[u8]) -> Option<&InlineValue> {
+        self.map.get(key)?.inline.as_ref()
+    }
+
+    // The offset of the record for the first key at or after `key`, if there is one.
+    pub fn seek_offset(&self, key: &[u8]) -> Option<u32> {
+        self.map
+            .range::<[u8], _>((Bound::Included(key), Bound::Unbounded))
+            .next()
+            .map(|(_, entry)| entry.offset)
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
     }
 
     pub fn from_index_reader<T: Read + Seek>(r: IndexReader<T>) -> io::Result<Index> {
-        let mut map = HashMap::new();
+        Index::from_entries(r)
+    }
+
+    pub fn from_entries<I: IntoIterator<Item = io::Result<IndexEntry>>>(
+        entries: I,
+    ) -> io::Result<Index> {
+        let mut map = BTreeMap::new();
 
-        // Requirement: IndexReader iterates through keys in ascending sorted order.
+        // Requirement: Entries are in ascending sorted order by key.
         let mut key_start = None;
         let mut key_end = None;
 
-        for i in r {
+        for i in entries {
             let i = i?;
             if key_start.is_none() {
                 key_start = Some(i.key.clone());
             }
             key_end = Some(i.key.clone());
 
-            map.insert(i.key, i.offset);
+            // A key with more than one version is indexed by its first, and newest, record.
+            map.entry(i.key).or_insert(Entry {
+                offset: i.offset,
+                inline: i.inline,
+            });
+        }
+
+        match (key_start, key_end) {
+            (Some(key_start), Some(key_end)) => Ok(Index {
+                map,
+                key_start,
+                key_end,
+            }),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "table index has no entries",
+            )),
         }
+    }
+}
 
-        Ok(Index {
-            map,
-            key_start: key_start.expect("IndexReader must have a key"),
-            key_end: key_end.expect("IndexReader must have a key"),
-        })
+// The newest record for a key, carried in its index entry.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InlineValue {
+    Value(Vec<u8>),
+    Deleted,
+}
+
+impl InlineValue {
+    // What to carry in the index entry for a key's newest record, given the largest value to
+    // inline. Values in blob files are never inlined, since reading them means going to disk anyway.
+    pub fn for_record(record: &WriteRecord, limit: usize) -> Option<Self> {
+        match record {
+            WriteRecord::Exists { val, .. } if val.len() <= limit => {
+                Some(InlineValue::Value(val.to_vec()))
+            }
+            WriteRecord::Deleted { .. } => Some(InlineValue::Deleted),
+            _ => None,
+        }
     }
 }
 
 pub struct IndexEntry {
-    key: Vec<u8>,
-    offset: u32,
+    pub key: Vec<u8>,
+    pub offset: u32,
+    // Only ever set for tables with INDEX_INLINE_VALUES.
+    pub inline: Option<InlineValue>,
+}
+
+impl IndexEntry {
+    // Writes the entry, ending with its inline value if the table has them.
+    pub fn write_to<W: Write>(&self, w: &mut W, inline_values: bool) -> io::Result<usize> {
+        w.write_all(&self.offset.to_le_bytes())?;
+        w.write_all(&(self.key.len() as u32).to_le_bytes())?;
+        w.write_all(&self.key)?;
+
+        if inline_values {
+            match &self.inline {
+                None => w.write_all(&[NOT_INLINE_TAG])?,
+                Some(InlineValue::Deleted) => w.write_all(&[INLINE_DELETED_TAG])?,
+                Some(InlineValue::Value(val)) => {
+                    w.write_all(&[INLINE_VALUE_TAG])?;
+                    w.write_all(&(val.len() as u32).to_le_bytes())?;
+                    w.write_all(val)?;
+                }
+            }
+        }
+
+        Ok(self.size(inline_values))
+    }
+
+    // Size of the entry in bytes, as written by `write_to`.
+    pub fn size(&self, inline_values: bool) -> usize {
+        let inline = match &self.inline {
+            _ if !inline_values => 0,
+            Some(InlineValue::Value(val)) => 1 + 4 + val.len(),
+            _ => 1,
+        };
+        4 + 4 + self.key.len() + inline
+    }
 }
 
 pub struct IndexReader<T: Read + Seek>(pub T);
@@ -59,6 +163,8 @@ impl<T: Read + Seek> IntoIterator for IndexReader<T> {
             setup_err: None,
             index_length: 0,
             read: 0,
+            inline_values: false,
+            records_end: 0,
         };
 
         let seeked = match index_iter.r.seek(SeekFrom::End(-4)) {
@@ -77,6 +183,33 @@ impl<T: Read + Seek> IntoIterator for IndexReader<T> {
             }
         };
 
+        // Footer::new_from_reader has already verified that the index start and footer fit within
+        // the file.
+        index_iter.index_length = seeked as u32 + 4
+            - footer.index_start
+            - footer.footer_length.expect("footer must have length");
+
+        // A prefix filter ends the index, after the entries.
+        if footer.prefix_filter() {
+            let index_end = footer.index_start as u64 + index_iter.index_length as u64;
+            match PrefixFilter::length_at(&mut index_iter.r, index_end) {
+                Ok(length) if length <= index_iter.index_length => {
+                    index_iter.index_length -= length
+                }
+                Ok(length) => {
+                    index_iter.setup_err = Some(Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("prefix filter length {} is longer than the index", length),
+                    )));
+                    return index_iter;
+                }
+                Err(e) => {
+                    index_iter.setup_err = Some(Err(e));
+                    return index_iter;
+                }
+            }
+        }
+
         if let Err(e) = index_iter
             .r
             .seek(SeekFrom::Start(footer.index_start as u64))
@@ -85,9 +218,9 @@ impl<T: Read + Seek> IntoIterator for IndexReader<T> {
             return index_iter;
         }
 
-        index_iter.index_length = seeked as u32 + 4
-            - footer.index_start
-            - footer.footer_length.expect("footer must have length");
+        index_iter.done = index_iter.index_length == 0;
+        index_iter.inline_values = footer.inline_values();
+        index_iter.records_end = footer.records_end();
 
         index_iter
     }
@@ -99,6 +232,10 @@ pub struct IndexIter<T: Read> {
     setup_err: Option<io::Result<IndexEntry>>,
     index_length: u32, // In bytes
     read: u32,
+    inline_values: bool,
+    // Every entry must point at a record before this, and not into the range deletions, index, or
+    // footer.
+    records_end: u32,
 }
 
 impl<T: Read> Iterator for IndexIter<T> {
@@ -132,18 +269,76 @@ impl<T: Read> Iterator for IndexIter<T> {
                 .expect("must convert slice to byte array"),
         );
 
-        let mut key = vec![0; key_length as usize];
+        let key = match read_bytes(&mut self.r, key_length) {
+            Ok(key) => key,
+            Err(e) => {
+                self.done = true;
+                return Some(Err(e));
+            }
+        };
 
-        if let Err(e) = self.r.read_exact(&mut key) {
+        if offset >= self.records_end {
             self.done = true;
-            return Some(Err(e));
+            return Some(Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "index entry for key \"{}\" has offset {}, past the end of the records at {}",
+                    key.escape_ascii(),
+                    offset,
+                    self.records_end
+                ),
+            )));
         }
 
-        self.read += 4 + 4 + key_length;
-        if self.read == self.index_length {
+        let inline = match self.inline_values {
+            true => match read_inline(&mut self.r) {
+                Ok(inline) => inline,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            },
+            false => None,
+        };
+        let entry = IndexEntry {
+            key,
+            offset,
+            inline,
+        };
+
+        self.read = self
+            .read
+            .saturating_add(entry.size(self.inline_values) as u32);
+        if self.read >= self.index_length {
             self.done = true;
         }
+        if self.read > self.index_length {
+            return Some(Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "index entry extends past the end of the index",
+            )));
+        }
+
+        Some(Ok(entry))
+    }
+}
+
+fn read_inline<T: Read>(r: &mut T) -> io::Result<Option<InlineValue>> {
+    let mut tag = [0; 1];
+    r.read_exact(&mut tag)?;
 
-        Some(Ok(IndexEntry { key, offset }))
+    match tag[0] {
+        NOT_INLINE_TAG => Ok(None),
+        INLINE_DELETED_TAG => Ok(Some(InlineValue::Deleted)),
+        INLINE_VALUE_TAG => {
+            let mut buf = [0; 4];
+            r.read_exact(&mut buf)?;
+            let val = read_bytes(r, u32::from_le_bytes(buf))?;
+            Ok(Some(InlineValue::Value(val)))
+        }
+        tag => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid inline value tag {}", tag),
+        )),
     }
 }
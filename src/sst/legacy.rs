@@ -0,0 +1,148 @@
+use std::{fs, io, path};
+
+use crate::{
+    context::path_error,
+    protocol::{ReadRecord, WriteRecord, SST_EXT},
+    StoreError,
+};
+
+use super::{catalog::table_sequence, Catalog};
+
+// Before the store had levels, its tables sat directly in the data directory as "N.sst", with
+// higher numbers for newer tables. Each held its records followed by an index, and then just the
+// offset of the index as a u32 in place of a footer:
+//
+//      [records][index][index_start: u32]
+//
+// Such tables are never read in place. They are migrated into level 0 once, oldest first, and
+// removed.
+
+// Legacy tables in the root of `data_dir`, oldest first.
+pub(crate) fn legacy_tables(data_dir: &path::Path) -> Result<Vec<path::PathBuf>, StoreError> {
+    let list_err = |e| StoreError::CatalogInitialization(path_error("listing", data_dir, e));
+
+    let mut tables = Vec::new();
+    for entry in fs::read_dir(data_dir).map_err(list_err)? {
+        let path = entry.map_err(list_err)?.path();
+        let is_sst = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case(SST_EXT));
+        if path.is_file() && is_sst {
+            if let Some(seq) = table_sequence(&path) {
+                tables.push((seq, path));
+            }
+        }
+    }
+
+    tables.sort_unstable_by_key(|(seq, _)| *seq);
+    Ok(tables.into_iter().map(|(_, path)| path).collect())
+}
+
+// Rewrites each of `tables`, which must be oldest first, as a new level 0 table and removes it. The
+// tables are taken to be older than anything already in the catalog, which only holds for a store
+// that hasn't been written to since it was upgraded, or whose migration was interrupted. A table
+// that was rewritten but not yet removed when the migration was interrupted is simply rewritten
+// again.
+pub(crate) fn migrate_legacy_tables(
+    tables: &[path::PathBuf],
+    catalog: &mut Catalog,
+) -> Result<(), StoreError> {
+    for path in tables {
+        let records = read_legacy_table(path)?;
+        if !records.is_empty() {
+            catalog.write_records(records.iter().map(WriteRecord::from))?;
+        }
+
+        fs::remove_file(path)
+            .map_err(|e| StoreError::CatalogInitialization(path_error("removing", path, e)))?;
+    }
+
+    Ok(())
+}
+
+fn read_legacy_table(path: &path::Path) -> Result<Vec<ReadRecord>, StoreError> {
+    let bytes = fs::read(path).map_err(|e| StoreError::from_read(path, 0, e))?;
+
+    let corrupt = |offset: usize, detail: String| StoreError::Corruption {
+        path: path.into(),
+        offset: offset as u64,
+        detail,
+    };
+
+    let trailer_start = bytes
+        .len()
+        .checked_sub(4)
+        .ok_or_else(|| corrupt(0, "too short for a legacy table".to_string()))?;
+    let index_start = u32::from_le_bytes(
+        bytes[trailer_start..]
+            .try_into()
+            .expect("must convert slice to byte array"),
+    ) as usize;
+    if index_start > trailer_start {
+        return Err(corrupt(
+            trailer_start,
+            format!("index start {} is past the end of the table", index_start),
+        ));
+    }
+
+    let mut records = Vec::new();
+    let mut data = io::Cursor::new(&bytes[..index_start]);
+    while (data.position() as usize) < index_start {
+        let offset = data.position() as usize;
+        let record =
+            ReadRecord::read_from(&mut data).map_err(|e| corrupt(offset, e.to_string()))?;
+        records.push(record);
+    }
+
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_read_legacy_table() {
+        let dir = TempDir::new("testing").unwrap();
+        let path = dir.path().join("1.sst");
+
+        let mut bytes = Vec::new();
+        WriteRecord::Exists {
+            key: b"key1",
+            val: b"val1",
+        }
+        .write_to(&mut bytes)
+        .unwrap();
+        WriteRecord::Deleted { key: b"key2" }
+            .write_to(&mut bytes)
+            .unwrap();
+        let index_start = bytes.len() as u32;
+        bytes.extend_from_slice(b"an index the migration doesn't need");
+        bytes.extend_from_slice(&index_start.to_le_bytes());
+        fs::write(&path, &bytes).unwrap();
+
+        assert_eq!(
+            vec![
+                ReadRecord::Exists {
+                    key: b"key1".to_vec(),
+                    val: b"val1".to_vec()
+                },
+                ReadRecord::Deleted {
+                    key: b"key2".to_vec()
+                },
+            ],
+            read_legacy_table(&path).unwrap()
+        );
+
+        // An index start beyond the trailer can't be right.
+        let len = bytes.len();
+        bytes[len - 4..].copy_from_slice(&(len as u32).to_le_bytes());
+        fs::write(&path, &bytes).unwrap();
+        assert!(matches!(
+            read_legacy_table(&path),
+            Err(StoreError::Corruption { .. })
+        ));
+    }
+}
@@ -1,8 +1,30 @@
 mod catalog;
+mod filter;
+mod follow;
 mod index;
+mod legacy;
+mod pins;
+#[cfg(feature = "remote")]
+mod remote;
+mod repair;
 pub mod table;
+mod verify;
 
-pub use catalog::*;
+#[cfg(test)]
+pub(crate) use catalog::write_test_table;
+pub(crate) use catalog::{table_info, write_table, Found, TableFormat};
+pub use catalog::{Catalog, TableInfo};
+pub use filter::{PrefixFilter, PrefixFilterBuilder};
+pub use follow::Follower;
+pub use index::{IndexEntry, IndexReader, InlineValue};
+pub(crate) use legacy::*;
+pub use pins::PinGuard;
+pub(crate) use pins::{remove_obsolete, Pins, OBSOLETE_EXT};
+#[cfg(feature = "remote")]
+pub use remote::{ObjectStore, RemoteLevels};
+pub use repair::*;
+pub(crate) use table::verify_checksum_paced;
+pub use table::{PhysicalIter, Table, TableIter, TableRecord};
+pub use verify::*;
 
 use index::*;
-use table::*;
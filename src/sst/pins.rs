@@ -0,0 +1,183 @@
+use std::{
+    collections::HashMap,
+    fs, io, path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::{clock::Clock, context::IoContext, durable::TEMP_EXT, options::Options};
+
+// Tables replaced by a compaction while pinned are renamed with this extension, which takes them out
+// of the catalog, and removed once they are unpinned.
+pub(crate) const OBSOLETE_EXT: &str = "obsolete";
+
+// Tables that must not be removed while something still reads them. A compaction that replaces a
+// pinned table can still go ahead: The table is only set aside until the last pin on it is dropped.
+// With Options::obsolete_table_grace, every replaced table is set aside, and only removed once the
+// grace period has passed and it is no longer pinned.
+#[derive(Clone, Default)]
+pub(crate) struct Pins {
+    pins: Arc<Mutex<HashMap<path::PathBuf, Pin>>>,
+    grace: Option<Grace>,
+}
+
+#[derive(Clone)]
+struct Grace {
+    period: Duration,
+    clock: Arc<dyn Clock>,
+    // Unpinned tables that have been set aside, by the name they were set aside under, with when
+    // each was by the clock.
+    set_aside: Arc<Mutex<Vec<(path::PathBuf, Duration)>>>,
+}
+
+#[derive(Default)]
+struct Pin {
+    count: usize,
+    // Whether a compaction has replaced the table, so it is waiting to be removed.
+    obsolete: bool,
+}
+
+impl Pins {
+    pub(crate) fn new(options: &Options) -> Self {
+        Pins {
+            pins: Arc::default(),
+            grace: options.obsolete_table_grace.map(|period| Grace {
+                period,
+                clock: options.clock.clone(),
+                set_aside: Arc::default(),
+            }),
+        }
+    }
+
+    pub(crate) fn pin(&self, paths: Vec<path::PathBuf>) -> PinGuard {
+        let mut pins = self.pins.lock().unwrap();
+        for path in &paths {
+            pins.entry(path.clone()).or_default().count += 1;
+        }
+
+        PinGuard {
+            pins: self.clone(),
+            paths,
+        }
+    }
+
+    pub(crate) fn is_pinned(&self, path: &path::Path) -> bool {
+        self.pins.lock().unwrap().contains_key(path)
+    }
+
+    // Removes a table that a compaction has replaced, or sets it aside if it is pinned. A table
+    // that was already removed by someone else has still been replaced.
+    pub(crate) fn remove(&self, path: &path::Path) -> io::Result<()> {
+        self.expire()?;
+
+        let mut pins = self.pins.lock().unwrap();
+        let set_aside =
+            || fs::rename(path, path.with_extension(OBSOLETE_EXT)).with_path("renaming", path);
+        let result = match (pins.get_mut(path), &self.grace) {
+            (Some(pin), _) => {
+                pin.obsolete = true;
+                set_aside()
+            }
+            (None, Some(grace)) => set_aside().map(|_| grace.set_aside(path)),
+            (None, None) => fs::remove_file(path).with_path("removing", path),
+        };
+
+        match result {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    // Removes the tables set aside for longer than Options::obsolete_table_grace.
+    pub(crate) fn expire(&self) -> io::Result<()> {
+        let Some(grace) = &self.grace else {
+            return Ok(());
+        };
+
+        let now = grace.clock.monotonic();
+        let mut set_aside = grace.set_aside.lock().unwrap();
+        while let Some(i) = set_aside
+            .iter()
+            .position(|(_, at)| now.saturating_sub(*at) >= grace.period)
+        {
+            let obsolete = set_aside[i].0.clone();
+            match fs::remove_file(&obsolete) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                    return Err(e).with_path("removing", &obsolete)
+                }
+                _ => set_aside.swap_remove(i),
+            };
+        }
+        Ok(())
+    }
+}
+
+impl Grace {
+    // Level 0 table names are used again, so a table set aside may take the place of an earlier one
+    // of the same name, which then no longer needs removing.
+    fn set_aside(&self, path: &path::Path) {
+        let obsolete = path.with_extension(OBSOLETE_EXT);
+        let now = self.clock.monotonic();
+        let mut set_aside = self.set_aside.lock().unwrap();
+        set_aside.retain(|(set, _)| *set != obsolete);
+        set_aside.push((obsolete, now));
+    }
+}
+
+// Unpins its tables when dropped.
+pub struct PinGuard {
+    pins: Pins,
+    paths: Vec<path::PathBuf>,
+}
+
+impl Drop for PinGuard {
+    fn drop(&mut self) {
+        let mut pins = self.pins.pins.lock().unwrap();
+        for path in &self.paths {
+            let Some(pin) = pins.get_mut(path) else {
+                continue;
+            };
+            pin.count -= 1;
+            if pin.count == 0 {
+                match &self.pins.grace {
+                    // The grace period starts once nothing in this process reads the table.
+                    Some(grace) if pin.obsolete => grace.set_aside(path),
+                    // Anything left behind is cleaned up the next time the store is opened.
+                    None if pin.obsolete => {
+                        let _ = fs::remove_file(path.with_extension(OBSOLETE_EXT));
+                    }
+                    _ => (),
+                }
+                pins.remove(path);
+            }
+        }
+    }
+}
+
+// Removes the tables that were still pinned when the store was last closed, and any that were still
+// being written under a temporary name, see durable::atomic_rename_and_sync. With
+// Options::obsolete_table_grace, set aside tables are kept, and their grace period starts over.
+pub(crate) fn remove_obsolete(data_dir: &path::Path, pins: &Pins) -> io::Result<()> {
+    for entry in fs::read_dir(data_dir).with_path("listing", data_dir)? {
+        let dir = entry.with_path("listing", data_dir)?.path();
+        if !dir.is_dir() {
+            continue;
+        }
+
+        for entry in fs::read_dir(&dir).with_path("listing", &dir)? {
+            let path = entry.with_path("listing", &dir)?.path();
+            let Some(ext) = path.extension() else {
+                continue;
+            };
+            match &pins.grace {
+                Some(grace) if ext == OBSOLETE_EXT => grace.set_aside(&path),
+                _ if ext == OBSOLETE_EXT || ext == TEMP_EXT => {
+                    fs::remove_file(&path).with_path("removing", &path)?
+                }
+                _ => (),
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,214 @@
+// Serving a catalog whose deeper levels live in an object store, such as an S3-compatible bucket,
+// while level 0 and anything shallower stays on local disk. Any store can be used by implementing
+// ObjectStore for it.
+//
+// A remote table is the object named `<level>/<name>.sst`, with the same contents as the file it
+// was written as. Tables are never changed once written, so each is fetched once, checked against
+// its checksum, and kept in a local cache directory from then on. Reads are only ever served from
+// the cache, never from the object store itself.
+//
+// For now this is read only: Tables are written to the object store by whatever copies them
+// there, not by compactions.
+
+use std::{
+    collections::HashSet,
+    fs,
+    io::{self, BufWriter, Write},
+    path,
+    sync::Arc,
+};
+
+use crate::{
+    context::{path_error, IoContext},
+    durable::{atomic_rename_and_sync, TEMP_EXT},
+    options::RecoveryMode,
+    protocol::SST_EXT,
+    recovery::RecoveryReport,
+    StoreError,
+};
+
+use super::{table::verify_checksum, Catalog, Table};
+
+// Deeper than any store gets, so a table named as being below it can only be misnamed, and is
+// rejected rather than making room for that many levels.
+const MAX_REMOTE_LEVEL: usize = 64;
+
+// A store of named objects. Names are made of `/`-separated parts, as object store keys usually
+// are.
+pub trait ObjectStore: Send + Sync {
+    // The names of the objects whose names start with `prefix`, in any order.
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>>;
+
+    // Writes the whole of the object named `name` to `w`.
+    fn get(&self, name: &str, w: &mut dyn Write) -> io::Result<()>;
+}
+
+// Where a catalog's remote levels are kept, and where the tables fetched from them are cached.
+#[derive(Clone)]
+pub struct RemoteLevels {
+    store: Arc<dyn ObjectStore>,
+    cache_dir: path::PathBuf,
+    first_level: usize,
+}
+
+impl RemoteLevels {
+    // Levels 1 and deeper are in `store`, and cached in `cache_dir`.
+    pub fn new(store: Arc<dyn ObjectStore>, cache_dir: &path::Path) -> Self {
+        RemoteLevels {
+            store,
+            cache_dir: cache_dir.to_owned(),
+            first_level: 1,
+        }
+    }
+
+    // Sets the shallowest level kept in the object store. Level 0 is always local.
+    pub fn first_level(mut self, level: usize) -> Self {
+        self.first_level = level;
+        self
+    }
+
+    // The remote tables, as (level, name) pairs in no particular order. Objects that aren't
+    // tables are ignored.
+    fn list(&self) -> Result<Vec<(usize, String)>, StoreError> {
+        let objects = self
+            .store
+            .list("")
+            .map_err(|e| StoreError::CatalogInitialization(io::Error::other(e)))?;
+
+        let mut tables = Vec::new();
+        for object in objects {
+            let Some((level, name)) = object.split_once('/') else {
+                continue;
+            };
+            let Ok(level) = level.parse::<usize>() else {
+                continue;
+            };
+            let is_sst = path::Path::new(name)
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case(SST_EXT));
+            if level < self.first_level || !is_sst || name.contains('/') {
+                continue;
+            }
+            if level > MAX_REMOTE_LEVEL {
+                return Err(StoreError::Corruption {
+                    path: path::PathBuf::from(object),
+                    offset: 0,
+                    detail: format!(
+                        "level {} is deeper than the deepest supported, {}",
+                        level, MAX_REMOTE_LEVEL
+                    ),
+                });
+            }
+            tables.push((level, name.to_string()));
+        }
+        Ok(tables)
+    }
+
+    // The cached copy of a remote table, fetching it first if it isn't cached yet. A copy is only
+    // put in the cache once it has been fetched in full and checked, so a fetch that fails part
+    // way leaves nothing behind.
+    fn cached(&self, level: usize, name: &str) -> Result<path::PathBuf, StoreError> {
+        let dir = self.cache_dir.join(level.to_string());
+        let path = dir.join(name);
+        if path.exists() {
+            return Ok(path);
+        }
+
+        fs::create_dir_all(&dir)
+            .with_path("creating", &dir)
+            .map_err(StoreError::CatalogInitialization)?;
+        let tmp = path.with_extension(TEMP_EXT);
+        let fetch = || -> io::Result<()> {
+            let mut w = BufWriter::new(fs::File::create(&tmp).with_path("creating", &tmp)?);
+            self.store.get(&format!("{}/{}", level, name), &mut w)?;
+            w.into_inner()?.sync_all().with_path("syncing", &tmp)
+        };
+        if let Err(e) = fetch() {
+            let _ = fs::remove_file(&tmp);
+            return Err(StoreError::Read { path, source: e });
+        }
+
+        if let Err(e) = verify_checksum(&tmp) {
+            let _ = fs::remove_file(&tmp);
+            return Err(e);
+        }
+        atomic_rename_and_sync(&tmp, &path).map_err(StoreError::CatalogInitialization)?;
+        Ok(path)
+    }
+
+    // Removes cached tables, and leftovers of failed fetches, that aren't in `keep`.
+    fn evict(&self, keep: &HashSet<path::PathBuf>) -> Result<(), StoreError> {
+        let list_err =
+            |e| StoreError::CatalogInitialization(path_error("listing", &self.cache_dir, e));
+        let entries = match fs::read_dir(&self.cache_dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(list_err(e)),
+        };
+
+        for entry in entries {
+            let dir = entry.map_err(list_err)?.path();
+            if !dir.is_dir() {
+                continue;
+            }
+            for entry in fs::read_dir(&dir).map_err(list_err)? {
+                let path = entry.map_err(list_err)?.path();
+                if path.is_file() && !keep.contains(&path) {
+                    fs::remove_file(&path)
+                        .with_path("removing", &path)
+                        .map_err(StoreError::CatalogInitialization)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Catalog {
+    // Opens a read only catalog of the tables in `data_dir` and in `remote`'s levels. Remote tables
+    // not yet cached are fetched before this returns, and cached tables that are no longer in the
+    // object store are removed. If any table can't be fetched, or is found damaged, the catalog
+    // isn't opened, and what was cached is kept to be used next time.
+    //
+    // The levels in `remote` must not also have tables in `data_dir`.
+    pub fn open_remote(data_dir: &path::Path, remote: &RemoteLevels) -> Result<Self, StoreError> {
+        if remote.first_level == 0 {
+            return Err(StoreError::InvalidArgument(
+                "level 0 can't be kept in an object store".to_string(),
+            ));
+        }
+
+        let mut catalog = Catalog::open(
+            data_dir,
+            RecoveryMode::Strict,
+            &mut RecoveryReport::default(),
+        )?;
+        if let Some(level) = (remote.first_level..catalog.num_levels())
+            .find(|level| !catalog.tables(*level).is_empty())
+        {
+            return Err(StoreError::InvalidArgument(format!(
+                "level {} is kept in an object store, but has tables in {}",
+                level,
+                data_dir.display()
+            )));
+        }
+
+        let mut tables = Vec::new();
+        for (level, name) in remote.list()? {
+            tables.push((level, remote.cached(level, &name)?));
+        }
+        remote.evict(&tables.iter().map(|(_, path)| path.clone()).collect())?;
+
+        for (level, path) in tables {
+            if catalog.ssts.len() <= level {
+                catalog.ssts.resize_with(level + 1, Vec::new);
+            }
+            catalog.ssts[level].push(Arc::new(Table::new(&path)?));
+        }
+        for level in catalog.ssts.iter_mut().skip(remote.first_level) {
+            level.sort_by_key(|table| table.key_start());
+        }
+
+        Ok(catalog)
+    }
+}
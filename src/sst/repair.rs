@@ -0,0 +1,214 @@
+use std::{
+    fs,
+    io::{self, BufReader, BufWriter, Write},
+    path,
+    sync::Arc,
+};
+
+use crate::{
+    codec::{Codecs, Encoded, IDENTITY_CODEC_ID},
+    context::IoContext,
+    durable::atomic_rename_and_sync,
+    protocol::{Footer, ReadRecord, WriteRecord, SST_EXT},
+};
+
+use super::{
+    catalog::{write_table_contents, TableFormat},
+    PhysicalIter, Table,
+};
+
+// The outcome of repairing a single table.
+#[derive(Debug)]
+pub struct RepairReport {
+    pub path: path::PathBuf,
+    pub records_recovered: usize,
+    // Bytes following the last recovered record that were dropped, including whatever was left of
+    // the old index and footer. Records in this region can't be counted since they couldn't be
+    // decoded.
+    pub bytes_discarded: u64,
+    // Why the data section could not be read in full, if it couldn't.
+    pub error: Option<String>,
+}
+
+// Rebuilds the index and footer of a table from its data section. Records are read from the start
+// of the file until one fails to decode or is out of key order, and the table is rewritten to hold
+// just those records. The original file is only replaced once the rewritten one is complete.
+//
+// Values are copied as they are, so a table whose values are encoded, see Options::value_codec,
+// keeps the codec named in its footer. A table whose footer is too damaged to read loses it, and
+// its values read back still encoded.
+pub fn repair(path: &path::Path) -> io::Result<RepairReport> {
+    let file_length = fs::metadata(path).with_path("reading", path)?.len();
+    let codec_id = fs::File::open(path)
+        .and_then(|file| Footer::new_from_reader(&mut BufReader::new(file)))
+        .map_or(IDENTITY_CODEC_ID, |footer| footer.codec_id());
+
+    let mut records: Vec<ReadRecord> = Vec::new();
+    let mut range_deletions: Vec<ReadRecord> = Vec::new();
+    let mut error = None;
+    for next in PhysicalIter::open(path)? {
+        match next {
+            // Range deletions follow the other records, and aren't in key order.
+            Ok((_, record @ ReadRecord::RangeDeleted { .. })) => range_deletions.push(record),
+            Ok((offset, record)) if !range_deletions.is_empty() => {
+                error = Some(format!(
+                    "key \"{}\" at offset {} follows the range deletions",
+                    record.key().escape_ascii(),
+                    offset
+                ));
+                break;
+            }
+            Ok((offset, record)) => {
+                // Older versions of a key follow the newest, so keys may repeat.
+                if records.last().is_some_and(|last| record.key() < last.key()) {
+                    error = Some(format!(
+                        "key \"{}\" at offset {} is out of order",
+                        record.key().escape_ascii(),
+                        offset
+                    ));
+                    break;
+                }
+                records.push(record);
+            }
+            Err(e) => {
+                error = Some(e.to_string());
+                break;
+            }
+        }
+    }
+
+    if records.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "no records could be recovered",
+        ))
+        .with_path("repairing", path);
+    }
+
+    let recovered_length = records
+        .iter()
+        .chain(&range_deletions)
+        .map(|r| r.size() as u64)
+        .sum::<u64>();
+
+    let tmp = path.with_extension("repair");
+    let file = fs::File::create(&tmp).with_path("creating", &tmp)?;
+    let mut w = BufWriter::new(&file);
+    let records_to_write = records.iter().map(WriteRecord::from).collect::<Vec<_>>();
+    let range_deletions_to_write = range_deletions
+        .iter()
+        .map(WriteRecord::from)
+        .collect::<Vec<_>>();
+    let format = TableFormat {
+        codecs: Codecs::default().with_writer(Arc::new(Encoded(codec_id))),
+        ..TableFormat::default()
+    };
+    write_table_contents(
+        &mut w,
+        &records_to_write,
+        &range_deletions_to_write,
+        &format,
+    )
+    .and_then(|_| w.flush())
+    .with_path("writing", &tmp)?;
+    drop(w);
+    file.sync_all().with_path("syncing", &tmp)?;
+    atomic_rename_and_sync(&tmp, path)?;
+
+    Ok(RepairReport {
+        path: path.to_owned(),
+        records_recovered: records.len() + range_deletions.len(),
+        bytes_discarded: file_length - recovered_length,
+        error,
+    })
+}
+
+// Repairs every table in a data directory that can't be opened. Tables that open cleanly are left
+// alone. The store must not be open while this runs.
+pub fn repair_dir(data_dir: &path::Path) -> io::Result<Vec<RepairReport>> {
+    let mut reports = Vec::new();
+
+    for entry in fs::read_dir(data_dir).with_path("listing", data_dir)? {
+        let dir = entry.with_path("listing", data_dir)?.path();
+        let is_level = dir
+            .file_name()
+            .and_then(|n| n.to_str())
+            .is_some_and(|n| n.parse::<usize>().is_ok());
+        if !dir.is_dir() || !is_level {
+            continue;
+        }
+
+        for entry in fs::read_dir(&dir).with_path("listing", &dir)? {
+            let path = entry.with_path("listing", &dir)?.path();
+            let is_sst = path
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case(SST_EXT));
+            if path.is_file() && is_sst && Table::new(&path).is_err() {
+                reports.push(repair(&path)?);
+            }
+        }
+    }
+
+    Ok(reports)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Seek, SeekFrom};
+
+    use tempdir::TempDir;
+
+    use crate::sst::write_test_table;
+
+    use super::*;
+
+    fn keys(path: &path::Path) -> Vec<Vec<u8>> {
+        Table::new(path)
+            .unwrap()
+            .iter()
+            .unwrap()
+            .map(|r| r.unwrap().key().to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn test_repair_truncated_index() {
+        let dir = TempDir::new("testing").unwrap();
+        let path = write_test_table(dir.path());
+
+        // Chop off the footer and part of the index.
+        let file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(50).unwrap();
+        assert!(Table::new(&path).is_err());
+
+        let report = repair(&path).unwrap();
+        assert_eq!(3, report.records_recovered);
+        assert_eq!(3, report.bytes_discarded);
+        assert!(report.error.is_some());
+        assert_eq!(
+            vec![b"key1".to_vec(), b"key2".to_vec(), b"key3".to_vec()],
+            keys(&path)
+        );
+        assert!(!path.with_extension("repair").exists());
+    }
+
+    #[test]
+    fn test_repair_corrupt_record() {
+        let dir = TempDir::new("testing").unwrap();
+        let path = write_test_table(dir.path());
+
+        // Clobber the op byte of the second record. Only the first can be kept.
+        let mut file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.seek(SeekFrom::Start(17)).unwrap();
+        file.write_all(b"x").unwrap();
+
+        let report = repair(&path).unwrap();
+        assert_eq!(1, report.records_recovered);
+        assert_eq!(vec![b"key1".to_vec()], keys(&path));
+
+        // Nothing can be recovered if the first record is bad.
+        let mut file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.write_all(b"x").unwrap();
+        assert!(repair(&path).is_err());
+    }
+}
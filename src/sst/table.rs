@@ -1,43 +1,373 @@
 use std::{
     fs,
-    io::{self, BufReader, Seek, SeekFrom},
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom},
+    marker::PhantomData,
     path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, OnceLock,
+    },
 };
 
-use crate::protocol::{self, ReadRecord};
+use crate::{
+    checksum,
+    codec::{unknown_codec, Codecs, ValueCodec, IDENTITY_CODEC_ID},
+    context::{path_error, IoContext},
+    options::RecoveryMode,
+    protocol::{self, KeyRecord, Op, ReadRecord},
+    recovery::RecoveryReport,
+    tombstone::{self, RangeTombstone},
+    StoreError,
+};
 
-use super::{Index, IndexReader};
+use super::{catalog::table_sequence, Index, IndexEntry, IndexReader, InlineValue, PrefixFilter};
 
+// A single table file, which can be opened and read on its own as well as through a Catalog.
+//
+// Opening a table reads its footer and index, and fails with StoreError::Corruption if either
+// can't be decoded, so a table that opens has a usable index; `open_verified` also checks the
+// whole file against its checksum first. Records are read from the file as they are asked for, and
+// a record that can't be read is an error for that read only. Iterators from `iter` and friends
+// have their own handles to the file, so they can outlive the table.
+//
+// A table whose values are encoded, see Options::value_codec, decodes them as they are read, with
+// the codec given to `set_codecs`. Built-in codecs that don't need a key are found without it.
 pub struct Table {
     index: Index,
+    // Range deletions are few, so they are kept in memory rather than indexed.
+    range_deletions: Vec<RangeTombstone>,
+    // Tables written with Options::prefix_bloom_length have one.
+    prefix_filter: Option<PrefixFilter>,
+    // From the footer, see Options::table_split_points.
+    split_keys: Vec<Vec<u8>>,
     file: fs::File,
     pub path: path::PathBuf,
+    // Set once the table's file is found to have been deleted out from under the store.
+    missing: AtomicBool,
+    // From the footer, see Options::value_codec.
+    codec_id: u8,
+    codec: OnceLock<Arc<dyn ValueCodec>>,
 }
 
 impl Table {
-    pub fn new(path: &path::Path) -> io::Result<Self> {
-        let file = fs::OpenOptions::new().read(true).open(path)?;
-        let mut r = BufReader::new(&file);
+    pub fn new(path: &path::Path) -> Result<Self, StoreError> {
+        Table::open(path, RecoveryMode::Strict, &mut RecoveryReport::default())
+    }
+
+    // Same as `new`, but first reads the whole table and checks it against the checksum in its
+    // footer, so that damage anywhere in it is found before it is used rather than when the
+    // damaged part is read. Tables written before checksums existed can't be checked, and are
+    // opened as they are.
+    pub fn open_verified(path: &path::Path) -> Result<Self, StoreError> {
+        verify_checksum(path)?;
+        Table::new(path)
+    }
+
+    // Opens a table, tolerating damage according to `mode`. With RecoveryMode::BestEffort, a table
+    // whose index can't be read has its index rebuilt from the records that can be, and anything
+    // left out is noted in `report`.
+    pub fn open(
+        path: &path::Path,
+        mode: RecoveryMode,
+        report: &mut RecoveryReport,
+    ) -> Result<Self, StoreError> {
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .open(path)
+            .map_err(|e| StoreError::from_read(path, 0, e))?;
+
+        let (index, range_deletions, prefix_filter, split_keys, codec_id) =
+            match read_index(&file, path) {
+                Ok(read) => read,
+                Err(e) if mode == RecoveryMode::BestEffort => {
+                    let (index, range_deletions) = rebuild_index(path, report)?;
+                    report.skip(
+                        path,
+                        error_offset(&e),
+                        format!("rebuilt the index from the table's records: {}", e),
+                    );
+                    // The footer may still name the codec even if the index is damaged.
+                    let codec_id = protocol::Footer::new_from_reader(&mut BufReader::new(&file))
+                        .map_or(IDENTITY_CODEC_ID, |footer| footer.codec_id());
+                    (index, range_deletions, None, Vec::new(), codec_id)
+                }
+                Err(e) => return Err(e),
+            };
 
         Ok(Table {
-            index: Index::from_index_reader(IndexReader(&mut r))?,
+            index,
+            range_deletions,
+            prefix_filter,
+            split_keys,
             file,
             path: path.into(),
+            missing: AtomicBool::new(false),
+            codec_id,
+            codec: OnceLock::new(),
         })
     }
 
-    pub fn get(&self, key: &[u8]) -> io::Result<Option<ReadRecord>> {
-        match self.index.get_offset(key) {
-            Some(offset) => {
-                let mut r = &self.file.try_clone()?;
-                r.seek(SeekFrom::Start(*offset as u64))?;
-                // There should always be a record here since we found it in the index.
-                Some(ReadRecord::read_from(&mut r)).transpose()
+    // Gives the table the codec its values are encoded with from among `codecs`, failing with
+    // StoreError::InvalidArgument if it isn't there.
+    pub(crate) fn set_codecs(&self, codecs: &Codecs) -> Result<(), StoreError> {
+        if self.codec_id == IDENTITY_CODEC_ID {
+            return Ok(());
+        }
+        match codecs.get(self.codec_id) {
+            Some(codec) => {
+                // A table shared between catalogs may already have it.
+                let _ = self.codec.set(codec);
+                Ok(())
             }
+            None => Err(StoreError::InvalidArgument(format!(
+                "the values in {} are encoded with codec {}, which wasn't given to \
+                 Options::decode_values_with",
+                self.path.display(),
+                self.codec_id
+            ))),
+        }
+    }
+
+    // The codec the table's values are decoded with, or None if they aren't encoded.
+    fn codec(&self) -> io::Result<Option<Arc<dyn ValueCodec>>> {
+        if self.codec_id == IDENTITY_CODEC_ID {
+            return Ok(None);
+        }
+        match self.codec.get() {
+            Some(codec) => Ok(Some(codec.clone())),
+            None => Codecs::default()
+                .get(self.codec_id)
+                .map(Some)
+                .ok_or_else(|| unknown_codec(self.codec_id)),
+        }
+    }
+
+    // A key without a record of its own reads as deleted if one of the table's range deletions
+    // covers it.
+    pub fn get(&self, key: &[u8]) -> Result<Option<ReadRecord>, StoreError> {
+        if let Some(record) = self.inline_record(key) {
+            return Ok(Some(record));
+        }
+
+        match self.locate(key) {
+            // There should always be a record here since we found it in the index.
+            Some(offset) => self.read_record(offset, key, false).map(Some),
+            None if self.range_deleted(key) => Ok(Some(ReadRecord::Deleted { key: key.to_vec() })),
             None => Ok(None),
         }
     }
 
+    // Reads the record for `key` at an offset returned by `locate`, checking it as `read_verified`
+    // does if `verify` is set. Once the table's file is found to be missing, every read fails with
+    // StoreError::MissingTable without going to disk.
+    pub(crate) fn read_record(
+        &self,
+        offset: u32,
+        key: &[u8],
+        verify: bool,
+    ) -> Result<ReadRecord, StoreError> {
+        if self.is_missing() {
+            return Err(StoreError::MissingTable {
+                path: self.path.clone(),
+            });
+        }
+
+        let record = if verify {
+            self.read_verified(offset, key)
+        } else {
+            self.read_at(offset)
+        };
+        record.map_err(|e| self.read_error(offset, e))
+    }
+
+    // The op of the record at an offset returned by `locate`, and its value as a reader of just
+    // those bytes and their length, rather than read into memory. The reader has its own handle to
+    // the table's file, so it stays readable for as long as it lives, even if the table is
+    // compacted away in the meantime. Encoded values can only be decoded whole, so they are read
+    // into memory after all.
+    pub(crate) fn value_reader(
+        &self,
+        offset: u32,
+    ) -> Result<(Op, u32, Box<dyn Read + Send>), StoreError> {
+        if self.is_missing() {
+            return Err(StoreError::MissingTable {
+                path: self.path.clone(),
+            });
+        }
+
+        let open = || -> io::Result<(Op, u32, Box<dyn Read + Send>)> {
+            let mut r = PositionedReader {
+                file: self.file.try_clone()?,
+                pos: offset as u64,
+            };
+            let (op, _, val_length) = protocol::read_header(&mut r)?;
+            match self.codec()? {
+                Some(codec) if op == Op::Exists => {
+                    let val = codec.decode(&protocol::read_bytes(&mut r, val_length)?)?;
+                    Ok((op, val.len() as u32, Box::new(io::Cursor::new(val))))
+                }
+                _ => Ok((op, val_length, Box::new(r.take(val_length as u64)))),
+            }
+        };
+        open().map_err(|e| self.read_error(offset, e))
+    }
+
+    // Classifies a failed read at `offset`, noting if it found the table's file to be missing.
+    fn read_error(&self, offset: u32, e: io::Error) -> StoreError {
+        let err = StoreError::from_read(&self.path, offset as u64, e);
+        if matches!(err, StoreError::MissingTable { .. }) {
+            self.missing.store(true, Ordering::Relaxed);
+        }
+        err
+    }
+
+    // Every version of a key in the table, newest first. Versions older than the newest are only
+    // kept when compaction is asked to keep them, see Options::versions_to_keep. Range deletions
+    // aren't included.
+    pub fn versions(&self, key: &[u8]) -> Result<Vec<ReadRecord>, StoreError> {
+        let Some(offset) = self.locate(key) else {
+            return Ok(Vec::new());
+        };
+        if self.is_missing() {
+            return Err(StoreError::MissingTable {
+                path: self.path.clone(),
+            });
+        }
+
+        let file = self.file.try_clone().map_err(|source| StoreError::Read {
+            path: self.path.clone(),
+            source,
+        })?;
+        let mut iter: TableIter = TableIter::new(file, &self.path)
+            .with_codec(self.codec().map_err(|e| self.read_error(offset, e))?);
+        iter.seek(offset)
+            .map_err(|e| StoreError::from_read(&self.path, offset as u64, e))?;
+
+        iter.take_while(|record| !matches!(record, Ok(record) if record.key() != key))
+            .collect::<io::Result<Vec<_>>>()
+            .map_err(|e| StoreError::from_read(&self.path, offset as u64, e))
+    }
+
+    // Checks whether the table's file still exists. Some platforms keep a deleted file readable
+    // through handles that are already open, so reads alone may never notice.
+    pub fn check_missing(&self) -> bool {
+        if let Err(e) = fs::metadata(&self.path) {
+            if e.kind() == io::ErrorKind::NotFound {
+                self.missing.store(true, Ordering::Relaxed);
+            }
+        }
+        self.is_missing()
+    }
+
+    // Whether the table's file has been found to be missing.
+    pub fn is_missing(&self) -> bool {
+        self.missing.load(Ordering::Relaxed)
+    }
+
+    // Whether one of the table's range deletions covers a key. Records in the table were written
+    // after its range deletions, so this only matters for keys that `locate` doesn't find.
+    pub fn range_deleted(&self, key: &[u8]) -> bool {
+        tombstone::covered(&self.range_deletions, key)
+    }
+
+    // Whether the table may have records with keys starting with `prefix`, according to its prefix
+    // filter. A table without one always may.
+    pub fn may_contain_prefix(&self, prefix: &[u8]) -> bool {
+        self.prefix_filter
+            .as_ref()
+            .is_none_or(|filter| filter.may_contain(prefix))
+    }
+
+    pub fn range_deletions(&self) -> &[RangeTombstone] {
+        &self.range_deletions
+    }
+
+    // Keys that split the table into runs of about the same number of keys, in key order. Tables
+    // written without Options::table_split_points, or before it existed, have none.
+    pub fn split_keys(&self) -> &[Vec<u8>] {
+        &self.split_keys
+    }
+
+    // The number of keys in the table with records other than range deletions.
+    pub fn num_entries(&self) -> usize {
+        self.index.len()
+    }
+
+    // The offset of the record for a key, if the table has one. This only consults the index, so a
+    // caller that reads the same key repeatedly can keep the offset and use `read_at` directly.
+    pub fn locate(&self, key: &[u8]) -> Option<u32> {
+        self.index.get_offset(key).copied()
+    }
+
+    // The newest record for a key if the index carries it, which it does for small values in tables
+    // written with Options::inline_value_size. A value that fails to decode isn't given, leaving it
+    // to be read from the table, which reports the error.
+    pub fn inline_record(&self, key: &[u8]) -> Option<ReadRecord> {
+        let record = match self.index.get_inline(key)? {
+            InlineValue::Value(val) => ReadRecord::Exists {
+                key: key.to_vec(),
+                val: val.clone(),
+            },
+            InlineValue::Deleted => ReadRecord::Deleted { key: key.to_vec() },
+        };
+        match self.codec().ok()? {
+            Some(codec) => record.decode(codec.as_ref()).ok(),
+            None => Some(record),
+        }
+    }
+
+    // Reads the record at an offset returned by `locate`. Any other offset is unlikely to be the
+    // start of a record, and will either fail to decode or return garbage.
+    pub fn read_at(&self, offset: u32) -> io::Result<ReadRecord> {
+        let mut r = BufReader::new(PositionedReader {
+            file: &self.file,
+            pos: offset as u64,
+        });
+        let record = ReadRecord::read_from(&mut r)?;
+        match self.codec()? {
+            Some(codec) => record.decode(codec.as_ref()),
+            None => Ok(record),
+        }
+    }
+
+    // Like `read_at`, but checks that the record read is the one the index says is there, rather
+    // than trusting the index. Records have no checksums of their own, so this can't catch a record
+    // whose contents were damaged in a way that still decodes; `verify` can, by reading the whole
+    // table.
+    pub fn read_verified(&self, offset: u32, key: &[u8]) -> io::Result<ReadRecord> {
+        let record = self.read_at(offset)?;
+        if record.key() != key || matches!(record, ReadRecord::RangeDeleted { .. }) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "index entry for key \"{}\" points to a record for \"{}\"",
+                    key.escape_ascii(),
+                    record.key().escape_ascii()
+                ),
+            ));
+        }
+        Ok(record)
+    }
+
+    // Reads the whole table and checks it against the checksum in its footer, returning the
+    // checksum. Tables written before checksums existed have none, and pass unchecked as None.
+    pub fn verify(&self) -> Result<Option<u32>, StoreError> {
+        verify_checksum(&self.path)
+    }
+
+    // The number a level 0 table is named with, which is larger for newer tables. Tables in other
+    // levels don't have one.
+    pub fn sequence(&self) -> Option<u32> {
+        table_sequence(&self.path)
+    }
+
+    // Whether any of the table's keys are in [start, end), or from start onward if there is no
+    // end. Only records are considered, not range deletions.
+    pub fn overlaps(&self, start: &[u8], end: Option<&[u8]>) -> bool {
+        self.index.key_end.as_slice() >= start
+            && end.is_none_or(|end| self.index.key_start.as_slice() < end)
+    }
+
     pub fn key_start(&self) -> Vec<u8> {
         self.index.key_start.clone()
     }
@@ -45,6 +375,61 @@ impl Table {
     pub fn key_end(&self) -> Vec<u8> {
         self.index.key_end.clone()
     }
+
+    // Size of the table file in bytes.
+    pub fn size(&self) -> io::Result<u64> {
+        self.file
+            .metadata()
+            .map(|m| m.len())
+            .with_path("reading metadata of", &self.path)
+    }
+
+    // Reads up to `limit` bytes from the start of the table and discards them, which leaves them in
+    // the OS's cache for the reads that follow. Returns the number of bytes read.
+    pub fn read_ahead(&self, limit: u64) -> io::Result<u64> {
+        let mut r = PositionedReader {
+            file: &self.file,
+            pos: 0,
+        }
+        .take(limit);
+        io::copy(&mut r, &mut io::sink()).with_path("reading", &self.path)
+    }
+
+    // Iterates the records of the table in key order without consuming it. Range deletions are left
+    // out, see `range_deletions`. The iterator has its own handle to the file, so it remains valid
+    // even if the table is dropped.
+    pub fn iter(&self) -> io::Result<TableIter> {
+        let file = self.file.try_clone().with_path("opening", &self.path)?;
+        let codec = self.codec().with_path("reading", &self.path)?;
+        Ok(TableIter::new(file, &self.path).with_codec(codec))
+    }
+
+    // Same as `iter`, but starts at the first record with a key at or after `start`, which is found
+    // with the index rather than by reading the records before it.
+    pub fn iter_from(&self, start: &[u8]) -> io::Result<TableIter> {
+        let mut iter = self.iter()?;
+        match self.index.seek_offset(start) {
+            Some(offset) => iter.seek(offset).with_path("reading", &self.path)?,
+            None => iter.done = true,
+        }
+        Ok(iter)
+    }
+
+    // Same as `iter`, but reads only the key of each record and the length of its value, skipping
+    // over the value itself. This is much cheaper for working out where a table's space goes.
+    // Values aren't decoded, so the lengths are of the values as they are on disk.
+    pub fn key_iter(&self) -> io::Result<TableIter<KeyRecord>> {
+        let file = self.file.try_clone().with_path("opening", &self.path)?;
+        Ok(TableIter::new(file, &self.path))
+    }
+
+    // Iterates the records of the table in the order they are laid out in the file, along with the
+    // offset of each. Range deletions come last. This never consults the index. See PhysicalIter::open for reading a table
+    // whose index is too damaged for it to be opened at all.
+    pub fn physical_iter(&self) -> io::Result<PhysicalIter> {
+        let file = self.file.try_clone().with_path("opening", &self.path)?;
+        Ok(PhysicalIter::new(file, &self.path))
+    }
 }
 
 impl IntoIterator for Table {
@@ -52,59 +437,193 @@ impl IntoIterator for Table {
     type IntoIter = TableIter;
 
     fn into_iter(self) -> Self::IntoIter {
-        let r = BufReader::new(self.file);
+        let codec = self.codec();
+        let mut iter = TableIter::new(self.file, &self.path);
+        match codec {
+            Ok(codec) => iter.with_codec(codec),
+            Err(e) => {
+                iter.setup_err = Some(e);
+                iter
+            }
+        }
+    }
+}
+
+// How a TableIter reads each record: In full, or as just its key and value length.
+pub trait TableRecord: Sized {
+    fn read_from<R: Read + Seek>(r: &mut R) -> io::Result<Self>;
+    fn key(&self) -> &[u8];
+    fn size(&self) -> usize;
+    // The record with its value decoded, if it has one.
+    fn decode(self, codec: &dyn ValueCodec) -> io::Result<Self>;
+}
+
+impl TableRecord for ReadRecord {
+    fn read_from<R: Read + Seek>(r: &mut R) -> io::Result<Self> {
+        ReadRecord::read_from(r)
+    }
+
+    fn key(&self) -> &[u8] {
+        self.key()
+    }
+
+    fn size(&self) -> usize {
+        self.size()
+    }
+
+    fn decode(self, codec: &dyn ValueCodec) -> io::Result<Self> {
+        self.decode(codec)
+    }
+}
+
+impl TableRecord for KeyRecord {
+    fn read_from<R: Read + Seek>(r: &mut R) -> io::Result<Self> {
+        KeyRecord::read_from(r)
+    }
+
+    fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    fn size(&self) -> usize {
+        self.size()
+    }
+
+    fn decode(self, _: &dyn ValueCodec) -> io::Result<Self> {
+        Ok(self)
+    }
+}
+
+// The records of a table in ascending key order, with the versions of a key kept by
+// Options::versions_to_keep newest first. Range deletions aren't included.
+//
+// An error reading a record is returned in its place, and ends the iteration. Once the records run
+// out, they are checked against the counts in the table's footer, and a mismatch is returned as a
+// final error after the last record.
+pub struct TableIter<T = ReadRecord> {
+    r: BufReader<PositionedReader<fs::File>>,
+    done: bool,
+    setup_err: Option<io::Error>,
+    entries_length: u32,
+    read: u32,
+    path: path::PathBuf,
+    // The footer's counts, which are checked against what was read once the records run out. Tables
+    // written before the footer had them aren't checked.
+    num_entries: Option<u32>,
+    data_length: Option<u32>,
+    // The number of keys read so far, and the last of them.
+    keys_read: u32,
+    last_key: Vec<u8>,
+    // A mismatch with the footer's counts, returned after the last record.
+    end_err: Option<io::Error>,
+    // What values are decoded with, if they are encoded.
+    codec: Option<Arc<dyn ValueCodec>>,
+    record: PhantomData<T>,
+}
+
+impl<T: TableRecord> TableIter<T> {
+    fn new(file: fs::File, path: &path::Path) -> Self {
+        let r = BufReader::new(PositionedReader { file, pos: 0 });
 
         let mut table_iter = TableIter {
-            r: r,
+            path: path.to_owned(),
+            r,
             done: false,
             setup_err: None,
             entries_length: 0,
             read: 0,
+            num_entries: None,
+            data_length: None,
+            keys_read: 0,
+            last_key: Vec::new(),
+            end_err: None,
+            codec: None,
+            record: PhantomData,
         };
 
         let footer = match protocol::Footer::new_from_reader(&mut table_iter.r) {
             Ok(f) => f,
             Err(e) => {
-                table_iter.setup_err = Some(Err(e));
+                table_iter.setup_err = Some(e);
                 return table_iter;
             }
         };
 
-        table_iter.entries_length = footer.index_start;
+        table_iter.entries_length = footer.records_end();
+        table_iter.num_entries = footer.num_entries;
+        table_iter.data_length = footer.data_length;
 
         if let Err(e) = table_iter.r.seek(SeekFrom::Start(0)) {
-            table_iter.setup_err = Some(Err(e));
+            table_iter.setup_err = Some(e);
         }
 
         table_iter
     }
+
+    fn with_codec(mut self, codec: Option<Arc<dyn ValueCodec>>) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    // Continues from the record at `offset` rather than wherever the iterator is.
+    fn seek(&mut self, offset: u32) -> io::Result<()> {
+        if let Some(e) = self.setup_err.take() {
+            self.done = true;
+            return Err(e);
+        }
+
+        self.r.seek(SeekFrom::Start(offset as u64))?;
+        self.read = offset;
+        self.done = self.read >= self.entries_length;
+        // The keys before the offset won't be counted.
+        self.num_entries = None;
+        Ok(())
+    }
 }
 
-pub struct TableIter {
-    r: BufReader<fs::File>,
-    done: bool,
-    setup_err: Option<io::Result<ReadRecord>>,
-    entries_length: u32,
-    read: u32,
+impl<T> TableIter<T> {
+    // Checks what was read against the footer's counts, once the records have run out.
+    fn check_counts(&self) -> Option<io::Error> {
+        let detail = match (self.data_length, self.num_entries) {
+            (Some(data_length), _) if self.read != data_length => format!(
+                "records end at offset {}, but the footer gives a data length of {}",
+                self.read, data_length
+            ),
+            (_, Some(num_entries)) if self.keys_read != num_entries => format!(
+                "read {} keys, but the footer counts {} entries",
+                self.keys_read, num_entries
+            ),
+            _ => return None,
+        };
+
+        Some(path_error(
+            "reading",
+            &self.path,
+            io::Error::new(io::ErrorKind::InvalidData, detail),
+        ))
+    }
 }
 
 // This needs to be like the index iterator where it knows how far to go. In the into_iter, read the
 // footer to get this information. Keep track of how much we have read and set done when we have
 // read it all. Then that weird fill_buff function can go away.
-impl Iterator for TableIter {
-    type Item = io::Result<ReadRecord>;
+impl<T: TableRecord> Iterator for TableIter<T> {
+    type Item = io::Result<T>;
 
     fn next(&mut self) -> Option<Self::Item> {
         if self.done {
-            return None;
+            return self.end_err.take().map(Err);
         }
 
         if self.setup_err.is_some() {
             self.done = true;
-            return self.setup_err.take();
+            return self
+                .setup_err
+                .take()
+                .map(|err| Err(path_error("reading", &self.path, err)));
         }
 
-        let record = ReadRecord::read_from(&mut self.r);
+        let record = T::read_from(&mut self.r).with_path("reading", &self.path);
         if record.is_err() {
             self.done = true;
             return Some(record);
@@ -112,10 +631,533 @@ impl Iterator for TableIter {
 
         let record = record.unwrap();
         self.read += record.size() as u32;
-        if self.read == self.entries_length {
+        if self.keys_read == 0 || self.last_key != record.key() {
+            self.keys_read += 1;
+            self.last_key.clear();
+            self.last_key.extend_from_slice(record.key());
+        }
+        if self.read >= self.entries_length {
+            self.done = true;
+            self.end_err = self.check_counts();
+        }
+
+        match &self.codec {
+            Some(codec) => {
+                let record = record
+                    .decode(codec.as_ref())
+                    .with_path("reading", &self.path);
+                if record.is_err() {
+                    self.done = true;
+                    self.end_err = None;
+                }
+                Some(record)
+            }
+            None => Some(Ok(record)),
+        }
+    }
+}
+
+// Reads the index of a table along with its range deletions.
+// See Table::verify, which this does for a table that may not open.
+pub(crate) fn verify_checksum(path: &path::Path) -> Result<Option<u32>, StoreError> {
+    verify_checksum_paced(path, &mut |_| Ok(()))
+}
+
+// Same as `verify_checksum`, but calls `pace` with the number of bytes read after each read of the
+// table, which can slow the reads down, or stop them by returning an error.
+pub(crate) fn verify_checksum_paced(
+    path: &path::Path,
+    pace: &mut dyn FnMut(usize) -> io::Result<()>,
+) -> Result<Option<u32>, StoreError> {
+    let mut file = fs::File::open(path).map_err(|e| StoreError::from_read(path, 0, e))?;
+    let footer = protocol::Footer::new_from_reader(&mut BufReader::new(&file))
+        .map_err(|e| StoreError::from_read(path, 0, e))?;
+    let Some(checksum) = footer.checksum else {
+        return Ok(None);
+    };
+
+    // The checksum is followed only by the footer length.
+    let mut read = || -> io::Result<(u64, u32)> {
+        let checksum_start = file.seek(SeekFrom::End(-8))?;
+        file.seek(SeekFrom::Start(0))?;
+        let mut r = PacedReader {
+            r: BufReader::new(&file).take(checksum_start),
+            pace,
+        };
+        let actual = checksum::crc32c_reader(&mut r)?;
+        Ok((checksum_start, actual))
+    };
+    let (checksum_start, actual) = read().map_err(|e| StoreError::from_read(path, 0, e))?;
+
+    if actual != checksum {
+        return Err(StoreError::Corruption {
+            path: path.into(),
+            offset: checksum_start,
+            detail: format!(
+                "checksum {:#010x} in the footer doesn't match the table's contents, {:#010x}",
+                checksum, actual
+            ),
+        });
+    }
+
+    Ok(Some(checksum))
+}
+
+struct PacedReader<'a, R: Read> {
+    r: R,
+    pace: &'a mut dyn FnMut(usize) -> io::Result<()>,
+}
+
+impl<R: Read> Read for PacedReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.r.read(buf)?;
+        (self.pace)(n)?;
+        Ok(n)
+    }
+}
+
+type ReadIndex = (
+    Index,
+    Vec<RangeTombstone>,
+    Option<PrefixFilter>,
+    Vec<Vec<u8>>,
+    u8,
+);
+
+fn read_index(file: &fs::File, path: &path::Path) -> Result<ReadIndex, StoreError> {
+    let mut r = BufReader::new(file);
+
+    // The footer is parsed up front so that a problem with the index can be reported relative to
+    // where the index starts.
+    let footer = protocol::Footer::new_from_reader(&mut r).map_err(|e| {
+        let file_length = file.metadata().map(|m| m.len()).unwrap_or_default();
+        StoreError::from_read(path, file_length.saturating_sub(4), e)
+    })?;
+
+    let index = Index::from_index_reader(IndexReader(&mut r))
+        .map_err(|e| StoreError::from_read(path, footer.index_start as u64, e))?;
+
+    let range_deletions = read_range_deletions(file, &footer)
+        .map_err(|e| StoreError::from_read(path, footer.records_end() as u64, e))?;
+
+    let mut prefix_filter = None;
+    if footer.prefix_filter() {
+        let mut read = || -> io::Result<PrefixFilter> {
+            let file_length = file.metadata()?.len();
+            let index_end =
+                file_length - footer.footer_length.expect("footer must have length") as u64;
+            PrefixFilter::read_from(&mut r, index_end)
+        };
+        prefix_filter =
+            Some(read().map_err(|e| StoreError::from_read(path, footer.index_start as u64, e))?);
+    }
+
+    let codec_id = footer.codec_id();
+    Ok((
+        index,
+        range_deletions,
+        prefix_filter,
+        footer.split_keys,
+        codec_id,
+    ))
+}
+
+fn read_range_deletions(
+    file: &fs::File,
+    footer: &protocol::Footer,
+) -> io::Result<Vec<RangeTombstone>> {
+    let start = footer.records_end();
+    let mut r = BufReader::new(PositionedReader {
+        file,
+        pos: start as u64,
+    })
+    .take((footer.index_start - start) as u64);
+
+    let mut range_deletions = Vec::new();
+    while r.limit() > 0 {
+        match ReadRecord::read_from(&mut r)? {
+            ReadRecord::RangeDeleted { start, end } => {
+                range_deletions.push(RangeTombstone { start, end })
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "expected only range deletions after the other records",
+                ))
+            }
+        }
+    }
+
+    Ok(range_deletions)
+}
+
+// Builds an index from the records that can be read from the start of the table, up to the first
+// that can't. Range deletions among them are returned separately.
+fn rebuild_index(
+    path: &path::Path,
+    report: &mut RecoveryReport,
+) -> Result<(Index, Vec<RangeTombstone>), StoreError> {
+    let mut iter = PhysicalIter::open(path).map_err(|source| StoreError::Read {
+        path: path.into(),
+        source,
+    })?;
+
+    let mut entries = Vec::new();
+    let mut range_deletions = Vec::new();
+    loop {
+        match iter.next() {
+            Some(Ok((_, ReadRecord::RangeDeleted { start, end }))) => {
+                range_deletions.push(RangeTombstone { start, end })
+            }
+            Some(Ok((offset, record))) => entries.push(Ok(IndexEntry {
+                key: record.key().to_vec(),
+                offset: offset as u32,
+                inline: None,
+            })),
+            Some(Err(e)) => {
+                report.skip(path, iter.offset(), format!("unreadable records: {}", e));
+                break;
+            }
+            None => break,
+        }
+    }
+
+    let index = Index::from_entries(entries).map_err(|e| StoreError::from_read(path, 0, e))?;
+    Ok((index, range_deletions))
+}
+
+fn error_offset(err: &StoreError) -> u64 {
+    match err {
+        StoreError::Corruption { offset, .. } => *offset,
+        _ => 0,
+    }
+}
+
+// Reads records sequentially from the start of a table file without using its index, so that data
+// can be salvaged from a table that can't otherwise be read. If the footer is intact, reading stops
+// where the index begins. If it isn't, reading continues until a record fails to decode, and that
+// failure is returned as the final item.
+pub struct PhysicalIter {
+    r: BufReader<PositionedReader<fs::File>>,
+    offset: u64,
+    data_end: Option<u64>,
+    done: bool,
+    path: path::PathBuf,
+}
+
+impl PhysicalIter {
+    pub fn open(path: &path::Path) -> io::Result<Self> {
+        let file = fs::File::open(path).with_path("opening", path)?;
+        Ok(PhysicalIter::new(file, path))
+    }
+
+    fn new(file: fs::File, path: &path::Path) -> Self {
+        let mut r = BufReader::new(PositionedReader { file, pos: 0 });
+        let data_end = protocol::Footer::new_from_reader(&mut r)
+            .ok()
+            .map(|footer| footer.index_start as u64);
+
+        PhysicalIter {
+            r: BufReader::new(PositionedReader {
+                file: r.into_inner().file,
+                pos: 0,
+            }),
+            offset: 0,
+            data_end,
+            done: false,
+            path: path.to_owned(),
+        }
+    }
+
+    // The offset of the next record to be read. After an error, this is where the unreadable record
+    // starts.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    fn read_next(&mut self) -> io::Result<Option<(u64, ReadRecord)>> {
+        match self.data_end {
+            Some(end) if self.offset >= end => return Ok(None),
+            None if self.r.fill_buf()?.is_empty() => return Ok(None),
+            _ => (),
+        }
+
+        let record = ReadRecord::read_from(&mut self.r)?;
+        let offset = self.offset;
+        self.offset += record.size() as u64;
+
+        if self.data_end.is_some_and(|end| self.offset > end) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "record at offset {} overruns the start of the index",
+                    offset
+                ),
+            ));
+        }
+
+        Ok(Some((offset, record)))
+    }
+}
+
+impl Iterator for PhysicalIter {
+    type Item = io::Result<(u64, ReadRecord)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let next = self.read_next().with_path("reading", &self.path);
+        if !matches!(next, Ok(Some(_))) {
             self.done = true;
         }
 
-        Some(Ok(record))
+        next.transpose()
+    }
+}
+
+// Reads from a file starting at its own position rather than the file cursor, which is shared
+// between all handles cloned from the same file. This allows any number of readers of a table to be
+// active at once without disturbing each other.
+struct PositionedReader<F> {
+    file: F,
+    pos: u64,
+}
+
+impl<F: std::borrow::Borrow<fs::File>> Read for PositionedReader<F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = read_at(self.file.borrow(), buf, self.pos)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<F: std::borrow::Borrow<fs::File>> Seek for PositionedReader<F> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(n) => {
+                self.pos = n;
+                return Ok(n);
+            }
+            SeekFrom::End(n) => (self.file.borrow().metadata()?.len(), n),
+            SeekFrom::Current(n) => (self.pos, n),
+        };
+
+        match base.checked_add_signed(offset) {
+            Some(n) => {
+                self.pos = n;
+                Ok(n)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn read_at(file: &fs::File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::unix::fs::FileExt::read_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn read_at(file: &fs::File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    // seek_read moves the file cursor, but nothing else relies on it.
+    std::os::windows::fs::FileExt::seek_read(file, buf, offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use tempdir::TempDir;
+
+    use crate::sst::write_test_table;
+
+    use super::*;
+
+    fn want() -> Vec<(u64, ReadRecord)> {
+        vec![
+            (
+                0,
+                ReadRecord::Exists {
+                    key: b"key1".to_vec(),
+                    val: b"val1".to_vec(),
+                },
+            ),
+            (
+                17,
+                ReadRecord::Exists {
+                    key: b"key2".to_vec(),
+                    val: b"val2".to_vec(),
+                },
+            ),
+            (
+                34,
+                ReadRecord::Deleted {
+                    key: b"key3".to_vec(),
+                },
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_locate_and_read_at() {
+        let dir = TempDir::new("testing").unwrap();
+        let path = write_test_table(dir.path());
+        let table = Table::new(&path).unwrap();
+
+        for (offset, record) in want() {
+            let located = table.locate(record.key()).unwrap();
+            assert_eq!(offset, located as u64);
+            assert_eq!(record, table.read_at(located).unwrap());
+            assert_eq!(Some(&record), table.get(record.key()).unwrap().as_ref());
+        }
+
+        assert_eq!(None, table.locate(b"key4"));
+        assert_eq!(None, table.get(b"key4").unwrap());
+
+        // The middle of a record isn't the start of one.
+        assert!(table.read_at(1).is_err());
+    }
+
+    #[test]
+    fn test_iter_checks_footer_counts() {
+        let dir = TempDir::new("testing").unwrap();
+        let path = write_test_table(dir.path());
+
+        let table = Table::new(&path).unwrap();
+        let got = table
+            .iter()
+            .unwrap()
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(want().into_iter().map(|(_, r)| r).collect::<Vec<_>>(), got);
+
+        // Overwrite the footer's entry count, and then its data length, which comes after it. They
+        // are followed by the index flags, split key count, checksum, and footer length. Each
+        // mismatch is reported after the last record.
+        for (from_end, want_detail) in [(-24, "footer counts 4 entries"), (-20, "data length of 4")]
+        {
+            let mut file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+            file.seek(SeekFrom::End(from_end)).unwrap();
+            file.write_all(&4_u32.to_le_bytes()).unwrap();
+
+            let got = Table::new(&path)
+                .unwrap()
+                .iter()
+                .unwrap()
+                .collect::<Vec<_>>();
+            assert_eq!(4, got.len());
+            assert!(got[..3].iter().all(Result::is_ok));
+            let err = got[3].as_ref().unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidData, err.kind());
+            assert!(err.to_string().contains(want_detail), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_verify() {
+        let dir = TempDir::new("testing").unwrap();
+        let path = write_test_table(dir.path());
+
+        let table = Table::new(&path).unwrap();
+        assert!(table.verify().unwrap().is_some());
+
+        // A changed value still decodes, but no longer matches the checksum.
+        let mut file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.seek(SeekFrom::Start(13)).unwrap();
+        file.write_all(b"x").unwrap();
+        assert!(table.get(b"key1").is_ok());
+        assert!(matches!(table.verify(), Err(StoreError::Corruption { .. })));
+    }
+
+    #[test]
+    fn test_index_offset_out_of_range() {
+        let dir = TempDir::new("testing").unwrap();
+        let path = write_test_table(dir.path());
+
+        // Point the entry for "key1" at the start of the index, past the last record.
+        let index_start = 47;
+        let mut file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.seek(SeekFrom::Start(index_start)).unwrap();
+        file.write_all(&(index_start as u32).to_le_bytes()).unwrap();
+
+        match Table::new(&path) {
+            Err(StoreError::Corruption { offset, detail, .. }) => {
+                assert_eq!(index_start, offset);
+                assert!(detail.contains("has offset 47"), "{}", detail);
+            }
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("opened a table with a bad index"),
+        }
+    }
+
+    #[test]
+    fn test_key_iter() {
+        let dir = TempDir::new("testing").unwrap();
+        let path = write_test_table(dir.path());
+
+        let got = Table::new(&path)
+            .unwrap()
+            .key_iter()
+            .unwrap()
+            .map(|record| {
+                let record = record.unwrap();
+                (record.key, record.value_len, record.is_tombstone)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                (b"key1".to_vec(), 4, false),
+                (b"key2".to_vec(), 4, false),
+                (b"key3".to_vec(), 0, true),
+            ],
+            got
+        );
+    }
+
+    #[test]
+    fn test_physical_iter() {
+        let dir = TempDir::new("testing").unwrap();
+        let path = write_test_table(dir.path());
+
+        let table = Table::new(&path).unwrap();
+        let got = table
+            .physical_iter()
+            .unwrap()
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(want(), got);
+    }
+
+    #[test]
+    fn test_physical_iter_corrupt_index() {
+        let dir = TempDir::new("testing").unwrap();
+        let path = write_test_table(dir.path());
+
+        // Clobber the first index entry so that the table can no longer be opened.
+        let index_start = 47;
+        let mut file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.seek(SeekFrom::Start(index_start)).unwrap();
+        file.write_all(&u32::MAX.to_le_bytes()).unwrap();
+        file.write_all(&u32::MAX.to_le_bytes()).unwrap();
+        assert!(Table::new(&path).is_err());
+
+        let got = PhysicalIter::open(&path)
+            .unwrap()
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(want(), got);
+
+        // Without a footer the records are still recovered, and the index is reported as the
+        // first thing that fails to decode.
+        file.set_len(index_start + 4).unwrap();
+        let mut got = PhysicalIter::open(&path).unwrap().collect::<Vec<_>>();
+        assert!(got.pop().unwrap().is_err());
+        let got = got.into_iter().collect::<io::Result<Vec<_>>>().unwrap();
+        assert_eq!(want(), got);
     }
 }
@@ -0,0 +1,521 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, BufReader},
+    path,
+};
+
+use crate::{
+    context::IoContext,
+    protocol::{self, ReadRecord, SST_EXT},
+    StoreError,
+};
+
+use super::{catalog::table_sequence, table::verify_checksum, IndexReader, PhysicalIter};
+
+// The outcome of checking every table in a store. Problems are collected rather than stopping at the
+// first, so a single pass shows everything that is wrong.
+#[derive(Debug, Default)]
+pub struct IntegrityReport {
+    pub tables_checked: usize,
+    pub problems: Vec<Problem>,
+}
+
+impl IntegrityReport {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    // Problems found with a specific file or directory.
+    pub fn problems_for<'a>(&'a self, path: &'a path::Path) -> impl Iterator<Item = &'a Problem> {
+        self.problems.iter().filter(move |p| p.path == path)
+    }
+
+    // Adds a problem, returning it so that its offset and key can be filled in.
+    fn problem(&mut self, path: &path::Path, kind: ProblemKind, detail: String) -> &mut Problem {
+        self.problems.push(Problem {
+            path: path.to_owned(),
+            kind,
+            offset: None,
+            key: None,
+            detail,
+        });
+        self.problems.last_mut().expect("problem was just added")
+    }
+}
+
+#[derive(Debug)]
+pub struct Problem {
+    pub path: path::PathBuf,
+    pub kind: ProblemKind,
+    // The byte offset into the file of the bad data, if the problem is with part of a file.
+    pub offset: Option<u64>,
+    // The key involved, if it could be read.
+    pub key: Option<Vec<u8>>,
+    pub detail: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProblemKind {
+    // A level directory or level 0 table isn't named as expected.
+    BadName,
+    // The table file couldn't be opened.
+    Unopenable,
+    UnreadableFooter,
+    UnreadableRecord,
+    // A record's key is less than the key of the record before it. Equal keys are versions of the
+    // same key.
+    OutOfOrderKey,
+    // A record's key is outside of the range given by the footer.
+    KeyOutOfRange,
+    // The footer's start or end key isn't the first or last key in the table.
+    FooterKeyMismatch,
+    UnreadableIndex,
+    // An index entry doesn't point at the start of the record for its key.
+    BadIndexEntry,
+    // Records that have no index entry.
+    UnindexedRecords,
+    // The footer's count of entries doesn't match the records in the table.
+    EntryCountMismatch,
+    // A table past level 0 overlaps another table in its level.
+    LevelOverlap,
+    // The table's contents don't match the checksum in its footer.
+    ChecksumMismatch,
+}
+
+// Checks the tables in a data directory for consistency: That each can be decoded, that its records
+// are in ascending key order and agree with its index and footer, and that the levels they are in
+// are laid out correctly. An error is only returned if the directory itself can't be listed.
+pub fn verify(data_dir: &path::Path) -> io::Result<IntegrityReport> {
+    let mut report = IntegrityReport::default();
+
+    let mut levels = Vec::new();
+    for entry in fs::read_dir(data_dir).with_path("listing", data_dir)? {
+        let path = entry.with_path("listing", data_dir)?.path();
+        if !path.is_dir() {
+            continue;
+        }
+
+        match path
+            .file_name()
+            .and_then(|n| n.to_str()?.parse::<usize>().ok())
+        {
+            Some(level) => levels.push((level, path)),
+            None => {
+                report.problem(
+                    &path,
+                    ProblemKind::BadName,
+                    "level directory name is not an integer".to_string(),
+                );
+            }
+        }
+    }
+    levels.sort_unstable_by_key(|(level, _)| *level);
+
+    for (level, dir) in levels {
+        let mut ranges = Vec::new();
+
+        for entry in fs::read_dir(&dir).with_path("listing", &dir)? {
+            let path = entry.with_path("listing", &dir)?.path();
+            let is_sst = path
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case(SST_EXT));
+            if !path.is_file() || !is_sst {
+                continue;
+            }
+
+            // Level 0 tables are ordered by their sequence number, so they can't be placed without
+            // one.
+            if level == 0 && table_sequence(&path).is_none() {
+                report.problem(
+                    &path,
+                    ProblemKind::BadName,
+                    "level 0 table name is not a sequence number".to_string(),
+                );
+            }
+
+            report.tables_checked += 1;
+            if let Some(range) = verify_table(&path, &mut report) {
+                ranges.push((range, path));
+            }
+        }
+
+        // Tables past level 0 must cover disjoint key ranges.
+        if level > 0 {
+            ranges.sort_by(|((a, _), _), ((b, _), _)| a.cmp(b));
+            for pair in ranges.windows(2) {
+                let ((_, prev_end), prev_path) = &pair[0];
+                let ((next_start, _), next_path) = &pair[1];
+                if next_start <= prev_end {
+                    report
+                        .problem(
+                            next_path,
+                            ProblemKind::LevelOverlap,
+                            format!(
+                                "key range overlaps {} in level {}",
+                                prev_path.display(),
+                                level
+                            ),
+                        )
+                        .key = Some(next_start.clone());
+                }
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+// Checks a single table, returning its key range according to the footer if the footer could be
+// read.
+fn verify_table(path: &path::Path, report: &mut IntegrityReport) -> Option<(Vec<u8>, Vec<u8>)> {
+    let file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) => {
+            report.problem(path, ProblemKind::Unopenable, e.to_string());
+            return None;
+        }
+    };
+    let file_length = file.metadata().map(|m| m.len()).unwrap_or_default();
+
+    let (footer, footer_offset) =
+        match protocol::Footer::new_from_reader(&mut BufReader::new(&file)) {
+            Ok(footer) => {
+                let footer_length = footer.footer_length.expect("footer must have length");
+                (Some(footer), file_length - footer_length as u64)
+            }
+            Err(e) => {
+                report
+                    .problem(path, ProblemKind::UnreadableFooter, e.to_string())
+                    .offset = Some(file_length.saturating_sub(4));
+                (None, 0)
+            }
+        };
+
+    // Walk the data section, which doesn't rely on the footer being intact.
+    let mut records: HashMap<u64, Vec<u8>> = HashMap::new();
+    let mut first_key = None;
+    let mut last_key: Option<Vec<u8>> = None;
+    let mut in_range_deletions = false;
+    match PhysicalIter::open(path) {
+        Ok(mut iter) => {
+            while let Some(next) = iter.next() {
+                let (offset, record) = match next {
+                    Ok(next) => next,
+                    Err(e) => {
+                        report
+                            .problem(path, ProblemKind::UnreadableRecord, e.to_string())
+                            .offset = Some(iter.offset());
+                        break;
+                    }
+                };
+
+                let key = record.key().to_vec();
+                let mut problem = |kind, detail| {
+                    let problem = report.problem(path, kind, detail);
+                    problem.offset = Some(offset);
+                    problem.key = Some(key.clone());
+                };
+
+                // Range deletions follow the other records, aren't in key order, and aren't
+                // indexed.
+                if matches!(record, ReadRecord::RangeDeleted { .. }) {
+                    in_range_deletions = true;
+                    continue;
+                }
+                if in_range_deletions {
+                    problem(
+                        ProblemKind::OutOfOrderKey,
+                        "record follows the range deletions".to_string(),
+                    );
+                }
+
+                // Older versions of a key follow the newest, and aren't indexed.
+                let is_version = last_key.as_ref() == Some(&key);
+                if last_key.as_ref().is_some_and(|last| &key < last) {
+                    problem(
+                        ProblemKind::OutOfOrderKey,
+                        "key is less than the key before it".to_string(),
+                    );
+                }
+                if let Some(footer) = &footer {
+                    if key < footer.start_key || key > footer.end_key {
+                        problem(
+                            ProblemKind::KeyOutOfRange,
+                            "key is outside of the range given by the footer".to_string(),
+                        );
+                    }
+                }
+
+                if first_key.is_none() {
+                    first_key = Some(key.clone());
+                }
+                last_key = Some(key.clone());
+                if !is_version {
+                    records.insert(offset, key);
+                }
+            }
+        }
+        Err(e) => {
+            report.problem(path, ProblemKind::Unopenable, e.to_string());
+        }
+    }
+
+    let footer = footer?;
+    if let Err(e) = verify_checksum(path) {
+        let offset = match &e {
+            StoreError::Corruption { offset, .. } => Some(*offset),
+            _ => None,
+        };
+        report
+            .problem(path, ProblemKind::ChecksumMismatch, e.to_string())
+            .offset = offset;
+    }
+
+    for (footer_key, table_key, which) in [
+        (&footer.start_key, &first_key, "first"),
+        (&footer.end_key, &last_key, "last"),
+    ] {
+        if Some(footer_key) != table_key.as_ref() {
+            let problem = report.problem(
+                path,
+                ProblemKind::FooterKeyMismatch,
+                format!("footer key is not the {} key in the table", which),
+            );
+            problem.offset = Some(footer_offset);
+            problem.key = Some(footer_key.clone());
+        }
+    }
+
+    // Every index entry must point at the record for its key, and every record must be indexed.
+    let mut indexed = 0;
+    let mut entry_offset = footer.index_start as u64;
+    for entry in IndexReader(BufReader::new(&file)) {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                report
+                    .problem(path, ProblemKind::UnreadableIndex, e.to_string())
+                    .offset = Some(entry_offset);
+                break;
+            }
+        };
+
+        let detail = match records.get(&(entry.offset as u64)) {
+            Some(key) if *key == entry.key => None,
+            Some(key) => Some(format!(
+                "index entry points at the record for \"{}\" at offset {}",
+                key.escape_ascii(),
+                entry.offset
+            )),
+            None => Some(format!(
+                "index entry points at offset {}, which is not the start of a record",
+                entry.offset
+            )),
+        };
+        match detail {
+            Some(detail) => {
+                let problem = report.problem(path, ProblemKind::BadIndexEntry, detail);
+                problem.offset = Some(entry_offset);
+                problem.key = Some(entry.key.clone());
+            }
+            None => indexed += 1,
+        }
+
+        entry_offset += entry.size(footer.inline_values()) as u64;
+    }
+    if let Some(num_entries) = footer.num_entries {
+        if num_entries as usize != records.len() {
+            let problem = report.problem(
+                path,
+                ProblemKind::EntryCountMismatch,
+                format!(
+                    "footer counts {} entries, but the table has {} records",
+                    num_entries,
+                    records.len()
+                ),
+            );
+            problem.offset = Some(footer_offset);
+        }
+    }
+    if indexed < records.len() {
+        report.problem(
+            path,
+            ProblemKind::UnindexedRecords,
+            format!("{} records are not in the index", records.len() - indexed),
+        );
+    }
+
+    Some((footer.start_key, footer.end_key))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Seek, SeekFrom, Write};
+
+    use tempdir::TempDir;
+
+    use crate::{memtable::MemTable, sst::Catalog};
+
+    use super::*;
+
+    fn write_test_table(catalog: &mut Catalog, keys: &[&[u8]]) {
+        let mut memtable = MemTable::new();
+        for key in keys {
+            memtable.put(key, b"val");
+        }
+        catalog.write_records(&memtable).unwrap();
+    }
+
+    fn find<'a>(report: &'a IntegrityReport, path: &path::Path, kind: ProblemKind) -> &'a Problem {
+        report
+            .problems
+            .iter()
+            .find(|p| p.path == path && p.kind == kind)
+            .unwrap_or_else(|| panic!("no {:?} problem for {}", kind, path.display()))
+    }
+
+    #[test]
+    fn test_verify_clean() {
+        let dir = TempDir::new("testing").unwrap();
+        let mut catalog = Catalog::new(dir.path()).unwrap();
+        write_test_table(&mut catalog, &[b"a", b"b"]);
+        write_test_table(&mut catalog, &[b"a", b"c"]);
+
+        // Range deletions are neither in key order nor indexed.
+        let mut memtable = MemTable::new();
+        memtable.put(b"b", b"val");
+        memtable.delete_range(b"x", None);
+        memtable.delete_range(b"c", Some(b"d"));
+        catalog.write_records(&memtable).unwrap();
+
+        let report = verify(dir.path()).unwrap();
+        assert!(report.is_ok(), "{:?}", report);
+        assert_eq!(3, report.tables_checked);
+    }
+
+    #[test]
+    fn test_verify_corruption() {
+        let dir = TempDir::new("testing").unwrap();
+        let mut catalog = Catalog::new(dir.path()).unwrap();
+        write_test_table(&mut catalog, &[b"a", b"b"]);
+        write_test_table(&mut catalog, &[b"c", b"d"]);
+        write_test_table(&mut catalog, &[b"e", b"f"]);
+        write_test_table(&mut catalog, &[b"b", b"g"]);
+
+        let level_0 = dir.path().join("0");
+        let level_1 = dir.path().join("1");
+
+        // An invalid op byte in the second record of the first table.
+        let bad_record = level_0.join("1.sst");
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .open(&bad_record)
+            .unwrap();
+        file.seek(SeekFrom::Start(13)).unwrap();
+        file.write_all(b"x").unwrap();
+
+        // The index entry for "d" in the second table now points at the record for "c".
+        let bad_index = level_0.join("2.sst");
+        let mut file = fs::OpenOptions::new().write(true).open(&bad_index).unwrap();
+        file.seek(SeekFrom::Start(26 + 9)).unwrap();
+        file.write_all(&0_u32.to_le_bytes()).unwrap();
+
+        // The third and fourth tables overlap once they are both in level 1.
+        fs::create_dir(&level_1).unwrap();
+        fs::rename(level_0.join("3.sst"), level_1.join("3.sst")).unwrap();
+        fs::rename(level_0.join("4.sst"), level_1.join("4.sst")).unwrap();
+
+        // And a level that isn't named by its number.
+        fs::create_dir(dir.path().join("level")).unwrap();
+
+        let report = verify(dir.path()).unwrap();
+        assert_eq!(4, report.tables_checked);
+
+        let problem = find(&report, &bad_record, ProblemKind::UnreadableRecord);
+        assert_eq!(Some(13), problem.offset);
+
+        let problem = find(&report, &bad_index, ProblemKind::BadIndexEntry);
+        assert_eq!(Some(26 + 9), problem.offset);
+        assert_eq!(Some(b"d".to_vec()), problem.key);
+        assert!(problem.detail.contains("points at the record for \"c\""));
+
+        assert_eq!(0, report.problems_for(&level_1.join("4.sst")).count());
+        let problem = find(&report, &level_1.join("3.sst"), ProblemKind::LevelOverlap);
+        assert_eq!(Some(b"e".to_vec()), problem.key);
+
+        find(&report, &dir.path().join("level"), ProblemKind::BadName);
+    }
+
+    #[test]
+    fn test_verify_key_order() {
+        let dir = TempDir::new("testing").unwrap();
+        let mut catalog = Catalog::new(dir.path()).unwrap();
+        write_test_table(&mut catalog, &[b"a", b"b"]);
+
+        // Change the first key from "a" to "c", which puts it after "b" and outside the footer's
+        // range of keys.
+        let path = dir.path().join("0").join("1.sst");
+        let mut file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.seek(SeekFrom::Start(9)).unwrap();
+        file.write_all(b"c").unwrap();
+
+        let report = verify(dir.path()).unwrap();
+
+        let problem = find(&report, &path, ProblemKind::OutOfOrderKey);
+        assert_eq!(
+            (Some(13), Some(b"b".to_vec())),
+            (problem.offset, problem.key.clone())
+        );
+
+        let problem = find(&report, &path, ProblemKind::KeyOutOfRange);
+        assert_eq!(
+            (Some(0), Some(b"c".to_vec())),
+            (problem.offset, problem.key.clone())
+        );
+
+        let problem = find(&report, &path, ProblemKind::FooterKeyMismatch);
+        assert_eq!(Some(b"a".to_vec()), problem.key);
+
+        let problem = find(&report, &path, ProblemKind::BadIndexEntry);
+        assert_eq!(Some(b"a".to_vec()), problem.key);
+    }
+
+    #[test]
+    fn test_verify_entry_count() {
+        let dir = TempDir::new("testing").unwrap();
+        let mut catalog = Catalog::new(dir.path()).unwrap();
+        write_test_table(&mut catalog, &[b"a", b"b"]);
+
+        // The entry count comes before the data length, index flags, split key count, checksum, and
+        // the footer's length.
+        let path = dir.path().join("0").join("1.sst");
+        let mut file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.seek(SeekFrom::End(-24)).unwrap();
+        file.write_all(&3_u32.to_le_bytes()).unwrap();
+
+        let report = verify(dir.path()).unwrap();
+        let problem = find(&report, &path, ProblemKind::EntryCountMismatch);
+        assert!(problem.detail.contains("footer counts 3 entries"));
+        // The checksum covers the footer too.
+        let problem = find(&report, &path, ProblemKind::ChecksumMismatch);
+        assert_eq!(Some(fs::metadata(&path).unwrap().len() - 8), problem.offset);
+        assert_eq!(2, report.problems.len());
+    }
+
+    #[test]
+    fn test_verify_truncated() {
+        let dir = TempDir::new("testing").unwrap();
+        let mut catalog = Catalog::new(dir.path()).unwrap();
+        write_test_table(&mut catalog, &[b"a", b"b"]);
+
+        let path = dir.path().join("0").join("1.sst");
+        let file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(30).unwrap();
+
+        let report = verify(dir.path()).unwrap();
+        find(&report, &path, ProblemKind::UnreadableFooter);
+        let problem = find(&report, &path, ProblemKind::UnreadableRecord);
+        assert_eq!(Some(26), problem.offset);
+    }
+}
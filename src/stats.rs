@@ -0,0 +1,276 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+// Cumulative counters updated on the read path. These are always on, so they are kept as relaxed
+// atomics to stay cheap. Flushes are rare enough next to reads to be summed up under a lock.
+#[derive(Default)]
+pub struct ReadCounters {
+    gets: AtomicU64,
+    tables_probed: AtomicU64,
+    seeks: AtomicU64,
+    prefix_skips: AtomicU64,
+    range_skips: AtomicU64,
+    read_compactions: AtomicU64,
+    flushes: Mutex<FlushStats>,
+}
+
+impl ReadCounters {
+    pub fn record_get(&self) {
+        self.gets.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_table_probe(&self) {
+        self.tables_probed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_seek(&self) {
+        self.seeks.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_prefix_skip(&self) {
+        self.prefix_skips.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_range_skip(&self) {
+        self.range_skips.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_read_compaction(&self) {
+        self.read_compactions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_flush(&self, records: u64, bytes: u64, duration: Duration) {
+        self.flushes.lock().unwrap().add(records, bytes, duration);
+    }
+
+    // Adds counts taken separately, such as those of a single read.
+    pub fn add(&self, stats: Stats) {
+        self.gets.fetch_add(stats.gets, Ordering::Relaxed);
+        self.tables_probed
+            .fetch_add(stats.tables_probed, Ordering::Relaxed);
+        self.seeks.fetch_add(stats.seeks, Ordering::Relaxed);
+        self.prefix_skips
+            .fetch_add(stats.prefix_skips, Ordering::Relaxed);
+        self.range_skips
+            .fetch_add(stats.range_skips, Ordering::Relaxed);
+        self.read_compactions
+            .fetch_add(stats.read_compactions, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> Stats {
+        Stats {
+            gets: self.gets.load(Ordering::Relaxed),
+            tables_probed: self.tables_probed.load(Ordering::Relaxed),
+            seeks: self.seeks.load(Ordering::Relaxed),
+            prefix_skips: self.prefix_skips.load(Ordering::Relaxed),
+            range_skips: self.range_skips.load(Ordering::Relaxed),
+            read_compactions: self.read_compactions.load(Ordering::Relaxed),
+            flushes: *self.flushes.lock().unwrap(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Stats {
+    // Total calls to `Store::get`.
+    pub gets: u64,
+    // Total tables whose index was consulted while looking for a key.
+    pub tables_probed: u64,
+    // Total seeks into the data region of a table to read a record.
+    pub seeks: u64,
+    // Total tables left out of prefix scans by their prefix filter.
+    pub prefix_skips: u64,
+    // Total tables left out of scans because none of their keys are in the scanned range.
+    pub range_skips: u64,
+    // Total compactions made due by gets that probed too many tables, see
+    // Options::read_compaction_threshold.
+    pub read_compactions: u64,
+    // Memtable flushes, for tuning Options::wal_size_limit and the like.
+    pub flushes: FlushStats,
+}
+
+// A summary of the memtable flushes since the store was opened: How many there were, and how
+// large and how long they were, each in total and at the least and most.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FlushStats {
+    pub count: u64,
+    // Records flushed, and bytes of tables written.
+    pub records: u64,
+    pub bytes: u64,
+    pub min_bytes: u64,
+    pub max_bytes: u64,
+    // Time taken by the store's clock, from the start of the flush to when the WAL had been
+    // emptied.
+    pub duration: Duration,
+    pub min_duration: Duration,
+    pub max_duration: Duration,
+}
+
+impl FlushStats {
+    fn add(&mut self, records: u64, bytes: u64, duration: Duration) {
+        if self.count == 0 {
+            self.min_bytes = bytes;
+            self.min_duration = duration;
+        }
+        self.count += 1;
+        self.records += records;
+        self.bytes += bytes;
+        self.min_bytes = self.min_bytes.min(bytes);
+        self.max_bytes = self.max_bytes.max(bytes);
+        self.duration += duration;
+        self.min_duration = self.min_duration.min(duration);
+        self.max_duration = self.max_duration.max(duration);
+    }
+
+    // The average bytes of tables written by a flush, or zero if there were none.
+    pub fn avg_bytes(&self) -> u64 {
+        self.bytes.checked_div(self.count).unwrap_or(0)
+    }
+
+    // The average time a flush took, or zero if there were none.
+    pub fn avg_duration(&self) -> Duration {
+        match self.count {
+            0 => Duration::ZERO,
+            count => self.duration / count as u32,
+        }
+    }
+}
+
+impl Stats {
+    // Average number of tables probed per get. Gets served from the memtable count as zero probes.
+    pub fn read_amplification(&self) -> f64 {
+        if self.gets == 0 {
+            0.0
+        } else {
+            self.tables_probed as f64 / self.gets as f64
+        }
+    }
+
+    // The stats in Prometheus's text exposition format, with metric names starting with
+    // `namespace` and an underscore, as in `crucible_gets_total`. The namespace must be a valid
+    // metric name itself. Counters are cumulative since the store was opened, so a store that is
+    // reopened starts them from zero, which Prometheus treats as a counter reset.
+    pub fn to_prometheus(&self, namespace: &str) -> String {
+        let counters = [
+            ("gets_total", "Calls to get.", self.gets),
+            (
+                "tables_probed_total",
+                "Tables whose index was consulted while looking for a key.",
+                self.tables_probed,
+            ),
+            (
+                "seeks_total",
+                "Seeks into the data region of a table to read a record.",
+                self.seeks,
+            ),
+            (
+                "prefix_skips_total",
+                "Tables left out of prefix scans by their prefix filter.",
+                self.prefix_skips,
+            ),
+            (
+                "range_skips_total",
+                "Tables left out of scans for having no keys in the scanned range.",
+                self.range_skips,
+            ),
+            (
+                "read_compactions_total",
+                "Compactions made due by gets that probed too many tables.",
+                self.read_compactions,
+            ),
+            ("flushes_total", "Memtable flushes.", self.flushes.count),
+            (
+                "flushed_records_total",
+                "Records written to tables by memtable flushes.",
+                self.flushes.records,
+            ),
+            (
+                "flushed_bytes_total",
+                "Bytes of tables written by memtable flushes.",
+                self.flushes.bytes,
+            ),
+        ];
+
+        let mut out = String::new();
+        for (name, help, value) in counters {
+            out.push_str(&format!(
+                "# HELP {ns}_{name} {help}\n# TYPE {ns}_{name} counter\n{ns}_{name} {value}\n",
+                ns = namespace
+            ));
+        }
+        out.push_str(&format!(
+            "# HELP {ns}_read_amplification Average number of tables probed per get.\n\
+             # TYPE {ns}_read_amplification gauge\n\
+             {ns}_read_amplification {value}\n",
+            ns = namespace,
+            value = self.read_amplification()
+        ));
+        out.push_str(&format!(
+            "# HELP {ns}_flush_seconds_total Time taken by memtable flushes.\n\
+             # TYPE {ns}_flush_seconds_total counter\n\
+             {ns}_flush_seconds_total {value}\n",
+            ns = namespace,
+            value = self.flushes.duration.as_secs_f64()
+        ));
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_prometheus() {
+        let stats = Stats {
+            gets: 4,
+            tables_probed: 6,
+            seeks: 3,
+            prefix_skips: 2,
+            range_skips: 1,
+            read_compactions: 0,
+            flushes: FlushStats {
+                count: 2,
+                records: 10,
+                bytes: 300,
+                min_bytes: 100,
+                max_bytes: 200,
+                duration: Duration::from_millis(1500),
+                min_duration: Duration::from_millis(500),
+                max_duration: Duration::from_secs(1),
+            },
+        };
+        let text = stats.to_prometheus("crucible");
+
+        let lines = text.lines().map(|line| Ok(line.to_string()));
+        let scrape = prometheus_parse::Scrape::parse(lines).unwrap();
+        let value = |name: &str| {
+            let sample = scrape
+                .samples
+                .iter()
+                .find(|sample| sample.metric == name)
+                .unwrap();
+            match sample.value {
+                prometheus_parse::Value::Counter(v) | prometheus_parse::Value::Gauge(v) => v,
+                _ => panic!("{} is neither a counter nor a gauge", name),
+            }
+        };
+
+        assert_eq!(11, scrape.samples.len());
+        assert_eq!(4.0, value("crucible_gets_total"));
+        assert_eq!(6.0, value("crucible_tables_probed_total"));
+        assert_eq!(0.0, value("crucible_read_compactions_total"));
+        assert_eq!(1.5, value("crucible_read_amplification"));
+        assert_eq!(2.0, value("crucible_flushes_total"));
+        assert_eq!(1.5, value("crucible_flush_seconds_total"));
+        assert_eq!(
+            Some(&"Calls to get.".to_string()),
+            scrape.docs.get("crucible_gets_total")
+        );
+    }
+}
@@ -1,22 +1,99 @@
-use std::{fs, io, path};
+use std::{
+    env, fs, io,
+    ops::ControlFlow,
+    path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use uuid::Uuid;
 
 use crate::{
-    compactor::compactor, memtable::MemTable, protocol::WriteRecord, sst::Catalog, wal, StoreError,
+    batch::WriteBatch,
+    blob::{self, BlobFileIter, BlobWriter},
+    compactor::{
+        background::BackgroundCompactor,
+        combiner::{combine_tables, CombineTable},
+        compactor::{self, CompactionStats},
+        decision::{CompactionDecision, DecisionLog},
+        read_trigger::ReadTrigger,
+    },
+    context::{path_error, IoContext},
+    identity::{
+        Identity, CODEC_FORMAT_VERSION, IDENTITY_FILE_NAME, INLINE_VALUES_FORMAT_VERSION,
+        PREFIX_FILTER_FORMAT_VERSION,
+    },
+    indexing,
+    memtable::MemTable,
+    options::{
+        CompactionMode, Durability, IterOptions, MergeOptions, Options, RecoveryMode,
+        WalArchiveHook, WarmOptions,
+    },
+    protocol::{ReadRecord, WriteRecord},
+    recovery::RecoveryReport,
+    replace,
+    scan::{self, prefix_end, RawScan, Scan},
+    scrub::{Scrub, ScrubReport, Scrubber},
+    snapshot::{self, ReadOnlySnapshot, SnapshotIter, ValueReader},
+    sst::{
+        self, table::Table, Catalog, IntegrityReport, Pins, RepairReport, TableFormat, TableInfo,
+    },
+    stats::{ReadCounters, Stats},
+    wal, StoreError,
 };
 
-const WAL_FILE_NAME: &'static str = "data.wal";
-const WAL_SIZE_LIMIT: u32 = 4 * 1024 * 1024;
-const TABLE_SIZE_LIMIT: usize = 4 * 1024 * 1024;
-const LEVEL_0_FILE_LIMIT: usize = 5;
+pub(crate) const WAL_FILE_NAME: &str = "data.wal";
+
+// A flush is imminent once the WAL is within this fraction (1/N) of its size limit.
+const FLUSH_IMMINENT_FRACTION: u32 = 10;
 
 pub struct Store {
-    memtable: MemTable,
+    // The memtable and catalog are shared with any snapshots of the store, and are copied on write
+    // while a snapshot is alive.
+    memtable: Arc<MemTable>,
     wal: wal::Writer,
-    catalog: Option<Catalog>,
-    wal_size_limit: u32, // bytes
+    catalog: Arc<Catalog>,
+    options: Options,
     wal_file_path: path::PathBuf,
     data_dir: path::PathBuf,
     compactor: compactor::Compactor,
+    read_counters: Arc<ReadCounters>,
+    // The number of the most recently archived WAL segment, if archiving is enabled.
+    wal_archive_seq: u64,
+    recovery_report: RecoveryReport,
+    identity: Identity,
+    // The number of writes appended to the WAL since the store was opened.
+    sequence: u64,
+    // How much of the WAL the memtable has caught up with, see `recover_pending`.
+    applied_wal_size: u32,
+    // The number of live FreezeGuards.
+    freezes: Arc<AtomicUsize>,
+    // Held while changing the tables on disk or loading the catalog from them, see
+    // BackgroundCompactor.
+    tables_lock: Arc<Mutex<()>>,
+    // Runs automatic compactions with CompactionMode::Background.
+    background: Option<BackgroundCompactor>,
+    // Tables that compactions must leave in place for now, see `snapshot_iter`.
+    pins: Pins,
+    // Where large values go with Options::min_blob_size.
+    blobs: Option<BlobWriter>,
+    // When the oldest write in the memtable was made, by the options' monotonic clock, for
+    // Options::memtable_flush_after.
+    oldest_unflushed: Option<Duration>,
+    // Notes gets that probe many tables, with Options::read_compaction_threshold.
+    read_trigger: Option<Arc<ReadTrigger>>,
+    // Automatic compaction decisions, with Options::explain_compactions.
+    decisions: Option<DecisionLog>,
+    scrub: Scrub,
+    // Scrubs the tables in the background, with Options::scrub_rate. Only held so that its thread
+    // stops along with the store.
+    _scrubber: Option<Scrubber>,
+    // Removes the data directory of a store made by `new_temp`. Kept last, so that it drops once
+    // everything else that could still write there has stopped.
+    _temp_dir: Option<TempDir>,
 }
 
 impl Store {
@@ -26,96 +103,1674 @@ impl Store {
         table_size_limit: Option<usize>,
         level_0_file_limit: Option<usize>,
     ) -> Result<Store, StoreError> {
-        let wal_file_path = data_dir.join(&WAL_FILE_NAME);
+        let mut options = Options::default();
+        if let Some(limit) = wal_size_limit {
+            options = options.wal_size_limit(limit);
+        }
+        if let Some(limit) = table_size_limit {
+            options = options.table_size_limit(limit);
+        }
+        if let Some(limit) = level_0_file_limit {
+            options = options.level_0_file_limit(limit);
+        }
 
-        let mut sst = Catalog::new(&data_dir).map_err(|e| StoreError::CatalogInitialization(e))?;
+        Store::open(data_dir, options)
+    }
+
+    pub fn open(data_dir: &path::Path, options: Options) -> Result<Store, StoreError> {
+        options.validate()?;
+        // A directory of its own inside the data directory would be taken for a level of tables.
+        if let Some(wal_dir) = &options.wal_dir {
+            if inside(data_dir, wal_dir) {
+                return Err(StoreError::InvalidArgument(format!(
+                    "wal_dir {} must not be inside the data directory",
+                    wal_dir.display()
+                )));
+            }
+        }
+        if let Some(cold_dir) = &options.cold_dir {
+            if inside(data_dir, cold_dir) {
+                return Err(StoreError::InvalidArgument(format!(
+                    "cold_dir {} must not be inside the data directory",
+                    cold_dir.display()
+                )));
+            }
+        }
+
+        let wal_dir = options.wal_dir.as_deref().unwrap_or(data_dir);
+        let wal_file_path = wal_dir.join(WAL_FILE_NAME);
+        // A swap cut short by a crash is finished before anything is read, see replace_with.
+        replace::finish(data_dir, &[&data_dir.join(WAL_FILE_NAME), &wal_file_path])
+            .map_err(StoreError::CatalogInitialization)?;
+
+        let identity = load_identity(data_dir, &options)?;
+        fs::create_dir_all(wal_dir)
+            .with_path("creating", wal_dir)
+            .map_err(StoreError::WalInitialization)?;
+        let recover_from = wal_to_recover(data_dir, &wal_file_path)?;
+
+        let mut recovery_report = RecoveryReport::default();
+        let pins = Pins::new(&options);
+        sst::remove_obsolete(data_dir, &pins).map_err(StoreError::CatalogInitialization)?;
+        if let Some(cold_dir) = &options.cold_dir {
+            fs::create_dir_all(cold_dir)
+                .with_path("creating", cold_dir)
+                .map_err(StoreError::CatalogInitialization)?;
+            sst::remove_obsolete(cold_dir, &pins).map_err(StoreError::CatalogInitialization)?;
+        }
+        let mut sst = Catalog::open_tiered(
+            data_dir,
+            options.cold_dir.as_deref(),
+            options.recovery_mode,
+            &mut recovery_report,
+        )?
+        .with_format(TableFormat::new(&options))?;
+        if options.verify_files_on_open {
+            for table in sst.ssts.iter().flatten() {
+                table.verify()?;
+            }
+        }
+
+        // Legacy tables are older than the WAL, so they are migrated before it is converted.
+        let legacy = sst::legacy_tables(data_dir)?;
+        if !legacy.is_empty() {
+            if !options.migrate_legacy_tables {
+                return Err(StoreError::InvalidArgument(format!(
+                    "{} has tables in the legacy layout, such as {}; open it with \
+                     Options::migrate_legacy_tables to upgrade them",
+                    data_dir.display(),
+                    legacy[0].display()
+                )));
+            }
+            sst::migrate_legacy_tables(&legacy, &mut sst)?;
+        }
+
+        let mut wal_archive_seq = 0;
+        if options.wal_archive.is_some() {
+            wal_archive_seq =
+                wal::last_archived(&wal_file_path).map_err(StoreError::WalInitialization)?;
+        }
 
         // Convert any left-over wal file into an sst.
-        if let Some(len) = fs::metadata(&wal_file_path).ok().map(|meta| meta.len()) {
+        if let Some(len) = fs::metadata(&recover_from).ok().map(|meta| meta.len()) {
+            if len > 0 && options.durability == Durability::None {
+                return Err(StoreError::InvalidArgument(format!(
+                    "{} holds writes that haven't been flushed; open the store with \
+                     Durability::Wal to recover them first",
+                    recover_from.display()
+                )));
+            }
             if len > 0 {
-                let memtable: MemTable = wal::Reader::new(&wal_file_path)
-                    .map_err(|e| StoreError::WalRecovery(e))?
-                    .into_iter()
+                let mut reader = wal::Reader::new(&recover_from)
+                    .map_err(StoreError::WalRecovery)?
+                    .recovery_mode(options.recovery_mode)
+                    .codecs(options.codecs.clone());
+                let memtable: MemTable = reader
+                    .by_ref()
                     .collect::<Result<MemTable, io::Error>>()
-                    .map_err(|e| StoreError::WalRecovery(e))?;
+                    .map_err(StoreError::WalRecovery)?;
+                if let Some(skipped) = reader.skipped() {
+                    recovery_report.skipped.push(skipped.clone());
+                }
+
+                // Everything in the WAL may have been skipped.
+                if !memtable.is_empty() {
+                    sst.write_records(&memtable)?; // Should be owned
+                }
 
-                sst.write_records(&memtable) // Should be owned
-                    .map_err(|e| StoreError::WalRecovery(e))?;
+                if let Some(hook) = &options.wal_archive {
+                    archive_wal(&recover_from, &mut wal_archive_seq, hook)
+                        .map_err(StoreError::WalInitialization)?;
+                }
+            }
+            // A WAL left in the data directory by an earlier open without Options::wal_dir has
+            // been recovered, and must not be recovered again.
+            if recover_from != wal_file_path && recover_from.exists() {
+                fs::remove_file(&recover_from)
+                    .with_path("removing", &recover_from)
+                    .map_err(StoreError::WalInitialization)?;
             }
         };
 
+        let freezes = Arc::new(AtomicUsize::new(0));
+        let tables_lock = Arc::new(Mutex::new(()));
+        let read_trigger = ReadTrigger::new(&options).map(Arc::new);
+        let decisions = DecisionLog::new(&options);
+        let background = (options.compaction_mode == CompactionMode::Background).then(|| {
+            BackgroundCompactor::spawn(
+                &options,
+                data_dir,
+                tables_lock.clone(),
+                freezes.clone(),
+                pins.clone(),
+                read_trigger.clone(),
+                decisions.clone(),
+            )
+        });
+
+        let scrub = Scrub::new(&options, data_dir, pins.clone());
+        let scrubber = options
+            .scrub_rate
+            .map(|rate| Scrubber::spawn(scrub.clone(), rate));
+
+        let blobs = match options.min_blob_size {
+            Some(_) => Some(BlobWriter::open(data_dir)?),
+            None => None,
+        };
+
         Ok(Store {
-            memtable: MemTable::new(),
-            wal: wal::Writer::new(&wal_file_path).map_err(|e| StoreError::WalInitialization(e))?,
-            catalog: Some(sst),
-            wal_size_limit: wal_size_limit.unwrap_or(WAL_SIZE_LIMIT),
+            memtable: Arc::new(MemTable::new()),
+            wal: open_wal(&wal_file_path, &options).map_err(StoreError::WalInitialization)?,
+            catalog: Arc::new(sst),
             wal_file_path,
             data_dir: data_dir.into(),
-            compactor: compactor::Compactor::new(
-                level_0_file_limit.unwrap_or(LEVEL_0_FILE_LIMIT),
-                table_size_limit.unwrap_or(TABLE_SIZE_LIMIT),
-                data_dir,
-            ),
+            compactor: compactor::Compactor::new(&options, data_dir)
+                .with_pins(pins.clone())
+                .with_decisions(decisions.clone()),
+            options,
+            read_counters: Arc::new(ReadCounters::default()),
+            wal_archive_seq,
+            recovery_report,
+            identity,
+            sequence: 0,
+            applied_wal_size: 0,
+            freezes,
+            tables_lock,
+            background,
+            pins,
+            blobs,
+            oldest_unflushed: None,
+            read_trigger,
+            decisions,
+            scrub,
+            _scrubber: scrubber,
+            _temp_dir: None,
         })
     }
 
-    pub fn put(&mut self, key: &[u8], val: &[u8]) -> io::Result<()> {
+    // Opens a store with the default options in a new directory under the system's temporary
+    // directory, which is removed along with everything in it when the store is dropped. A directory
+    // that can't be removed then is left behind.
+    pub fn new_temp() -> Result<Store, StoreError> {
+        let data_dir = env::temp_dir().join(format!("crucible-{}", Uuid::new_v4()));
+        fs::create_dir(&data_dir)
+            .with_path("creating", &data_dir)
+            .map_err(StoreError::CatalogInitialization)?;
+        let guard = TempDir(data_dir);
+        let mut store = Store::open(&guard.0, Options::default())?;
+        store._temp_dir = Some(guard);
+        Ok(store)
+    }
+
+    pub fn put(&mut self, key: &[u8], val: &[u8]) -> Result<(), StoreError> {
+        self.options.validate_write(key, Some(val))?;
+        self.write_indexed(key, Some(val))
+    }
+
+    fn put_record(&mut self, key: &[u8], val: &[u8]) -> Result<(), StoreError> {
         self.exec_wal(|store| {
-            store.wal.append(WriteRecord::Exists { key, val })?;
-            store.memtable.put(key, val);
+            let separate = store
+                .options
+                .min_blob_size
+                .is_some_and(|min| val.len() >= min);
+            match &mut store.blobs {
+                // The value must be durable before the WAL refers to it.
+                Some(blobs) if separate => {
+                    let blob = blobs.append(key, val)?;
+                    store
+                        .wal
+                        .append(WriteRecord::Blob { key, blob })
+                        .map_err(StoreError::Wal)?;
+                    Arc::make_mut(&mut store.memtable).put_blob(key, blob);
+                }
+                _ => {
+                    store
+                        .wal
+                        .append(WriteRecord::Exists { key, val })
+                        .map_err(StoreError::Wal)?;
+                    Arc::make_mut(&mut store.memtable).put(key, val);
+                }
+            }
             Ok(())
         })
     }
 
-    pub fn get(&self, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
-        if let Some(val) = self.memtable.get(key) {
-            Ok(Some(val.to_vec()))
-        } else if let Some(rec) = self.catalog.as_ref().unwrap().get(key)? {
-            match rec {
-                crate::protocol::ReadRecord::Exists { val, .. } => Ok(Some(val)),
-                crate::protocol::ReadRecord::Deleted { .. } => Ok(None),
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, StoreError> {
+        self.get_triggering(key, false)
+    }
+
+    // The value of a key as a reader, so that a large value can be copied somewhere without
+    // holding all of it in memory. A value in a table or blob file is read from a handle of the
+    // reader's own, which stays readable even if the store compacts the table away meanwhile.
+    pub fn get_reader(&self, key: &[u8]) -> Result<Option<ValueReader>, StoreError> {
+        snapshot::get_reader(&self.memtable, &self.catalog, &self.read_counters, key)
+    }
+
+    // Same as `get`, but checks that a record read from a table really is for the key, rather than
+    // trusting the table's index. A mismatch is returned as StoreError::Corruption. This costs
+    // little, but `get` skips it since a mismatch can only come from damage to the table.
+    pub fn get_verified(&self, key: &[u8]) -> Result<Option<Vec<u8>>, StoreError> {
+        self.get_triggering(key, true)
+    }
+
+    // Gets a key, noting how many tables it probed with Options::read_compaction_threshold.
+    fn get_triggering(&self, key: &[u8], verify: bool) -> Result<Option<Vec<u8>>, StoreError> {
+        let Some(trigger) = &self.read_trigger else {
+            return snapshot::get(
+                &self.memtable,
+                &self.catalog,
+                &self.read_counters,
+                key,
+                verify,
+            );
+        };
+
+        // The get's own counts show how many tables it probed.
+        let counters = ReadCounters::default();
+        let val = snapshot::get(&self.memtable, &self.catalog, &counters, key, verify);
+        let stats = counters.snapshot();
+        self.read_counters.add(stats);
+
+        if trigger.record(key, stats.tables_probed, self.options.clock.monotonic()) {
+            self.read_counters.record_read_compaction();
+            if let Some(background) = &self.background {
+                background.wake();
+            }
+        }
+        val
+    }
+
+    // Up to `limit` of the most recent values of a key, newest first, with None for a deletion.
+    // Compaction only keeps older versions with Options::versions_to_keep, but versions that have
+    // yet to be compacted are returned either way.
+    pub fn get_versions(
+        &self,
+        key: &[u8],
+        limit: usize,
+    ) -> Result<Vec<Option<Vec<u8>>>, StoreError> {
+        self.read_counters.record_get();
+
+        let mut versions = Vec::new();
+        match self.memtable.lookup(key) {
+            Some(WriteRecord::Exists { val, .. }) => versions.push(Some(val.to_vec())),
+            Some(WriteRecord::Blob { blob, .. }) => {
+                versions.push(Some(blob::read(&self.data_dir, blob)?))
+            }
+            Some(WriteRecord::Deleted { .. } | WriteRecord::RangeDeleted { .. }) => {
+                versions.push(None)
+            }
+            None => (),
+        }
+        // A range deletion in the memtable is older than its records, and hides every version in
+        // the tables.
+        let range_deleted = self.memtable.range_deleted(key);
+        if range_deleted {
+            versions.push(None);
+        }
+        if range_deleted || versions.len() >= limit {
+            versions.truncate(limit);
+            return Ok(versions);
+        }
+
+        for record in self.catalog.get_versions(key, limit - versions.len())? {
+            versions.push(match record {
+                ReadRecord::Exists { val, .. } => Some(val),
+                ReadRecord::Blob { blob, .. } => Some(blob::read(&self.data_dir, blob)?),
+                ReadRecord::Deleted { .. } | ReadRecord::RangeDeleted { .. } => None,
+            });
+        }
+
+        Ok(versions)
+    }
+
+    // Live records with keys in [start, end), or from start onward if there is no end.
+    pub fn scan(&self, start: &[u8], end: Option<&[u8]>) -> Result<Scan, StoreError> {
+        Scan::new(
+            self.memtable.clone(),
+            &self.catalog,
+            start,
+            end,
+            &self.read_counters,
+        )
+    }
+
+    // What a scan of the store reads from, for scans of more than one store, see scan::merge_scan.
+    pub(crate) fn scan_sources(&self) -> (Arc<MemTable>, &Catalog, &ReadCounters) {
+        (self.memtable.clone(), &self.catalog, &self.read_counters)
+    }
+
+    // Up to `limit` live records with keys in [start, end), or from start onward if there is no end,
+    // as one page of results. The page says where the next one starts, which is the key of the
+    // first live record after it, so that asking for [resume, end) continues exactly where this
+    // page left off.
+    pub fn scan_limited(
+        &self,
+        start: &[u8],
+        end: Option<&[u8]>,
+        limit: usize,
+    ) -> Result<ScanPage, StoreError> {
+        let mut scan = self.scan(start, end)?;
+        let records = scan.by_ref().take(limit).collect::<Result<Vec<_>, _>>()?;
+        let resume = scan.next().transpose()?.map(|(key, _)| key);
+        Ok(ScanPage { records, resume })
+    }
+
+    // Calls `f` with the key and value of each live record with a key in [start, end), or from start
+    // onward if there is no end, in key order, until it returns `Break`. An error from `f` stops
+    // the scan and is returned as StoreError::Io.
+    pub fn for_each_in_range<F>(
+        &self,
+        start: &[u8],
+        end: Option<&[u8]>,
+        mut f: F,
+    ) -> Result<(), StoreError>
+    where
+        F: FnMut(&[u8], &[u8]) -> io::Result<ControlFlow<()>>,
+    {
+        for record in self.scan(start, end)? {
+            let (key, val) = record?;
+            if f(&key, &val)?.is_break() {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Records with keys in [start, end), or from start onward if there is no end, along with
+    // deletions and where each record was read from, as set in `options`.
+    pub fn scan_raw(
+        &self,
+        start: &[u8],
+        end: Option<&[u8]>,
+        options: IterOptions,
+    ) -> Result<RawScan, StoreError> {
+        RawScan::new(
+            self.memtable.clone(),
+            &self.catalog,
+            start,
+            end,
+            options,
+            &self.read_counters,
+        )
+    }
+
+    // Live records with keys starting with `prefix`. With Options::prefix_bloom_length, tables
+    // without any such keys are skipped, as long as the prefix is at least that long.
+    pub fn scan_prefix(&self, prefix: &[u8]) -> Result<Scan, StoreError> {
+        Scan::prefix(
+            self.memtable.clone(),
+            &self.catalog,
+            prefix,
+            &self.read_counters,
+        )
+    }
+
+    // Returns a read-only view of the store that won't see any subsequent writes. Unlike a backup,
+    // no files are copied, so this is cheap enough to call repeatedly.
+    pub fn freeze(&self) -> ReadOnlySnapshot {
+        ReadOnlySnapshot {
+            memtable: self.memtable.clone(),
+            catalog: self.catalog.clone(),
+            read_counters: self.read_counters.clone(),
+        }
+    }
+
+    // Iterates every live record in the store, in key order, as of the call. Writes made afterwards
+    // aren't visible to it. Its tables are pinned until it is dropped: Compactions carry on as
+    // usual, but leave the files of the tables they replace in place until then.
+    pub fn snapshot_iter(&self) -> Result<SnapshotIter, StoreError> {
+        // Not in the middle of a background compaction, which could remove the tables before
+        // they are pinned.
+        let _tables = self.tables_lock.lock().unwrap();
+        let pin = self
+            .pins
+            .pin(self.tables().map(|(_, table)| table.path.clone()).collect());
+        let scan = Scan::new(
+            self.memtable.clone(),
+            &self.catalog,
+            b"",
+            None,
+            &self.read_counters,
+        )?;
+
+        Ok(SnapshotIter { scan, _pin: pin })
+    }
+
+    // Makes the store read-only until the returned guard is dropped. Writes, flushes, and
+    // compactions fail with StoreError::Frozen in the meantime, while reads carry on as usual. Any of
+    // those already under way finish first, since they hold the store mutably. Background
+    // compactions don't start while the store is frozen, though one already running finishes.
+    // Freezes nest: The store stays frozen until every guard is dropped.
+    pub fn freeze_writes(&self) -> FreezeGuard {
+        self.freezes.fetch_add(1, Ordering::SeqCst);
+        FreezeGuard {
+            freezes: self.freezes.clone(),
+        }
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        self.freezes.load(Ordering::SeqCst) > 0
+    }
+
+    fn check_frozen(&self) -> Result<(), StoreError> {
+        if self.is_frozen() {
+            return Err(StoreError::Frozen);
+        }
+        Ok(())
+    }
+
+    pub fn del(&mut self, key: &[u8]) -> Result<(), StoreError> {
+        self.options.validate_write(key, None)?;
+        self.write_indexed(key, None)
+    }
+
+    // Reads the value of `key`, passes it to `f`, and writes back what `f` returns: Putting a value,
+    // or deleting the key for None. Nothing is written if there was no value and `f` returns None.
+    // The store is held throughout, so no other write can come in between the read and the write.
+    // Returns the value now at the key.
+    pub fn update<F>(&mut self, key: &[u8], f: F) -> Result<Option<Vec<u8>>, StoreError>
+    where
+        F: FnOnce(Option<&[u8]>) -> Option<Vec<u8>>,
+    {
+        let current = self.get(key)?;
+        match f(current.as_deref()) {
+            Some(val) => {
+                self.put(key, &val)?;
+                Ok(Some(val))
             }
+            None if current.is_some() => {
+                self.del(key)?;
+                Ok(None)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn del_record(&mut self, key: &[u8]) -> Result<(), StoreError> {
+        self.exec_wal(|store| {
+            store
+                .wal
+                .append(WriteRecord::Deleted { key })
+                .map_err(StoreError::Wal)?;
+            Arc::make_mut(&mut store.memtable).del(key);
+            Ok(())
+        })
+    }
+
+    // Puts `val` at `key`, or deletes it if there is no value, along with the changes to index
+    // entries that go with it. New entries are written before the record and stale ones removed
+    // after, see the indexing module.
+    fn write_indexed(&mut self, key: &[u8], val: Option<&[u8]>) -> Result<(), StoreError> {
+        let (added, removed) = if self.options.indexes.is_empty() {
+            (Vec::new(), Vec::new())
         } else {
-            Ok(None)
+            let old = self.get(key)?;
+            indexing::changes(&self.options.indexes, key, old.as_deref(), val)
+        };
+        self.check_index_entries(&added)?;
+
+        for entry in &added {
+            self.put_record(entry, b"")?;
+        }
+        match val {
+            Some(val) => self.put_record(key, val)?,
+            None => self.del_record(key)?,
+        }
+        for entry in &removed {
+            self.del_record(entry)?;
+        }
+        Ok(())
+    }
+
+    // Entries are longer than the keys they point to, so they may not fit where the key did.
+    fn check_index_entries(&self, added: &[Vec<u8>]) -> Result<(), StoreError> {
+        if let Some(entry) = added.iter().find(|e| e.len() > self.options.max_key_size) {
+            return Err(StoreError::InvalidArgument(format!(
+                "index entry length {} exceeds the maximum key size of {} bytes",
+                entry.len(),
+                self.options.max_key_size
+            )));
         }
+        Ok(())
     }
 
-    pub fn del(&mut self, key: &[u8]) -> io::Result<()> {
+    // Writes every put and delete in `batch` at once. They are appended to the WAL as a single
+    // batch, along with the changes to index entries that go with them, so after a crash either
+    // all of them are recovered or none are. Nothing is written if any op is invalid.
+    //
+    // The batch is durable once it is in the WAL, before it is applied to the memtable. If applying
+    // it is cut short, such as by a panic that is caught, the batch is still recovered when the
+    // store is next opened, but reads don't see it until then, or until the next write, flush or
+    // call to `recover_pending`, which apply it first. An error returned before the batch reached
+    // the WAL means none of it was written.
+    pub fn write_batch(&mut self, batch: &WriteBatch) -> Result<(), StoreError> {
+        for (key, val) in batch.iter() {
+            self.options.validate_write(key, val)?;
+        }
+        if batch.is_empty() {
+            return Ok(());
+        }
+
+        let mut added = Vec::new();
+        let mut removed = Vec::new();
+        if !self.options.indexes.is_empty() {
+            for (key, val) in batch.iter() {
+                let old = self.get(key)?;
+                let (a, r) = indexing::changes(&self.options.indexes, key, old.as_deref(), val);
+                added.extend(a);
+                removed.extend(r);
+            }
+        }
+        self.check_index_entries(&added)?;
+
+        self.exec_wal(|store| {
+            let mut records = Vec::with_capacity(added.len() + batch.len() + removed.len());
+            records.extend(
+                added
+                    .iter()
+                    .map(|key| WriteRecord::Exists { key, val: b"" }),
+            );
+            for (key, val) in batch.iter() {
+                let Some(val) = val else {
+                    records.push(WriteRecord::Deleted { key });
+                    continue;
+                };
+                let separate = store
+                    .options
+                    .min_blob_size
+                    .is_some_and(|min| val.len() >= min);
+                match &mut store.blobs {
+                    // The value must be durable before the WAL refers to it.
+                    Some(blobs) if separate => {
+                        let blob = blobs.append(key, val)?;
+                        records.push(WriteRecord::Blob { key, blob });
+                    }
+                    _ => records.push(WriteRecord::Exists { key, val }),
+                }
+            }
+            records.extend(removed.iter().map(|key| WriteRecord::Deleted { key }));
+
+            store.wal.append_batch(&records).map_err(StoreError::Wal)?;
+            let memtable = Arc::make_mut(&mut store.memtable);
+            for record in &records {
+                memtable.apply(record);
+            }
+            Ok(())
+        })
+    }
+
+    // The primary keys of the records found under `index_key` in the index called `name`, in
+    // ascending order. Each is checked against its record's current value, which is a get for
+    // every entry, so that stale entries are never returned.
+    pub fn lookup_index(&self, name: &str, index_key: &[u8]) -> Result<Vec<Vec<u8>>, StoreError> {
+        let Some(index) = self.options.indexes.iter().find(|i| i.name == name) else {
+            return Err(StoreError::InvalidArgument(format!(
+                "no index is called {:?}",
+                name
+            )));
+        };
+
+        let prefix = indexing::entry_prefix(name, index_key);
+        let mut keys = Vec::new();
+        for entry in self.scan_prefix(&prefix)? {
+            let (entry, _) = entry?;
+            let key = &entry[prefix.len()..];
+            if let Some(val) = self.get(key)? {
+                if indexing::matches(index, index_key, key, &val) {
+                    keys.push(key.to_vec());
+                }
+            }
+        }
+        Ok(keys)
+    }
+
+    // Deletes every key starting with `prefix` by writing a single range deletion, however many keys
+    // there are, and returns the number of live keys it deleted. The deletion runs up to the
+    // smallest key past the prefix, see scan::prefix_end, or to the end of the key space for a
+    // prefix of only 0xff bytes. Keys written under the prefix afterwards are unaffected. Counting
+    // the keys means reading through them, but nothing is written for each. Index entries for the
+    // deleted records are left in place, and filtered out by lookups, and a prefix that would cover
+    // the entries themselves is rejected.
+    pub fn delete_prefix(&mut self, prefix: &[u8]) -> Result<u64, StoreError> {
+        self.options.validate_write(prefix, None)?;
+        if !self.options.indexes.is_empty() && indexing::overlaps_entries(prefix) {
+            return Err(StoreError::InvalidArgument(
+                "delete_prefix must not cover index entries".to_string(),
+            ));
+        }
+        self.check_frozen()?;
+        let end = prefix_end(prefix);
+
+        let mut deleted = 0;
+        for record in self.scan_prefix(prefix)? {
+            record?;
+            deleted += 1;
+        }
+
         self.exec_wal(|store| {
-            store.wal.append(WriteRecord::Deleted { key })?;
-            store.memtable.del(key);
+            store
+                .wal
+                .append(WriteRecord::RangeDeleted {
+                    start: prefix,
+                    end: end.as_deref(),
+                })
+                .map_err(StoreError::Wal)?;
+            Arc::make_mut(&mut store.memtable).delete_range(prefix, end.as_deref());
             Ok(())
+        })?;
+        Ok(deleted)
+    }
+
+    // Reclaims the space of overwritten and deleted values in blob files. Each blob file in which
+    // live values make up less than `min_live_ratio` of the value bytes has its live values written
+    // again, as if they were put anew, and is then removed. Returns the number of files removed.
+    // The file being appended to is left alone. Snapshots and scans from before the call, and older
+    // versions kept by Options::versions_to_keep, can't read values from a removed file.
+    pub fn collect_blobs(&mut self, min_live_ratio: f64) -> Result<usize, StoreError> {
+        self.check_frozen()?;
+        self.catch_up()?;
+
+        let active = self.blobs.as_ref().map(BlobWriter::id);
+        let mut removed = 0;
+        for file in blob::blob_files(&self.data_dir)? {
+            if Some(file) == active {
+                continue;
+            }
+
+            let mut live = Vec::new();
+            let (mut live_bytes, mut total_bytes) = (0, 0);
+            for entry in BlobFileIter::new(&self.data_dir, file)? {
+                let (key, blob) = entry?;
+                total_bytes += blob.len as u64;
+                if self.current_blob(&key)? == Some(blob) {
+                    live_bytes += blob.len as u64;
+                    live.push((key, blob));
+                }
+            }
+            if total_bytes > 0 && live_bytes as f64 >= min_live_ratio * total_bytes as f64 {
+                continue;
+            }
+
+            // Every rewritten value is synced to the WAL before the file is removed.
+            for (key, blob) in live {
+                // The value is unchanged, so its index entries are too.
+                let val = blob::read(&self.data_dir, blob)?;
+                self.put_record(&key, &val)?;
+            }
+
+            let path = blob::blob_path(&self.data_dir, file);
+            fs::remove_file(&path).map_err(|e| path_error("removing", &path, e))?;
+            removed += 1;
+        }
+
+        Ok(removed)
+    }
+
+    // The blob file reference in the newest record for a key, if that's what it has.
+    fn current_blob(&self, key: &[u8]) -> Result<Option<blob::BlobRef>, StoreError> {
+        match self.memtable.lookup(key) {
+            Some(WriteRecord::Blob { blob, .. }) => return Ok(Some(blob)),
+            Some(_) => return Ok(None),
+            None if self.memtable.range_deleted(key) => return Ok(None),
+            None => (),
+        }
+
+        match self.catalog.get(key)? {
+            Some(ReadRecord::Blob { blob, .. }) => Ok(Some(blob)),
+            _ => Ok(None),
+        }
+    }
+
+    // Brings the records of the store in `other_dir` into this one, in new level 0 tables rather
+    // than as individual writes. The other store is only read, and must not be open while this
+    // runs; the newest record for each of its keys is taken from its tables and from the writes in
+    // its WAL. `options` says which store wins for keys that both have, and whether the other
+    // store's deletions are brought in. Values the other store keeps in blob files are copied into
+    // the new tables. The memtable is flushed first, so that everything already in this store is
+    // in tables. Returns the number of records brought in.
+    pub fn merge_from(
+        &mut self,
+        other_dir: &path::Path,
+        options: MergeOptions,
+    ) -> Result<u64, StoreError> {
+        let same_store = fs::canonicalize(other_dir)
+            .and_then(|other| Ok(other == fs::canonicalize(&self.data_dir)?))
+            .map_err(|e| path_error("resolving", other_dir, e))?;
+        if same_store {
+            return Err(StoreError::InvalidArgument(format!(
+                "can't merge {} into itself",
+                other_dir.display()
+            )));
+        }
+        // The merged records don't go through put, so nothing would add their index entries.
+        if !self.options.indexes.is_empty() {
+            return Err(StoreError::InvalidArgument(
+                "can't merge into a store with indexes".to_string(),
+            ));
+        }
+
+        self.flush_memtable()?;
+
+        // Anything unreadable in the other store is an error, since it would otherwise be lost.
+        // Its values are decoded with this store's codecs.
+        let other = Catalog::open(
+            other_dir,
+            RecoveryMode::Strict,
+            &mut RecoveryReport::default(),
+        )?
+        .with_format(TableFormat::new(&self.options))?;
+        let other_wal = options
+            .wal_dir
+            .as_deref()
+            .unwrap_or(other_dir)
+            .join(WAL_FILE_NAME);
+        let other_memtable = match fs::metadata(&other_wal) {
+            Ok(meta) if meta.len() > 0 => wal::Reader::new(&other_wal)
+                .and_then(|reader| {
+                    reader
+                        .codecs(self.options.codecs.clone())
+                        .collect::<io::Result<MemTable>>()
+                })
+                .map_err(StoreError::WalRecovery)?,
+            _ => MemTable::new(),
+        };
+
+        let mut batch = MemTable::new();
+        let mut batch_size = 0;
+        let mut merged = 0;
+        for record in scan::merged_records(
+            Arc::new(other_memtable),
+            &other,
+            b"",
+            None,
+            None,
+            &ReadCounters::default(),
+        )? {
+            let record = record?;
+            // Everything in this store is in its tables now that the memtable is flushed.
+            if !options.prefer_source && self.catalog.has_record(record.key()) {
+                continue;
+            }
+
+            match record {
+                ReadRecord::Exists { key, val } => {
+                    self.options.validate_write(&key, Some(&val))?;
+                    batch_size += key.len() + val.len();
+                    batch.put(&key, &val);
+                }
+                ReadRecord::Blob { key, blob } => {
+                    let val = blob::read(other_dir, blob)?;
+                    self.options.validate_write(&key, Some(&val))?;
+                    batch_size += key.len() + val.len();
+                    batch.put(&key, &val);
+                }
+                ReadRecord::Deleted { key } if options.include_tombstones => {
+                    self.options.validate_write(&key, None)?;
+                    batch_size += key.len();
+                    batch.del(&key);
+                }
+                ReadRecord::Deleted { .. } | ReadRecord::RangeDeleted { .. } => continue,
+            }
+            merged += 1;
+
+            // The records come in key order, so each table covers keys after the last.
+            if batch_size >= self.options.table_size_limit {
+                self.write_table(&batch)?;
+                batch = MemTable::new();
+                batch_size = 0;
+            }
+        }
+        if !batch.is_empty() {
+            self.write_table(&batch)?;
+        }
+
+        self.maybe_compact()?;
+        Ok(merged)
+    }
+
+    // Creates a new store in `target_dir` holding just the live records with keys in [start, end),
+    // or from start onward if there is no end, as of the call. The records are streamed into level
+    // 1 tables rather than gathered in memory, and values kept in blob files are copied into the
+    // tables. `target_dir` must be empty or not exist yet.
+    pub fn export_range(
+        &self,
+        start: &[u8],
+        end: Option<&[u8]>,
+        target_dir: &path::Path,
+    ) -> Result<ExportReport, StoreError> {
+        if end.is_some_and(|end| end <= start) {
+            return Err(StoreError::InvalidArgument(
+                "export range end must be after its start".to_string(),
+            ));
+        }
+
+        fs::create_dir_all(target_dir).map_err(|e| path_error("creating", target_dir, e))?;
+        let is_empty = fs::read_dir(target_dir)
+            .map_err(|e| path_error("listing", target_dir, e))?
+            .next()
+            .is_none();
+        if !is_empty {
+            return Err(StoreError::InvalidArgument(format!(
+                "{} must be empty to export into",
+                target_dir.display()
+            )));
+        }
+        Identity::load_or_create(target_dir)?;
+
+        let mut report = ExportReport::default();
+        let mut scan = self.scan(start, end)?.peekable();
+        // A table needs at least one record.
+        if scan.peek().is_some() {
+            // An error from the scan ends the records written, and is returned in place of the
+            // I/O error that reports it to the writer.
+            let mut scan_err = None;
+            let records = std::iter::from_fn(|| match scan.next()? {
+                Ok((key, val)) => {
+                    report.records += 1;
+                    report.bytes += (key.len() + val.len()) as u64;
+                    Some(Ok(ReadRecord::Exists { key, val }))
+                }
+                Err(e) => {
+                    let err = io::Error::other(e.to_string());
+                    scan_err = Some(e);
+                    Some(Err(err))
+                }
+            });
+            let written = combine_tables(
+                vec![CombineTable {
+                    table: records,
+                    level: 1,
+                    sequence: None,
+                }],
+                self.options.table_size_limit,
+                1,
+                &[],
+                1,
+                &TableFormat::default(),
+                target_dir,
+            );
+            if let Some(e) = scan_err {
+                return Err(e);
+            }
+            written.map_err(|source| StoreError::Flush {
+                path: target_dir.into(),
+                source,
+            })?;
+        }
+
+        let wal_file_path = target_dir.join(WAL_FILE_NAME);
+        wal::Writer::new(&wal_file_path)
+            .and_then(|mut wal| wal.sync())
+            .map_err(StoreError::WalInitialization)?;
+
+        Ok(report)
+    }
+
+    // Replaces all of the store's data with that of the store in `staging`, such as one built by
+    // `export_range` or opened there on its own and since closed. Writes not yet flushed are
+    // dropped along with the rest of the old data, and the WAL starts over empty. Reads through the
+    // store see either all of the old data or all of the new. Snapshots taken before keep reading
+    // the old tables through the handles they have open on most platforms, but not old values kept
+    // in blob files. The staging store must not be open, must have flushed all of its writes, and
+    // must be on the same filesystem as the data directory. Its data is moved rather than copied.
+    //
+    // A crash part way through leaves the swap to be finished when the store is next opened, see
+    // the replace module. Stores with Options::cold_dir can't be replaced.
+    pub fn replace_with(&mut self, staging: &path::Path) -> Result<(), StoreError> {
+        self.check_frozen()?;
+        if self.options.cold_dir.is_some() {
+            return Err(StoreError::InvalidArgument(
+                "can't replace the data of a store with a cold_dir".to_string(),
+            ));
+        }
+        let same_store = fs::canonicalize(staging)
+            .and_then(|staging| Ok(staging == fs::canonicalize(&self.data_dir)?))
+            .map_err(|e| path_error("resolving", staging, e))?;
+        if same_store {
+            return Err(StoreError::InvalidArgument(format!(
+                "can't replace {} with itself",
+                staging.display()
+            )));
+        }
+        if !staging.join(IDENTITY_FILE_NAME).exists() {
+            return Err(StoreError::InvalidArgument(format!(
+                "{} doesn't hold a store",
+                staging.display()
+            )));
+        }
+        let staging_wal = staging.join(WAL_FILE_NAME);
+        if fs::metadata(&staging_wal).is_ok_and(|meta| meta.len() > 0) {
+            return Err(StoreError::InvalidArgument(format!(
+                "{} holds writes that haven't been flushed",
+                staging_wal.display()
+            )));
+        }
+        // The staged tables must be readable by this store before they take the place of its own.
+        Catalog::open(
+            staging,
+            RecoveryMode::Strict,
+            &mut RecoveryReport::default(),
+        )?
+        .with_format(TableFormat::new(&self.options))?;
+
+        self.wait_for_compactions()?;
+        let tables_lock = self.tables_lock.clone();
+        let _tables = tables_lock.lock().unwrap();
+
+        let data_wal = self.data_dir.join(WAL_FILE_NAME);
+        replace::replace(&self.data_dir, staging, &[&data_wal, &self.wal_file_path])
+            .map_err(StoreError::Io)?;
+
+        self.identity = load_identity(&self.data_dir, &self.options)?;
+        self.wal = open_wal(&self.wal_file_path, &self.options).map_err(StoreError::Wal)?;
+        self.applied_wal_size = 0;
+        self.memtable = Arc::new(MemTable::new());
+        self.oldest_unflushed = None;
+        if self.blobs.is_some() {
+            self.blobs = Some(BlobWriter::open(&self.data_dir)?);
+        }
+        self.catalog = Arc::new(self.open_catalog()?);
+        Ok(())
+    }
+
+    // Writes records straight to a new level 0 table, as a flush would.
+    fn write_table(&mut self, records: &MemTable) -> Result<(), StoreError> {
+        let tables_lock = self.tables_lock.clone();
+        let _tables = tables_lock.lock().unwrap();
+        Arc::make_mut(&mut self.catalog).write_records(records)
+    }
+
+    // Checks every table in the store for internal consistency. Problems are reported rather than
+    // returned as errors, so this can be used to assess a store after an incident.
+    pub fn verify_integrity(&self) -> io::Result<IntegrityReport> {
+        let mut report = sst::verify(&self.data_dir)?;
+        if let Some(cold_dir) = &self.options.cold_dir {
+            let cold = sst::verify(cold_dir)?;
+            report.tables_checked += cold.tables_checked;
+            report.problems.extend(cold.problems);
+        }
+        Ok(report)
+    }
+
+    // Reads every table in the store through and checks it against its checksum, right away and
+    // as fast as it can, as the background scrubber does slowly with Options::scrub_rate. Damaged
+    // tables are reported to Options::on_scrub_error as well as in the report, and are otherwise
+    // left as they are. See TableInfo::last_verified.
+    pub fn scrub_now(&self) -> io::Result<ScrubReport> {
+        self.scrub.pass(None)
+    }
+
+    // The bytes on disk used by each part of the store. Only files the store knows to be its own are
+    // counted, so anything else left in its directories isn't. Tables that a compaction has
+    // replaced but are kept until a snapshot using them is dropped aren't counted either.
+    pub fn disk_usage(&self) -> io::Result<DiskUsage> {
+        let mut levels = Vec::with_capacity(self.catalog.ssts.len());
+        for tables in &self.catalog.ssts {
+            let mut bytes = 0;
+            for table in tables {
+                bytes += file_size(&table.path)?;
+            }
+            levels.push(bytes);
+        }
+
+        let mut blobs = 0;
+        for id in blob::blob_files(&self.data_dir)? {
+            blobs += file_size(&blob::blob_path(&self.data_dir, id))?;
+        }
+
+        Ok(DiskUsage {
+            wal: file_size(&self.wal_file_path)?,
+            levels,
+            blobs,
+            identity: file_size(&self.data_dir.join(IDENTITY_FILE_NAME))?,
         })
     }
 
-    fn exec_wal<T>(&mut self, mut f: T) -> io::Result<()>
+    // Rebuilds the index and footer of any table in the store at `data_dir` that can't be opened,
+    // keeping as much of its data as can be read. The store must not be open while this runs.
+    pub fn repair(data_dir: &path::Path) -> io::Result<Vec<RepairReport>> {
+        sst::repair_dir(data_dir)
+    }
+
+    // Uniquely identifies this store, including across copies of its directory.
+    pub fn id(&self) -> Uuid {
+        self.identity.id
+    }
+
+    // Makes sure every write so far is durable, returning the sequence number of the last of them.
+    // Writes are numbered from 1 in the order they were made since the store was opened. With
+    // Durability::None, only flushed writes are ever durable, and this doesn't flush.
+    pub fn barrier(&mut self) -> io::Result<u64> {
+        self.wal.sync()?;
+        Ok(self.sequence)
+    }
+
+    // Applies to the memtable any writes that reached the WAL without being applied to it, such as
+    // one cut short by a panic that was caught, so that reads see them without opening the store
+    // again. Returns the number of writes applied, which is usually none. Each write and flush does
+    // the same before anything else, so such writes are never lost or reordered. With
+    // Durability::None, writes are never in the WAL alone, so there is nothing to do.
+    pub fn recover_pending(&mut self) -> Result<usize, StoreError> {
+        if self.applied_wal_size == self.wal.size() || self.options.durability == Durability::None {
+            return Ok(0);
+        }
+
+        // Records waiting to be synced can't be read until they are written out.
+        self.wal.sync().map_err(StoreError::Wal)?;
+        let applied = self.applied_wal_size as u64;
+        let mut reader = wal::Reader::new(&self.wal_file_path)
+            .map_err(StoreError::WalRecovery)?
+            .codecs(self.options.codecs.clone());
+        let memtable = Arc::make_mut(&mut self.memtable);
+        // Records in a batch all come from the same write, which ends where the batch does.
+        let mut last_end = applied;
+        let mut writes = 0;
+        while let Some(record) = reader.next() {
+            let record = record.map_err(StoreError::WalRecovery)?;
+            if reader.offset() <= applied {
+                continue;
+            }
+            memtable.apply(&WriteRecord::from(&record));
+            if reader.offset() != last_end {
+                last_end = reader.offset();
+                writes += 1;
+            }
+        }
+
+        self.applied_wal_size = self.wal.size();
+        self.sequence += writes as u64;
+        if writes > 0 && self.oldest_unflushed.is_none() {
+            self.oldest_unflushed = Some(self.options.clock.monotonic());
+        }
+        Ok(writes)
+    }
+
+    // The directory the store keeps its data in.
+    pub fn data_dir(&self) -> &path::Path {
+        &self.data_dir
+    }
+
+    // The options the store was opened with.
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    // What was skipped when the store was opened, given its recovery mode.
+    pub fn recovery_report(&self) -> &RecoveryReport {
+        &self.recovery_report
+    }
+
+    // Cumulative statistics since the store was opened.
+    pub fn stats(&self) -> Stats {
+        self.read_counters.snapshot()
+    }
+
+    // The most recent checks for whether an automatic compaction was due, oldest first, with
+    // Options::explain_compactions. Stores without it keep none. Checks made by the background
+    // compactor are included, as of when they were made.
+    pub fn recent_compaction_decisions(&self) -> Vec<CompactionDecision> {
+        self.decisions
+            .as_ref()
+            .map(DecisionLog::recent)
+            .unwrap_or_default()
+    }
+
+    // Reads tables ahead of time according to `options`, so that a service can pay for a cold cache
+    // at startup rather than on its first requests. Returns the number of bytes read.
+    pub fn warm(&self, options: WarmOptions) -> io::Result<u64> {
+        let mut remaining = options.max_bytes.unwrap_or(u64::MAX);
+        let mut read = 0;
+
+        if options.bottom_level {
+            let bottom = self
+                .catalog
+                .ssts
+                .iter()
+                .rev()
+                .find(|tables| !tables.is_empty());
+            for table in bottom.into_iter().flatten() {
+                if remaining == 0 {
+                    break;
+                }
+                let n = table.read_ahead(remaining)?;
+                remaining -= n;
+                read += n;
+            }
+        }
+
+        Ok(read)
+    }
+
+    // Bytes written to the WAL since the last flush.
+    pub fn wal_size(&self) -> u32 {
+        self.wal.size()
+    }
+
+    // Whether the WAL is close enough to its size limit that a write is likely to cause a flush.
+    // Callers that batch writes can use this to flush at a convenient point instead.
+    pub fn flush_imminent(&self) -> bool {
+        let limit = self.options.wal_size_limit;
+        self.wal.size() >= limit - limit / FLUSH_IMMINENT_FRACTION
+    }
+
+    fn exec_wal<T>(&mut self, mut f: T) -> Result<(), StoreError>
     where
-        T: FnMut(&mut Store) -> io::Result<()>,
+        T: FnMut(&mut Store) -> Result<(), StoreError>,
     {
+        self.check_frozen()?;
+        self.catch_up()?;
+        // The memtable must have caught up with the WAL for the write to be counted from there.
+        self.recover_pending()?;
         f(self)?;
+        self.applied_wal_size = self.wal.size();
+        self.sequence += 1;
+        if self.oldest_unflushed.is_none() {
+            self.oldest_unflushed = Some(self.options.clock.monotonic());
+        }
 
-        if self.wal.size() > self.wal_size_limit {
+        if self.wal.size() > self.options.wal_size_limit || self.flush_due() {
             self.flush_memtable()?;
         }
 
         Ok(())
     }
 
+    // Flushes the memtable if its oldest write has been there for longer than
+    // Options::memtable_flush_after, and runs any compactions that gets have made due with
+    // Options::read_compaction_threshold, or that Options::compaction_scheduler asks for. Tables
+    // set aside for longer than Options::obsolete_table_grace are removed. Writes check the first
+    // themselves, so this is for stores that may go a while without any.
+    pub fn tick(&mut self) -> Result<(), StoreError> {
+        if self.flush_due() {
+            self.flush_memtable()?;
+        }
+        self.pins.expire().map_err(StoreError::Io)?;
+        // Without background compactions, compactions that gets made due wait for this or a flush.
+        // A scheduler may choose to compact at any time, so it is asked on each tick.
+        if self.read_trigger.as_ref().is_some_and(|t| t.has_pending())
+            || self.options.compaction_scheduler.is_some()
+        {
+            self.check_frozen()?;
+            self.catch_up()?;
+            self.maybe_compact()?;
+        }
+        Ok(())
+    }
+
+    fn flush_due(&self) -> bool {
+        match (self.options.memtable_flush_after, self.oldest_unflushed) {
+            (Some(after), Some(oldest)) => {
+                !self.memtable.is_empty()
+                    && self.options.clock.monotonic().saturating_sub(oldest) >= after
+            }
+            _ => false,
+        }
+    }
+
     // TODO: Ideally this would be async.
-    pub fn flush_memtable(&mut self) -> io::Result<()> {
+    pub fn flush_memtable(&mut self) -> Result<(), StoreError> {
+        self.check_frozen()?;
+        self.catch_up()?;
+
+        // Writes in the WAL but not the memtable would be lost along with the WAL.
+        self.recover_pending()?;
+        // There would be nothing to put in the table.
+        if self.memtable.is_empty() {
+            return Ok(());
+        }
+
+        let started = self.options.clock.monotonic();
+        let memtable = self.memtable.clone();
+        let existing = self.catalog.ssts.first().map_or(0, Vec::len);
+        self.write_table(&memtable)?;
+        // A table whose size can't be read isn't counted, rather than failing a flush that has
+        // already been written.
+        let bytes = self.catalog.ssts[0][existing..]
+            .iter()
+            .map(|table| table.size().unwrap_or(0))
+            .sum();
+
+        // The flushed records are now in a table, so the WAL can be set aside for archiving. Any
+        // records still waiting to be synced are synced first, so that an archived WAL is never
+        // missing the end of what was written to it.
+        if self.wal.durable() < self.wal.appended() {
+            self.wal.sync().map_err(StoreError::Wal)?;
+        }
+        if let Some(hook) = &self.options.wal_archive {
+            archive_wal(&self.wal_file_path, &mut self.wal_archive_seq, hook)
+                .map_err(StoreError::Wal)?;
+        }
+        self.wal = open_wal(&self.wal_file_path, &self.options).map_err(StoreError::Wal)?;
+        self.applied_wal_size = 0;
+        self.memtable = Arc::new(MemTable::new());
+        self.oldest_unflushed = None;
+        self.read_counters.record_flush(
+            memtable.len() as u64,
+            bytes,
+            self.options.clock.monotonic().saturating_sub(started),
+        );
+
+        self.maybe_compact()
+    }
+
+    // Runs or schedules a compaction if new level 0 tables have made one due.
+    fn maybe_compact(&mut self) -> Result<(), StoreError> {
+        match &self.background {
+            Some(background) => {
+                background.wake();
+                Ok(())
+            }
+            None => {
+                let tables_lock = self.tables_lock.clone();
+                let _tables = tables_lock.lock().unwrap();
+                let result = self.compactor.maybe_compact(&self.catalog.ssts);
+                self.reload_after_compaction(result)?;
+
+                let pending = self
+                    .read_trigger
+                    .as_ref()
+                    .map(|t| t.take_pending())
+                    .unwrap_or_default();
+                for prefix in pending {
+                    let end = prefix_end(&prefix);
+                    let result =
+                        self.compactor
+                            .compact_range(&self.catalog.ssts, &prefix, end.as_deref());
+                    self.reload_after_compaction(result)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    // Flushes the memtable and merges every table into the bottom level, whether or not automatic
+    // compaction would have done so.
+    pub fn compact(&mut self) -> Result<(), StoreError> {
+        self.flush_memtable()?;
+
+        let tables_lock = self.tables_lock.clone();
+        let _tables = tables_lock.lock().unwrap();
+        // A background compaction may have changed the tables since the catalog was loaded.
+        self.catalog = Arc::new(self.open_catalog()?);
+        let result = self.compactor.compact_all(&self.catalog.ssts);
+        self.reload_after_compaction(result)
+    }
+
+    // Flushes the memtable and compacts the tables with keys in [start, end), or from start onward
+    // if there is no end, into the bottom level. This reclaims the space of a range that was just
+    // deleted without rewriting the rest of the store. Older level 0 tables that overlap the ones
+    // compacted are compacted along with them.
+    pub fn compact_range(&mut self, start: &[u8], end: Option<&[u8]>) -> Result<(), StoreError> {
+        if end.is_some_and(|end| end <= start) {
+            return Err(StoreError::InvalidArgument(
+                "range end must be after its start".to_string(),
+            ));
+        }
+
+        self.flush_memtable()?;
+
+        let tables_lock = self.tables_lock.clone();
+        let _tables = tables_lock.lock().unwrap();
+        self.catalog = Arc::new(self.open_catalog()?);
+        let result = self.compactor.compact_range(&self.catalog.ssts, start, end);
+        self.reload_after_compaction(result)?;
+        Ok(())
+    }
+
+    // Compacts every table in `level` into the level below, along with the tables there that they
+    // overlap, whether or not automatic compaction would have done so. Level 1 is the bottom, and
+    // compacting it rewrites its tables in place. The memtable isn't flushed first. Returns what was
+    // rewritten.
+    pub fn compact_level(&mut self, level: usize) -> Result<CompactionStats, StoreError> {
+        if level > 1 {
+            return Err(StoreError::InvalidArgument(format!(
+                "level {} is below the bottom level, 1",
+                level
+            )));
+        }
+
+        let tables_lock = self.tables_lock.clone();
+        let _tables = tables_lock.lock().unwrap();
+        // A background compaction may have changed the tables since the catalog was loaded.
+        self.catalog = Arc::new(self.open_catalog()?);
+        let result = self.compactor.compact_level(&self.catalog.ssts, level);
+        self.reload_after_compaction(result)
+    }
+
+    // Waits for a background compaction that is due or under way to finish, and picks up the tables
+    // it wrote. With CompactionMode::Inline, compactions have always finished already.
+    pub fn wait_for_compactions(&mut self) -> Result<(), StoreError> {
+        if let Some(background) = &self.background {
+            background.wait();
+        }
+        self.catch_up()
+    }
+
+    // The tables whose files have been deleted out from under the store. Reads of keys in these
+    // tables fail with StoreError::MissingTable until `rescan_tables` is called.
+    pub fn missing_tables(&self) -> Vec<path::PathBuf> {
+        // Tables removed by a background compaction that the store has yet to catch up with aren't
+        // missing, just replaced.
+        let removed = |path| self.background.as_ref().is_some_and(|b| b.removed(path));
+        self.tables()
+            .filter(|(_, table)| !removed(&table.path) && table.check_missing())
+            .map(|(_, table)| table.path.clone())
+            .collect()
+    }
+
+    // Reloads the tables from disk, leaving out any that are missing. Their data is lost as far as
+    // the store is concerned, and older versions of their keys become visible again.
+    pub fn rescan_tables(&mut self) -> Result<(), StoreError> {
+        self.reload_catalog()
+    }
+
+    // Every table in the store along with its level. Level 0 tables are listed oldest first.
+    pub fn tables(&self) -> impl Iterator<Item = (usize, &Table)> {
         self.catalog
-            .as_mut()
-            .unwrap()
-            .write_records(&self.memtable)?;
-        self.wal = wal::Writer::new(&self.wal_file_path)?;
-        self.memtable = MemTable::new();
-        self.compactor
-            .maybe_compact(self.catalog.take().unwrap().ssts)?;
-
-        // TODO: Re-reading the entire SST catalog from disk every flush is going to be very
-        // inefficient. This is a temporary placeholder.
-        self.catalog = Some(Catalog::new(&self.data_dir)?);
+            .ssts
+            .iter()
+            .enumerate()
+            .flat_map(|(level, tables)| tables.iter().map(move |table| (level, table.as_ref())))
+    }
+
+    // A description of every table in the store, in the order of `tables`.
+    pub fn table_info(&self) -> Vec<TableInfo> {
+        let mut info = self.catalog.table_info();
+        for table in &mut info {
+            table.last_verified = self.scrub.last_verified(&table.path);
+        }
+        info
+    }
+
+    // The number of levels the store has, counting level 0. Levels are numbered from 0, and a level
+    // at or past this number has no tables.
+    pub fn num_levels(&self) -> usize {
+        self.catalog.ssts.len()
+    }
+
+    // The number of tables in `level`, which is 0 for a level the store doesn't have.
+    pub fn tables_at_level(&self, level: usize) -> usize {
+        self.catalog.ssts.get(level).map_or(0, Vec::len)
+    }
+
+    fn reload_catalog(&mut self) -> Result<(), StoreError> {
+        let tables_lock = self.tables_lock.clone();
+        let _tables = tables_lock.lock().unwrap();
+        self.catalog = Arc::new(self.open_catalog()?);
 
         Ok(())
     }
+
+    // Picks up the tables a compaction wrote, and passes on its result. One that failed may still
+    // have removed some of its inputs, so the catalog is reloaded either way, and the store carries
+    // on with whatever tables are left. The caller must hold the tables lock.
+    fn reload_after_compaction<T>(
+        &mut self,
+        result: Result<T, StoreError>,
+    ) -> Result<T, StoreError> {
+        match self.open_catalog() {
+            Ok(catalog) => {
+                self.catalog = Arc::new(catalog);
+                result
+            }
+            // The compaction's error says more about what went wrong.
+            Err(e) => {
+                result?;
+                Err(e)
+            }
+        }
+    }
+
+    // The caller must hold the tables lock.
+    fn open_catalog(&self) -> Result<Catalog, StoreError> {
+        // TODO: Re-reading the entire SST catalog from disk after every change is going to be very
+        // inefficient. This is a temporary placeholder.
+        // Anything skipped here was already skipped when the store was opened.
+        Catalog::open_tiered(
+            &self.data_dir,
+            self.options.cold_dir.as_deref(),
+            self.options.recovery_mode,
+            &mut RecoveryReport::default(),
+        )
+        .and_then(|catalog| catalog.with_format(TableFormat::new(&self.options)))
+    }
+
+    // Picks up the tables written by background compactions that have finished since the last
+    // call, or returns the error one of them ran into.
+    fn catch_up(&mut self) -> Result<(), StoreError> {
+        let Some(background) = &self.background else {
+            return Ok(());
+        };
+
+        match background.take_finished() {
+            Ok(removed) if removed.is_empty() => Ok(()),
+            Ok(_) => self.reload_catalog(),
+            // Tables may have changed before the compaction failed.
+            Err(e) => {
+                let _ = self.reload_catalog();
+                Err(e)
+            }
+        }
+    }
+}
+
+// A page of the records from `Store::scan_limited`, in key order.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ScanPage {
+    pub records: Vec<(Vec<u8>, Vec<u8>)>,
+    // Where the next page starts, or None if there are no more records in the range.
+    pub resume: Option<Vec<u8>>,
+}
+
+// What `Store::export_range` wrote.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ExportReport {
+    pub records: u64,
+    // Of keys and values, not counting the overhead of the tables.
+    pub bytes: u64,
+}
+
+// The bytes on disk used by each part of a store, see `Store::disk_usage`.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct DiskUsage {
+    pub wal: u64,
+    // Of the tables in each level, by level number.
+    pub levels: Vec<u64>,
+    // Of the files holding values kept apart from the tables, with Options::min_blob_size.
+    pub blobs: u64,
+    // Of the file recording the store's ID and format.
+    pub identity: u64,
+}
+
+impl DiskUsage {
+    pub fn total(&self) -> u64 {
+        self.wal + self.levels.iter().sum::<u64>() + self.blobs + self.identity
+    }
+}
+
+// The size of the file at `path`, or 0 if there isn't one. A table may be removed by a background
+// compaction before the store has caught up.
+fn file_size(path: &path::Path) -> io::Result<u64> {
+    match fs::metadata(path) {
+        Ok(metadata) => Ok(metadata.len()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(0),
+        Err(e) => Err(path_error("reading", path, e)),
+    }
+}
+
+// Keeps a store frozen for writes while it lives. See Store::freeze_writes.
+pub struct FreezeGuard {
+    freezes: Arc<AtomicUsize>,
+}
+
+impl Drop for FreezeGuard {
+    fn drop(&mut self) {
+        self.freezes.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+struct TempDir(path::PathBuf);
+
+// A drop can't fail, so a directory that can't be removed is left behind, for the system to clear
+// out of its temporary directory in time.
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+// The WAL to recover writes from when opening a store whose WAL belongs at `wal_file_path`. That
+// is where it is, unless the store was last opened without Options::wal_dir, in which case it is in
+// the data directory. Both holding writes means the store was opened with different WAL directories
+// without being flushed in between, and which writes are newer can't be told.
+fn wal_to_recover(
+    data_dir: &path::Path,
+    wal_file_path: &path::Path,
+) -> Result<path::PathBuf, StoreError> {
+    let default = data_dir.join(WAL_FILE_NAME);
+    let holds_writes = |path: &path::Path| fs::metadata(path).is_ok_and(|meta| meta.len() > 0);
+
+    let same = match (fs::canonicalize(data_dir), wal_file_path.parent()) {
+        (Ok(data_dir), Some(wal_dir)) => fs::canonicalize(wal_dir).is_ok_and(|d| d == data_dir),
+        _ => false,
+    };
+    if same || !holds_writes(&default) {
+        return Ok(wal_file_path.to_owned());
+    }
+    if holds_writes(wal_file_path) {
+        return Err(StoreError::InvalidArgument(format!(
+            "both {} and {} hold writes that haven't been flushed",
+            default.display(),
+            wal_file_path.display()
+        )));
+    }
+    Ok(default)
+}
+
+// Whether `dir` is somewhere below `data_dir`, after resolving any links and `..` in either.
+fn inside(data_dir: &path::Path, dir: &path::Path) -> bool {
+    match (resolve(data_dir), resolve(dir)) {
+        (Some(data_dir), Some(dir)) => dir != data_dir && dir.starts_with(data_dir),
+        _ => false,
+    }
+}
+
+// The canonical form of `path`, which may not exist yet: Its nearest existing ancestor is
+// canonicalized, and the rest of it is added on.
+fn resolve(path: &path::Path) -> Option<path::PathBuf> {
+    let path = path::absolute(path).ok()?;
+    let mut ancestor = path.as_path();
+    let mut resolved = loop {
+        if let Ok(resolved) = fs::canonicalize(ancestor) {
+            break resolved;
+        }
+        ancestor = ancestor.parent()?;
+    };
+
+    for component in path.strip_prefix(ancestor).ok()?.components() {
+        match component {
+            path::Component::ParentDir => {
+                resolved.pop();
+            }
+            path::Component::CurDir => {}
+            component => resolved.push(component),
+        }
+    }
+    Some(resolved)
+}
+
+// Reads the identity of the store in `data_dir`, moving it to the format its options need.
+fn load_identity(data_dir: &path::Path, options: &Options) -> Result<Identity, StoreError> {
+    let mut identity = Identity::load_or_create(data_dir)?;
+    // Older versions would misread the indexes of tables with inline values.
+    if options.inline_value_size.is_some() {
+        identity = identity.upgrade(data_dir, INLINE_VALUES_FORMAT_VERSION)?;
+    }
+    // Or the indexes of tables with prefix filters.
+    if options.prefix_bloom_length.is_some() {
+        identity = identity.upgrade(data_dir, PREFIX_FILTER_FORMAT_VERSION)?;
+    }
+    // Or encoded values.
+    if options.codecs.encodes() {
+        identity = identity.upgrade(data_dir, CODEC_FORMAT_VERSION)?;
+    }
+    Ok(identity)
+}
+
+// Writes are only counted toward the WAL size limit with Durability::None, which leaves the file
+// alone.
+fn open_wal(path: &path::Path, options: &Options) -> io::Result<wal::Writer> {
+    match options.durability {
+        Durability::Wal => {
+            wal::Writer::new(path).map(|wal| wal.value_codec(options.codecs.writer().clone()))
+        }
+        Durability::None => Ok(wal::Writer::discarding(path)),
+    }
+}
+
+fn archive_wal(path: &path::Path, seq: &mut u64, hook: &WalArchiveHook) -> io::Result<()> {
+    let archived = wal::archive(path, *seq + 1)?;
+    *seq += 1;
+    (hook.0)(&archived);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_recover_pending() {
+        let dir = TempDir::new("testing").unwrap();
+        let mut store = Store::open(dir.path(), Options::default()).unwrap();
+        store.put(b"key1", b"val1").unwrap();
+        assert_eq!(0, store.recover_pending().unwrap());
+
+        // As if applying a batch to the memtable had been cut short after it reached the WAL.
+        let records = [
+            WriteRecord::Exists {
+                key: b"key2",
+                val: b"val2",
+            },
+            WriteRecord::Deleted { key: b"key1" },
+        ];
+        store.wal.append_batch(&records).unwrap();
+        store
+            .wal
+            .append(WriteRecord::Exists {
+                key: b"key3",
+                val: b"val3",
+            })
+            .unwrap();
+        assert_eq!(Some(b"val1".to_vec()), store.get(b"key1").unwrap());
+        assert_eq!(None, store.get(b"key2").unwrap());
+
+        assert_eq!(2, store.recover_pending().unwrap());
+        assert_eq!(None, store.get(b"key1").unwrap());
+        assert_eq!(Some(b"val2".to_vec()), store.get(b"key2").unwrap());
+        assert_eq!(Some(b"val3".to_vec()), store.get(b"key3").unwrap());
+        assert_eq!(3, store.barrier().unwrap());
+        assert_eq!(0, store.recover_pending().unwrap());
+
+        // A write applies what is pending before it is made, and a flush before the WAL goes.
+        store
+            .wal
+            .append(WriteRecord::Deleted { key: b"key2" })
+            .unwrap();
+        store.put(b"key4", b"val4").unwrap();
+        assert_eq!(None, store.get(b"key2").unwrap());
+        store
+            .wal
+            .append(WriteRecord::Exists {
+                key: b"key5",
+                val: b"val5",
+            })
+            .unwrap();
+        store.flush_memtable().unwrap();
+        assert_eq!(Some(b"val5".to_vec()), store.get(b"key5").unwrap());
+        assert_eq!(0, store.recover_pending().unwrap());
+    }
 }
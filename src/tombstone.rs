@@ -0,0 +1,45 @@
+use std::io;
+
+use crate::protocol::{ReadRecord, WriteRecord};
+
+// A deletion of every key in [start, end), or from start onward if there is no end. A range
+// tombstone shadows older versions of the keys it covers, but not versions written after it, so
+// writing one is a single record no matter how many keys it deletes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RangeTombstone {
+    pub start: Vec<u8>,
+    pub end: Option<Vec<u8>>,
+}
+
+impl RangeTombstone {
+    pub fn covers(&self, key: &[u8]) -> bool {
+        key >= self.start.as_slice() && self.end.as_ref().is_none_or(|end| key < end.as_slice())
+    }
+
+    // Whether the tombstone covers any key from `first` to `last` inclusive.
+    pub fn overlaps(&self, first: &[u8], last: &[u8]) -> bool {
+        last >= self.start.as_slice() && self.end.as_ref().is_none_or(|end| first < end.as_slice())
+    }
+
+    pub fn to_record(&self) -> WriteRecord<'_> {
+        WriteRecord::RangeDeleted {
+            start: &self.start,
+            end: self.end.as_deref(),
+        }
+    }
+}
+
+pub(crate) fn covered(tombstones: &[RangeTombstone], key: &[u8]) -> bool {
+    tombstones.iter().any(|t| t.covers(key))
+}
+
+// Drops the records covered by any of `tombstones`, which must all be newer than the records.
+pub(crate) fn without_covered<I>(
+    iter: I,
+    tombstones: Vec<RangeTombstone>,
+) -> impl Iterator<Item = io::Result<ReadRecord>>
+where
+    I: Iterator<Item = io::Result<ReadRecord>>,
+{
+    iter.filter(move |rec| !matches!(rec, Ok(rec) if covered(&tombstones, rec.key())))
+}
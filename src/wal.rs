@@ -1,85 +1,390 @@
 use std::{
+    collections::VecDeque,
     fs,
     io::{self, BufReader, BufWriter, Write},
     path,
+    sync::Arc,
 };
 
-use crate::protocol::{ReadRecord, WriteRecord};
+use crate::{
+    codec::{unknown_codec, Codecs, ValueCodec, IDENTITY_CODEC_ID},
+    context::IoContext,
+    options::RecoveryMode,
+    protocol::{self, ReadRecord, WalEntry, WriteRecord},
+    recovery::Skipped,
+};
 
+// Appends records to a WAL. Records are appended and then synced, and only count as durable once
+// they have been: `appended` and `durable` give how many records have reached each point. By
+// default every append is synced before it returns, so the two never differ between calls; with
+// `sync_on_append(false)`, records wait in a buffer until the next `sync`.
+//
+// With a value codec, values are encoded as they are appended, and the codec is named at the start
+// of the WAL, ahead of the first record.
 pub struct Writer {
-    w: BufWriter<fs::File>,
+    w: Option<BufWriter<fs::File>>, // None if records are only counted, see `discarding`
     size: u32,
+    path: path::PathBuf,
+    sync_on_append: bool,
+    appended: u64,
+    durable: u64,
+    codec: Option<Arc<dyn ValueCodec>>,
+    // Whether the codec has been named yet.
+    codec_named: bool,
 }
 
 impl Writer {
     pub fn new(path: &path::Path) -> io::Result<Self> {
         Ok(Writer {
-            w: BufWriter::new(
+            w: Some(BufWriter::new(
                 fs::OpenOptions::new()
                     .write(true)
                     .truncate(true)
                     .create(true)
-                    .open(path)?,
-            ),
+                    .open(path)
+                    .with_path("opening", path)?,
+            )),
             size: 0,
+            path: path.to_owned(),
+            sync_on_append: true,
+            appended: 0,
+            durable: 0,
+            codec: None,
+            codec_named: false,
         })
     }
 
+    // Encodes values with `codec`, see Options::value_codec.
+    pub(crate) fn value_codec(mut self, codec: Arc<dyn ValueCodec>) -> Self {
+        self.codec = (codec.id() != IDENTITY_CODEC_ID).then_some(codec);
+        self
+    }
+
+    // Whether each append syncs the WAL before returning. Without it, appended records are only
+    // durable after a call to `sync`, which lets a number of them share one sync.
+    pub fn sync_on_append(mut self, sync: bool) -> Self {
+        self.sync_on_append = sync;
+        self
+    }
+
+    // A writer that keeps count of the size of the records appended to it without writing them
+    // anywhere, for a store opened with Durability::None. The file at `path` is left alone.
+    // Nothing written by it is ever durable.
+    pub fn discarding(path: &path::Path) -> Self {
+        Writer {
+            w: None,
+            size: 0,
+            path: path.to_owned(),
+            sync_on_append: false,
+            appended: 0,
+            durable: 0,
+            codec: None,
+            codec_named: false,
+        }
+    }
+
     pub fn append(&mut self, rec: WriteRecord) -> io::Result<usize> {
-        let written = rec.write_to(&mut self.w)?;
-        self.w.flush()?;
-        // TODO: Compare to sync_data().
-        self.w.get_ref().sync_all()?;
+        let Some(w) = &mut self.w else {
+            let written = rec.write_to(&mut io::sink())?;
+            self.size += written as u32;
+            self.appended += 1;
+            return Ok(written);
+        };
+
+        let mut written =
+            name_codec(w, &self.codec, &mut self.codec_named).with_path("writing", &self.path)?;
+        written += match &self.codec {
+            Some(codec) => encode_values(codec.as_ref(), &[rec])[0].write_to(w),
+            None => rec.write_to(w),
+        }
+        .with_path("writing", &self.path)?;
         self.size += written as u32;
+        self.appended += 1;
+        if self.sync_on_append {
+            self.sync()?;
+        }
         Ok(written)
     }
 
+    // Appends `records` as a single batch, which is recovered all together or not at all. The
+    // batch counts as one record for `appended` and `durable`.
+    pub fn append_batch(&mut self, records: &[WriteRecord]) -> io::Result<usize> {
+        let Some(w) = &mut self.w else {
+            let written = protocol::write_batch(&mut io::sink(), records)?;
+            self.size += written as u32;
+            self.appended += 1;
+            return Ok(written);
+        };
+
+        let mut written =
+            name_codec(w, &self.codec, &mut self.codec_named).with_path("writing", &self.path)?;
+        written += match &self.codec {
+            Some(codec) => {
+                let encoded = encode_values(codec.as_ref(), records);
+                let records = encoded.iter().map(WriteRecord::from).collect::<Vec<_>>();
+                protocol::write_batch(w, &records)
+            }
+            None => protocol::write_batch(w, records),
+        }
+        .with_path("writing", &self.path)?;
+        self.size += written as u32;
+        self.appended += 1;
+        if self.sync_on_append {
+            self.sync()?;
+        }
+        Ok(written)
+    }
+
+    // Flushes and syncs everything appended so far.
+    pub fn sync(&mut self) -> io::Result<()> {
+        let Some(w) = &mut self.w else {
+            return Ok(());
+        };
+        w.flush().with_path("writing", &self.path)?;
+        // TODO: Compare to sync_data().
+        w.get_ref().sync_all().with_path("syncing", &self.path)?;
+        self.durable = self.appended;
+        Ok(())
+    }
+
+    // The number of records appended so far.
+    pub fn appended(&self) -> u64 {
+        self.appended
+    }
+
+    // The number of records appended so far that have been synced, and so would survive a crash.
+    pub fn durable(&self) -> u64 {
+        self.durable
+    }
+
     pub fn size(&self) -> u32 {
         self.size
     }
 }
 
+// Names the codec at the start of the WAL if it hasn't been yet, returning the bytes written.
+fn name_codec<W: Write>(
+    w: &mut W,
+    codec: &Option<Arc<dyn ValueCodec>>,
+    named: &mut bool,
+) -> io::Result<usize> {
+    match codec {
+        Some(codec) if !*named => {
+            let written = protocol::write_codec(w, codec.id())?;
+            *named = true;
+            Ok(written)
+        }
+        _ => Ok(0),
+    }
+}
+
+fn encode_values(codec: &dyn ValueCodec, records: &[WriteRecord]) -> Vec<ReadRecord> {
+    records
+        .iter()
+        .map(|record| ReadRecord::from(*record).encode(codec))
+        .collect()
+}
+
+// Moves a WAL segment aside as archive number `seq`, returning its new path.
+pub fn archive(path: &path::Path, seq: u64) -> io::Result<path::PathBuf> {
+    let mut name = path
+        .file_name()
+        .expect("WAL must have a file name")
+        .to_owned();
+    name.push(format!(".{}", seq));
+    let archived = path.with_file_name(name);
+
+    fs::rename(path, &archived).with_path("archiving", path)?;
+    Ok(archived)
+}
+
+// The highest archive number of the segments archived from the WAL at `path`, or 0 if there are
+// none.
+pub fn last_archived(path: &path::Path) -> io::Result<u64> {
+    let dir = path.parent().expect("WAL must have a parent directory");
+    let prefix = format!(
+        "{}.",
+        path.file_name()
+            .expect("WAL must have a file name")
+            .to_string_lossy()
+    );
+
+    let mut last = 0;
+    for entry in fs::read_dir(dir).with_path("listing", dir)? {
+        let name = entry.with_path("listing", dir)?.file_name();
+        let seq = name
+            .to_str()
+            .and_then(|name| name.strip_prefix(&prefix))
+            .and_then(|seq| seq.parse().ok());
+        if let Some(seq) = seq {
+            last = last.max(seq);
+        }
+    }
+
+    Ok(last)
+}
+
+// Reads the records in a WAL in the order they were appended. The records of a batch are read one
+// at a time, like any others, but only once the whole batch has been read. Values encoded by the
+// writer are decoded, as long as the reader is given the codec, see `codecs`; reading a value
+// without it is an error.
 pub struct Reader {
     r: BufReader<fs::File>,
+    // The rest of the batch being read, with the offset of each record.
+    pending: VecDeque<(u64, ReadRecord)>,
+    // The offset of the record last returned.
+    position: u64,
     done: bool,
     size: u32,
     read: u32,
+    path: path::PathBuf,
+    mode: RecoveryMode,
+    skipped: Option<Skipped>,
+    codecs: Codecs,
+    // The id of the codec named by the WAL, if it isn't Identity, and the codec if it is known.
+    codec: Option<(u8, Option<Arc<dyn ValueCodec>>)>,
 }
 
 impl Reader {
     pub fn new(path: &path::Path) -> io::Result<Self> {
-        let f = fs::OpenOptions::new().read(true).create(false).open(path)?;
-        let size = f.metadata()?.len() as u32;
+        let f = fs::OpenOptions::new()
+            .read(true)
+            .create(false)
+            .open(path)
+            .with_path("opening", path)?;
+        let size = f.metadata().with_path("opening", path)?.len() as u32;
 
         Ok(Reader {
             r: BufReader::new(f),
+            pending: VecDeque::new(),
+            position: 0,
             done: false,
             size,
             read: 0,
+            path: path.to_owned(),
+            mode: RecoveryMode::Strict,
+            skipped: None,
+            codecs: Codecs::default(),
+            codec: None,
         })
     }
+
+    // The codecs the values in the WAL may be encoded with. Built-in codecs that don't need a key
+    // are known without them.
+    pub(crate) fn codecs(mut self, codecs: Codecs) -> Self {
+        self.codecs = codecs;
+        self
+    }
+
+    // Sets how much damage to tolerate. Rather than returning an error, a tolerated unreadable
+    // record ends iteration and is reported by `skipped`, along with everything after it.
+    pub fn recovery_mode(mut self, mode: RecoveryMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn skipped(&self) -> Option<&Skipped> {
+        self.skipped.as_ref()
+    }
+
+    // The offset in the WAL of the record last returned. A record in a batch is after the batch's
+    // header and the records before it.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    // The length of the WAL read so far, which is always a whole number of records and batches.
+    pub fn offset(&self) -> u64 {
+        self.read as u64
+    }
+
+    fn decode(&self, record: ReadRecord) -> io::Result<ReadRecord> {
+        match &self.codec {
+            Some((_, Some(codec))) => record.decode(codec.as_ref()),
+            Some((id, None)) if matches!(record, ReadRecord::Exists { .. }) => {
+                Err(unknown_codec(*id))
+            }
+            _ => Ok(record),
+        }
+        .with_path("reading", &self.path)
+    }
 }
 
 impl Iterator for Reader {
     type Item = io::Result<ReadRecord>;
 
     fn next(&mut self) -> Option<Self::Item> {
+        if let Some((position, next)) = self.pending.pop_front() {
+            self.position = position;
+            return Some(Ok(next));
+        }
         if self.done {
             return None;
         }
 
-        let next = ReadRecord::read_from(&mut self.r);
-        if next.is_err() {
-            self.done = true;
-            return Some(next);
-        }
+        let next = match WalEntry::read_from(&mut self.r) {
+            Ok(next) => next,
+            Err(e) => {
+                self.done = true;
 
-        let next = next.unwrap();
+                let tolerated = match self.mode {
+                    RecoveryMode::Strict => false,
+                    // A record cut short by the end of the file.
+                    RecoveryMode::TolerateCorruptTail => e.kind() == io::ErrorKind::UnexpectedEof,
+                    RecoveryMode::BestEffort => true,
+                };
+                if !tolerated {
+                    return Some(Err(e).with_path("reading", &self.path));
+                }
+
+                self.skipped = Some(Skipped {
+                    path: self.path.clone(),
+                    offset: self.read as u64,
+                    detail: format!(
+                        "dropped the last {} bytes of the WAL: {}",
+                        self.size - self.read,
+                        e
+                    ),
+                });
+                return None;
+            }
+        };
+
+        let start = self.read as u64;
         self.read += next.size() as u32;
         if self.read == self.size {
             self.done = true;
         }
 
-        Some(Ok(next))
+        match next {
+            WalEntry::Record(record) => {
+                self.position = start;
+                let record = self.decode(record);
+                self.done |= record.is_err();
+                Some(record)
+            }
+            WalEntry::Batch(records) => {
+                let mut position = start + 9;
+                for record in records {
+                    let size = record.size() as u64;
+                    match self.decode(record) {
+                        Ok(record) => self.pending.push_back((position, record)),
+                        Err(e) => {
+                            self.done = true;
+                            self.pending.clear();
+                            self.position = position;
+                            return Some(Err(e));
+                        }
+                    }
+                    position += size;
+                }
+                // An empty batch has nothing to return, so reading moves on past it.
+                self.next()
+            }
+            WalEntry::Codec(id) => {
+                self.codec = (id != IDENTITY_CODEC_ID).then(|| (id, self.codecs.get(id)));
+                self.next()
+            }
+        }
     }
 }
@@ -0,0 +1,45 @@
+#![cfg(feature = "async")]
+
+use std::sync::Arc;
+
+use crucible::{async_store::AsyncStore, options::Options, store::Store, StoreError};
+use tempdir::TempDir;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_async_store() {
+    let dir = TempDir::new("testing").unwrap();
+    let options = Options::default().write_queue_depth(2).wal_size_limit(1024);
+    let store = Arc::new(AsyncStore::new(Store::open(dir.path(), options).unwrap()));
+
+    // A burst of writers never has more than the queue depth waiting on the writer.
+    let writers = (0..50u32)
+        .map(|i| {
+            let store = store.clone();
+            tokio::spawn(async move {
+                let key = format!("key{:02}", i);
+                store.put(key.as_bytes(), b"val").await.unwrap();
+                assert!(store.queued_writes() <= 2);
+            })
+        })
+        .collect::<Vec<_>>();
+    for writer in writers {
+        writer.await.unwrap();
+    }
+    assert_eq!(0, store.queued_writes());
+
+    assert_eq!(Some(b"val".to_vec()), store.get(b"key07").await.unwrap());
+    store.del(b"key07").await.unwrap();
+    assert_eq!(None, store.get(b"key07").await.unwrap());
+
+    // Errors from the store are returned to the writer.
+    assert!(matches!(
+        store.put(b"", b"val").await,
+        Err(StoreError::InvalidArgument(_))
+    ));
+
+    Arc::into_inner(store).unwrap().close().await.unwrap();
+
+    let store = Store::new(dir.path(), None, None, None).unwrap();
+    assert_eq!(Some(b"val".to_vec()), store.get(b"key49").unwrap());
+    assert_eq!(None, store.get(b"key07").unwrap());
+}
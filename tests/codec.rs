@@ -0,0 +1,69 @@
+#![cfg(any(feature = "lz4", feature = "aes-gcm"))]
+
+use std::fs;
+
+use crucible::{batch::WriteBatch, options::Options, store::Store, StoreError};
+use tempdir::TempDir;
+
+// Writes values through the WAL and a batch, flushes some of them to a table, and checks that all
+// of them read back once the store is opened again.
+fn round_trip(options: impl Fn() -> Options) -> TempDir {
+    let dir = TempDir::new("testing").unwrap();
+    let mut store = Store::open(dir.path(), options()).unwrap();
+    let val = b"a value that repeats ".repeat(20);
+    store.put(b"key1", &val).unwrap();
+    store.flush_memtable().unwrap();
+    let mut batch = WriteBatch::new();
+    batch.put(b"key2", &val);
+    batch.put(b"key3", b"short");
+    store.write_batch(&batch).unwrap();
+    drop(store);
+
+    let store = Store::open(dir.path(), options()).unwrap();
+    assert_eq!(Some(val.clone()), store.get(b"key1").unwrap());
+    assert_eq!(Some(val), store.get(b"key2").unwrap());
+    assert_eq!(Some(b"short".to_vec()), store.get(b"key3").unwrap());
+    dir
+}
+
+#[cfg(feature = "lz4")]
+#[test]
+fn test_lz4() {
+    use crucible::codec::Lz4;
+
+    let dir = round_trip(|| Options::default().value_codec(Lz4));
+    let tables = fs::read_dir(dir.path().join("0")).unwrap();
+    let size = tables
+        .map(|entry| entry.unwrap().metadata().unwrap().len())
+        .sum::<u64>();
+    assert!(size < 420, "tables take {} bytes", size);
+
+    // LZ4 needs no key, so a store that no longer compresses can still read what was compressed.
+    let store = Store::open(dir.path(), Options::default()).unwrap();
+    assert!(store.get(b"key1").unwrap().is_some());
+}
+
+#[cfg(feature = "aes-gcm")]
+#[test]
+fn test_aes_gcm() {
+    use crucible::codec::AesGcm;
+
+    let key = [7; 32];
+    let dir = round_trip(|| Options::default().value_codec(AesGcm::new(&key)));
+    for entry in fs::read_dir(dir.path().join("0")).unwrap() {
+        let contents = fs::read(entry.unwrap().path()).unwrap();
+        assert!(!contents.windows(7).any(|w| w == b"a value"));
+    }
+
+    // Without the key, the store can't be opened, and with the wrong key its values can't be read.
+    assert!(matches!(
+        Store::open(dir.path(), Options::default()),
+        Err(StoreError::InvalidArgument(_))
+    ));
+    let store = Store::open(
+        dir.path(),
+        Options::default().value_codec(AesGcm::new(&[8; 32])),
+    )
+    .unwrap();
+    assert!(store.get(b"key1").is_err());
+}
@@ -0,0 +1,61 @@
+#![cfg(target_os = "linux")]
+
+use std::fs;
+
+use crucible::{
+    env::Env,
+    options::{CompactionMode, Options},
+    store::Store,
+};
+use tempdir::TempDir;
+
+// The threads of this process. Tests in other files run in processes of their own, so this only
+// counts threads started here.
+fn thread_count() -> usize {
+    fs::read_dir("/proc/self/task").unwrap().count()
+}
+
+#[test]
+fn test_shared_env() {
+    let before = thread_count();
+
+    let env = Env::new(2);
+    let options = Options::default()
+        .compaction_mode(CompactionMode::Background)
+        .level_0_file_limit(2)
+        .env(env.clone());
+
+    let dirs = (0..100)
+        .map(|_| TempDir::new("testing").unwrap())
+        .collect::<Vec<_>>();
+    let mut stores = dirs
+        .iter()
+        .map(|dir| Store::open(dir.path(), options.clone()).unwrap())
+        .collect::<Vec<_>>();
+
+    // Every store makes a compaction due, and they all run on the Env's two threads.
+    for round in 0..3 {
+        for (i, store) in stores.iter_mut().enumerate() {
+            let key = format!("store{:03}/key{}", i, round);
+            store.put(key.as_bytes(), b"val").unwrap();
+            store.flush_memtable().unwrap();
+        }
+    }
+    assert!(thread_count() <= before + env.threads());
+
+    for (i, store) in stores.iter_mut().enumerate() {
+        store.wait_for_compactions().unwrap();
+        assert!(store.tables().filter(|(level, _)| *level == 0).count() < 2);
+        for round in 0..3 {
+            let key = format!("store{:03}/key{}", i, round);
+            assert_eq!(Some(b"val".to_vec()), store.get(key.as_bytes()).unwrap());
+        }
+    }
+    assert!(thread_count() <= before + env.threads());
+
+    // The threads stop once the stores, and everything else holding the Env, are gone.
+    drop(stores);
+    drop(options);
+    drop(env);
+    assert_eq!(before, thread_count());
+}
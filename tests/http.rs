@@ -0,0 +1,93 @@
+#![cfg(feature = "http")]
+
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpStream},
+    sync::Arc,
+    thread,
+};
+
+use crucible::{http::Server, store::Store};
+use tempdir::TempDir;
+
+// Sends a request and returns the status code and body of the response.
+fn request(addr: SocketAddr, method: &str, target: &str, body: &[u8]) -> (u16, Vec<u8>) {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n\r\n",
+        method,
+        target,
+        body.len()
+    )
+    .unwrap();
+    stream.write_all(body).unwrap();
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+
+    let header_end = response
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .expect("response must have headers");
+    let status = String::from_utf8_lossy(&response[9..12]).parse().unwrap();
+    (status, response[header_end + 4..].to_vec())
+}
+
+#[test]
+fn test_http() {
+    let dir = TempDir::new("testing").unwrap();
+    let store = Store::new(dir.path(), None, None, None).unwrap();
+    let server = Arc::new(Server::bind(store, "127.0.0.1:0").unwrap());
+    let addr = server.local_addr().unwrap();
+    let running = {
+        let server = server.clone();
+        thread::spawn(move || server.run())
+    };
+
+    assert_eq!((200, b"ok".to_vec()), request(addr, "GET", "/healthz", b""));
+
+    assert_eq!(204, request(addr, "PUT", "/kv/key1", b"val1").0);
+    assert_eq!(204, request(addr, "PUT", "/kv/key2", b"val2").0);
+    assert_eq!(204, request(addr, "PUT", "/kv/%00%FF", &[0, 1, 2]).0);
+    assert_eq!(204, request(addr, "PUT", "/kv/other", b"x").0);
+
+    assert_eq!(
+        (200, b"val1".to_vec()),
+        request(addr, "GET", "/kv/key1", b"")
+    );
+    assert_eq!(
+        (200, vec![0, 1, 2]),
+        request(addr, "GET", "/kv/%00%ff", b"")
+    );
+    assert_eq!(404, request(addr, "GET", "/kv/missing", b"").0);
+
+    assert_eq!(204, request(addr, "DELETE", "/kv/key2", b"").0);
+    assert_eq!(404, request(addr, "GET", "/kv/key2", b"").0);
+
+    let (status, body) = request(addr, "GET", "/kv?prefix=key", b"");
+    assert_eq!(200, status);
+    assert_eq!(
+        "{\"key\":\"a2V5MQ==\",\"value\":\"dmFsMQ==\"}\n",
+        String::from_utf8(body).unwrap()
+    );
+    let (_, body) = request(addr, "GET", "/kv?limit=2", b"");
+    assert_eq!(2, String::from_utf8(body).unwrap().lines().count());
+
+    let (status, body) = request(addr, "GET", "/stats", b"");
+    assert_eq!(200, status);
+    assert!(String::from_utf8(body).unwrap().contains("\"gets\":4"));
+
+    // Bad requests.
+    assert_eq!(400, request(addr, "GET", "/kv?limit=many", b"").0);
+    assert_eq!(400, request(addr, "GET", "/kv/%zz", b"").0);
+    assert_eq!(400, request(addr, "PUT", "/kv/", b"empty key").0);
+    assert_eq!(404, request(addr, "GET", "/elsewhere", b"").0);
+    assert_eq!(405, request(addr, "POST", "/kv/key1", b"").0);
+
+    server.stop();
+    running.join().unwrap();
+
+    let store = Arc::into_inner(server).unwrap().into_store();
+    assert_eq!(Some(b"val1".to_vec()), store.get(b"key1").unwrap());
+}
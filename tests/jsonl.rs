@@ -0,0 +1,69 @@
+#![cfg(feature = "serde_json")]
+
+use crucible::{encoding::KeyEncoding, store::Store, StoreError};
+use tempdir::TempDir;
+
+#[test]
+fn test_jsonl_round_trip() {
+    let dir = TempDir::new("testing").unwrap();
+    let mut store = Store::new(dir.path(), None, None, None).unwrap();
+    store.put(b"key1", b"val1").unwrap();
+    store.put(&[0, 255], &[10, 13, 34]).unwrap();
+    store.put(b"key2", b"").unwrap();
+    store.del(b"key1").unwrap();
+
+    for encoding in [KeyEncoding::Base64, KeyEncoding::Hex] {
+        let mut exported = Vec::new();
+        assert_eq!(2, store.export_jsonl(&mut exported, encoding).unwrap());
+
+        let other = TempDir::new("testing").unwrap();
+        let mut imported = Store::new(other.path(), None, None, None).unwrap();
+        assert_eq!(2, imported.import_jsonl(&exported[..], encoding).unwrap());
+
+        let want = store
+            .scan(b"", None)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let got = imported
+            .scan(b"", None)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(want, got);
+    }
+
+    let mut exported = Vec::new();
+    store.export_jsonl(&mut exported, KeyEncoding::Hex).unwrap();
+    assert_eq!(
+        "{\"key\":\"00ff\",\"value\":\"0a0d22\"}\n{\"key\":\"6b657932\",\"value\":\"\"}\n",
+        String::from_utf8(exported).unwrap()
+    );
+}
+
+#[test]
+fn test_jsonl_malformed() {
+    let dir = TempDir::new("testing").unwrap();
+    let mut store = Store::new(dir.path(), None, None, None).unwrap();
+
+    let input = "{\"key\":\"a\",\"value\":\"b\"}\n\n{\"key\":\"c\"}\n";
+    let err = store
+        .import_jsonl(input.as_bytes(), KeyEncoding::Utf8Lossy)
+        .unwrap_err();
+    assert!(matches!(&err, StoreError::InvalidArgument(detail) if detail.starts_with("line 3:")));
+    // Lines before the malformed one were imported.
+    assert_eq!(Some(b"b".to_vec()), store.get(b"a").unwrap());
+
+    let err = store
+        .import_jsonl("not json\n".as_bytes(), KeyEncoding::Utf8Lossy)
+        .unwrap_err();
+    assert!(matches!(&err, StoreError::InvalidArgument(detail) if detail.starts_with("line 1:")));
+
+    let err = store
+        .import_jsonl(
+            "{\"key\":\"zz\",\"value\":\"00\"}".as_bytes(),
+            KeyEncoding::Hex,
+        )
+        .unwrap_err();
+    assert!(matches!(&err, StoreError::InvalidArgument(detail) if detail.starts_with("line 1:")));
+}
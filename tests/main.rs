@@ -1,6 +1,33 @@
-use std::collections::{HashMap, VecDeque};
+use std::{
+    collections::{HashMap, VecDeque},
+    error::Error,
+    fs,
+    io::{self, Read},
+    ops::ControlFlow,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
 
-use crucible::{protocol::ReadRecord, store::Store};
+use crucible::{
+    batch::WriteBatch,
+    clock::ManualClock,
+    codec::ValueCodec,
+    compactor::{
+        decision::{CompactionDecision, Trigger},
+        scheduler::{CompactionJob, CompactionScheduler},
+    },
+    indexing::{self, IndexDef},
+    options::{CompactionMode, Durability, IterOptions, MergeOptions, Options, WarmOptions},
+    protocol::{ReadRecord, WriteRecord},
+    scan::RecordSource,
+    sst::{Catalog, Follower, Table, TableInfo},
+    stats::Stats,
+    store::Store,
+    wal, StoreError,
+};
 use rand::{
     distributions::{Alphanumeric, DistString},
     Rng,
@@ -38,6 +65,7 @@ fn test_store() {
         match rec {
             ReadRecord::Exists { key, val } => store.put(&key, &val).unwrap(),
             ReadRecord::Deleted { key } => store.del(&key).unwrap(),
+            ReadRecord::RangeDeleted { .. } | ReadRecord::Blob { .. } => unreachable!(),
         }
     }
 
@@ -87,163 +115,3602 @@ fn test_store() {
     );
 }
 
-#[test]
-#[ignore]
-fn stress_test() {
-    // This is a test to simulate some amount of random load on the storage engine consisting mostly
-    // of writes with some updates and deletes mixed in. The store will be restarted randomly
-    // throughout the test. It is not run by default with `cargo test` since it is rather lengthy.
-    // It can be run with:
-    //      cargo test -- --ignored
+fn scan_all(scan: crucible::scan::Scan) -> Vec<(Vec<u8>, Vec<u8>)> {
+    scan.collect::<Result<Vec<_>, _>>().unwrap()
+}
 
-    // This will produce at least 200 memtable flushes, depending on how many times a restart is
-    // triggered.
-    let wal_size = 100 * 1024;
-    let table_size_limit = 100 * 1024;
-    let level_0_file_limit = 5;
+fn kv(key: &[u8], val: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    (key.to_vec(), val.to_vec())
+}
 
-    // Total number of "actions", which are inserts, deletes, updates, or even restarts.
-    let action_limit = 10_000;
+#[test]
+fn test_memtable_delete_shadows_table() {
+    let dir = TempDir::new("testing").unwrap();
+    let mut store = Store::new(dir.path(), None, None, None).unwrap();
 
-    // 1 in 1000 is a restart.
-    let restart_probability = 1000;
-    // 1 in 100 is a delete.
-    let delete_probability = 100;
-    // 1 in 5 is an update.
-    let update_probability = 5;
-    // When updating pick from the most recent 100 inserted keys. This simulates some churn on more
-    // recent data, while allowing the majority of data to become "old".
-    let update_pool_size = 100;
+    store.put(b"key1", b"val1").unwrap();
+    store.flush_memtable().unwrap();
+    store.del(b"key1").unwrap();
 
-    let key_length = 10..255;
-    let val_length = 1..4096;
+    assert_eq!(None, store.get(b"key1").unwrap());
+}
 
+#[test]
+fn test_scan() {
     let dir = TempDir::new("testing").unwrap();
-    let mut store = Store::new(
-        dir.path(),
-        Some(wal_size),
-        Some(table_size_limit as usize),
-        Some(level_0_file_limit),
-    )
-    .unwrap();
-    let mut ref_store: HashMap<Vec<u8>, Vec<u8>> = HashMap::new();
-    let mut pool: VecDeque<Vec<u8>> = VecDeque::with_capacity(update_pool_size);
+    let mut store = Store::new(dir.path(), Some(1024), Some(1024), Some(3)).unwrap();
 
-    let mut rng = rand::thread_rng();
+    // Enough writes to produce several flushes and a compaction, with some keys overwritten or
+    // deleted along the way.
+    for i in 0..200u32 {
+        store
+            .put(
+                format!("key{:03}", i).as_bytes(),
+                format!("val{}", i).as_bytes(),
+            )
+            .unwrap();
+    }
+    for i in (0..200u32).step_by(3) {
+        store
+            .put(format!("key{:03}", i).as_bytes(), b"updated")
+            .unwrap();
+    }
+    for i in (0..200u32).step_by(5) {
+        store.del(format!("key{:03}", i).as_bytes()).unwrap();
+    }
 
-    for i in 0..action_limit {
-        if i % 100 == 0 {
-            println!("processing action {} of {}...", i, action_limit);
-        }
+    let want = (0..200u32)
+        .filter(|i| i % 5 != 0)
+        .map(|i| {
+            let val = if i % 3 == 0 {
+                "updated".to_string()
+            } else {
+                format!("val{}", i)
+            };
+            (format!("key{:03}", i).into_bytes(), val.into_bytes())
+        })
+        .collect::<Vec<_>>();
 
-        let rand = rng.gen_range(1..=1000);
+    assert_eq!(want, scan_all(store.scan(b"", None).unwrap()));
+    assert_eq!(
+        want[..4].to_vec(),
+        scan_all(store.scan(b"key", Some(b"key006")).unwrap())
+    );
+    assert_eq!(
+        want[want.len() - 4..].to_vec(),
+        scan_all(store.scan(b"key196", None).unwrap())
+    );
+    assert!(scan_all(store.scan(b"zzz", None).unwrap()).is_empty());
 
-        if rand % restart_probability == 0 {
-            // Close an re-open the store, which will convert any left-over wal file into an sst.
-            drop(store);
-            store = Store::new(
-                dir.path(),
-                Some(wal_size),
-                Some(table_size_limit as usize),
-                Some(level_0_file_limit),
-            )
-            .unwrap();
-            continue;
+    // A range that ends where or before it starts is empty.
+    assert!(scan_all(store.scan(b"key100", Some(b"key050")).unwrap()).is_empty());
+    assert!(scan_all(store.scan(b"key100", Some(b"key100")).unwrap()).is_empty());
+    assert!(store
+        .scan_limited(b"key100", Some(b"key050"), 10)
+        .unwrap()
+        .records
+        .is_empty());
+}
+
+#[test]
+fn test_for_each_in_range() {
+    let dir = TempDir::new("testing").unwrap();
+    let mut store = Store::new(dir.path(), None, None, None).unwrap();
+    for i in 0..10 {
+        store.put(format!("key{}", i).as_bytes(), b"val").unwrap();
+        if i == 5 {
+            store.flush_memtable().unwrap();
         }
+    }
+    store.del(b"key3").unwrap();
 
-        if rand % delete_probability == 0 {
-            if pool.len() == 0 {
-                continue;
-            }
+    let mut keys = Vec::new();
+    store
+        .for_each_in_range(b"key2", Some(b"key8"), |key, val| {
+            assert_eq!(b"val", val);
+            keys.push(String::from_utf8(key.to_vec()).unwrap());
+            Ok(ControlFlow::Continue(()))
+        })
+        .unwrap();
+    assert_eq!(vec!["key2", "key4", "key5", "key6", "key7"], keys);
 
-            // Pick a random item from the last `update_pool_size` items to delete.
-            let idx = rand::random::<usize>() % pool.len();
-            let k = pool.remove(idx).unwrap();
+    // The callback can stop the scan early, or fail it.
+    let mut calls = 0;
+    store
+        .for_each_in_range(b"", None, |_, _| {
+            calls += 1;
+            Ok(if calls == 3 {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            })
+        })
+        .unwrap();
+    assert_eq!(3, calls);
 
-            // Delete it from both the reference store and the test store.
-            ref_store.remove(&k).unwrap();
-            store.del(&k).unwrap();
+    let result = store.for_each_in_range(b"", None, |_, _| Err(io::Error::other("stop")));
+    assert!(matches!(result, Err(StoreError::Io(e)) if e.to_string() == "stop"));
+}
 
-            continue;
-        }
+#[test]
+fn test_scan_raw() {
+    let dir = TempDir::new("testing").unwrap();
+    let mut store = Store::new(dir.path(), None, None, None).unwrap();
+    for key in [b"key1", b"key2", b"key3"] {
+        store.put(key, b"val").unwrap();
+    }
+    store.compact().unwrap();
+    store.del(b"key2").unwrap();
+    store.flush_memtable().unwrap();
+    store.del(b"key3").unwrap();
+    store.put(b"key4", b"val").unwrap();
 
-        if rand % update_probability == 0 {
-            if pool.len() == 0 {
-                // In case an update is randomly selected before any inserts, or if there was one
-                // insert followed by a delete etc.
-                continue;
-            }
+    let level_0 = store
+        .tables()
+        .find(|(level, _)| *level == 0)
+        .unwrap()
+        .1
+        .sequence();
+    assert!(level_0.is_some());
 
-            // Pick a random item from the last `update_pool_size` items to update.
-            let idx = rand % pool.len();
-            let k = pool.remove(idx).unwrap();
+    // The deletion of key2 is attributed to the level 0 table that hides the record in level 1.
+    let options = IterOptions::default()
+        .include_tombstones(true)
+        .include_metadata(true);
+    let records = store
+        .scan_raw(b"", None, options)
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(
+        vec![
+            (
+                ReadRecord::Exists {
+                    key: b"key1".to_vec(),
+                    val: b"val".to_vec()
+                },
+                Some(RecordSource::Table {
+                    level: 1,
+                    sequence: None
+                })
+            ),
+            (
+                ReadRecord::Deleted {
+                    key: b"key2".to_vec()
+                },
+                Some(RecordSource::Table {
+                    level: 0,
+                    sequence: level_0
+                })
+            ),
+            (
+                ReadRecord::Deleted {
+                    key: b"key3".to_vec()
+                },
+                Some(RecordSource::Memtable)
+            ),
+            (
+                ReadRecord::Exists {
+                    key: b"key4".to_vec(),
+                    val: b"val".to_vec()
+                },
+                Some(RecordSource::Memtable)
+            ),
+        ],
+        records
+    );
 
-            let new_val = Alphanumeric
-                .sample_string(&mut rand::thread_rng(), rng.gen_range(val_length.clone()))
-                .as_bytes()
-                .to_vec();
+    // By default, only the live records are yielded, the same as a normal scan.
+    let keys = store
+        .scan_raw(b"key2", None, IterOptions::default())
+        .unwrap()
+        .map(|rec| {
+            let (record, source) = rec.unwrap();
+            assert_eq!(None, source);
+            record.key().to_vec()
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(vec![b"key4".to_vec()], keys);
+    assert_eq!(2, store.scan(b"", None).unwrap().count());
+}
 
-            // Update it in both the reference store and the test store.
-            ref_store.insert(k.to_vec(), new_val.clone()).unwrap();
-            // TODO: It would be nice if this worked like HashMap, returning an option. But that
-            // would require a lookup for every put.
-            store.put(&k, &new_val).unwrap();
+#[test]
+fn test_freeze() {
+    let dir = TempDir::new("testing").unwrap();
+    let mut store = Store::new(dir.path(), None, None, Some(2)).unwrap();
 
-            continue;
-        }
+    store.put(b"key1", b"val1").unwrap();
+    store.flush_memtable().unwrap();
+    store.put(b"key2", b"val2").unwrap();
 
-        // Add a new record if none of those happened.
-        loop {
-            let key = Alphanumeric
-                .sample_string(&mut rand::thread_rng(), rng.gen_range(key_length.clone()))
-                .as_bytes()
-                .to_vec();
+    let snapshot = store.freeze();
 
-            let val = Alphanumeric
-                .sample_string(&mut rand::thread_rng(), rng.gen_range(val_length.clone()))
-                .as_bytes()
-                .to_vec();
+    // Changes to the memtable, a flush, and a compaction that replaces the snapshot's tables.
+    store.put(b"key1", b"val1updated").unwrap();
+    store.del(b"key2").unwrap();
+    store.put(b"key3", b"val3").unwrap();
+    store.flush_memtable().unwrap();
+    store.put(b"key4", b"val4").unwrap();
 
-            if ref_store.contains_key(&key) {
-                // Handle the case where we randomly generate a key for a new insert that is already
-                // in the store, as strange things can happen in this unlikely event.
-                continue;
-            }
+    assert_eq!(Some(b"val1".to_vec()), snapshot.get(b"key1").unwrap());
+    assert_eq!(Some(b"val2".to_vec()), snapshot.get(b"key2").unwrap());
+    assert_eq!(None, snapshot.get(b"key3").unwrap());
+    assert_eq!(
+        vec![kv(b"key1", b"val1"), kv(b"key2", b"val2")],
+        scan_all(snapshot.scan(b"", None).unwrap())
+    );
 
-            // Add to the ring buffer for updates/deletes.
-            if pool.len() >= update_pool_size {
-                pool.pop_front();
-            }
-            pool.push_back(key.clone());
+    assert_eq!(
+        vec![
+            kv(b"key1", b"val1updated"),
+            kv(b"key3", b"val3"),
+            kv(b"key4", b"val4")
+        ],
+        scan_all(store.scan(b"", None).unwrap())
+    );
+}
 
-            store.put(&key, &val).unwrap();
-            ref_store.insert(key, val);
+#[test]
+fn test_errors_name_files() {
+    // The WAL can't be created because a directory is in the way.
+    let dir = TempDir::new("testing").unwrap();
+    fs::create_dir(dir.path().join("data.wal")).unwrap();
+    let err = Store::new(dir.path(), None, None, None).err().unwrap();
+    assert!(err
+        .to_string()
+        .contains(&dir.path().join("data.wal").display().to_string()));
 
-            break;
-        }
+    // A flush can't create the level 0 directory.
+    let dir = TempDir::new("testing").unwrap();
+    let mut store = Store::new(dir.path(), None, None, Some(2)).unwrap();
+    fs::write(dir.path().join("0"), b"").unwrap();
+    store.put(b"key1", b"val1").unwrap();
+    let err = store.flush_memtable().err().unwrap();
+    let want = dir.path().join("0").display().to_string();
+    assert!(err.to_string().contains(&want));
+    assert!(err.source().unwrap().to_string().contains(&want));
+
+    // A compaction can't create the level 1 directory.
+    let dir = TempDir::new("testing").unwrap();
+    let mut store = Store::new(dir.path(), None, None, Some(2)).unwrap();
+    fs::write(dir.path().join("1"), b"").unwrap();
+    store.put(b"key1", b"val1").unwrap();
+    store.flush_memtable().unwrap();
+    store.put(b"key2", b"val2").unwrap();
+    let err = store.flush_memtable().err().unwrap();
+    assert!(err
+        .source()
+        .unwrap()
+        .to_string()
+        .contains(&format!("creating {}", dir.path().join("1").display())));
+
+    // A table is truncated out from under an open store.
+    let dir = TempDir::new("testing").unwrap();
+    let mut store = Store::new(dir.path(), None, None, None).unwrap();
+    store.put(b"key1", b"val1").unwrap();
+    store.flush_memtable().unwrap();
+    let table = dir.path().join("0").join("1.sst");
+    fs::OpenOptions::new()
+        .write(true)
+        .open(&table)
+        .unwrap()
+        .set_len(4)
+        .unwrap();
+    let err = store.scan(b"", None).err().unwrap();
+    assert!(err
+        .source()
+        .unwrap()
+        .to_string()
+        .contains(&table.display().to_string()));
+}
+
+#[test]
+fn test_get_verified() {
+    let dir = TempDir::new("testing").unwrap();
+    let mut store = Store::new(dir.path(), None, None, None).unwrap();
+    store.put(b"key1", b"val1").unwrap();
+    store.put(b"key2", b"val2").unwrap();
+    store.flush_memtable().unwrap();
+    drop(store);
+
+    // Point the index entry for key1, which starts right after the two 17 byte records, at the
+    // record for key2.
+    let table = dir.path().join("0").join("1.sst");
+    let mut contents = fs::read(&table).unwrap();
+    contents[34..38].copy_from_slice(&17u32.to_le_bytes());
+    fs::write(&table, contents).unwrap();
+
+    let store = Store::new(dir.path(), None, None, None).unwrap();
+    assert_eq!(Some(b"val2".to_vec()), store.get(b"key1").unwrap());
+    assert!(matches!(
+        store.get_verified(b"key1"),
+        Err(StoreError::Corruption { offset: 17, .. })
+    ));
+    assert_eq!(Some(b"val2".to_vec()), store.get_verified(b"key2").unwrap());
+    assert_eq!(None, store.get_verified(b"key3").unwrap());
+}
+
+#[test]
+fn test_missing_table() {
+    let dir = TempDir::new("testing").unwrap();
+    let mut store = Store::new(dir.path(), None, None, None).unwrap();
+    store.put(b"key1", b"val1").unwrap();
+    store.flush_memtable().unwrap();
+    store.put(b"key1", b"val2").unwrap();
+    store.put(b"key2", b"val2").unwrap();
+    store.flush_memtable().unwrap();
+    assert!(store.missing_tables().is_empty());
+
+    let table = dir.path().join("0").join("2.sst");
+    fs::remove_file(&table).unwrap();
+    assert_eq!(vec![table.clone()], store.missing_tables());
+
+    // Keys in the missing table can't be read, but everything else still can.
+    for key in [&b"key1"[..], b"key2"] {
+        assert!(matches!(store.get(key), Err(StoreError::MissingTable { path }) if path == table));
     }
+    assert!(matches!(
+        store.scan(b"", None),
+        Err(StoreError::MissingTable { .. })
+    ));
+    assert_eq!(None, store.get(b"key3").unwrap());
+    store.put(b"key3", b"val3").unwrap();
+    assert_eq!(Some(b"val3".to_vec()), store.get(b"key3").unwrap());
 
-    // All values in the reference in-memory store should exist in the store under test.
-    for (ref_key, ref_val) in ref_store.iter() {
-        let ref_got = store.get(ref_key).unwrap().unwrap();
-        assert_eq!(ref_val, &ref_got);
+    // Once the tables are rescanned, the store carries on without the missing one.
+    store.rescan_tables().unwrap();
+    assert!(store.missing_tables().is_empty());
+    assert_eq!(Some(b"val1".to_vec()), store.get(b"key1").unwrap());
+    assert_eq!(None, store.get(b"key2").unwrap());
+    assert_eq!(2, scan_all(store.scan(b"", None).unwrap()).len());
+}
+
+#[test]
+fn test_store_id() {
+    let dir = TempDir::new("testing").unwrap();
+    let id = Store::new(dir.path(), None, None, None).unwrap().id();
+    assert_eq!(id, Store::new(dir.path(), None, None, None).unwrap().id());
+
+    let other = TempDir::new("testing").unwrap();
+    assert_ne!(id, Store::new(other.path(), None, None, None).unwrap().id());
+}
+
+#[test]
+fn test_flush_imminent() {
+    let dir = TempDir::new("testing").unwrap();
+    // Each record is 9 + 4 + 4 = 17 bytes.
+    let mut store = Store::new(dir.path(), Some(110), None, None).unwrap();
+    assert_eq!(0, store.wal_size());
+
+    for (i, want) in [
+        (1, false),
+        (2, false),
+        (3, false),
+        (4, false),
+        (5, false),
+        (6, true),
+    ] {
+        store.put(format!("key{}", i).as_bytes(), b"val1").unwrap();
+        assert_eq!(17 * i, store.wal_size());
+        assert_eq!(want, store.flush_imminent());
     }
 
-    // Double check after re-opening the store.
-    drop(store);
-    store = Store::new(
+    // The next write goes over the limit and flushes.
+    store.put(b"key7", b"val1").unwrap();
+    assert_eq!(0, store.wal_size());
+    assert!(!store.flush_imminent());
+}
+
+#[test]
+fn test_input_validation() {
+    let dir = TempDir::new("testing").unwrap();
+    let mut store = Store::open(
         dir.path(),
-        Some(wal_size),
-        Some(table_size_limit as usize),
-        Some(level_0_file_limit),
+        Options::default().max_key_size(8).max_value_size(16),
     )
     .unwrap();
 
-    for (ref_key, ref_val) in ref_store.iter() {
-        let ref_got = store.get(ref_key).unwrap().unwrap();
-        assert_eq!(ref_val, &ref_got);
+    let invalid = |res: Result<(), StoreError>| matches!(res, Err(StoreError::InvalidArgument(_)));
+
+    assert!(invalid(store.put(b"", b"val")));
+    assert!(invalid(store.del(b"")));
+    assert!(invalid(store.put(b"123456789", b"val")));
+    assert!(invalid(store.del(b"123456789")));
+    assert!(invalid(store.put(b"key", &[0; 17])));
+
+    // Nothing was written to the WAL.
+    assert_eq!(0, fs::metadata(dir.path().join("data.wal")).unwrap().len());
+
+    // Writes right at the limits are fine.
+    store.put(b"12345678", &[0; 16]).unwrap();
+    assert_eq!(Some(vec![0; 16]), store.get(b"12345678").unwrap());
+
+    // The limits must fit within the format.
+    assert!(matches!(
+        Store::open(
+            dir.path(),
+            Options::default().max_value_size(u32::MAX as usize)
+        ),
+        Err(StoreError::InvalidArgument(_))
+    ));
+    assert!(matches!(
+        Store::open(dir.path(), Options::default().max_key_size(usize::MAX)),
+        Err(StoreError::InvalidArgument(_))
+    ));
+    assert!(matches!(
+        Store::open(
+            dir.path(),
+            Options::default()
+                .max_value_size(usize::MAX)
+                .max_sst_file_size(1 << 30)
+        ),
+        Err(StoreError::InvalidArgument(_))
+    ));
+}
+
+#[test]
+fn test_read_stats() {
+    let dir = TempDir::new("testing").unwrap();
+    let mut store = Store::new(dir.path(), None, None, None).unwrap();
+
+    // Two level 0 tables, each with one key.
+    store.put(b"key1", b"val1").unwrap();
+    store.flush_memtable().unwrap();
+    store.put(b"key2", b"val2").unwrap();
+    store.flush_memtable().unwrap();
+    store.put(b"key3", b"val3").unwrap();
+
+    // Served from the memtable without probing any tables.
+    store.get(b"key3").unwrap();
+    // Found in the newest table.
+    store.get(b"key2").unwrap();
+    // Found in the oldest table after probing both.
+    store.get(b"key1").unwrap();
+    // Probes both tables without finding anything.
+    store.get(b"missing").unwrap();
+
+    let stats = store.stats();
+    assert_eq!(4, stats.gets);
+    assert_eq!(5, stats.tables_probed);
+    assert_eq!(2, stats.seeks);
+    assert_eq!(1.25, stats.read_amplification());
+}
+
+#[test]
+fn test_repair() {
+    let dir = TempDir::new("testing").unwrap();
+    let mut store = Store::new(dir.path(), None, None, None).unwrap();
+    store.put(b"key1", b"val1").unwrap();
+    store.put(b"key2", b"val2").unwrap();
+    store.flush_memtable().unwrap();
+    store.put(b"key3", b"val3").unwrap();
+    store.flush_memtable().unwrap();
+    drop(store);
+
+    // Lose the index and footer of the first table, which makes the store impossible to open.
+    let damaged = dir.path().join("0").join("1.sst");
+    let file = fs::OpenOptions::new().write(true).open(&damaged).unwrap();
+    file.set_len(34).unwrap();
+    assert!(Store::new(dir.path(), None, None, None).is_err());
+
+    let reports = Store::repair(dir.path()).unwrap();
+    assert_eq!(1, reports.len());
+    assert_eq!(damaged, reports[0].path);
+    assert_eq!(2, reports[0].records_recovered);
+
+    let store = Store::new(dir.path(), None, None, None).unwrap();
+    assert_eq!(Some(b"val1".to_vec()), store.get(b"key1").unwrap());
+    assert_eq!(Some(b"val2".to_vec()), store.get(b"key2").unwrap());
+    assert_eq!(Some(b"val3".to_vec()), store.get(b"key3").unwrap());
+    assert!(store.verify_integrity().unwrap().is_ok());
+}
+
+#[test]
+fn test_wal_archive() {
+    let dir = TempDir::new("testing").unwrap();
+    let archived = Arc::new(Mutex::new(Vec::new()));
+    let options = || {
+        let archived = archived.clone();
+        Options::default().archive_wal(move |path| archived.lock().unwrap().push(path.to_owned()))
+    };
+
+    let mut store = Store::open(dir.path(), options()).unwrap();
+    store.put(b"key1", b"val1").unwrap();
+    store.flush_memtable().unwrap();
+    store.put(b"key2", b"val2").unwrap();
+    store.del(b"key1").unwrap();
+    store.flush_memtable().unwrap();
+
+    // The records left in the WAL are archived when they are recovered.
+    store.put(b"key3", b"val3").unwrap();
+    drop(store);
+    let store = Store::open(dir.path(), options()).unwrap();
+
+    let archived = archived.lock().unwrap().clone();
+    assert_eq!(
+        vec![
+            dir.path().join("data.wal.1"),
+            dir.path().join("data.wal.2"),
+            dir.path().join("data.wal.3")
+        ],
+        archived
+    );
+
+    let records = archived
+        .iter()
+        .map(|path| {
+            wal::Reader::new(path)
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        vec![
+            vec![ReadRecord::Exists {
+                key: b"key1".to_vec(),
+                val: b"val1".to_vec()
+            }],
+            vec![
+                ReadRecord::Exists {
+                    key: b"key2".to_vec(),
+                    val: b"val2".to_vec()
+                },
+                ReadRecord::Deleted {
+                    key: b"key1".to_vec()
+                }
+            ],
+            vec![ReadRecord::Exists {
+                key: b"key3".to_vec(),
+                val: b"val3".to_vec()
+            }],
+        ],
+        records
+    );
+
+    // Archived segments aren't replayed.
+    assert_eq!(None, store.get(b"key1").unwrap());
+    assert_eq!(Some(b"val3".to_vec()), store.get(b"key3").unwrap());
+}
+
+// Writes three tables and leaves two records in the WAL. Optionally the tables are damaged, with the
+// first being unreadable and the second having a corrupt index, and the last record in the WAL is
+// either truncated or corrupted.
+fn damaged_store(dir: &std::path::Path, damage_tables: bool, truncate_wal: bool) {
+    let mut store = Store::new(dir, None, None, None).unwrap();
+    for key in ["key1", "key2", "key3"] {
+        store.put(key.as_bytes(), b"val").unwrap();
+        store.flush_memtable().unwrap();
     }
+    store.put(b"key4", b"val").unwrap();
+    store.put(b"key5", b"val").unwrap();
+    drop(store);
 
-    // Likewise, all values in the store under test should exist in the reference in-memory store.
-    // But that will have to wait until we implement iteration on the store.
+    if damage_tables {
+        let table = fs::OpenOptions::new()
+            .write(true)
+            .open(dir.join("0").join("1.sst"))
+            .unwrap();
+        table.set_len(3).unwrap();
+
+        // The table's single record is 16 bytes, and the index comes right after it.
+        let path = dir.join("0").join("2.sst");
+        let mut data = fs::read(&path).unwrap();
+        data[16..24].copy_from_slice(&[0xff; 8]);
+        fs::write(&path, data).unwrap();
+    }
+
+    let wal = dir.join("data.wal");
+    let mut data = fs::read(&wal).unwrap();
+    if truncate_wal {
+        data.truncate(data.len() - 2);
+    } else {
+        data[16] = b'x';
+    }
+    fs::write(&wal, data).unwrap();
+}
+
+#[test]
+fn test_recovery_modes() {
+    use crucible::options::RecoveryMode::*;
+
+    let open = |damage_tables, truncate_wal, mode| {
+        let dir = TempDir::new("testing").unwrap();
+        damaged_store(dir.path(), damage_tables, truncate_wal);
+        let store = Store::open(dir.path(), Options::default().recovery_mode(mode));
+        (dir, store)
+    };
+
+    // Only a truncated WAL record.
+    let (_dir, store) = open(false, true, Strict);
+    assert!(matches!(store, Err(StoreError::WalRecovery(_))));
+
+    for mode in [TolerateCorruptTail, BestEffort] {
+        let (dir, store) = open(false, true, mode);
+        let store = store.unwrap();
+        let skipped = &store.recovery_report().skipped;
+        assert_eq!(1, skipped.len());
+        assert_eq!(dir.path().join("data.wal"), skipped[0].path);
+        assert_eq!(16, skipped[0].offset);
+        assert_eq!(Some(b"val".to_vec()), store.get(b"key4").unwrap());
+        assert_eq!(None, store.get(b"key5").unwrap());
+    }
+
+    // A corrupt WAL record is more than just a torn write.
+    for mode in [Strict, TolerateCorruptTail] {
+        let (_dir, store) = open(false, false, mode);
+        assert!(matches!(store, Err(StoreError::WalRecovery(_))));
+    }
+    let (_dir, store) = open(false, false, BestEffort);
+    assert_eq!(1, store.unwrap().recovery_report().skipped.len());
+
+    // Damaged tables are only tolerated on a best effort basis.
+    for mode in [Strict, TolerateCorruptTail] {
+        let (_dir, store) = open(true, true, mode);
+        assert!(matches!(store, Err(StoreError::Corruption { .. })));
+    }
+
+    let (dir, store) = open(true, true, BestEffort);
+    let store = store.unwrap();
+    let skipped = store
+        .recovery_report()
+        .skipped
+        .iter()
+        .map(|s| s.path.clone())
+        .collect::<Vec<_>>();
+    assert!(skipped.contains(&dir.path().join("0").join("1.sst")));
+    assert!(skipped.contains(&dir.path().join("0").join("2.sst")));
+    assert!(skipped.contains(&dir.path().join("data.wal")));
+
+    assert_eq!(None, store.get(b"key1").unwrap());
+    for key in ["key2", "key3", "key4"] {
+        assert_eq!(Some(b"val".to_vec()), store.get(key.as_bytes()).unwrap());
+    }
+    assert_eq!(None, store.get(b"key5").unwrap());
+}
+
+#[test]
+fn test_compact() {
+    let dir = TempDir::new("testing").unwrap();
+    let mut store = Store::new(dir.path(), None, None, None).unwrap();
+
+    // Flushing an empty memtable does nothing.
+    store.flush_memtable().unwrap();
+    assert_eq!(0, store.tables().count());
+
+    store.put(b"key1", b"val1").unwrap();
+    store.flush_memtable().unwrap();
+    store.put(b"key2", b"val2").unwrap();
+    store.del(b"key1").unwrap();
+    store.flush_memtable().unwrap();
+    store.put(b"key3", b"val3").unwrap();
+    assert_eq!(
+        vec![0, 0],
+        store.tables().map(|(l, _)| l).collect::<Vec<_>>()
+    );
+
+    // Everything, including the memtable, ends up in level 1.
+    store.compact().unwrap();
+    let tables = store.tables().collect::<Vec<_>>();
+    assert_eq!(1, tables.len());
+    assert_eq!(1, tables[0].0);
+    assert_eq!(b"key1".to_vec(), tables[0].1.key_start());
+    assert_eq!(b"key3".to_vec(), tables[0].1.key_end());
+
+    assert_eq!(None, store.get(b"key1").unwrap());
+    assert_eq!(Some(b"val2".to_vec()), store.get(b"key2").unwrap());
+    assert_eq!(Some(b"val3".to_vec()), store.get(b"key3").unwrap());
+}
+
+#[test]
+fn test_compaction_error() {
+    let dir = TempDir::new("testing").unwrap();
+    let mut store = Store::new(dir.path(), None, None, None).unwrap();
+    store.put(b"key1", b"val1").unwrap();
+    store.put(b"key2", b"val2").unwrap();
+    store.flush_memtable().unwrap();
+    drop(store);
+
+    // Give the record for key2, which starts right after the 17 byte record for key1, a key length
+    // that runs past the end of the table.
+    let table = dir.path().join("0").join("1.sst");
+    let mut contents = fs::read(&table).unwrap();
+    contents[18..22].copy_from_slice(&u32::MAX.to_le_bytes());
+    fs::write(&table, contents).unwrap();
+
+    // The next flush makes a compaction due, which fails partway through reading the table.
+    let options = Options::default().level_0_file_limit(2);
+    let mut store = Store::open(dir.path(), options.clone()).unwrap();
+    store.put(b"key3", b"val3").unwrap();
+    assert!(matches!(
+        store.flush_memtable(),
+        Err(StoreError::Compaction { .. })
+    ));
+
+    // The store carries on with the tables it had, including the one just flushed.
+    assert_eq!(Some(b"val1".to_vec()), store.get(b"key1").unwrap());
+    assert_eq!(Some(b"val3".to_vec()), store.get(b"key3").unwrap());
+    store.put(b"key4", b"val4").unwrap();
+    assert_eq!(Some(b"val4".to_vec()), store.get(b"key4").unwrap());
+    assert!(matches!(
+        store.compact(),
+        Err(StoreError::Compaction { .. })
+    ));
+    assert_eq!(Some(b"val4".to_vec()), store.get(b"key4").unwrap());
+    assert_eq!(
+        vec![0, 0, 0],
+        store.tables().map(|(l, _)| l).collect::<Vec<_>>()
+    );
+    drop(store);
+
+    // Nothing the compactions started writing is left behind to keep the store from opening.
+    let store = Store::open(dir.path(), options).unwrap();
+    assert_eq!(Some(b"val3".to_vec()), store.get(b"key3").unwrap());
+    assert_eq!(Some(b"val4".to_vec()), store.get(b"key4").unwrap());
+}
+
+#[test]
+fn test_compact_range() {
+    let dir = TempDir::new("testing").unwrap();
+    let mut store = Store::new(dir.path(), None, None, None).unwrap();
+
+    store.put(b"a", b"val").unwrap();
+    store.flush_memtable().unwrap();
+    store.put(b"m", b"val").unwrap();
+    store.put(b"n", b"val").unwrap();
+    store.flush_memtable().unwrap();
+    store.delete_prefix(b"m").unwrap();
+
+    // The deletion and the table it covers are compacted, leaving the table outside the range.
+    store.compact_range(b"m", Some(b"n")).unwrap();
+    let mut tables = store
+        .tables()
+        .map(|(l, t)| (l, t.key_end()))
+        .collect::<Vec<_>>();
+    tables.sort();
+    assert_eq!(vec![(0, b"a".to_vec()), (1, b"n".to_vec())], tables);
+
+    assert_eq!(Some(b"val".to_vec()), store.get(b"a").unwrap());
+    assert_eq!(None, store.get(b"m").unwrap());
+    assert_eq!(Some(b"val".to_vec()), store.get(b"n").unwrap());
+
+    assert!(matches!(
+        store.compact_range(b"n", Some(b"m")),
+        Err(StoreError::InvalidArgument(_))
+    ));
+}
+
+#[test]
+fn test_compact_level() {
+    let dir = TempDir::new("testing").unwrap();
+    let mut store = Store::open(dir.path(), Options::default().level_0_file_limit(100)).unwrap();
+
+    for round in 0..3 {
+        for i in 0..50 {
+            store
+                .put(format!("key{:02}", i).as_bytes(), &[round; 100])
+                .unwrap();
+        }
+        store.flush_memtable().unwrap();
+    }
+    assert_eq!(1, store.num_levels());
+    assert_eq!(3, store.tables_at_level(0));
+
+    // Level 0 is drained into level 1, even though it is nowhere near its limit.
+    let stats = store.compact_level(0).unwrap();
+    assert_eq!(3, stats.inputs.len());
+    assert!(stats.inputs.iter().all(|path| !path.exists()));
+    assert!(!stats.outputs.is_empty());
+    assert!(stats.bytes_written < stats.bytes_read);
+    assert_eq!(2, store.num_levels());
+    assert_eq!(0, store.tables_at_level(0));
+    assert_eq!(stats.outputs.len(), store.tables_at_level(1));
+    assert_eq!(0, store.tables_at_level(2));
+    assert_eq!(Some(vec![2; 100]), store.get(b"key07").unwrap());
+
+    // An empty level has nothing to compact.
+    assert_eq!(0, store.compact_level(0).unwrap().inputs.len());
+
+    // Compacting the bottom level rewrites it in place, leaving level 0 alone.
+    for i in 0..40 {
+        store.del(format!("key{:02}", i).as_bytes()).unwrap();
+    }
+    store.flush_memtable().unwrap();
+    let level_1 = store.compact_level(1).unwrap();
+    assert_eq!(stats.outputs, level_1.inputs);
+    assert_eq!(1, store.tables_at_level(0));
+
+    // Compacting the deletions drops the keys they cover.
+    let stats = store.compact_level(0).unwrap();
+    assert!(stats.bytes_written < stats.bytes_read / 2);
+    assert_eq!(None, store.get(b"key07").unwrap());
+    assert_eq!(Some(vec![2; 100]), store.get(b"key42").unwrap());
+
+    assert!(matches!(
+        store.compact_level(2),
+        Err(StoreError::InvalidArgument(_))
+    ));
+}
+
+#[test]
+fn test_max_sst_file_size() {
+    let dir = TempDir::new("testing").unwrap();
+    let options = Options::default()
+        .max_key_size(16)
+        .max_value_size(64)
+        .max_sst_file_size(1024)
+        .level_0_file_limit(100);
+    let mut store = Store::open(dir.path(), options.clone()).unwrap();
+
+    for i in 0..200 {
+        store
+            .put(format!("key{:03}", i).as_bytes(), &[i as u8; 50])
+            .unwrap();
+    }
+    store.delete_prefix(b"key19").unwrap();
+
+    let sizes = |store: &Store| {
+        store
+            .tables()
+            .map(|(_, table)| fs::metadata(&table.path).unwrap().len())
+            .collect::<Vec<_>>()
+    };
+
+    // A single flush rolls over to a new table each time one would get too large.
+    store.flush_memtable().unwrap();
+    let flushed = sizes(&store);
+    assert!(flushed.len() > 10);
+    assert!(flushed.iter().all(|size| *size <= 1024), "{:?}", flushed);
+    assert_eq!(None, store.get(b"key195").unwrap());
+    assert_eq!(Some(vec![189; 50]), store.get(b"key189").unwrap());
+
+    // So does a compaction, well short of the table size limit.
+    store.compact().unwrap();
+    let compacted = sizes(&store);
+    assert!(compacted.len() > 10);
+    assert!(
+        compacted.iter().all(|size| *size <= 1024),
+        "{:?}",
+        compacted
+    );
+    assert!(store.tables().all(|(level, _)| level == 1));
+    assert_eq!(190, store.scan(b"", None).unwrap().count());
+    assert!(store.verify_integrity().unwrap().is_ok());
+
+    // The limit must leave room for the largest record.
+    assert!(matches!(
+        Store::open(dir.path(), options.max_sst_file_size(100)),
+        Err(StoreError::InvalidArgument(_))
+    ));
+}
+
+#[test]
+fn test_barrier() {
+    let dir = TempDir::new("testing").unwrap();
+    let mut store = Store::new(dir.path(), None, None, None).unwrap();
+    assert_eq!(0, store.barrier().unwrap());
+
+    store.put(b"key1", b"val1").unwrap();
+    store.put(b"key2", b"val2").unwrap();
+    store.del(b"key1").unwrap();
+    assert_eq!(3, store.barrier().unwrap());
+    assert_eq!(3, store.barrier().unwrap());
+
+    // Rejected writes aren't numbered, and numbering carries on across flushes.
+    assert!(store.put(b"", b"val").is_err());
+    store.flush_memtable().unwrap();
+    store.put(b"key3", b"val3").unwrap();
+    assert_eq!(4, store.barrier().unwrap());
+}
+
+#[test]
+fn test_delete_prefix() {
+    let dir = TempDir::new("testing").unwrap();
+    let mut store = Store::new(dir.path(), None, None, Some(10)).unwrap();
+
+    // Keys under the prefix in an older table, a newer table, and the memtable.
+    store.put(b"tenant:a:1", b"val").unwrap();
+    store.put(b"tenant:b:1", b"val").unwrap();
+    store.flush_memtable().unwrap();
+    store.put(b"tenant:a:2", b"val").unwrap();
+    store.flush_memtable().unwrap();
+    store.put(b"tenant:a:3", b"val").unwrap();
+    store.put(b"tenant:c:1", b"val").unwrap();
+
+    assert_eq!(3, store.delete_prefix(b"tenant:a:").unwrap());
+    // A later write under the prefix shadows the deletion.
+    store.put(b"tenant:a:2", b"new").unwrap();
+
+    let want = vec![
+        (b"tenant:a:2".to_vec(), b"new".to_vec()),
+        (b"tenant:b:1".to_vec(), b"val".to_vec()),
+        (b"tenant:c:1".to_vec(), b"val".to_vec()),
+    ];
+    let check = |store: &Store| {
+        assert_eq!(None, store.get(b"tenant:a:1").unwrap());
+        assert_eq!(Some(b"new".to_vec()), store.get(b"tenant:a:2").unwrap());
+        assert_eq!(None, store.get(b"tenant:a:3").unwrap());
+        assert_eq!(want, scan_all(store.scan(b"", None).unwrap()));
+    };
+    check(&store);
+
+    // The deletion survives recovery from the WAL, a flush, and a compaction.
+    drop(store);
+    let mut store = Store::new(dir.path(), None, None, Some(10)).unwrap();
+    check(&store);
+    store.put(b"tenant:a:4", b"val").unwrap();
+    assert_eq!(1, store.delete_prefix(b"tenant:a:4").unwrap());
+    assert_eq!(0, store.delete_prefix(b"tenant:a:4").unwrap());
+    store.flush_memtable().unwrap();
+    check(&store);
+    store.compact().unwrap();
+    check(&store);
+
+    // A prefix of 0xff bytes has no end, so the deletion runs to the end of the key space.
+    store.put(&[0xff, 0xff, 1], b"val").unwrap();
+    store.flush_memtable().unwrap();
+    store.put(&[0xfe, 0xff], b"val").unwrap();
+    assert_eq!(1, store.delete_prefix(&[0xff]).unwrap());
+    assert_eq!(None, store.get(&[0xff, 0xff, 1]).unwrap());
+    // One ending in 0xff bytes runs up to the key after the last byte that isn't.
+    store.put(&[0xfe, 0xff, 0xff], b"val").unwrap();
+    store.put(&[0xff], b"val").unwrap();
+    assert_eq!(2, store.delete_prefix(&[0xfe, 0xff]).unwrap());
+    assert_eq!(Some(b"val".to_vec()), store.get(&[0xff]).unwrap());
+    store.del(&[0xff]).unwrap();
+    check(&store);
+
+    assert!(matches!(
+        store.delete_prefix(b""),
+        Err(StoreError::InvalidArgument(_))
+    ));
+}
+
+#[test]
+fn test_freeze_writes() {
+    let dir = TempDir::new("testing").unwrap();
+    let mut store = Store::new(dir.path(), None, None, None).unwrap();
+    store.put(b"key1", b"val1").unwrap();
+
+    let outer = store.freeze_writes();
+    let inner = store.freeze_writes();
+    assert!(matches!(
+        store.put(b"key2", b"val2"),
+        Err(StoreError::Frozen)
+    ));
+    assert!(matches!(store.del(b"key1"), Err(StoreError::Frozen)));
+    assert!(matches!(store.flush_memtable(), Err(StoreError::Frozen)));
+    assert!(matches!(store.compact(), Err(StoreError::Frozen)));
+    assert_eq!(Some(b"val1".to_vec()), store.get(b"key1").unwrap());
+
+    // The store stays frozen until the last guard is dropped.
+    drop(outer);
+    assert!(matches!(
+        store.put(b"key2", b"val2"),
+        Err(StoreError::Frozen)
+    ));
+    drop(inner);
+    store.put(b"key2", b"val2").unwrap();
+    assert_eq!(Some(b"val2".to_vec()), store.get(b"key2").unwrap());
+
+    // A compaction under way when the store is frozen completes, since it holds the store.
+    let store = Arc::new(Mutex::new(store));
+    let (started, wait) = std::sync::mpsc::channel();
+    let compaction = {
+        let store = store.clone();
+        std::thread::spawn(move || {
+            let mut store = store.lock().unwrap();
+            started.send(()).unwrap();
+            store.compact().unwrap();
+        })
+    };
+    wait.recv().unwrap();
+    let guard = store.lock().unwrap().freeze_writes();
+    compaction.join().unwrap();
+
+    let mut store = store.lock().unwrap();
+    assert_eq!(
+        vec![1],
+        store.tables().map(|(level, _)| level).collect::<Vec<_>>()
+    );
+    assert!(matches!(
+        store.put(b"key3", b"val3"),
+        Err(StoreError::Frozen)
+    ));
+    drop(guard);
+    store.put(b"key3", b"val3").unwrap();
+}
+
+#[test]
+#[ignore]
+fn stress_test() {
+    // This is a test to simulate some amount of random load on the storage engine consisting mostly
+    // of writes with some updates and deletes mixed in. The store will be restarted randomly
+    // throughout the test. It is not run by default with `cargo test` since it is rather lengthy.
+    // It can be run with:
+    //      cargo test -- --ignored
+
+    // This will produce at least 200 memtable flushes, depending on how many times a restart is
+    // triggered.
+    let wal_size = 100 * 1024;
+    let table_size_limit = 100 * 1024;
+    let level_0_file_limit = 5;
+
+    // Total number of "actions", which are inserts, deletes, updates, or even restarts.
+    let action_limit = 10_000;
+
+    // 1 in 1000 is a restart.
+    let restart_probability = 1000;
+    // 1 in 100 is a delete.
+    let delete_probability = 100;
+    // 1 in 5 is an update.
+    let update_probability = 5;
+    // When updating pick from the most recent 100 inserted keys. This simulates some churn on more
+    // recent data, while allowing the majority of data to become "old".
+    let update_pool_size = 100;
+
+    let key_length = 10..255;
+    let val_length = 1..4096;
+
+    let dir = TempDir::new("testing").unwrap();
+    let mut store = Store::new(
+        dir.path(),
+        Some(wal_size),
+        Some(table_size_limit as usize),
+        Some(level_0_file_limit),
+    )
+    .unwrap();
+    let mut ref_store: HashMap<Vec<u8>, Vec<u8>> = HashMap::new();
+    let mut pool: VecDeque<Vec<u8>> = VecDeque::with_capacity(update_pool_size);
+
+    let mut rng = rand::thread_rng();
+
+    for i in 0..action_limit {
+        if i % 100 == 0 {
+            println!("processing action {} of {}...", i, action_limit);
+        }
+
+        let rand = rng.gen_range(1..=1000);
+
+        if rand % restart_probability == 0 {
+            // Close an re-open the store, which will convert any left-over wal file into an sst.
+            drop(store);
+            store = Store::new(
+                dir.path(),
+                Some(wal_size),
+                Some(table_size_limit as usize),
+                Some(level_0_file_limit),
+            )
+            .unwrap();
+            continue;
+        }
+
+        if rand % delete_probability == 0 {
+            if pool.is_empty() {
+                continue;
+            }
+
+            // Pick a random item from the last `update_pool_size` items to delete.
+            let idx = rand::random::<usize>() % pool.len();
+            let k = pool.remove(idx).unwrap();
+
+            // Delete it from both the reference store and the test store.
+            ref_store.remove(&k).unwrap();
+            store.del(&k).unwrap();
+
+            continue;
+        }
+
+        if rand % update_probability == 0 {
+            if pool.is_empty() {
+                // In case an update is randomly selected before any inserts, or if there was one
+                // insert followed by a delete etc.
+                continue;
+            }
+
+            // Pick a random item from the last `update_pool_size` items to update.
+            let idx = rand % pool.len();
+            let k = pool.remove(idx).unwrap();
+
+            let new_val = Alphanumeric
+                .sample_string(&mut rand::thread_rng(), rng.gen_range(val_length.clone()))
+                .as_bytes()
+                .to_vec();
+
+            // Update it in both the reference store and the test store.
+            ref_store.insert(k.to_vec(), new_val.clone()).unwrap();
+            // TODO: It would be nice if this worked like HashMap, returning an option. But that
+            // would require a lookup for every put.
+            store.put(&k, &new_val).unwrap();
+
+            continue;
+        }
+
+        // Add a new record if none of those happened.
+        loop {
+            let key = Alphanumeric
+                .sample_string(&mut rand::thread_rng(), rng.gen_range(key_length.clone()))
+                .as_bytes()
+                .to_vec();
+
+            let val = Alphanumeric
+                .sample_string(&mut rand::thread_rng(), rng.gen_range(val_length.clone()))
+                .as_bytes()
+                .to_vec();
+
+            if ref_store.contains_key(&key) {
+                // Handle the case where we randomly generate a key for a new insert that is already
+                // in the store, as strange things can happen in this unlikely event.
+                continue;
+            }
+
+            // Add to the ring buffer for updates/deletes.
+            if pool.len() >= update_pool_size {
+                pool.pop_front();
+            }
+            pool.push_back(key.clone());
+
+            store.put(&key, &val).unwrap();
+            ref_store.insert(key, val);
+
+            break;
+        }
+    }
+
+    // All values in the reference in-memory store should exist in the store under test.
+    for (ref_key, ref_val) in ref_store.iter() {
+        let ref_got = store.get(ref_key).unwrap().unwrap();
+        assert_eq!(ref_val, &ref_got);
+    }
+
+    // Double check after re-opening the store.
+    drop(store);
+    store = Store::new(
+        dir.path(),
+        Some(wal_size),
+        Some(table_size_limit as usize),
+        Some(level_0_file_limit),
+    )
+    .unwrap();
+
+    for (ref_key, ref_val) in ref_store.iter() {
+        let ref_got = store.get(ref_key).unwrap().unwrap();
+        assert_eq!(ref_val, &ref_got);
+    }
+
+    // Likewise, all values in the store under test should exist in the reference in-memory store.
+    let mut scanned = 0;
+    for rec in store.scan(b"", None).unwrap() {
+        let (key, val) = rec.unwrap();
+        assert_eq!(ref_store.get(&key), Some(&val));
+        scanned += 1;
+    }
+    assert_eq!(ref_store.len(), scanned);
+
+    // Compaction must have left the tables consistent, with no overlap in level 1.
+    let report = store.verify_integrity().unwrap();
+    assert!(report.is_ok(), "{:?}", report.problems);
+}
+
+#[test]
+fn test_migrate_legacy_tables() {
+    // Legacy tables hold their records, an index, and then the index's offset as a u32.
+    let write_legacy = |path: &std::path::Path, records: &[WriteRecord]| {
+        let mut bytes = Vec::new();
+        for record in records {
+            record.write_to(&mut bytes).unwrap();
+        }
+        let index_start = bytes.len() as u32;
+        bytes.extend_from_slice(b"index");
+        bytes.extend_from_slice(&index_start.to_le_bytes());
+        fs::write(path, bytes).unwrap();
+    };
+
+    let dir = TempDir::new("testing").unwrap();
+    write_legacy(
+        &dir.path().join("1.sst"),
+        &[
+            WriteRecord::Exists {
+                key: b"key1",
+                val: b"old",
+            },
+            WriteRecord::Exists {
+                key: b"key2",
+                val: b"val2",
+            },
+        ],
+    );
+    write_legacy(
+        &dir.path().join("2.sst"),
+        &[
+            WriteRecord::Exists {
+                key: b"key1",
+                val: b"new",
+            },
+            WriteRecord::Deleted { key: b"key2" },
+        ],
+    );
+
+    // The store won't open over legacy tables unless asked to migrate them.
+    assert!(matches!(
+        Store::open(dir.path(), Options::default()),
+        Err(StoreError::InvalidArgument(_))
+    ));
+
+    let store = Store::open(dir.path(), Options::default().migrate_legacy_tables(true)).unwrap();
+    assert_eq!(Some(b"new".to_vec()), store.get(b"key1").unwrap());
+    assert_eq!(None, store.get(b"key2").unwrap());
+    assert_eq!(
+        vec![0, 0],
+        store.tables().map(|(level, _)| level).collect::<Vec<_>>()
+    );
+    assert!(!dir.path().join("1.sst").exists());
+    assert!(!dir.path().join("2.sst").exists());
+    drop(store);
+
+    let store = Store::open(dir.path(), Options::default()).unwrap();
+    assert_eq!(Some(b"new".to_vec()), store.get(b"key1").unwrap());
+}
+
+#[test]
+fn test_blob_values() {
+    let dir = TempDir::new("testing").unwrap();
+    let blob_files = || {
+        fs::read_dir(dir.path())
+            .unwrap()
+            .filter(|entry| {
+                entry
+                    .as_ref()
+                    .unwrap()
+                    .path()
+                    .extension()
+                    .is_some_and(|ext| ext == "blob")
+            })
+            .count()
+    };
+
+    let options = Options::default().min_blob_size(100);
+    let mut store = Store::open(dir.path(), options.clone()).unwrap();
+    for i in 0..10u8 {
+        store.put(&[b'k', i], &[i; 1000]).unwrap();
+    }
+    store.put(b"small", b"val").unwrap();
+
+    // Only references to the large values go through the WAL.
+    assert!(store.wal_size() < 1000);
+    assert_eq!(Some(vec![3; 1000]), store.get(&[b'k', 3]).unwrap());
+
+    store.compact().unwrap();
+    assert_eq!(Some(vec![3; 1000]), store.get(&[b'k', 3]).unwrap());
+    assert_eq!(Some(b"val".to_vec()), store.get(b"small").unwrap());
+    assert_eq!(11, store.scan(b"", None).unwrap().count());
+    assert!(store.verify_integrity().unwrap().is_ok());
+    drop(store);
+
+    // Values in blob files are read without the option too.
+    let store = Store::open(dir.path(), Options::default()).unwrap();
+    assert_eq!(Some(vec![9; 1000]), store.get(&[b'k', 9]).unwrap());
+    drop(store);
+
+    // Most of the values in the first file are replaced, so it is collected. The live ones are
+    // written to the file the reopened store appends to.
+    let mut store = Store::open(dir.path(), options).unwrap();
+    for i in 0..8u8 {
+        store.put(&[b'k', i], &[i + 100; 1000]).unwrap();
+    }
+    store.del(&[b'k', 8]).unwrap();
+    assert_eq!(2, blob_files());
+
+    assert_eq!(0, store.collect_blobs(0.05).unwrap());
+    assert_eq!(1, store.collect_blobs(0.5).unwrap());
+    assert_eq!(1, blob_files());
+    for i in 0..8u8 {
+        assert_eq!(Some(vec![i + 100; 1000]), store.get(&[b'k', i]).unwrap());
+    }
+    assert_eq!(None, store.get(&[b'k', 8]).unwrap());
+    assert_eq!(Some(vec![9; 1000]), store.get(&[b'k', 9]).unwrap());
+}
+
+#[test]
+fn test_inline_values() {
+    let dir = TempDir::new("testing").unwrap();
+    let mut store = Store::open(dir.path(), Options::default().inline_value_size(8)).unwrap();
+
+    store.put(b"small", b"val").unwrap();
+    store.put(b"large", &[7; 100]).unwrap();
+    store.put(b"deleted", b"val").unwrap();
+    store.flush_memtable().unwrap();
+    store.del(b"deleted").unwrap();
+    store.flush_memtable().unwrap();
+
+    // Small values and deletions are read from the index, without a seek.
+    assert_eq!(Some(b"val".to_vec()), store.get(b"small").unwrap());
+    assert_eq!(None, store.get(b"deleted").unwrap());
+    assert_eq!(0, store.stats().seeks);
+    assert_eq!(Some(vec![7; 100]), store.get(b"large").unwrap());
+    assert_eq!(1, store.stats().seeks);
+
+    store.compact().unwrap();
+    assert_eq!(Some(b"val".to_vec()), store.get(b"small").unwrap());
+    assert_eq!(1, store.stats().seeks);
+    assert_eq!(2, store.scan(b"", None).unwrap().count());
+    assert!(store.verify_integrity().unwrap().is_ok());
+    drop(store);
+
+    // Tables with inline values are read without the option too.
+    let store = Store::open(dir.path(), Options::default()).unwrap();
+    assert_eq!(Some(b"val".to_vec()), store.get(b"small").unwrap());
+    assert_eq!(Some(vec![7; 100]), store.get(b"large").unwrap());
+    let identity = fs::read_to_string(dir.path().join("IDENTITY")).unwrap();
+    assert!(!identity.contains("format_version 1\n"));
+}
+
+#[test]
+fn test_prefix_bloom() {
+    let dir = TempDir::new("testing").unwrap();
+    let mut store = Store::open(dir.path(), Options::default().prefix_bloom_length(4)).unwrap();
+
+    for i in 0..10 {
+        store
+            .put(format!("user{:02}", i).as_bytes(), b"val")
+            .unwrap();
+    }
+    store.flush_memtable().unwrap();
+    for i in 0..5 {
+        store
+            .put(format!("item{:02}", i).as_bytes(), b"val")
+            .unwrap();
+    }
+    store.flush_memtable().unwrap();
+    store.del(b"user03").unwrap();
+    store.flush_memtable().unwrap();
+
+    let keys = |store: &Store, prefix: &[u8]| {
+        store
+            .scan_prefix(prefix)
+            .unwrap()
+            .map(|rec| String::from_utf8(rec.unwrap().0).unwrap())
+            .collect::<Vec<_>>()
+    };
+
+    // Only the table with items is read.
+    assert_eq!(
+        vec!["item00", "item01", "item02", "item03", "item04"],
+        keys(&store, b"item")
+    );
+    assert_eq!(2, store.stats().prefix_skips);
+
+    assert_eq!(9, keys(&store, b"user").len());
+    assert_eq!(vec!["user05"], keys(&store, b"user05"));
+    assert_eq!(4, store.stats().prefix_skips);
+
+    // Prefixes shorter than the filter's can't be ruled out.
+    assert_eq!(9, keys(&store, b"us").len());
+    assert!(keys(&store, b"other").is_empty());
+    assert_eq!(7, store.stats().prefix_skips);
+
+    // A table that is skipped still applies its range deletions to older tables.
+    store.delete_prefix(b"item").unwrap();
+    store.flush_memtable().unwrap();
+    assert!(keys(&store, b"item").is_empty());
+
+    store.compact().unwrap();
+    assert_eq!(9, keys(&store, b"user").len());
+    assert!(store.verify_integrity().unwrap().is_ok());
+    drop(store);
+
+    // Tables with prefix filters are read without the option too.
+    let store = Store::open(dir.path(), Options::default()).unwrap();
+    assert_eq!(9, keys(&store, b"user").len());
+    assert_eq!(Some(b"val".to_vec()), store.get(b"user09").unwrap());
+}
+
+#[test]
+fn test_scan_pruning() {
+    let dir = TempDir::new("testing").unwrap();
+    let mut store = Store::open(dir.path(), Options::default().level_0_file_limit(100)).unwrap();
+
+    // Twenty tables, each with its own slice of the keys.
+    for table in 0..20 {
+        for i in 0..10 {
+            store
+                .put(format!("key{:02}/{}", table, i).as_bytes(), b"val")
+                .unwrap();
+        }
+        store.flush_memtable().unwrap();
+    }
+
+    let keys = |store: &Store, start: &[u8], end: &[u8]| {
+        store
+            .scan(start, Some(end))
+            .unwrap()
+            .map(|rec| String::from_utf8(rec.unwrap().0).unwrap())
+            .collect::<Vec<_>>()
+    };
+
+    // A range within one table only reads that table, from the start of the range.
+    assert_eq!(
+        vec!["key05/3", "key05/4", "key05/5"],
+        keys(&store, b"key05/3", b"key05/6")
+    );
+    assert_eq!(19, store.stats().range_skips);
+
+    // A range spanning two tables reads both.
+    assert_eq!(
+        vec!["key05/9", "key06/0"],
+        keys(&store, b"key05/9", b"key06/1")
+    );
+    assert_eq!(37, store.stats().range_skips);
+
+    // A range between tables' keys reads none of them.
+    assert!(keys(&store, b"key05/99", b"key06").is_empty());
+    assert_eq!(57, store.stats().range_skips);
+
+    // Range deletions in a table that is skipped still hide the keys of older tables.
+    store.delete_prefix(b"key05/").unwrap();
+    store.put(b"other", b"val").unwrap();
+    store.flush_memtable().unwrap();
+    assert!(keys(&store, b"key05/3", b"key05/6").is_empty());
+    assert_eq!(
+        vec!["key04/9", "key06/0"],
+        keys(&store, b"key04/9", b"key06/1")
+    );
+}
+
+#[test]
+fn test_warm() {
+    let dir = TempDir::new("testing").unwrap();
+    let mut store = Store::open(dir.path(), Options::default()).unwrap();
+    for i in 0..100 {
+        store
+            .put(format!("key{:03}", i).as_bytes(), &[b'x'; 100])
+            .unwrap();
+    }
+    store.flush_memtable().unwrap();
+    store.compact().unwrap();
+    store.put(b"newer", b"val").unwrap();
+    store.flush_memtable().unwrap();
+
+    // Only the bottom level is read, and no more of it than allowed.
+    let bottom = store
+        .tables()
+        .filter(|(level, _)| *level == 1)
+        .map(|(_, table)| table.size().unwrap())
+        .sum::<u64>();
+    assert!(bottom > 0);
+    assert_eq!(0, store.warm(WarmOptions::default()).unwrap());
+    assert_eq!(
+        bottom,
+        store
+            .warm(WarmOptions::default().bottom_level(true))
+            .unwrap()
+    );
+    assert_eq!(
+        100,
+        store
+            .warm(WarmOptions::default().bottom_level(true).max_bytes(100))
+            .unwrap()
+    );
+    assert_eq!(Some(vec![b'x'; 100]), store.get(b"key042").unwrap());
+}
+
+#[test]
+fn test_wal_dir() {
+    let dir = TempDir::new("testing").unwrap();
+    let wal_dir = TempDir::new("testing").unwrap();
+    let wal = wal_dir.path().join("wal");
+    let options = Options::default().wal_dir(&wal);
+
+    // The WAL is created in its own directory, which holds the writes until they are flushed.
+    let mut store = Store::open(dir.path(), options.clone()).unwrap();
+    store.put(b"key1", b"val1").unwrap();
+    assert!(wal.join("data.wal").exists());
+    assert!(!dir.path().join("data.wal").exists());
+    drop(store);
+
+    let mut store = Store::open(dir.path(), options.clone()).unwrap();
+    assert_eq!(Some(b"val1".to_vec()), store.get(b"key1").unwrap());
+    store.put(b"key2", b"val2").unwrap();
+    store.flush_memtable().unwrap();
+    assert!(dir.path().join("0").read_dir().unwrap().count() > 0);
+    assert!(wal
+        .read_dir()
+        .unwrap()
+        .all(|entry| entry.unwrap().path().is_file()));
+    store.put(b"key3", b"val3").unwrap();
+    drop(store);
+
+    // Writes left in the data directory from before the WAL was moved are recovered. Those in the
+    // removed WAL directory are lost.
+    fs::remove_dir_all(&wal).unwrap();
+    let mut store = Store::open(dir.path(), Options::default()).unwrap();
+    store.put(b"key4", b"val4").unwrap();
+    drop(store);
+    let mut store = Store::open(dir.path(), options.clone()).unwrap();
+    assert!(!dir.path().join("data.wal").exists());
+    for key in [b"key1", b"key2", b"key4"] {
+        assert!(store.get(key).unwrap().is_some());
+    }
+    assert_eq!(None, store.get(b"key3").unwrap());
+    store.put(b"key5", b"val5").unwrap();
+    drop(store);
+
+    // If both hold writes, which are newer can't be told.
+    let mut store = Store::open(dir.path(), Options::default()).unwrap();
+    store.put(b"key6", b"val6").unwrap();
+    drop(store);
+    assert!(matches!(
+        Store::open(dir.path(), options.clone()),
+        Err(StoreError::InvalidArgument(_))
+    ));
+
+    // Merging from the store reads its WAL from where it is kept.
+    let target_dir = TempDir::new("testing").unwrap();
+    let mut target = Store::open(target_dir.path(), Options::default()).unwrap();
+    target
+        .merge_from(dir.path(), MergeOptions::default().wal_dir(&wal))
+        .unwrap();
+    assert_eq!(Some(b"val5".to_vec()), target.get(b"key5").unwrap());
+    assert_eq!(None, target.get(b"key6").unwrap());
+
+    // A WAL directory inside the data directory would be read as a level of tables, however the
+    // path to it is written.
+    let name = dir.path().file_name().unwrap();
+    let mut nested = vec![
+        dir.path().join("wal"),
+        dir.path().join("..").join(name).join("wal"),
+    ];
+    #[cfg(unix)]
+    {
+        let link = wal_dir.path().join("link");
+        std::os::unix::fs::symlink(dir.path(), &link).unwrap();
+        nested.push(link.join("wal"));
+    }
+    for wal in nested {
+        assert!(matches!(
+            Store::open(dir.path(), Options::default().wal_dir(&wal)),
+            Err(StoreError::InvalidArgument(_))
+        ));
+    }
+    assert!(!dir.path().join("wal").exists());
+}
+
+#[test]
+fn test_durability_none() {
+    let dir = TempDir::new("testing").unwrap();
+    let options = Options::default()
+        .durability(Durability::None)
+        .wal_size_limit(1024);
+    let mut store = Store::open(dir.path(), options.clone()).unwrap();
+
+    // Writes are flushed to tables as if they had gone through the WAL, which is never written.
+    for i in 0..100u32 {
+        store.put(&i.to_be_bytes(), b"val").unwrap();
+    }
+    assert!(store.tables().count() > 0);
+    assert!(store.wal_size() > 0);
+    assert!(!dir.path().join("data.wal").exists());
+    store.del(&7u32.to_be_bytes()).unwrap();
+    assert_eq!(None, store.get(&7u32.to_be_bytes()).unwrap());
+    drop(store);
+
+    // Whatever wasn't flushed is gone, and the rest is still there.
+    let store = Store::open(dir.path(), options.clone()).unwrap();
+    assert_eq!(
+        Some(b"val".to_vec()),
+        store.get(&0u32.to_be_bytes()).unwrap()
+    );
+    assert_eq!(
+        Some(b"val".to_vec()),
+        store.get(&7u32.to_be_bytes()).unwrap()
+    );
+    drop(store);
+
+    // Writes left in the WAL by a durable store would be lost, so they must be recovered first.
+    let mut store = Store::open(dir.path(), Options::default()).unwrap();
+    store.put(b"durable", b"val").unwrap();
+    drop(store);
+    assert!(matches!(
+        Store::open(dir.path(), options.clone()),
+        Err(StoreError::InvalidArgument(_))
+    ));
+    drop(Store::open(dir.path(), Options::default()).unwrap());
+    let store = Store::open(dir.path(), options).unwrap();
+    assert_eq!(Some(b"val".to_vec()), store.get(b"durable").unwrap());
+
+    assert!(matches!(
+        Store::open(
+            dir.path(),
+            Options::default()
+                .durability(Durability::None)
+                .archive_wal(|_| ())
+        ),
+        Err(StoreError::InvalidArgument(_))
+    ));
+}
+
+#[test]
+fn test_verify_files_on_open() {
+    let dir = TempDir::new("testing").unwrap();
+    let mut store = Store::open(dir.path(), Options::default()).unwrap();
+    store.put(b"key", b"val").unwrap();
+    store.flush_memtable().unwrap();
+    drop(store);
+
+    let options = Options::default().verify_files_on_open(true);
+    drop(Store::open(dir.path(), options.clone()).unwrap());
+
+    // Damage that still decodes is only caught by the checksum.
+    let path = dir.path().join("0").join("1.sst");
+    let mut bytes = fs::read(&path).unwrap();
+    bytes[12] = b'x';
+    fs::write(&path, &bytes).unwrap();
+
+    match Store::open(dir.path(), options) {
+        Err(StoreError::Corruption { path: bad, .. }) => assert_eq!(path, bad),
+        Err(e) => panic!("unexpected error: {}", e),
+        Ok(_) => panic!("opened a damaged store"),
+    }
+    let store = Store::open(dir.path(), Options::default()).unwrap();
+    assert_eq!(Some(b"xal".to_vec()), store.get(b"key").unwrap());
+}
+
+#[test]
+fn test_merge_from() {
+    let source = TempDir::new("testing").unwrap();
+    let mut store = Store::open(source.path(), Options::default().min_blob_size(100)).unwrap();
+    store.put(b"b", b"source").unwrap();
+    store.put(b"c", b"source").unwrap();
+    store.put(b"d", &[7; 100]).unwrap();
+    store.put(b"e", b"source").unwrap();
+    store.flush_memtable().unwrap();
+    store.del(b"e").unwrap();
+    store.del(b"a").unwrap();
+    // Left in the WAL.
+    store.put(b"f", b"source").unwrap();
+    drop(store);
+
+    let contents = |dir: &std::path::Path| {
+        let mut files = Vec::new();
+        let mut dirs = vec![dir.to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            for entry in fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    dirs.push(path);
+                } else {
+                    files.push((fs::read(&path).unwrap(), path));
+                }
+            }
+        }
+        files.sort();
+        files
+    };
+    let before = contents(source.path());
+
+    let target = |options: MergeOptions| {
+        let dir = TempDir::new("testing").unwrap();
+        let mut store = Store::open(dir.path(), Options::default()).unwrap();
+        store.put(b"a", b"target").unwrap();
+        store.put(b"c", b"target").unwrap();
+        store.put(b"e", b"target").unwrap();
+        store.put(b"b", b"target").unwrap();
+        store.del(b"b").unwrap();
+        let merged = store.merge_from(source.path(), options).unwrap();
+        let records = store
+            .scan(b"", None)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        (dir, merged, records)
+    };
+    let record = |key: &[u8], val: &[u8]| (key.to_vec(), val.to_vec());
+
+    // The target's records win, including its deletion of "b".
+    let (_dir, merged, records) = target(MergeOptions::default());
+    assert_eq!(2, merged);
+    assert_eq!(
+        vec![
+            record(b"a", b"target"),
+            record(b"c", b"target"),
+            record(b"d", &[7; 100]),
+            record(b"e", b"target"),
+            record(b"f", b"source"),
+        ],
+        records
+    );
+
+    // The source's records win, but its deletions are left out.
+    let (_dir, merged, records) = target(MergeOptions::default().prefer_source(true));
+    assert_eq!(4, merged);
+    assert_eq!(
+        vec![
+            record(b"a", b"target"),
+            record(b"b", b"source"),
+            record(b"c", b"source"),
+            record(b"d", &[7; 100]),
+            record(b"e", b"target"),
+            record(b"f", b"source"),
+        ],
+        records
+    );
+
+    // And with them, the source's deletions delete keys in the target.
+    let (dir, merged, records) = target(
+        MergeOptions::default()
+            .prefer_source(true)
+            .include_tombstones(true),
+    );
+    assert_eq!(6, merged);
+    assert_eq!(
+        vec![
+            record(b"b", b"source"),
+            record(b"c", b"source"),
+            record(b"d", &[7; 100]),
+            record(b"f", b"source"),
+        ],
+        records
+    );
+
+    // Merged records are in tables, and survive reopening.
+    let store = Store::open(dir.path(), Options::default()).unwrap();
+    assert_eq!(Some(b"source".to_vec()), store.get(b"f").unwrap());
+    assert_eq!(None, store.get(b"e").unwrap());
+
+    assert_eq!(before, contents(source.path()));
+}
+
+#[test]
+fn test_export_range() {
+    let dir = TempDir::new("testing").unwrap();
+    let options = Options::default().table_size_limit(256).min_blob_size(100);
+    let mut store = Store::open(dir.path(), options).unwrap();
+    for i in 0..50u32 {
+        store.put(format!("a{:02}", i).as_bytes(), b"val").unwrap();
+        store.put(format!("b{:02}", i).as_bytes(), b"val").unwrap();
+    }
+    store.flush_memtable().unwrap();
+    store.del(b"b07").unwrap();
+    store.delete_prefix(b"b1").unwrap();
+    store.put(b"b20", &[7; 100]).unwrap();
+
+    let target = TempDir::new("testing").unwrap();
+    let export_dir = target.path().join("export");
+    let report = store.export_range(b"b", Some(b"c"), &export_dir).unwrap();
+    assert_eq!(39, report.records);
+    assert_eq!(38 * (3 + 3) + 3 + 100, report.bytes);
+
+    // Only a fresh directory can be exported into.
+    assert!(matches!(
+        store.export_range(b"b", Some(b"c"), &export_dir),
+        Err(StoreError::InvalidArgument(_))
+    ));
+
+    // The export holds just the live records in the range, in tables that check out.
+    let exported = Store::open(&export_dir, Options::default()).unwrap();
+    assert!(exported.tables().count() > 1);
+    assert!(exported.verify_integrity().unwrap().is_ok());
+    assert_eq!(
+        store
+            .scan(b"b", Some(b"c"))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap(),
+        exported
+            .scan(b"", None)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap()
+    );
+    assert_ne!(store.id(), exported.id());
+
+    // An empty range makes an empty store.
+    let empty_dir = target.path().join("empty");
+    let report = store.export_range(b"x", None, &empty_dir).unwrap();
+    assert_eq!(0, report.records);
+    let exported = Store::open(&empty_dir, Options::default()).unwrap();
+    assert_eq!(0, exported.scan(b"", None).unwrap().count());
+}
+
+#[test]
+fn test_versions_to_keep() {
+    let dir = TempDir::new("testing").unwrap();
+    let mut store = Store::open(dir.path(), Options::default().versions_to_keep(3)).unwrap();
+
+    store.put(b"a", b"a").unwrap();
+    store.put(b"key", b"val1").unwrap();
+    store.flush_memtable().unwrap();
+    store.put(b"key", b"val2").unwrap();
+    store.flush_memtable().unwrap();
+    store.del(b"key").unwrap();
+    store.flush_memtable().unwrap();
+    store.put(b"key", b"val4").unwrap();
+    store.put(b"z", b"z").unwrap();
+
+    // Versions that haven't been compacted are all there, whatever the option.
+    let all = vec![
+        Some(b"val4".to_vec()),
+        None,
+        Some(b"val2".to_vec()),
+        Some(b"val1".to_vec()),
+    ];
+    assert_eq!(all, store.get_versions(b"key", 10).unwrap());
+    assert_eq!(all[..2], store.get_versions(b"key", 2).unwrap());
+
+    // Compaction keeps the newest three, counting the deletion.
+    store.flush_memtable().unwrap();
+    store.compact().unwrap();
+    assert_eq!(all[..3], store.get_versions(b"key", 10).unwrap());
+    assert!(store.verify_integrity().unwrap().is_ok());
+    drop(store);
+
+    let mut store = Store::open(dir.path(), Options::default().versions_to_keep(3)).unwrap();
+    assert_eq!(all[..3], store.get_versions(b"key", 10).unwrap());
+    assert_eq!(Some(b"val4".to_vec()), store.get(b"key").unwrap());
+    assert_eq!(
+        vec![b"a".to_vec(), b"key".to_vec(), b"z".to_vec()],
+        store
+            .scan(b"", None)
+            .unwrap()
+            .map(|r| r.unwrap().0)
+            .collect::<Vec<_>>()
+    );
+
+    // A range deletion hides every older version, and compaction drops them, leaving at most a
+    // deletion.
+    store.delete_prefix(b"key").unwrap();
+    assert_eq!(vec![None], store.get_versions(b"key", 10).unwrap());
+    store.flush_memtable().unwrap();
+    assert_eq!(vec![None], store.get_versions(b"key", 10).unwrap());
+    store.compact().unwrap();
+    assert!(store
+        .get_versions(b"key", 10)
+        .unwrap()
+        .iter()
+        .all(Option::is_none));
+    assert_eq!(Some(b"z".to_vec()), store.get(b"z").unwrap());
+
+    // By default, compaction keeps only the newest version.
+    let dir = TempDir::new("testing").unwrap();
+    let mut store = Store::new(dir.path(), None, None, None).unwrap();
+    store.put(b"key", b"val1").unwrap();
+    store.flush_memtable().unwrap();
+    store.put(b"key", b"val2").unwrap();
+    store.flush_memtable().unwrap();
+    store.compact().unwrap();
+    assert_eq!(
+        vec![Some(b"val2".to_vec())],
+        store.get_versions(b"key", 10).unwrap()
+    );
+}
+
+#[test]
+fn test_background_compaction() {
+    let dir = TempDir::new("testing").unwrap();
+    let options = Options::default()
+        .compaction_mode(CompactionMode::Background)
+        .level_0_file_limit(2)
+        .wal_size_limit(256);
+    let mut store = Store::open(dir.path(), options.clone()).unwrap();
+
+    // Writes keep flushing while compactions run behind them.
+    for i in 0..500 {
+        store
+            .put(
+                format!("key{:03}", i % 200).as_bytes(),
+                format!("{}", i).as_bytes(),
+            )
+            .unwrap();
+        if i % 100 == 0 {
+            assert!(store.missing_tables().is_empty());
+        }
+    }
+    store.flush_memtable().unwrap();
+    store.wait_for_compactions().unwrap();
+
+    assert!(store.tables().filter(|(level, _)| *level == 0).count() < 2);
+    assert!(store.verify_integrity().unwrap().is_ok());
+    for i in 300..500 {
+        assert_eq!(
+            Some(format!("{}", i).into_bytes()),
+            store.get(format!("key{:03}", i % 200).as_bytes()).unwrap()
+        );
+    }
+    assert_eq!(200, store.scan(b"", None).unwrap().count());
+    drop(store);
+
+    let store = Store::open(dir.path(), options).unwrap();
+    assert_eq!(Some(b"499".to_vec()), store.get(b"key099").unwrap());
+}
+
+#[test]
+fn test_snapshot_iter() {
+    let dir = TempDir::new("testing").unwrap();
+    let mut store = Store::new(dir.path(), None, None, None).unwrap();
+    store.put(b"key1", b"val1").unwrap();
+    store.flush_memtable().unwrap();
+    store.put(b"key2", b"val2").unwrap();
+    store.flush_memtable().unwrap();
+    store.put(b"key3", b"val3").unwrap();
+
+    // The number of tables set aside in any level.
+    let obsolete = |dir: &std::path::Path| {
+        ["0", "1"]
+            .iter()
+            .filter_map(|level| fs::read_dir(dir.join(level)).ok())
+            .flatten()
+            .filter(|e| e.as_ref().unwrap().path().extension().unwrap() == "obsolete")
+            .count()
+    };
+
+    let iter = store.snapshot_iter().unwrap();
+
+    // Writes and compactions carry on, but the compacted tables are left in place for the iterator.
+    store.put(b"key1", b"new").unwrap();
+    store.del(b"key2").unwrap();
+    store.put(b"key4", b"val4").unwrap();
+    store.compact().unwrap();
+    assert_eq!(2, obsolete(dir.path()));
+    assert_eq!(
+        vec![1],
+        store.tables().map(|(level, _)| level).collect::<Vec<_>>()
+    );
+
+    assert_eq!(
+        vec![
+            (b"key1".to_vec(), b"val1".to_vec()),
+            (b"key2".to_vec(), b"val2".to_vec()),
+            (b"key3".to_vec(), b"val3".to_vec()),
+        ],
+        iter.map(Result::unwrap).collect::<Vec<_>>()
+    );
+    assert_eq!(0, obsolete(dir.path()));
+
+    // Tables still pinned when the store is closed are removed when it is next opened.
+    store.put(b"key5", b"val5").unwrap();
+    store.flush_memtable().unwrap();
+    let iter = store.snapshot_iter().unwrap();
+    store.compact().unwrap();
+    std::mem::forget(iter);
+    assert_eq!(2, obsolete(dir.path()));
+    drop(store);
+    let store = Store::new(dir.path(), None, None, None).unwrap();
+    assert_eq!(0, obsolete(dir.path()));
+    assert_eq!(Some(b"new".to_vec()), store.get(b"key1").unwrap());
+    assert_eq!(None, store.get(b"key2").unwrap());
+}
+
+#[test]
+fn test_memtable_flush_after() {
+    let dir = TempDir::new("testing").unwrap();
+    let clock = ManualClock::default();
+    let options = Options::default()
+        .clock(clock.clone())
+        .memtable_flush_after(Duration::from_secs(10));
+    let mut store = Store::open(dir.path(), options).unwrap();
+    let tables = |store: &Store| store.tables().count();
+
+    // Nothing is flushed before the oldest write has been in the memtable for long enough.
+    store.put(b"key1", b"val1").unwrap();
+    clock.set(Duration::from_secs(5));
+    store.put(b"key2", b"val2").unwrap();
+    store.tick().unwrap();
+    assert_eq!(0, tables(&store));
+
+    // The time is counted from the oldest write, not the latest.
+    clock.set(Duration::from_secs(10));
+    store.tick().unwrap();
+    assert_eq!(1, tables(&store));
+
+    // An empty memtable is never flushed, however long it has been.
+    clock.set(Duration::from_secs(100));
+    store.tick().unwrap();
+    assert_eq!(1, tables(&store));
+
+    // The time starts again with the first write after a flush, and a write past it flushes.
+    store.put(b"key3", b"val3").unwrap();
+    clock.set(Duration::from_secs(109));
+    store.tick().unwrap();
+    assert_eq!(1, tables(&store));
+    clock.set(Duration::from_secs(110));
+    store.put(b"key4", b"val4").unwrap();
+    assert_eq!(2, tables(&store));
+
+    for i in 1..=4 {
+        let key = format!("key{}", i);
+        let val = format!("val{}", i);
+        assert_eq!(Some(val.into_bytes()), store.get(key.as_bytes()).unwrap());
+    }
+}
+
+#[test]
+fn test_indexes() {
+    // Values are "city,name", indexed by city.
+    fn city(_key: &[u8], val: &[u8]) -> Vec<Vec<u8>> {
+        val.split(|b| *b == b',')
+            .take(1)
+            .map(<[u8]>::to_vec)
+            .collect()
+    }
+
+    let dir = TempDir::new("testing").unwrap();
+    let options = Options::default().index(IndexDef::new("city", city));
+    let mut store = Store::open(dir.path(), options.clone()).unwrap();
+    let lookup = |store: &Store, city: &[u8]| store.lookup_index("city", city).unwrap();
+
+    store.put(b"user1", b"paris,ann").unwrap();
+    store.put(b"user2", b"paris,bob").unwrap();
+    store.put(b"user3", b"rome,cat").unwrap();
+    assert_eq!(
+        vec![b"user1".to_vec(), b"user2".to_vec()],
+        lookup(&store, b"paris")
+    );
+    assert_eq!(vec![b"user3".to_vec()], lookup(&store, b"rome"));
+    assert!(lookup(&store, b"par").is_empty());
+
+    // Overwrites and deletions remove the entries for the old value.
+    store.put(b"user2", b"rome,bob").unwrap();
+    store.del(b"user3").unwrap();
+    assert_eq!(vec![b"user1".to_vec()], lookup(&store, b"paris"));
+    assert_eq!(vec![b"user2".to_vec()], lookup(&store, b"rome"));
+    let entries = store
+        .scan_prefix(indexing::INDEX_PREFIX)
+        .unwrap()
+        .map(Result::unwrap)
+        .count();
+    assert_eq!(2, entries);
+
+    // Deleted prefixes leave their entries behind, but lookups don't return them.
+    store.delete_prefix(b"user1").unwrap();
+    assert!(lookup(&store, b"paris").is_empty());
+
+    // The entries are kept in tables like any other record.
+    store.put(b"user4", b"rome,dan").unwrap();
+    store.flush_memtable().unwrap();
+    drop(store);
+    let mut store = Store::open(dir.path(), options).unwrap();
+    assert_eq!(
+        vec![b"user2".to_vec(), b"user4".to_vec()],
+        lookup(&store, b"rome")
+    );
+
+    assert!(matches!(
+        store.lookup_index("name", b"bob"),
+        Err(StoreError::InvalidArgument(_))
+    ));
+    let mut reserved = indexing::INDEX_PREFIX.to_vec();
+    reserved.extend_from_slice(b"key");
+    assert!(matches!(
+        store.put(&reserved, b"val"),
+        Err(StoreError::InvalidArgument(_))
+    ));
+    assert!(matches!(
+        store.delete_prefix(b"\xff"),
+        Err(StoreError::InvalidArgument(_))
+    ));
+
+    assert!(matches!(
+        Store::open(
+            dir.path(),
+            Options::default()
+                .index(IndexDef::new("city", city))
+                .index(IndexDef::new("city", city))
+        ),
+        Err(StoreError::InvalidArgument(_))
+    ));
+}
+
+#[test]
+fn test_get_reader() {
+    let dir = TempDir::new("testing").unwrap();
+    let options = Options::default().min_blob_size(1000).inline_value_size(8);
+    let mut store = Store::open(dir.path(), options).unwrap();
+    let read = |store: &Store, key: &[u8]| {
+        store.get_reader(key).unwrap().map(|mut r| {
+            let mut val = Vec::new();
+            io::copy(&mut r, &mut val).unwrap();
+            assert_eq!(r.len(), val.len() as u64);
+            val
+        })
+    };
+
+    let large = vec![7; 100_000];
+    let blob = vec![8; 5000];
+    store.put(b"small", b"val").unwrap();
+    store.put(b"large", &large).unwrap();
+    store.put(b"blob", &blob).unwrap();
+    store.put(b"deleted", b"val").unwrap();
+    store.del(b"deleted").unwrap();
+
+    // From the memtable.
+    assert_eq!(Some(b"val".to_vec()), read(&store, b"small"));
+    assert_eq!(Some(large.clone()), read(&store, b"large"));
+    assert_eq!(Some(blob.clone()), read(&store, b"blob"));
+    assert_eq!(None, read(&store, b"deleted"));
+    assert_eq!(None, read(&store, b"missing"));
+
+    // From the tables, where small values are carried by the index.
+    store.flush_memtable().unwrap();
+    assert_eq!(Some(b"val".to_vec()), read(&store, b"small"));
+    assert_eq!(Some(large.clone()), read(&store, b"large"));
+    assert_eq!(Some(blob.clone()), read(&store, b"blob"));
+    assert_eq!(None, read(&store, b"deleted"));
+
+    // A reader is unaffected by the table it reads being compacted away.
+    let mut r = store.get_reader(b"large").unwrap().unwrap();
+    let mut start = [0; 10];
+    r.read_exact(&mut start).unwrap();
+    store.put(b"large", b"new").unwrap();
+    store.flush_memtable().unwrap();
+    store.compact().unwrap();
+    let mut rest = Vec::new();
+    r.read_to_end(&mut rest).unwrap();
+    assert_eq!(large.len(), start.len() + rest.len());
+    assert_eq!(Some(b"new".to_vec()), read(&store, b"large"));
+}
+
+#[test]
+fn test_manual_compactions_during_background_compactions() {
+    let dir = TempDir::new("testing").unwrap();
+    let options = Options::default()
+        .compaction_mode(CompactionMode::Background)
+        .wal_size_limit(2 * 1024)
+        .table_size_limit(4 * 1024)
+        .level_0_file_limit(2);
+    let mut store = Store::open(dir.path(), options.clone()).unwrap();
+    let mut model = HashMap::new();
+    let mut rng = rand::thread_rng();
+
+    // Writes flush often enough to keep background compactions going, and manual ones of every
+    // kind are started in among them.
+    for i in 0..3000 {
+        let key = format!("key{:04}", rng.gen_range(0..500));
+        if rng.gen_range(0..10) == 0 {
+            store.del(key.as_bytes()).unwrap();
+            model.remove(key.as_bytes());
+        } else {
+            let val = format!("val{}", i);
+            store.put(key.as_bytes(), val.as_bytes()).unwrap();
+            model.insert(key.into_bytes(), val.into_bytes());
+        }
+
+        match i % 250 {
+            0 => store.compact().unwrap(),
+            50 => store.compact_range(b"key0100", Some(b"key0300")).unwrap(),
+            100 => {
+                store.compact_level(0).unwrap();
+            }
+            150 => {
+                store.compact_level(1).unwrap();
+            }
+            _ => (),
+        }
+    }
+    store.wait_for_compactions().unwrap();
+
+    let check = |store: &Store| {
+        assert!(store.missing_tables().is_empty());
+        assert!(store.verify_integrity().unwrap().is_ok());
+        let mut expected = model.clone().into_iter().collect::<Vec<_>>();
+        expected.sort();
+        assert_eq!(expected, scan_all(store.scan(b"", None).unwrap()));
+    };
+    check(&store);
+
+    // Every table the store knows of is on disk, and nothing else is.
+    let on_disk = |level: &str| {
+        fs::read_dir(dir.path().join(level))
+            .map(|entries| entries.count())
+            .unwrap_or(0)
+    };
+    assert_eq!(store.tables().count(), on_disk("0") + on_disk("1"));
+
+    drop(store);
+    let store = Store::open(dir.path(), options).unwrap();
+    check(&store);
+}
+
+#[test]
+fn test_read_compaction() {
+    let dir = TempDir::new("testing").unwrap();
+    let options = Options::default()
+        .level_0_file_limit(100)
+        .read_compaction_threshold(4)
+        .read_compaction_prefix_length(4);
+    let mut store = Store::open(dir.path(), options).unwrap();
+
+    // Ten overlapping level 0 tables, with the oldest versions in the first.
+    for i in 0..10 {
+        store.put(format!("hot/{}", i).as_bytes(), b"val").unwrap();
+        store.put(b"hot/z", format!("val{}", i).as_bytes()).unwrap();
+        store.put(b"cold", b"val").unwrap();
+        store.flush_memtable().unwrap();
+    }
+    let level_0 = |store: &Store| store.tables().filter(|(level, _)| *level == 0).count();
+    assert_eq!(10, level_0(&store));
+
+    // Gets of the newest version of a key probe a single table, which is fine.
+    for _ in 0..200 {
+        store.get(b"cold").unwrap();
+        store.tick().unwrap();
+    }
+    assert_eq!(10, level_0(&store));
+
+    // Gets of keys in the oldest table probe every table, and their range gets compacted.
+    for _ in 0..200 {
+        assert_eq!(Some(b"val".to_vec()), store.get(b"hot/0").unwrap());
+    }
+    assert_eq!(1, store.stats().read_compactions);
+    store.tick().unwrap();
+    assert_eq!(0, level_0(&store));
+    assert_eq!(Some(b"val9".to_vec()), store.get(b"hot/z").unwrap());
+
+    // The range isn't compacted again straight away.
+    for i in 0..10 {
+        store.put(format!("hot/{}", i).as_bytes(), b"new").unwrap();
+        store.flush_memtable().unwrap();
+    }
+    for _ in 0..200 {
+        store.get(b"hot/z").unwrap();
+    }
+    store.tick().unwrap();
+    assert_eq!(1, store.stats().read_compactions);
+    assert_eq!(10, level_0(&store));
+}
+
+#[test]
+fn test_table_info() {
+    let dir = TempDir::new("testing").unwrap();
+    let mut store = Store::new(dir.path(), None, None, Some(100)).unwrap();
+
+    store.put(b"key1", b"val").unwrap();
+    store.put(b"key3", b"val").unwrap();
+    store.flush_memtable().unwrap();
+    store.compact().unwrap();
+    store.put(b"key2", b"old").unwrap();
+    store.flush_memtable().unwrap();
+    store.put(b"key2", b"new").unwrap();
+    store.put(b"key4", b"val").unwrap();
+    store.flush_memtable().unwrap();
+
+    let info = store.table_info();
+    assert_eq!(
+        vec![(0, true), (0, true), (1, false)],
+        info.iter()
+            .map(|t| (t.level, t.overlapping))
+            .collect::<Vec<_>>()
+    );
+
+    // The newer of the level 0 tables, which `get` reads first, has the larger sequence number.
+    assert!(info[0].sequence < info[1].sequence);
+    assert_eq!(None, info[2].sequence);
+    assert_eq!(
+        (b"key2".to_vec(), b"key4".to_vec(), 2),
+        (
+            info[1].key_start.clone(),
+            info[1].key_end.clone(),
+            info[1].num_entries
+        )
+    );
+    assert_eq!(Some(b"new".to_vec()), store.get(b"key2").unwrap());
+    assert_eq!(
+        store
+            .tables()
+            .map(|(_, t)| t.path.clone())
+            .collect::<Vec<_>>(),
+        info.into_iter().map(|t| t.path).collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn test_catalog() {
+    let dir = TempDir::new("testing").unwrap();
+    let mut store = Store::new(dir.path(), None, None, Some(100)).unwrap();
+    store.put(b"key1", b"val1").unwrap();
+    store.put(b"key2", b"val2").unwrap();
+    store.flush_memtable().unwrap();
+    store.compact().unwrap();
+    store.put(b"key3", b"val3").unwrap();
+    store.del(b"key1").unwrap();
+    store.flush_memtable().unwrap();
+    // Not flushed, so only in the WAL.
+    store.put(b"key4", b"val4").unwrap();
+    drop(store);
+
+    let listing = |dir: &std::path::Path| {
+        let mut files = Vec::new();
+        let mut dirs = vec![dir.to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            for entry in fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    dirs.push(path);
+                } else {
+                    files.push((path.clone(), fs::read(&path).unwrap()));
+                }
+            }
+        }
+        files.sort();
+        files
+    };
+    let before = listing(dir.path());
+
+    let catalog = Catalog::new(dir.path()).unwrap();
+    assert_eq!(2, catalog.num_levels());
+    assert_eq!(1, catalog.tables(0).len());
+    assert_eq!(1, catalog.tables(1).len());
+    assert!(catalog.tables(2).is_empty());
+    assert_eq!(
+        Some((b"key1".to_vec(), b"key3".to_vec())),
+        catalog.key_range()
+    );
+
+    assert_eq!(
+        Some(ReadRecord::Deleted {
+            key: b"key1".to_vec()
+        }),
+        catalog.get(b"key1").unwrap()
+    );
+    assert_eq!(None, catalog.get(b"key4").unwrap());
+    assert_eq!(
+        vec![kv(b"key2", b"val2"), kv(b"key3", b"val3")],
+        scan_all(catalog.scan(b"", None).unwrap())
+    );
+
+    // Nothing in the directory was changed, and the store still has its unflushed write.
+    drop(catalog);
+    assert_eq!(before, listing(dir.path()));
+    let store = Store::new(dir.path(), None, None, Some(100)).unwrap();
+    assert_eq!(Some(b"val4".to_vec()), store.get(b"key4").unwrap());
+}
+
+#[test]
+fn test_open_table() {
+    let dir = TempDir::new("testing").unwrap();
+    let mut store = Store::new(dir.path(), None, None, None).unwrap();
+    for i in (0..100).rev() {
+        store
+            .put(format!("key{:03}", i).as_bytes(), b"val")
+            .unwrap();
+    }
+    store.flush_memtable().unwrap();
+    let path = store.tables().next().unwrap().1.path.clone();
+    drop(store);
+
+    // Records come out in key order, whatever order they were written in.
+    let table = Table::open_verified(&path).unwrap();
+    let keys = table
+        .iter()
+        .unwrap()
+        .map(|record| record.unwrap().key().to_vec())
+        .collect::<Vec<_>>();
+    assert_eq!(100, keys.len());
+    assert!(keys.windows(2).all(|w| w[0] < w[1]));
+
+    // Damage to a record's value leaves the index readable, so the table opens, but it no longer
+    // passes verification.
+    let mut bytes = fs::read(&path).unwrap();
+    bytes[16] ^= 0xff;
+    fs::write(&path, &bytes).unwrap();
+    assert!(Table::new(&path).is_ok());
+    assert!(matches!(
+        Table::open_verified(&path),
+        Err(StoreError::Corruption { .. })
+    ));
+}
+
+#[test]
+fn test_scan_limited() {
+    let dir = TempDir::new("testing").unwrap();
+    let mut store = Store::new(dir.path(), None, None, None).unwrap();
+    for i in 0..10 {
+        store.put(format!("key{}", i).as_bytes(), b"val").unwrap();
+    }
+    store.flush_memtable().unwrap();
+    store.del(b"key3").unwrap();
+    store.del(b"key4").unwrap();
+
+    // Pages through [key1, key9), which has six live records, three at a time.
+    let page = store.scan_limited(b"key1", Some(b"key9"), 3).unwrap();
+    assert_eq!(
+        vec![
+            kv(b"key1", b"val"),
+            kv(b"key2", b"val"),
+            kv(b"key5", b"val")
+        ],
+        page.records
+    );
+    assert_eq!(Some(b"key6".to_vec()), page.resume);
+
+    let page = store
+        .scan_limited(&page.resume.unwrap(), Some(b"key9"), 3)
+        .unwrap();
+    assert_eq!(
+        vec![
+            kv(b"key6", b"val"),
+            kv(b"key7", b"val"),
+            kv(b"key8", b"val")
+        ],
+        page.records
+    );
+    assert_eq!(None, page.resume);
+
+    let page = store.scan_limited(b"key", None, 0).unwrap();
+    assert!(page.records.is_empty());
+    assert_eq!(Some(b"key0".to_vec()), page.resume);
+}
+
+#[test]
+fn test_wal_durable() {
+    let dir = TempDir::new("testing").unwrap();
+    let path = dir.path().join("batched.wal");
+    let read_back = |path: &std::path::Path| {
+        wal::Reader::new(path)
+            .unwrap()
+            .map(|record| record.unwrap().key().to_vec())
+            .collect::<Vec<_>>()
+    };
+
+    // Appended records wait to be synced together, and aren't durable until then.
+    let mut w = wal::Writer::new(&path).unwrap().sync_on_append(false);
+    for key in [b"key1", b"key2", b"key3"] {
+        w.append(WriteRecord::Exists { key, val: b"val" }).unwrap();
+    }
+    assert_eq!((3, 0), (w.appended(), w.durable()));
+    assert_eq!(0, fs::metadata(&path).unwrap().len());
+
+    w.sync().unwrap();
+    assert_eq!((3, 3), (w.appended(), w.durable()));
+    w.append(WriteRecord::Deleted { key: b"key4" }).unwrap();
+    assert_eq!((4, 3), (w.appended(), w.durable()));
+    assert_eq!(3, read_back(&path).len());
+    w.sync().unwrap();
+    assert_eq!(
+        vec![
+            b"key1".to_vec(),
+            b"key2".to_vec(),
+            b"key3".to_vec(),
+            b"key4".to_vec()
+        ],
+        read_back(&path)
+    );
+
+    // By default, each record is durable by the time its append returns.
+    let path = dir.path().join("synced.wal");
+    let mut w = wal::Writer::new(&path).unwrap();
+    w.append(WriteRecord::Deleted { key: b"key" }).unwrap();
+    assert_eq!((1, 1), (w.appended(), w.durable()));
+    assert_eq!(1, read_back(&path).len());
+
+    // Nothing a discarding writer appends is ever durable.
+    let mut w = wal::Writer::discarding(&dir.path().join("discarded.wal"));
+    w.append(WriteRecord::Deleted { key: b"key" }).unwrap();
+    w.sync().unwrap();
+    assert_eq!((1, 0), (w.appended(), w.durable()));
+}
+
+#[test]
+fn test_table_split_keys() {
+    let dir = TempDir::new("testing").unwrap();
+    let options = Options::default().table_split_points(4);
+    let mut store = Store::open(dir.path(), options).unwrap();
+
+    for i in 0..100 {
+        store
+            .put(format!("key{:03}", i).as_bytes(), b"val")
+            .unwrap();
+    }
+    store.flush_memtable().unwrap();
+    store.put(b"key100", b"val").unwrap();
+    store.put(b"key101", b"val").unwrap();
+    store.flush_memtable().unwrap();
+
+    // Each table is split into runs of about the same number of keys. One too small to split that
+    // many ways has as many split keys as it can.
+    let info = store.table_info();
+    assert_eq!(
+        vec![b"key025".to_vec(), b"key050".to_vec(), b"key075".to_vec()],
+        info[0].split_keys()
+    );
+    assert_eq!(vec![b"key101".to_vec()], info[1].split_keys());
+
+    // Compactions record them for the tables they write.
+    store.compact().unwrap();
+    let info = store.table_info();
+    assert_eq!(1, info.len());
+    assert_eq!(
+        vec![b"key025".to_vec(), b"key051".to_vec(), b"key076".to_vec()],
+        info[0].split_keys()
+    );
+
+    // Tables written without the option have none.
+    drop(store);
+    let mut store = Store::open(dir.path(), Options::default()).unwrap();
+    store.put(b"key102", b"val").unwrap();
+    store.flush_memtable().unwrap();
+    let info = store.table_info();
+    assert_eq!(3, info[1].split_keys().len());
+    assert!(info[0].split_keys().is_empty());
+
+    assert!(Store::open(dir.path(), Options::default().table_split_points(1)).is_err());
+}
+
+#[test]
+fn test_compaction_decisions() {
+    let dir = TempDir::new("testing").unwrap();
+    let options = Options::default()
+        .level_0_file_limit(2)
+        .explain_compactions(2);
+    let mut store = Store::open(dir.path(), options).unwrap();
+
+    store.put(b"a", b"val").unwrap();
+    store.compact().unwrap();
+    store.put(b"m1", b"val").unwrap();
+    store.flush_memtable().unwrap();
+    store.put(b"m2", b"val").unwrap();
+    store.flush_memtable().unwrap();
+
+    // Only the most recent decisions are kept.
+    let decisions = store.recent_compaction_decisions();
+    assert_eq!(2, decisions.len());
+    assert!(decisions[0].at <= decisions[1].at);
+
+    // Decisions not to compact are kept too, with every trigger that was checked.
+    assert_eq!(None, decisions[0].chosen);
+    assert_eq!(
+        vec![
+            (Trigger::Level0FileLimit, 1, 2, false),
+            (Trigger::SmallTables, 1, 4, false)
+        ],
+        decisions[0]
+            .triggers
+            .iter()
+            .map(|t| (t.trigger, t.value, t.threshold, t.fired))
+            .collect::<Vec<_>>()
+    );
+    assert!(decisions[0].inputs.is_empty());
+
+    // Checking stops at the first trigger that fires. The level 1 table is left out of the
+    // compaction since it doesn't overlap level 0.
+    let decision = &decisions[1];
+    assert_eq!(Some(Trigger::Level0FileLimit), decision.chosen);
+    assert_eq!(1, decision.triggers.len());
+    assert_eq!(2, decision.inputs.len());
+    let level_1 = decision.candidates.iter().find(|c| c.level == 1).unwrap();
+    assert!(!level_1.chosen);
+    assert_eq!("doesn't overlap level 0", level_1.reason);
+    assert_eq!(
+        decision.inputs,
+        decision
+            .candidates
+            .iter()
+            .filter(|c| c.chosen)
+            .map(|c| c.path.clone())
+            .collect::<Vec<_>>()
+    );
+    assert!(decision.error.is_none());
+
+    drop(store);
+    let store = Store::open(dir.path(), Options::default()).unwrap();
+    assert!(store.recent_compaction_decisions().is_empty());
+}
+
+#[test]
+fn test_scrub() {
+    let dir = TempDir::new("testing").unwrap();
+    let errors = Arc::new(Mutex::new(Vec::new()));
+    let options = {
+        let errors = errors.clone();
+        Options::default().on_scrub_error(move |e| errors.lock().unwrap().push(e.to_string()))
+    };
+    let mut store = Store::open(dir.path(), options).unwrap();
+    store.put(b"key1", b"val1").unwrap();
+    store.flush_memtable().unwrap();
+    store.put(b"key2", b"val2").unwrap();
+    store.flush_memtable().unwrap();
+
+    let damaged = dir.path().join("0").join("1.sst");
+    assert!(store.table_info().iter().all(|t| t.last_verified.is_none()));
+    let report = store.scrub_now().unwrap();
+    assert!(report.is_ok(), "{:?}", report);
+    assert_eq!(2, report.tables_checked);
+    assert!(report.bytes_read > 0);
+    let info = store.table_info();
+    let verified = info
+        .iter()
+        .find(|t| t.path == damaged)
+        .unwrap()
+        .last_verified;
+    assert!(verified.is_some());
+
+    // Damage the first table's record, which the store has already read the index of.
+    let mut contents = fs::read(&damaged).unwrap();
+    contents[9] ^= 0xff;
+    fs::write(&damaged, contents).unwrap();
+
+    // The damage is reported, and the store carries on serving the rest.
+    let report = store.scrub_now().unwrap();
+    assert_eq!(2, report.tables_checked);
+    assert_eq!(1, report.errors.len());
+    assert!(
+        matches!(&report.errors[0], StoreError::Corruption { path, .. } if *path == damaged),
+        "{:?}",
+        report.errors
+    );
+    assert_eq!(1, errors.lock().unwrap().len());
+    assert_eq!(Some(b"val2".to_vec()), store.get(b"key2").unwrap());
+    let info = store.table_info();
+    assert_eq!(
+        verified,
+        info.iter()
+            .find(|t| t.path == damaged)
+            .unwrap()
+            .last_verified
+    );
+    drop(store);
+
+    // The background scrubber finds it too, starting with a pass when the store is opened.
+    let (found, recv) = std::sync::mpsc::channel();
+    let found = Mutex::new(found);
+    let options = Options::default()
+        .scrub_rate(1024 * 1024)
+        .on_scrub_error(move |e| found.lock().unwrap().send(e.to_string()).unwrap());
+    let store = Store::open(dir.path(), options).unwrap();
+    let error = recv.recv_timeout(Duration::from_secs(10)).unwrap();
+    assert!(error.contains("checksum"), "{}", error);
+    drop(store);
+
+    assert!(Store::open(dir.path(), Options::default().scrub_rate(0)).is_err());
+}
+
+#[test]
+fn test_not_a_store() {
+    let dir = TempDir::new("testing").unwrap();
+    fs::create_dir(dir.path().join("Downloads")).unwrap();
+    fs::write(dir.path().join("Downloads").join("report.pdf"), b"").unwrap();
+    fs::create_dir(dir.path().join("Downloads").join("2024")).unwrap();
+
+    // Nothing is written to a directory that doesn't look like a store.
+    let err = Store::open(&dir.path().join("Downloads"), Options::default())
+        .err()
+        .unwrap();
+    assert!(matches!(err, StoreError::NotAStore { .. }), "{:?}", err);
+    assert_eq!(
+        2,
+        fs::read_dir(dir.path().join("Downloads")).unwrap().count()
+    );
+
+    // An empty directory becomes a store.
+    let empty = dir.path().join("empty");
+    fs::create_dir(&empty).unwrap();
+    let mut store = Store::open(&empty, Options::default()).unwrap();
+    store.put(b"key", b"val").unwrap();
+    store.flush_memtable().unwrap();
+    drop(store);
+    let store = Store::open(&empty, Options::default()).unwrap();
+    assert_eq!(Some(b"val".to_vec()), store.get(b"key").unwrap());
+}
+
+#[test]
+fn test_disk_usage() {
+    let dir = TempDir::new("testing").unwrap();
+    let options = Options::default().min_blob_size(100);
+    let mut store = Store::open(dir.path(), options).unwrap();
+
+    store.put(b"small", b"val").unwrap();
+    store.put(b"large", &[b'v'; 200]).unwrap();
+    store.flush_memtable().unwrap();
+    store.compact().unwrap();
+    store.put(b"other", b"val").unwrap();
+    store.flush_memtable().unwrap();
+    store.put(b"unflushed", b"val").unwrap();
+
+    // Stray files aren't counted.
+    fs::write(dir.path().join("notes.txt"), [0; 1000]).unwrap();
+    fs::write(dir.path().join("1").join("notes.txt"), [0; 1000]).unwrap();
+
+    let usage = store.disk_usage().unwrap();
+    let size = |path: &std::path::Path| fs::metadata(path).unwrap().len();
+    let tables = |level: usize| {
+        store
+            .table_info()
+            .iter()
+            .filter(|t| t.level == level)
+            .map(|t| size(&t.path))
+            .sum::<u64>()
+    };
+    assert_eq!(vec![tables(0), tables(1)], usage.levels);
+    assert!(usage.levels.iter().all(|bytes| *bytes > 0));
+    assert_eq!(size(&dir.path().join("data.wal")), usage.wal);
+    assert!(usage.wal > 0);
+    assert!(usage.blobs >= 200);
+    assert_eq!(size(&dir.path().join("IDENTITY")), usage.identity);
+    assert_eq!(
+        usage.wal + tables(0) + tables(1) + usage.blobs + usage.identity,
+        usage.total()
+    );
+}
+
+#[test]
+fn test_cold_dir() {
+    let dir = TempDir::new("testing").unwrap();
+    let cold = TempDir::new("cold").unwrap();
+    let cold_dir = cold.path().join("tables");
+
+    // Tables written before the store had a cold directory stay where they are.
+    let mut store = Store::open(dir.path(), Options::default()).unwrap();
+    store.put(b"a", b"old").unwrap();
+    store.compact().unwrap();
+    drop(store);
+
+    let options = Options::default().cold_dir(&cold_dir);
+    let mut store = Store::open(dir.path(), options.clone()).unwrap();
+    store.put(b"b", b"cold").unwrap();
+    store.flush_memtable().unwrap();
+    store.compact_level(0).unwrap();
+    store.put(b"c", b"hot").unwrap();
+    store.flush_memtable().unwrap();
+
+    // Level 0 is in the data directory, and compactions write to the cold one.
+    let info = store.table_info();
+    assert_eq!(3, info.len());
+    let path = |key: &[u8]| {
+        info.iter()
+            .find(|t| t.key_start == key)
+            .unwrap()
+            .path
+            .clone()
+    };
+    assert!(path(b"a").starts_with(dir.path().join("1")));
+    assert!(path(b"b").starts_with(cold_dir.join("1")));
+    assert!(path(b"c").starts_with(dir.path().join("0")));
+
+    assert_eq!(Some(b"old".to_vec()), store.get(b"a").unwrap());
+    assert_eq!(Some(b"cold".to_vec()), store.get(b"b").unwrap());
+    assert_eq!(Some(b"hot".to_vec()), store.get(b"c").unwrap());
+    assert!(store.verify_integrity().unwrap().is_ok());
+    let scrub = store.scrub_now().unwrap();
+    assert_eq!(3, scrub.tables_checked);
+
+    // Compacting everything moves it all to the cold directory.
+    store.compact().unwrap();
+    drop(store);
+    let store = Store::open(dir.path(), options).unwrap();
+    let info = store.table_info();
+    assert_eq!(1, info.len());
+    assert!(info[0].path.starts_with(&cold_dir));
+    assert_eq!(3, store.scan(b"", None).unwrap().count());
+    assert_eq!(Some(b"old".to_vec()), store.get(b"a").unwrap());
+    drop(store);
+
+    // A cold directory inside the data directory would be read as a level of tables.
+    let nested = Options::default().cold_dir(&dir.path().join("cold"));
+    assert!(matches!(
+        Store::open(dir.path(), nested),
+        Err(StoreError::InvalidArgument(_))
+    ));
+    assert!(!dir.path().join("cold").exists());
+}
+
+#[test]
+fn test_empty_values() {
+    for options in [Options::default(), Options::default().inline_value_size(8)] {
+        let dir = TempDir::new("testing").unwrap();
+        let mut store = Store::open(dir.path(), options.clone()).unwrap();
+        let check = |store: &Store| {
+            assert_eq!(Some(Vec::new()), store.get(b"empty").unwrap());
+            assert_eq!(None, store.get(b"deleted").unwrap());
+            assert_eq!(Some(Vec::new()), store.get(b"emptied").unwrap());
+            assert_eq!(
+                vec![
+                    (b"emptied".to_vec(), Vec::new()),
+                    (b"empty".to_vec(), Vec::new())
+                ],
+                scan_all(store.scan(b"", None).unwrap())
+            );
+        };
+
+        // In the memtable.
+        store.put(b"deleted", b"").unwrap();
+        store.del(b"deleted").unwrap();
+        store.put(b"emptied", b"val").unwrap();
+        store.put(b"emptied", b"").unwrap();
+        store.put(b"empty", b"").unwrap();
+        check(&store);
+
+        // Recovered from the WAL.
+        drop(store);
+        let mut store = Store::open(dir.path(), options.clone()).unwrap();
+        check(&store);
+
+        // In a table.
+        store.flush_memtable().unwrap();
+        check(&store);
+        store.put(b"emptied", b"val").unwrap();
+        store.flush_memtable().unwrap();
+        store.put(b"emptied", b"").unwrap();
+        assert_eq!(
+            vec![Some(Vec::new()), Some(b"val".to_vec()), Some(Vec::new())],
+            store.get_versions(b"emptied", 3).unwrap()
+        );
+        store.flush_memtable().unwrap();
+
+        // Shadowing older records, and shadowed by newer ones, across a compaction.
+        store.put(b"deleted", b"").unwrap();
+        store.flush_memtable().unwrap();
+        store.del(b"deleted").unwrap();
+        store.flush_memtable().unwrap();
+        store.compact().unwrap();
+        check(&store);
+        assert_eq!(1, store.tables().count());
+    }
+}
+
+#[test]
+fn test_compaction_scheduler() {
+    // Compacts only while allowed to, such as outside of busy hours.
+    struct Allowed {
+        allowed: Arc<Mutex<bool>>,
+        calls: Arc<AtomicU64>,
+    }
+
+    impl CompactionScheduler for Allowed {
+        fn next_job(&self, tables: &[TableInfo]) -> Option<CompactionJob> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            let level_0 = tables.iter().filter(|t| t.level == 0).count();
+            (*self.allowed.lock().unwrap() && level_0 > 0).then_some(CompactionJob::Level(0))
+        }
+    }
+
+    let dir = TempDir::new("testing").unwrap();
+    let allowed = Arc::new(Mutex::new(false));
+    let calls = Arc::new(AtomicU64::new(0));
+    let options = Options::default()
+        .level_0_file_limit(2)
+        .compaction_scheduler(Allowed {
+            allowed: allowed.clone(),
+            calls: calls.clone(),
+        });
+    let mut store = Store::open(dir.path(), options).unwrap();
+
+    // The built-in triggers don't apply.
+    for i in 0..4 {
+        store.put(format!("key{}", i).as_bytes(), b"val").unwrap();
+        store.flush_memtable().unwrap();
+    }
+    assert_eq!(4, store.tables_at_level(0));
+    assert_eq!(4, calls.load(Ordering::SeqCst));
+
+    // The scheduler is asked again on each tick.
+    *allowed.lock().unwrap() = true;
+    store.tick().unwrap();
+    assert_eq!(0, store.tables_at_level(0));
+    assert_eq!(1, store.tables_at_level(1));
+    assert_eq!(5, calls.load(Ordering::SeqCst));
+    for i in 0..4 {
+        assert_eq!(
+            Some(b"val".to_vec()),
+            store.get(format!("key{}", i).as_bytes()).unwrap()
+        );
+    }
+
+    // Store::compact still merges everything regardless.
+    *allowed.lock().unwrap() = false;
+    store.put(b"key4", b"val").unwrap();
+    store.compact().unwrap();
+    assert_eq!(0, store.tables_at_level(0));
+}
+
+#[test]
+fn test_compaction_scheduler_panics() {
+    struct Panicking;
+
+    impl CompactionScheduler for Panicking {
+        fn next_job(&self, _: &[TableInfo]) -> Option<CompactionJob> {
+            panic!("scheduler failed");
+        }
+    }
+
+    let dir = TempDir::new("testing").unwrap();
+    let options = Options::default()
+        .compaction_mode(CompactionMode::Background)
+        .compaction_scheduler(Panicking);
+    let mut store = Store::open(dir.path(), options).unwrap();
+    store.put(b"key", b"val").unwrap();
+    store.flush_memtable().unwrap();
+
+    // The panic is reported as the compaction's error, and the store can still be used and closed.
+    let err = store.wait_for_compactions().unwrap_err();
+    assert!(
+        matches!(&err, StoreError::Io(e) if e.to_string().contains("scheduler failed")),
+        "{:?}",
+        err
+    );
+    assert_eq!(Some(b"val".to_vec()), store.get(b"key").unwrap());
+    drop(store);
+}
+
+#[test]
+fn test_update() {
+    let dir = TempDir::new("testing").unwrap();
+    let mut store = Store::open(dir.path(), Options::default()).unwrap();
+    let increment = |val: Option<&[u8]>| {
+        let n = val.map_or(0, |val| u64::from_le_bytes(val.try_into().unwrap()));
+        Some((n + 1).to_le_bytes().to_vec())
+    };
+
+    assert_eq!(
+        Some(1u64.to_le_bytes().to_vec()),
+        store.update(b"counter", increment).unwrap()
+    );
+    store.flush_memtable().unwrap();
+
+    // A value only in a table.
+    assert_eq!(
+        Some(2u64.to_le_bytes().to_vec()),
+        store.update(b"counter", increment).unwrap()
+    );
+    assert_eq!(
+        Some(2u64.to_le_bytes().to_vec()),
+        store.get(b"counter").unwrap()
+    );
+
+    // Returning None deletes the key, and writes nothing for one that has no value.
+    store.flush_memtable().unwrap();
+    assert_eq!(None, store.update(b"counter", |_| None).unwrap());
+    assert_eq!(None, store.get(b"counter").unwrap());
+    let wal_size = store.wal_size();
+    let mut seen = Some(b"unset".to_vec());
+    assert_eq!(
+        None,
+        store
+            .update(b"missing", |val| {
+                seen = val.map(|val| val.to_vec());
+                None
+            })
+            .unwrap()
+    );
+    assert_eq!(None, seen);
+    assert_eq!(wal_size, store.wal_size());
+
+    // Updates are written to the WAL as puts and deletions are.
+    drop(store);
+    let mut store = Store::open(dir.path(), Options::default()).unwrap();
+    assert_eq!(None, store.get(b"counter").unwrap());
+    store.update(b"counter", increment).unwrap();
+    drop(store);
+    let mut store = Store::open(dir.path(), Options::default()).unwrap();
+    assert_eq!(
+        Some(1u64.to_le_bytes().to_vec()),
+        store.get(b"counter").unwrap()
+    );
+
+    // The value written must be valid.
+    assert!(matches!(
+        store.update(b"", |_| Some(b"val".to_vec())),
+        Err(StoreError::InvalidArgument(_))
+    ));
+}
+
+#[test]
+fn test_write_batch() {
+    let dir = TempDir::new("testing").unwrap();
+    let mut store = Store::new(dir.path(), None, None, None).unwrap();
+    store.put(b"a", b"old").unwrap();
+    store.put(b"b", b"old").unwrap();
+
+    // Reads through the batch see its own ops first, with the last op on a key winning.
+    let mut batch = WriteBatch::new();
+    batch.put(b"a", b"new");
+    batch.del(b"b");
+    batch.put(b"c", b"first");
+    batch.put(b"c", b"second");
+    batch.put(b"d", b"gone");
+    batch.del(b"d");
+    assert_eq!(Some(b"new".to_vec()), batch.get(&store, b"a").unwrap());
+    assert_eq!(None, batch.get(&store, b"b").unwrap());
+    assert_eq!(Some(b"second".to_vec()), batch.get(&store, b"c").unwrap());
+    assert_eq!(None, batch.get(&store, b"d").unwrap());
+    store.put(b"e", b"store").unwrap();
+    assert_eq!(Some(b"store".to_vec()), batch.get(&store, b"e").unwrap());
+    assert_eq!(
+        vec![
+            (&b"a"[..], Some(&b"new"[..])),
+            (b"b", None),
+            (b"c", Some(b"second")),
+            (b"d", None),
+        ],
+        batch.iter().collect::<Vec<_>>()
+    );
+    assert_eq!(4, batch.len());
+
+    // Nothing is in the store until the batch is written.
+    assert_eq!(Some(b"old".to_vec()), store.get(b"b").unwrap());
+    store.write_batch(&batch).unwrap();
+    assert_eq!(Some(b"new".to_vec()), store.get(b"a").unwrap());
+    assert_eq!(None, store.get(b"b").unwrap());
+    assert_eq!(Some(b"second".to_vec()), store.get(b"c").unwrap());
+
+    // An invalid op keeps any of the batch from being written.
+    let mut invalid = WriteBatch::new();
+    invalid.put(b"f", b"val");
+    invalid.put(b"", b"val");
+    assert!(matches!(
+        store.write_batch(&invalid),
+        Err(StoreError::InvalidArgument(_))
+    ));
+    assert_eq!(None, store.get(b"f").unwrap());
+
+    // The batch is recovered from the WAL along with the writes around it.
+    store.put(b"g", b"after").unwrap();
+    drop(store);
+    let store = Store::new(dir.path(), None, None, None).unwrap();
+    assert_eq!(Some(b"new".to_vec()), store.get(b"a").unwrap());
+    assert_eq!(None, store.get(b"b").unwrap());
+    assert_eq!(Some(b"second".to_vec()), store.get(b"c").unwrap());
+    assert_eq!(Some(b"after".to_vec()), store.get(b"g").unwrap());
+}
+
+#[test]
+fn test_write_batch_torn() {
+    use crucible::options::RecoveryMode::TolerateCorruptTail;
+
+    let dir = TempDir::new("testing").unwrap();
+    let mut store = Store::new(dir.path(), None, None, None).unwrap();
+    store.put(b"before", b"val").unwrap();
+    let mut batch = WriteBatch::new();
+    batch.put(b"key1", b"val");
+    batch.put(b"key2", b"val");
+    store.write_batch(&batch).unwrap();
+    drop(store);
+
+    // Cutting the end off the batch loses all of it, not just its last record.
+    let wal = dir.path().join("data.wal");
+    let mut data = fs::read(&wal).unwrap();
+    data.truncate(data.len() - 1);
+    fs::write(&wal, data).unwrap();
+
+    let store = Store::open(
+        dir.path(),
+        Options::default().recovery_mode(TolerateCorruptTail),
+    )
+    .unwrap();
+    assert_eq!(1, store.recovery_report().skipped.len());
+    assert_eq!(Some(b"val".to_vec()), store.get(b"before").unwrap());
+    assert_eq!(None, store.get(b"key1").unwrap());
+    assert_eq!(None, store.get(b"key2").unwrap());
+}
+
+#[test]
+fn test_follower() {
+    let dir = TempDir::new("testing").unwrap();
+    let clock = ManualClock::default();
+    let options = Options::default()
+        .clock(clock.clone())
+        .obsolete_table_grace(Duration::from_secs(60));
+    let mut store = Store::open(dir.path(), options).unwrap();
+    store.put(b"key1", b"val1").unwrap();
+    store.flush_memtable().unwrap();
+
+    let follower =
+        Follower::with_clock(dir.path(), Duration::from_secs(10), Arc::new(clock.clone())).unwrap();
+    let old = follower.catalog().unwrap();
+    assert!(old.get(b"key1").unwrap().is_some());
+
+    // Until the interval passes, the follower keeps to the catalog it has.
+    store.put(b"key2", b"val2").unwrap();
+    store.flush_memtable().unwrap();
+    store.compact().unwrap();
+    assert!(follower.catalog().unwrap().get(b"key2").unwrap().is_none());
+
+    // The tables the compaction replaced are set aside rather than removed, and a catalog that still
+    // has them can go on reading them.
+    let obsolete = dir.path().join("0").join("1.obsolete");
+    assert!(obsolete.exists());
+    assert!(old.get(b"key1").unwrap().is_some());
+
+    clock.set(Duration::from_secs(10));
+    let new = follower.catalog().unwrap();
+    assert_eq!(0, new.tables(0).len());
+    assert_eq!(1, new.tables(1).len());
+    assert!(new.get(b"key1").unwrap().is_some());
+    assert!(new.get(b"key2").unwrap().is_some());
+
+    // Unchanged tables aren't opened again.
+    assert!(Arc::ptr_eq(
+        &new.tables(1)[0],
+        &follower.refresh().unwrap().tables(1)[0]
+    ));
+
+    // Set aside tables outlast their grace period only until the next tick.
+    clock.set(Duration::from_secs(59));
+    store.tick().unwrap();
+    assert!(obsolete.exists());
+    clock.set(Duration::from_secs(60));
+    store.tick().unwrap();
+    assert!(!obsolete.exists());
+
+    // Tables set aside when the store was closed get a new grace period when it is opened again.
+    store.put(b"key3", b"val3").unwrap();
+    store.flush_memtable().unwrap();
+    store.compact().unwrap();
+    drop(store);
+    clock.set(Duration::from_secs(100));
+    let options = Options::default()
+        .clock(clock.clone())
+        .obsolete_table_grace(Duration::from_secs(60));
+    let mut store = Store::open(dir.path(), options).unwrap();
+    let obsolete = dir.path().join("0").join("1.obsolete");
+    assert!(obsolete.exists());
+    clock.set(Duration::from_secs(159));
+    store.tick().unwrap();
+    assert!(obsolete.exists());
+    clock.set(Duration::from_secs(160));
+    store.tick().unwrap();
+    assert!(!obsolete.exists());
+    assert!(follower.refresh().unwrap().get(b"key3").unwrap().is_some());
+}
+
+// Flips every bit of a value, so that it can't be read without being decoded.
+struct Inverted;
+
+impl ValueCodec for Inverted {
+    fn id(&self) -> u8 {
+        100
+    }
+
+    fn encode(&self, val: &[u8]) -> Vec<u8> {
+        val.iter().map(|b| !b).collect()
+    }
+
+    fn decode(&self, val: &[u8]) -> io::Result<Vec<u8>> {
+        Ok(val.iter().map(|b| !b).collect())
+    }
+}
+
+#[test]
+fn test_value_codec() {
+    let dir = TempDir::new("testing").unwrap();
+    let options = || Options::default().inline_value_size(8);
+    let mut store = Store::open(dir.path(), options().value_codec(Inverted)).unwrap();
+    store.put(b"key1", b"value one").unwrap();
+    store.put(b"key2", b"val2").unwrap();
+    store.flush_memtable().unwrap();
+    store.put(b"key3", b"value three").unwrap();
+
+    // Neither the table nor the WAL has the values as they were put.
+    let table = dir.path().join("0").join("1.sst");
+    let contents = fs::read(&table).unwrap();
+    assert!(!contents.windows(9).any(|w| w == b"value one"));
+    assert!(!contents.windows(4).any(|w| w == b"val2"));
+    let wal = fs::read(dir.path().join("data.wal")).unwrap();
+    assert!(!wal.windows(11).any(|w| w == b"value three"));
+
+    let want = [
+        (b"key1".to_vec(), b"value one".to_vec()),
+        (b"key2".to_vec(), b"val2".to_vec()),
+        (b"key3".to_vec(), b"value three".to_vec()),
+    ];
+    let check = |store: &Store| {
+        for (key, val) in &want {
+            assert_eq!(Some(val.clone()), store.get(key).unwrap());
+        }
+        let mut val = Vec::new();
+        store
+            .get_reader(b"key1")
+            .unwrap()
+            .unwrap()
+            .read_to_end(&mut val)
+            .unwrap();
+        assert_eq!(b"value one".to_vec(), val);
+        assert_eq!(
+            want.to_vec(),
+            store
+                .scan(b"key", None)
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap()
+        );
+    };
+    check(&store);
+
+    // The WAL is recovered, and the table read, with the codec.
+    drop(store);
+    let store = Store::open(dir.path(), options().value_codec(Inverted)).unwrap();
+    check(&store);
+
+    // Without it, the store can't be opened.
+    drop(store);
+    assert!(matches!(
+        Store::open(dir.path(), options()),
+        Err(StoreError::InvalidArgument(_))
+    ));
+
+    // A store that stops encoding values can still read what was encoded, and compaction rewrites
+    // it without.
+    let mut store = Store::open(dir.path(), options().decode_values_with(Inverted)).unwrap();
+    check(&store);
+    store.compact_level(0).unwrap();
+    check(&store);
+    drop(store);
+    let store = Store::open(dir.path(), options()).unwrap();
+    check(&store);
+
+    // Codecs must have distinct ids.
+    assert!(matches!(
+        Store::open(
+            dir.path(),
+            options().value_codec(Inverted).decode_values_with(Inverted)
+        ),
+        Err(StoreError::InvalidArgument(_))
+    ));
+}
+
+#[test]
+fn test_write_batch_savepoints() {
+    let dir = TempDir::new("testing").unwrap();
+    let mut store = Store::new(dir.path(), None, None, None).unwrap();
+    store.put(b"a", b"store").unwrap();
+
+    let mut batch = WriteBatch::new();
+    assert!(matches!(
+        batch.rollback_to_savepoint(),
+        Err(StoreError::InvalidArgument(_))
+    ));
+
+    batch.put(b"a", b"first");
+    batch.set_savepoint();
+    batch.put(b"a", b"second");
+    batch.put(b"b", b"second");
+    batch.set_savepoint();
+    batch.del(b"a");
+    batch.put(b"c", b"third");
+    assert_eq!(None, batch.get(&store, b"a").unwrap());
+
+    // Each rollback goes back to the most recent savepoint left, and reads see only what is left.
+    batch.rollback_to_savepoint().unwrap();
+    assert_eq!(Some(b"second".to_vec()), batch.get(&store, b"a").unwrap());
+    assert_eq!(None, batch.get(&store, b"c").unwrap());
+    batch.rollback_to_savepoint().unwrap();
+    assert_eq!(Some(b"first".to_vec()), batch.get(&store, b"a").unwrap());
+    assert_eq!(None, batch.get(&store, b"b").unwrap());
+    assert!(batch.rollback_to_savepoint().is_err());
+
+    // Ops added after a rollback are kept, and the batch commits what is left.
+    batch.put(b"d", b"after");
+    batch.set_savepoint();
+    batch.put(b"e", b"dropped");
+    batch.rollback_to_savepoint().unwrap();
+    assert_eq!(2, batch.len());
+    store.write_batch(&batch).unwrap();
+    assert_eq!(Some(b"first".to_vec()), store.get(b"a").unwrap());
+    assert_eq!(None, store.get(b"b").unwrap());
+    assert_eq!(None, store.get(b"c").unwrap());
+    assert_eq!(Some(b"after".to_vec()), store.get(b"d").unwrap());
+    assert_eq!(None, store.get(b"e").unwrap());
+
+    // Clearing drops savepoints along with the ops.
+    batch.set_savepoint();
+    batch.clear();
+    assert!(batch.is_empty());
+    assert!(batch.rollback_to_savepoint().is_err());
+}
+
+#[test]
+fn test_replace_with() {
+    let dir = TempDir::new("testing").unwrap();
+    let staging = TempDir::new("staging").unwrap();
+    let mut store = Store::new(dir.path(), None, None, None).unwrap();
+    store.put(b"old1", b"val").unwrap();
+    store.flush_memtable().unwrap();
+    store.put(b"old2", b"val").unwrap();
+    store.put(b"shared", b"old").unwrap();
+
+    let mut staged = Store::new(staging.path(), None, None, None).unwrap();
+    staged.put(b"new1", b"val").unwrap();
+    staged.put(b"shared", b"new").unwrap();
+    let staged_id = staged.id();
+
+    // The staging store must have flushed its writes.
+    assert!(matches!(
+        store.replace_with(staging.path()),
+        Err(StoreError::InvalidArgument(_))
+    ));
+    staged.flush_memtable().unwrap();
+    drop(staged);
+    assert!(matches!(
+        store.replace_with(dir.path()),
+        Err(StoreError::InvalidArgument(_))
+    ));
+
+    let snapshot = store.freeze();
+    store.replace_with(staging.path()).unwrap();
+    let check = |store: &Store| {
+        assert_eq!(None, store.get(b"old1").unwrap());
+        assert_eq!(None, store.get(b"old2").unwrap());
+        assert_eq!(Some(b"new".to_vec()), store.get(b"shared").unwrap());
+        assert_eq!(Some(b"val".to_vec()), store.get(b"new1").unwrap());
+        assert_eq!(staged_id, store.id());
+    };
+    check(&store);
+    assert_eq!(0, store.wal_size());
+    assert_eq!(Some(b"old".to_vec()), snapshot.get(b"shared").unwrap());
+    drop(snapshot);
+
+    // Nothing of the old data comes back once the store is opened again, including writes that
+    // were only in the WAL.
+    store.put(b"after", b"val").unwrap();
+    drop(store);
+    let store = Store::new(dir.path(), None, None, None).unwrap();
+    check(&store);
+    assert_eq!(Some(b"val".to_vec()), store.get(b"after").unwrap());
+}
+
+#[test]
+fn test_new_temp() {
+    let mut store = Store::new_temp().unwrap();
+    let dir = store.data_dir().to_path_buf();
+    assert!(dir.is_dir());
+    for i in 0..3 {
+        store.put(format!("key{}", i).as_bytes(), b"val").unwrap();
+        store.flush_memtable().unwrap();
+    }
+    store.compact_level(0).unwrap();
+    assert!(dir.join("1").is_dir());
+    assert_eq!(Some(b"val".to_vec()), store.get(b"key1").unwrap());
+
+    drop(store);
+    assert!(!dir.exists());
+}
+
+#[test]
+fn test_merge_scan() {
+    let new_dir = TempDir::new("testing").unwrap();
+    let old_dir = TempDir::new("testing").unwrap();
+    let mut new = Store::open(new_dir.path(), Options::default()).unwrap();
+    let mut old = Store::open(old_dir.path(), Options::default().min_blob_size(8)).unwrap();
+
+    old.put(b"a1", b"old").unwrap();
+    old.put(b"a2", b"a large old value").unwrap();
+    old.put(b"b1", b"old").unwrap();
+    old.put(b"b2", b"old").unwrap();
+    old.put(b"c1", b"old").unwrap();
+    old.flush_memtable().unwrap();
+    old.put(b"d1", b"old").unwrap();
+
+    new.put(b"a1", b"new").unwrap();
+    new.flush_memtable().unwrap();
+    new.del(b"c1").unwrap();
+    new.delete_prefix(b"b").unwrap();
+    new.put(b"b2", b"new").unwrap();
+
+    // The first store wins wherever both have a key, even where the other's write came later.
+    old.put(b"a1", b"newer").unwrap();
+    let merged = crucible::merge_scan(&[&new, &old], b"", None)
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(
+        vec![
+            (b"a1".to_vec(), b"new".to_vec()),
+            (b"a2".to_vec(), b"a large old value".to_vec()),
+            (b"b2".to_vec(), b"new".to_vec()),
+            (b"d1".to_vec(), b"old".to_vec()),
+        ],
+        merged
+    );
+
+    let merged = crucible::merge_scan(&[&old, &new], b"a", Some(b"c"))
+        .unwrap()
+        .map(|r| r.unwrap().0)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        vec![
+            b"a1".to_vec(),
+            b"a2".to_vec(),
+            b"b1".to_vec(),
+            b"b2".to_vec()
+        ],
+        merged
+    );
+}
+
+#[test]
+fn test_flush_stats() {
+    let dir = TempDir::new("testing").unwrap();
+    let mut store = Store::open(dir.path(), Options::default()).unwrap();
+    assert_eq!(0, store.stats().flushes.count);
+    assert_eq!(0, store.stats().flushes.avg_bytes());
+
+    store.put(b"key1", b"val1").unwrap();
+    store.flush_memtable().unwrap();
+    for i in 0..100 {
+        store.put(format!("key{}", i).as_bytes(), b"val").unwrap();
+    }
+    store.flush_memtable().unwrap();
+    // Nothing to flush isn't counted.
+    store.flush_memtable().unwrap();
+
+    let flushes = store.stats().flushes;
+    let sizes = store
+        .tables()
+        .map(|(_, table)| table.size().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(2, flushes.count);
+    assert_eq!(101, flushes.records);
+    assert_eq!(sizes.iter().sum::<u64>(), flushes.bytes);
+    assert_eq!(*sizes.iter().min().unwrap(), flushes.min_bytes);
+    assert_eq!(*sizes.iter().max().unwrap(), flushes.max_bytes);
+    assert_eq!(flushes.bytes / 2, flushes.avg_bytes());
+    assert!(flushes.min_duration <= flushes.avg_duration());
+    assert!(flushes.avg_duration() <= flushes.max_duration);
+}
+
+#[test]
+fn test_compact_overlapping_level_1() {
+    let dir = TempDir::new("testing").unwrap();
+    let mut store = Store::new(dir.path(), None, None, None).unwrap();
+    store.put(b"key1", b"val1").unwrap();
+    store.flush_memtable().unwrap();
+    store.compact().unwrap();
+    drop(store);
+
+    // As if a table had been copied into level 1 a second time.
+    let level_1 = dir.path().join("1");
+    let table = fs::read_dir(&level_1)
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap()
+        .path();
+    fs::copy(
+        &table,
+        level_1.join("00000000-0000-0000-0000-000000000000.sst"),
+    )
+    .unwrap();
+
+    // Reads still work, but compacting them fails without writing anything.
+    let mut store = Store::new(dir.path(), None, None, None).unwrap();
+    assert_eq!(Some(b"val1".to_vec()), store.get(b"key1").unwrap());
+    store.put(b"key2", b"val2").unwrap();
+    store.flush_memtable().unwrap();
+    match store.compact() {
+        Err(StoreError::Compaction { source, .. }) => {
+            assert_eq!(io::ErrorKind::InvalidData, source.kind());
+            assert!(source.to_string().contains("key1"), "{}", source);
+        }
+        other => panic!("expected a compaction error, got {:?}", other),
+    }
+    assert_eq!(2, fs::read_dir(&level_1).unwrap().count());
+}
+
+// Writes `n` records of `val_size` bytes, overwriting and deleting some of them along the way, and
+// checks that they all read back, both before and after the store is opened again. Returns the
+// stats and compaction decisions from before it was, and the store after a full compaction.
+fn run_preset_workload(
+    options: impl Fn() -> Options,
+    n: usize,
+    val_size: usize,
+) -> (Stats, Vec<CompactionDecision>, Store, TempDir) {
+    let dir = TempDir::new("testing").unwrap();
+    let mut store = Store::open(dir.path(), options()).unwrap();
+    let key = |i: usize| format!("key{:06}", i).into_bytes();
+    // Values that don't compress, so that they take up as much space with a value codec.
+    let val = |i: usize| {
+        let mut x = (i as u64 + 1).wrapping_mul(0x9e3779b97f4a7c15);
+        (0..val_size)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 7;
+                x ^= x << 17;
+                x as u8
+            })
+            .collect::<Vec<_>>()
+    };
+    for i in 0..n {
+        store.put(&key(i), &val(i)).unwrap();
+    }
+    for i in (0..n).step_by(3) {
+        store.put(&key(i), &val(i + 1)).unwrap();
+    }
+    for i in (0..n).step_by(5) {
+        store.del(&key(i)).unwrap();
+    }
+
+    let check = |store: &Store| {
+        for i in (0..n).step_by(7) {
+            let expected = match i {
+                i if i % 5 == 0 => None,
+                i if i % 3 == 0 => Some(val(i + 1)),
+                i => Some(val(i)),
+            };
+            assert_eq!(expected, store.get(&key(i)).unwrap(), "key {}", i);
+        }
+        assert_eq!(n - n.div_ceil(5), store.scan(b"", None).unwrap().count());
+    };
+    store.wait_for_compactions().unwrap();
+    check(&store);
+    let stats = store.stats();
+    let decisions = store.recent_compaction_decisions();
+    drop(store);
+    let mut store = Store::open(dir.path(), options()).unwrap();
+    check(&store);
+    store.compact().unwrap();
+    check(&store);
+    (stats, decisions, store, dir)
+}
+
+// Counts the decisions that chose `trigger`.
+fn chosen(decisions: &[CompactionDecision], trigger: Trigger) -> usize {
+    decisions
+        .iter()
+        .filter(|d| d.chosen == Some(trigger))
+        .count()
+}
+
+#[test]
+fn test_options_preset_point_lookups() {
+    // Enough writes to flush and compact level 0 several times.
+    let (stats, decisions, _, _dir) = run_preset_workload(
+        || Options::optimized_for_point_lookups(64 << 20).explain_compactions(1000),
+        12000,
+        2000,
+    );
+    assert!(stats.flushes.count > 4, "{:?}", stats);
+    assert!(chosen(&decisions, Trigger::Level0FileLimit) > 1);
+}
+
+#[test]
+fn test_options_preset_bulk_ingest() {
+    // The WAL and table limits are scaled down so that the workload flushes many times, which is
+    // what brings the preset's level 0 settings into play.
+    let (stats, decisions, _, _dir) = run_preset_workload(
+        || {
+            Options::optimized_for_bulk_ingest()
+                .wal_size_limit(16 * 1024)
+                .table_size_limit(64 * 1024)
+                .explain_compactions(1000)
+        },
+        5000,
+        100,
+    );
+    assert!(stats.flushes.count > 16, "{:?}", stats);
+
+    // Runs of small level 0 tables are merged while level 0 is below its limit, and it is compacted
+    // into level 1 each time it reaches it.
+    assert!(chosen(&decisions, Trigger::Level0Merge) > 1);
+    let compactions = decisions
+        .iter()
+        .enumerate()
+        .filter(|(_, d)| d.chosen == Some(Trigger::Level0FileLimit))
+        .map(|(i, _)| i)
+        .collect::<Vec<_>>();
+    assert!(compactions.len() > 1);
+
+    // Level 0 never grows past its limit, and only reaches it again after dropping below the rearm
+    // limit in between.
+    let level_0 = |d: &CompactionDecision| {
+        d.triggers
+            .iter()
+            .find(|t| t.trigger == Trigger::Level0FileLimit)
+            .unwrap()
+            .value
+    };
+    assert!(decisions.iter().all(|d| level_0(d) <= 8));
+    for pair in compactions.windows(2) {
+        assert!(decisions[pair[0] + 1..pair[1]]
+            .iter()
+            .any(|d| level_0(d) < 4));
+    }
+}
+
+#[test]
+fn test_options_preset_small_footprint() {
+    let (stats, decisions, _, _dir) = run_preset_workload(
+        || Options::small_footprint().explain_compactions(1000),
+        6000,
+        1000,
+    );
+    assert!(stats.flushes.count > 4, "{:?}", stats);
+    assert!(chosen(&decisions, Trigger::Level0FileLimit) > 1);
 }
@@ -0,0 +1,162 @@
+#![cfg(feature = "remote")]
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, Write},
+    sync::{Arc, Mutex},
+};
+
+use crucible::{
+    protocol::ReadRecord,
+    sst::{Catalog, ObjectStore, RemoteLevels},
+    store::Store,
+    StoreError,
+};
+use tempdir::TempDir;
+
+// An object store held in memory, which counts the objects fetched from it and can be made to fail
+// fetches part way through.
+#[derive(Default)]
+struct MemoryStore {
+    objects: Mutex<BTreeMap<String, Vec<u8>>>,
+    gets: Mutex<usize>,
+    fail_gets: Mutex<bool>,
+}
+
+impl ObjectStore for MemoryStore {
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>> {
+        let objects = self.objects.lock().unwrap();
+        Ok(objects
+            .keys()
+            .filter(|name| name.starts_with(prefix))
+            .cloned()
+            .collect())
+    }
+
+    fn get(&self, name: &str, w: &mut dyn Write) -> io::Result<()> {
+        *self.gets.lock().unwrap() += 1;
+        let objects = self.objects.lock().unwrap();
+        let object = objects.get(name).ok_or(io::ErrorKind::NotFound)?;
+        if *self.fail_gets.lock().unwrap() {
+            w.write_all(&object[..object.len() / 2])?;
+            return Err(io::Error::other("connection reset"));
+        }
+        w.write_all(object)
+    }
+}
+
+#[test]
+fn test_remote_levels() {
+    let dir = TempDir::new("testing").unwrap();
+    let cache = TempDir::new("cache").unwrap();
+    let mut store = Store::new(dir.path(), None, None, None).unwrap();
+    for i in 0..100 {
+        store
+            .put(format!("key{:03}", i).as_bytes(), b"old")
+            .unwrap();
+    }
+    store.flush_memtable().unwrap();
+    store.compact().unwrap();
+    store.put(b"key000", b"new").unwrap();
+    store.flush_memtable().unwrap();
+    drop(store);
+
+    // Level 1 is moved to the object store, leaving level 0 behind.
+    let remote = Arc::new(MemoryStore::default());
+    let mut table = Vec::new();
+    for entry in fs::read_dir(dir.path().join("1")).unwrap() {
+        let path = entry.unwrap().path();
+        let name = format!("1/{}", path.file_name().unwrap().to_str().unwrap());
+        table = fs::read(&path).unwrap();
+        remote.objects.lock().unwrap().insert(name, table.clone());
+        fs::remove_file(path).unwrap();
+    }
+    remote
+        .objects
+        .lock()
+        .unwrap()
+        .insert("1/README".to_string(), b"not a table".to_vec());
+    let levels = RemoteLevels::new(remote.clone(), cache.path());
+
+    let catalog = Catalog::open_remote(dir.path(), &levels).unwrap();
+    assert_eq!(1, catalog.tables(0).len());
+    assert_eq!(1, catalog.tables(1).len());
+    let value = |catalog: &Catalog, key: &[u8]| match catalog.get(key).unwrap() {
+        Some(ReadRecord::Exists { val, .. }) => val,
+        other => panic!("unexpected record: {:?}", other),
+    };
+    assert_eq!(b"new".to_vec(), value(&catalog, b"key000"));
+    assert_eq!(b"old".to_vec(), value(&catalog, b"key050"));
+    assert_eq!(100, catalog.scan(b"", None).unwrap().count());
+    assert_eq!(1, *remote.gets.lock().unwrap());
+
+    // Cached tables aren't fetched again.
+    drop(catalog);
+    let catalog = Catalog::open_remote(dir.path(), &levels).unwrap();
+    assert_eq!(b"old".to_vec(), value(&catalog, b"key050"));
+    assert_eq!(1, *remote.gets.lock().unwrap());
+    drop(catalog);
+
+    // A table that fails to fetch keeps the catalog from opening, and isn't cached.
+    let insert = |name: &str, contents: Vec<u8>| {
+        remote
+            .objects
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), contents)
+    };
+    insert("1/copy.sst", table.clone());
+    *remote.fail_gets.lock().unwrap() = true;
+    assert!(Catalog::open_remote(dir.path(), &levels).is_err());
+    assert!(!cache.path().join("1/copy.sst").exists());
+
+    // Neither is a damaged one.
+    *remote.fail_gets.lock().unwrap() = false;
+    let mut damaged = table.clone();
+    damaged[10] ^= 0xff;
+    insert("1/copy.sst", damaged);
+    assert!(matches!(
+        Catalog::open_remote(dir.path(), &levels),
+        Err(StoreError::Corruption { .. })
+    ));
+    assert!(!cache.path().join("1/copy.sst").exists());
+
+    // Tables removed from the object store are removed from the cache.
+    insert("1/copy.sst", table);
+    drop(Catalog::open_remote(dir.path(), &levels).unwrap());
+    assert!(cache.path().join("1/copy.sst").exists());
+    remote.objects.lock().unwrap().remove("1/copy.sst");
+    let catalog = Catalog::open_remote(dir.path(), &levels).unwrap();
+    assert_eq!(1, catalog.tables(1).len());
+    assert!(!cache.path().join("1/copy.sst").exists());
+    drop(catalog);
+
+    // Tables named as being in impossibly deep levels are rejected.
+    for name in ["18446744073709551615/x.sst", "99999999999/x.sst"] {
+        insert(name, Vec::new());
+        assert!(matches!(
+            Catalog::open_remote(dir.path(), &levels),
+            Err(StoreError::Corruption { .. })
+        ));
+        remote.objects.lock().unwrap().remove(name);
+    }
+
+    // Level 0 is always local, and remote levels can't have local tables.
+    let levels = RemoteLevels::new(remote.clone(), cache.path()).first_level(0);
+    assert!(matches!(
+        Catalog::open_remote(dir.path(), &levels),
+        Err(StoreError::InvalidArgument(_))
+    ));
+    let cached = fs::read_dir(cache.path().join("1"))
+        .unwrap()
+        .next()
+        .unwrap();
+    fs::create_dir_all(dir.path().join("2")).unwrap();
+    fs::copy(cached.unwrap().path(), dir.path().join("2/local.sst")).unwrap();
+    let levels = RemoteLevels::new(remote.clone(), cache.path());
+    assert!(matches!(
+        Catalog::open_remote(dir.path(), &levels),
+        Err(StoreError::InvalidArgument(_))
+    ));
+}
@@ -0,0 +1,247 @@
+use std::{
+    fs,
+    io::{BufRead, BufReader, Read, Write},
+    net::{Shutdown, TcpStream},
+    process::{Command, Stdio},
+};
+
+use crucible::store::Store;
+use tempdir::TempDir;
+
+#[test]
+fn test_sst_dump() {
+    let dir = TempDir::new("testing").unwrap();
+    let mut store = Store::new(dir.path(), None, None, None).unwrap();
+    store.put(b"key1", b"val1").unwrap();
+    store.del(b"key2").unwrap();
+    store.flush_memtable().unwrap();
+    let table = dir.path().join("0").join("1.sst");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_crucible-sst-dump"))
+        .args(["--records", "--json"])
+        .arg(&table)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains(
+        r#""records":[{"offset":0,"op":"put","key":"key1","value_length":4},{"offset":17,"op":"del","key":"key2"}]"#
+    ));
+
+    // Whatever can be read from a truncated table is still printed.
+    let file = fs::OpenOptions::new().write(true).open(&table).unwrap();
+    file.set_len(20).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_crucible-sst-dump"))
+        .arg(&table)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("put  \"key1\" (4 byte value)"));
+    assert!(stdout.contains("error at offset 17"));
+}
+
+#[test]
+fn test_wal_dump() {
+    let dir = TempDir::new("testing").unwrap();
+    let mut store = Store::new(dir.path(), None, None, None).unwrap();
+    store.put(b"key1", b"val1").unwrap();
+    store.del(b"key2").unwrap();
+    drop(store);
+    let wal = dir.path().join("data.wal");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_crucible-wal-dump"))
+        .args(["--json", "--values"])
+        .arg(&wal)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(
+        vec![
+            r#"{"offset":0,"op":"put","key":"key1","value":"val1"}"#,
+            r#"{"offset":17,"op":"del","key":"key2"}"#,
+            r#"{"records":2,"clean_bytes":30,"file_bytes":30,"error":null}"#,
+        ],
+        stdout.lines().collect::<Vec<_>>()
+    );
+
+    // A torn write at the end of the WAL is reported along with the readable prefix.
+    let file = fs::OpenOptions::new().write(true).open(&wal).unwrap();
+    file.set_len(25).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_crucible-wal-dump"))
+        .arg(&wal)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("put  \"key1\" (4 byte value)"));
+    assert!(stdout.contains("clean bytes: 17 of 25"));
+    assert!(stdout.contains("error at offset 17"));
+}
+
+#[test]
+fn test_cli() {
+    let dir = TempDir::new("testing").unwrap();
+    let crucible = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_crucible"))
+            .arg(dir.path())
+            .args(args)
+            .output()
+            .unwrap();
+        (
+            output.status.code().unwrap(),
+            String::from_utf8(output.stdout).unwrap(),
+        )
+    };
+
+    assert_eq!(0, crucible(&["put", "key1", "val1"]).0);
+    assert_eq!(0, crucible(&["put", "key2", "val2"]).0);
+    assert_eq!(0, crucible(&["--hex", "put", "6b657933", "00ff"]).0);
+    assert_eq!(0, crucible(&["del", "key2"]).0);
+
+    assert_eq!((0, "val1\n".to_string()), crucible(&["get", "key1"]));
+    assert_eq!(1, crucible(&["get", "key2"]).0);
+    assert_eq!(
+        (0, "key1\tval1\nkey3\t\\x00\\xff\n".to_string()),
+        crucible(&["scan", "--prefix", "key"])
+    );
+    assert_eq!(
+        (0, "a2V5Mw==\tAP8=\n".to_string()),
+        crucible(&["--base64", "scan", "--start", "a2V5Mg==", "--limit", "1"])
+    );
+
+    assert_eq!(0, crucible(&["compact"]).0);
+    let (code, stdout) = crucible(&["stats"]);
+    assert_eq!(0, code);
+    assert!(stdout.contains("live keys: 2"));
+    assert!(stdout.contains("level 1: 1 tables"));
+
+    // Usage errors are distinguished from failures.
+    assert_eq!(2, crucible(&["get"]).0);
+    assert_eq!(2, crucible(&["get", "key1", "--limit", "1"]).0);
+}
+
+#[test]
+fn test_server() {
+    let dir = TempDir::new("testing").unwrap();
+    let mut server = Command::new(env!("CARGO_BIN_EXE_crucible-server"))
+        .args(["--addr", "127.0.0.1:0"])
+        .arg(dir.path())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let mut line = String::new();
+    BufReader::new(server.stdout.take().unwrap())
+        .read_line(&mut line)
+        .unwrap();
+    let addr = line.trim().strip_prefix("listening on ").unwrap();
+
+    let client = redis::Client::open(format!("redis://{}/", addr)).unwrap();
+    let mut con = client.get_connection().unwrap();
+
+    let pong: String = redis::cmd("PING").query(&mut con).unwrap();
+    assert_eq!("PONG", pong);
+
+    for i in 0..25 {
+        let _: () = redis::cmd("SET")
+            .arg(format!("key{:02}", i))
+            .arg(format!("val{}", i))
+            .query(&mut con)
+            .unwrap();
+    }
+    let _: () = redis::cmd("SET")
+        .arg(&[0u8, 255][..])
+        .arg(&[13u8, 10][..])
+        .query(&mut con)
+        .unwrap();
+    let _: () = redis::cmd("SET")
+        .arg("other")
+        .arg("x")
+        .query(&mut con)
+        .unwrap();
+
+    let got: Option<String> = redis::cmd("GET").arg("key03").query(&mut con).unwrap();
+    assert_eq!(Some("val3".to_string()), got);
+    let got: Vec<u8> = redis::cmd("GET")
+        .arg(&[0u8, 255][..])
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(vec![13, 10], got);
+    let got: Option<String> = redis::cmd("GET").arg("missing").query(&mut con).unwrap();
+    assert_eq!(None, got);
+
+    let deleted: i64 = redis::cmd("DEL")
+        .arg("key00")
+        .arg("missing")
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(1, deleted);
+    let exists: i64 = redis::cmd("EXISTS")
+        .arg("key00")
+        .arg("key01")
+        .arg("other")
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(2, exists);
+
+    // Page through the prefix a few keys at a time.
+    let mut cursor = 0u64;
+    let mut scanned = Vec::new();
+    loop {
+        let (next, keys): (u64, Vec<String>) = redis::cmd("SCAN")
+            .arg(cursor)
+            .arg("MATCH")
+            .arg("key*")
+            .arg("COUNT")
+            .arg(10)
+            .query(&mut con)
+            .unwrap();
+        scanned.extend(keys);
+        if next == 0 {
+            break;
+        }
+        cursor = next;
+    }
+    let want = (1..25).map(|i| format!("key{:02}", i)).collect::<Vec<_>>();
+    assert_eq!(want, scanned);
+
+    // A COUNT larger than the store is just all of it.
+    let (next, keys): (u64, Vec<Vec<u8>>) = redis::cmd("SCAN")
+        .arg(0)
+        .arg("COUNT")
+        .arg(u64::MAX)
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(0, next);
+    assert_eq!(26, keys.len());
+
+    let err = redis::cmd("FLUSHALL").query::<()>(&mut con).unwrap_err();
+    assert!(err.to_string().contains("unknown command"));
+    let err = redis::cmd("GET").query::<()>(&mut con).unwrap_err();
+    assert!(err.to_string().contains("wrong number of arguments"));
+
+    // Clients can't make the server buffer more than they send, or an unending line.
+    let protocol_error = |request: &[u8]| {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(request).unwrap();
+        stream.shutdown(Shutdown::Write).unwrap();
+        let mut reply = String::new();
+        stream.read_to_string(&mut reply).unwrap();
+        reply
+    };
+    // Just past the 64 KiB limit, so that the server has read everything sent when it gives up.
+    let reply = protocol_error(&[b'a'; 64 * 1024 + 2]);
+    assert!(reply.starts_with("-ERR Protocol error: line too long"));
+    let reply = protocol_error(b"*1\r\n$536870912\r\nabc");
+    assert!(reply.starts_with("-ERR Protocol error: unexpected end"));
+
+    let _: () = redis::cmd("SHUTDOWN").query(&mut con).unwrap();
+    assert!(server.wait().unwrap().success());
+
+    // Everything written is there when the store is next opened.
+    let store = Store::new(dir.path(), None, None, None).unwrap();
+    assert_eq!(Some(b"val24".to_vec()), store.get(b"key24").unwrap());
+    assert_eq!(None, store.get(b"key00").unwrap());
+}